@@ -1,2 +1,4 @@
 pub mod loader;
+pub mod lock;
+pub mod npm;
 pub mod runtime;
@@ -1,25 +1,57 @@
 use std::{
     collections::HashMap,
-    path::Path,
+    path::{Path, PathBuf},
     pin::Pin,
     sync::{Arc, Mutex},
 };
 
 use anyhow::anyhow;
-use deno_ast::MediaType;
-use deno_core::{futures::FutureExt, ModuleSourceCode, ModuleType, RequestedModuleType};
+use deno_ast::{swc::ast, MediaType};
+use deno_core::{
+    futures::FutureExt, serde_json, ModuleSourceCode, ModuleType, RequestedModuleType,
+};
 use mdxjs::{MdxConstructs, MdxParseOptions};
+use tokio::sync::Semaphore;
+use tracing::instrument;
 use url::Url;
 
+use crate::{lock::Lockfile, npm::Npm};
+
+/// Compiles a non-JavaScript source into a JavaScript module.
+pub type Transform = Arc<dyn Fn(&Url, &str) -> Result<String, anyhow::Error> + Send + Sync>;
+
 #[derive(Clone)]
 pub struct LoaderOptions {
     pub jsx_import_source: String,
+    /// Whether JSX uses the automatic runtime, imported from
+    /// `jsx_import_source`, or the classic one, calling `jsx_factory`.
+    pub jsx_automatic: bool,
+    /// Classic runtime element factory, `React.createElement` if unset.
+    pub jsx_factory: Option<String>,
+    /// Classic runtime fragment, `React.Fragment` if unset.
+    pub jsx_fragment_factory: Option<String>,
+    /// Extensions, without the leading dot, compiled as MDX.
+    pub mdx_extensions: Vec<String>,
+    /// Transforms applied before transpiling, keyed by file name suffix.
+    pub transforms: Vec<(String, Transform)>,
+    /// Object defined as `import.meta.env` in modules using it.
+    pub import_meta_env: serde_json::Map<String, serde_json::Value>,
+    /// Remote modules fetched at once, at least one.
+    pub max_fetches: usize,
+    /// Lockfile remote modules are checked against, shared by clones.
+    pub lock: Option<Arc<Mutex<Lockfile>>>,
+    /// Directory `npm:` packages are cached in. `npm:` specifiers fail if
+    /// unset.
+    pub npm_cache: Option<PathBuf>,
 }
 
 #[derive(Clone)]
 pub struct Loader {
     client: reqwest::Client,
+    /// Permits to fetch remote modules, shared by clones.
+    fetches: Arc<Semaphore>,
     pub(crate) injected: Arc<Mutex<HashMap<Url, String>>>,
+    npm: Option<Npm>,
     options: LoaderOptions,
 }
 
@@ -27,7 +59,9 @@ impl Loader {
     pub fn new(options: LoaderOptions) -> Self {
         Self {
             client: reqwest::Client::new(),
+            fetches: Arc::new(Semaphore::new(options.max_fetches.max(1))),
             injected: Arc::new(Mutex::new(HashMap::new())),
+            npm: options.npm_cache.as_deref().map(Npm::new),
             options,
         }
     }
@@ -40,6 +74,27 @@ impl Loader {
         self.injected.lock().unwrap().get(url).map(|s| s.clone())
     }
 
+    /// Whether a module has been loaded, and so may be cached by the runtime.
+    pub fn is_loaded(&self, url: &Url) -> bool {
+        self.injected.lock().unwrap().contains_key(url)
+    }
+
+    pub fn npm(&self) -> Option<&Npm> {
+        self.npm.as_ref()
+    }
+
+    /// File URL of the module an `npm:` specifier points to.
+    async fn resolve_npm(&self, specifier: &Url) -> Result<Url, anyhow::Error> {
+        let npm = self
+            .npm
+            .as_ref()
+            .ok_or_else(|| anyhow!("npm: specifiers are not supported here, in {}", specifier))?;
+        // Registry requests count towards remote fetches
+        let _permit = self.fetches.acquire().await?;
+        npm.resolve(specifier, self.options.lock.as_deref()).await
+    }
+
+    #[instrument(name = "load", level = "debug", skip_all, fields(url = %specifier))]
     async fn load_to_string(&self, specifier: &Url) -> Result<String, anyhow::Error> {
         if let Some(code) = self.get_injected(specifier) {
             return Ok(code.clone());
@@ -52,18 +107,42 @@ impl Loader {
                 std::fs::read_to_string(path)?
             }
             "https" => {
-                self.client
-                    .get(specifier.as_str())
-                    .send()
-                    .await?
-                    .text()
-                    .await?
+                // Held until the whole response is read
+                let _permit = self.fetches.acquire().await?;
+                let response = self.client.get(specifier.as_str()).send().await?;
+                let resolved = response.url().clone();
+                let source = response.bytes().await?;
+                if let Some(lock) = &self.options.lock {
+                    lock.lock().unwrap().check(specifier, &resolved, &source)?;
+                }
+                String::from_utf8(source.to_vec())?
+            }
+            scheme => {
+                // Other schemes are only served by injected modules, so list
+                // those sharing it, in case of a typo
+                let mut known: Vec<String> = self
+                    .injected
+                    .lock()
+                    .unwrap()
+                    .keys()
+                    .filter(|url| url.scheme() == scheme)
+                    .map(Url::to_string)
+                    .collect();
+                known.sort();
+                return Err(if known.is_empty() {
+                    anyhow!("invalid scheme in url {}", specifier)
+                } else {
+                    anyhow!(
+                        "unknown module {}, expected one of {}",
+                        specifier,
+                        known.join(", ")
+                    )
+                });
             }
-            _ => return Err(anyhow!("invalid scheme in url {}", specifier.to_string())),
         };
 
         let code = if module_type == ModuleType::JavaScript {
-            transpile(&specifier, &code, &self.options.jsx_import_source)?
+            transpile(&specifier, &code, &self.options)?
         } else {
             code
         };
@@ -82,6 +161,12 @@ impl deno_graph::source::Loader for Loader {
         let specifier = specifier.clone();
         let loader = self.clone();
         async move {
+            if specifier.scheme() == "npm" {
+                let found = loader.resolve_npm(&specifier).await?;
+                return Ok(Some(deno_graph::source::LoadResponse::Redirect {
+                    specifier: found,
+                }));
+            }
             let code = loader.load_to_string(&specifier).await?;
             loader.inject(specifier.clone(), code.clone());
             Ok(Some(deno_graph::source::LoadResponse::Module {
@@ -101,6 +186,12 @@ impl deno_core::ModuleLoader for Loader {
         referrer: &str,
         _kind: deno_core::ResolutionKind,
     ) -> Result<Url, deno_core::error::AnyError> {
+        // Bare imports of npm packages, from inside other npm packages
+        if let (Some(npm), Ok(referrer)) = (&self.npm, Url::parse(referrer)) {
+            if let Some(url) = npm.resolve_bare(specifier, &referrer) {
+                return Ok(url);
+            }
+        }
         deno_core::resolve_import(specifier, referrer).map_err(|e| e.into())
     }
 
@@ -112,74 +203,108 @@ impl deno_core::ModuleLoader for Loader {
         _requested_module_type: RequestedModuleType,
     ) -> Pin<Box<deno_core::ModuleSourceFuture>> {
         let specifier = specifier.clone();
-        let module_type = module_type(&specifier);
         let loader = self.clone();
         async move {
-            let code = loader.load_to_string(&specifier).await?;
-            loader.inject(specifier.clone(), code.clone());
-            Ok(deno_core::ModuleSource::new(
-                module_type,
-                ModuleSourceCode::String(code.into()),
-                &specifier,
-            ))
+            let found = match specifier.scheme() {
+                "npm" => loader.resolve_npm(&specifier).await?,
+                _ => specifier.clone(),
+            };
+            let module_type = module_type(&found);
+            let code = loader.load_to_string(&found).await?;
+            loader.inject(found.clone(), code.clone());
+            Ok(if found == specifier {
+                deno_core::ModuleSource::new(
+                    module_type,
+                    ModuleSourceCode::String(code.into()),
+                    &specifier,
+                )
+            } else {
+                deno_core::ModuleSource::new_with_redirect(
+                    module_type,
+                    ModuleSourceCode::String(code.into()),
+                    &specifier,
+                    &found,
+                )
+            })
         }
         .boxed_local()
     }
 }
 
 /// Transpiles code if required
+#[instrument(level = "debug", skip_all, fields(url = %specifier))]
 pub(crate) fn transpile(
     specifier: &Url,
     code: &str,
-    jsx_import_source: &str,
+    options: &LoaderOptions,
 ) -> Result<String, anyhow::Error> {
-    let code = match Path::new(specifier.path())
-        .extension()
-        .map(|ext| ext.to_str().unwrap())
+    let jsx_import_source = &options.jsx_import_source;
+
+    let transformed;
+    let code = match options
+        .transforms
+        .iter()
+        .find(|(suffix, _)| specifier.path().ends_with(suffix.as_str()))
     {
-        Some("mdx" | "md") => {
-            let code = mdxjs::compile(
-                &code,
-                &mdxjs::Options {
-                    parse: MdxParseOptions {
-                        constructs: MdxConstructs {
-                            attention: true,
-                            block_quote: true,
-                            character_escape: true,
-                            character_reference: true,
-                            code_fenced: true,
-                            code_text: true,
-                            definition: true,
-                            frontmatter: true,
-                            gfm_autolink_literal: false,
-                            gfm_label_start_footnote: false,
-                            gfm_footnote_definition: false,
-                            gfm_strikethrough: false,
-                            gfm_table: false,
-                            gfm_task_list_item: false,
-                            hard_break_escape: true,
-                            hard_break_trailing: true,
-                            heading_atx: true,
-                            heading_setext: true,
-                            label_start_image: true,
-                            label_start_link: true,
-                            label_end: true,
-                            list_item: true,
-                            math_flow: true,
-                            math_text: true,
-                            thematic_break: true,
-                        },
-                        gfm_strikethrough_single_tilde: false,
-                        math_text_single_dollar: true,
+        Some((_, transform)) => {
+            transformed = transform(specifier, code)?;
+            transformed.as_str()
+        }
+        None => code,
+    };
+
+    let is_mdx = Path::new(specifier.path())
+        .extension()
+        .map_or(false, |ext| {
+            options
+                .mdx_extensions
+                .iter()
+                .any(|mdx_ext| ext.to_string_lossy() == mdx_ext.as_str())
+        });
+
+    let code = if is_mdx {
+        let code = mdxjs::compile(
+            &code,
+            &mdxjs::Options {
+                parse: MdxParseOptions {
+                    constructs: MdxConstructs {
+                        attention: true,
+                        block_quote: true,
+                        character_escape: true,
+                        character_reference: true,
+                        code_fenced: true,
+                        code_text: true,
+                        definition: true,
+                        frontmatter: true,
+                        gfm_autolink_literal: false,
+                        gfm_label_start_footnote: false,
+                        gfm_footnote_definition: false,
+                        gfm_strikethrough: false,
+                        gfm_table: false,
+                        gfm_task_list_item: false,
+                        hard_break_escape: true,
+                        hard_break_trailing: true,
+                        heading_atx: true,
+                        heading_setext: true,
+                        label_start_image: true,
+                        label_start_link: true,
+                        label_end: true,
+                        list_item: true,
+                        math_flow: true,
+                        math_text: true,
+                        thematic_break: true,
                     },
-                    jsx_import_source: Some(jsx_import_source.into()),
-                    ..Default::default()
+                    gfm_strikethrough_single_tilde: false,
+                    math_text_single_dollar: true,
                 },
-            )
-            .map_err(|err| anyhow!(err))?;
-            code.into()
-        }
-        _ => code.into(),
+                jsx_import_source: Some(jsx_import_source.into()),
+                ..Default::default()
+            },
+        )
+        .map_err(|err| anyhow!(err))?;
+        code.into()
+    } else {
+        code.into()
     };
 
     let media_type = if MediaType::from_specifier(specifier) == MediaType::Unknown {
@@ -193,6 +318,7 @@ pub(crate) fn transpile(
         _ => true,
     };
 
+    let mut declared = Vec::new();
     let code = if should_transpile {
         let parsed = deno_ast::parse_module(deno_ast::ParseParams {
             specifier: specifier.to_string(),
@@ -202,9 +328,16 @@ pub(crate) fn transpile(
             scope_analysis: false,
             maybe_syntax: None,
         })?;
+        declared = declared_components(parsed.module());
+        let defaults = deno_ast::EmitOptions::default();
         let transpiled = parsed.transpile(&deno_ast::EmitOptions {
             jsx_import_source: Some(jsx_import_source.into()),
-            jsx_automatic: true,
+            jsx_automatic: options.jsx_automatic,
+            jsx_factory: options.jsx_factory.clone().unwrap_or(defaults.jsx_factory),
+            jsx_fragment_factory: options
+                .jsx_fragment_factory
+                .clone()
+                .unwrap_or(defaults.jsx_fragment_factory),
             ..Default::default()
         })?;
         transpiled.text
@@ -212,9 +345,82 @@ pub(crate) fn transpile(
         code
     };
 
+    // Components are given the URL of their module, which their styles are
+    // relative to. Appended, so it runs once they are all declared
+    let runtime = format!("\"{}/jsx-runtime\"", jsx_import_source);
+    let code = if specifier.scheme() == "file" && !declared.is_empty() && code.contains(&runtime) {
+        let tag = format!(
+            ";import {{ setSource as __areumSetSource }} from {};\
+             __areumSetSource(import.meta.url, [{}]);\n",
+            runtime,
+            declared.join(", ")
+        );
+        // Source maps are read from the last line
+        match code.rfind("//# sourceMappingURL=") {
+            Some(at) => format!("{}{}{}", &code[..at], tag, &code[at..]),
+            None => code + "\n" + &tag,
+        }
+    } else {
+        code
+    };
+
+    // Set on the module's own `import.meta`, which bundles keep per module.
+    // Prepended to the first line, so line numbers stay the same
+    let code = if code.contains("import.meta.env") {
+        format!(
+            "import.meta.env = {};{}",
+            serde_json::to_string(&options.import_meta_env)?,
+            code
+        )
+    } else {
+        code
+    };
+
     Ok(code)
 }
 
+/// Names of the functions, classes and variables a module declares at its top
+/// level that could be components, being capitalized.
+fn declared_components(module: &ast::Module) -> Vec<String> {
+    let mut names = Vec::new();
+    for item in &module.body {
+        let decl = match item {
+            ast::ModuleItem::Stmt(ast::Stmt::Decl(decl)) => decl,
+            ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportDecl(export)) => &export.decl,
+            ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportDefaultDecl(export)) => {
+                if let ast::DefaultDecl::Fn(ast::FnExpr {
+                    ident: Some(ident), ..
+                }) = &export.decl
+                {
+                    names.push(ident.sym.to_string());
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        match decl {
+            // Overload signatures and ambient declarations are compiled away
+            ast::Decl::Fn(decl) if !decl.declare && decl.function.body.is_some() => {
+                names.push(decl.ident.sym.to_string())
+            }
+            ast::Decl::Class(decl) if !decl.declare => names.push(decl.ident.sym.to_string()),
+            ast::Decl::Var(decl) if !decl.declare => {
+                for declarator in &decl.decls {
+                    if let Some(ident) = declarator.name.as_ident() {
+                        names.push(ident.id.sym.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    names.retain(|name| name.starts_with(|c: char| c.is_uppercase()));
+    names.sort();
+    names.dedup();
+    names
+}
+
 fn module_type(specifier: &Url) -> ModuleType {
     let media_type = MediaType::from_specifier(specifier);
     match media_type {
@@ -222,3 +428,114 @@ fn module_type(specifier: &Url) -> ModuleType {
         _ => ModuleType::JavaScript,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> LoaderOptions {
+        LoaderOptions {
+            jsx_import_source: "/areum".into(),
+            jsx_automatic: true,
+            jsx_factory: None,
+            jsx_fragment_factory: None,
+            mdx_extensions: vec!["mdx".into()],
+            transforms: Vec::new(),
+            import_meta_env: serde_json::Map::new(),
+            max_fetches: 1,
+            lock: None,
+            npm_cache: None,
+        }
+    }
+
+    #[test]
+    fn transpiles_jsx_importing_the_runtime_from_the_import_source() {
+        let options = LoaderOptions {
+            jsx_import_source: "https://esm.sh/preact".into(),
+            ..options()
+        };
+        let runtime = r#"from "https://esm.sh/preact/jsx-runtime""#;
+
+        let url = Url::parse("file:///site/index.tsx").unwrap();
+        let code = transpile(&url, "export default () => <p>Hi</p>;", &options).unwrap();
+        assert!(code.contains(runtime), "{}", code);
+        assert!(!code.contains("/areum/jsx-runtime"), "{}", code);
+
+        let url = Url::parse("file:///site/post.mdx").unwrap();
+        let code = transpile(&url, "# Hi", &options).unwrap();
+        assert!(code.contains(runtime), "{}", code);
+    }
+
+    #[test]
+    fn transpiles_classic_jsx_with_the_factories() {
+        let url = Url::parse("file:///site/index.tsx").unwrap();
+        let source = "export default () => <><p>Hi</p></>;";
+        let options = LoaderOptions {
+            jsx_automatic: false,
+            jsx_factory: Some("h".into()),
+            jsx_fragment_factory: Some("Fragment".into()),
+            ..options()
+        };
+        let code = transpile(&url, source, &options).unwrap();
+        assert!(code.contains("h(Fragment"), "{}", code);
+        assert!(code.contains(r#"h("p""#), "{}", code);
+        assert!(!code.contains("jsx-runtime"), "{}", code);
+
+        // Pragmas switch single files to the classic runtime
+        let source = format!("/** @jsxRuntime classic */\n/** @jsx h */\n{}", source);
+        let code = transpile(&url, &source, &options()).unwrap();
+        assert!(code.contains(r#"h("p""#), "{}", code);
+        assert!(!code.contains("jsx-runtime"), "{}", code);
+    }
+
+    #[tokio::test]
+    async fn caps_concurrent_remote_fetches() {
+        use std::{
+            sync::atomic::{AtomicUsize, Ordering},
+            time::Duration,
+        };
+
+        use deno_core::futures::future::join_all;
+        use tokio::net::TcpListener;
+
+        for max_fetches in [1, 2] {
+            // Counts connections open at once, holding each for a while. The
+            // fetches themselves fail, as the server never answers with TLS.
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let open = Arc::new(AtomicUsize::new(0));
+            let most = Arc::new(AtomicUsize::new(0));
+            let server = tokio::spawn({
+                let (open, most) = (open.clone(), most.clone());
+                async move {
+                    loop {
+                        let (socket, _) = listener.accept().await.unwrap();
+                        let now = open.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        let open = open.clone();
+                        tokio::spawn(async move {
+                            tokio::time::sleep(Duration::from_millis(200)).await;
+                            // Released before closing, as the next fetch may
+                            // connect as soon as this one fails
+                            open.fetch_sub(1, Ordering::SeqCst);
+                            drop(socket);
+                        });
+                    }
+                }
+            });
+
+            let loader = Loader::new(LoaderOptions {
+                max_fetches,
+                ..options()
+            });
+            let urls: Vec<Url> = (0..4)
+                .map(|i| Url::parse(&format!("https://127.0.0.1:{}/{}.js", port, i)).unwrap())
+                .collect();
+            let loads = join_all(urls.iter().map(|url| loader.load_to_string(url))).await;
+            server.abort();
+
+            assert!(loads.iter().all(Result::is_err));
+            assert_eq!(most.load(Ordering::SeqCst), max_fetches);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use blake2::{digest::consts, Blake2b, Digest};
+use deno_core::serde_json;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Version of the lockfile format written.
+const VERSION: u32 = 1;
+
+/// Lockfile of remote modules, recording what each URL resolved to and a hash
+/// of its source, so later fetches serving something else fail.
+pub struct Lockfile {
+    path: PathBuf,
+    /// Whether fetches not matching the lockfile replace its entries instead
+    /// of failing.
+    update: bool,
+    remote: BTreeMap<String, LockEntry>,
+    /// Entries checked or added since the lockfile was read.
+    used: HashSet<String>,
+    changed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct LockEntry {
+    /// URL the module was served from, after redirects.
+    pub resolved: String,
+    /// `blake2b256-` followed by the hex BLAKE2b-256 hash of the source.
+    pub integrity: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LockFormat {
+    version: u32,
+    remote: BTreeMap<String, LockEntry>,
+}
+
+impl Lockfile {
+    /// Reads the lockfile at `path`, or starts an empty one if there is none.
+    /// With `update`, changed modules are recorded again rather than failing.
+    pub fn load(path: &Path, update: bool) -> Result<Self, anyhow::Error> {
+        let remote = match fs::read(path) {
+            Ok(lock) => {
+                let lock: LockFormat = serde_json::from_slice(&lock)
+                    .with_context(|| format!("invalid lockfile {}", path.display()))?;
+                if lock.version != VERSION {
+                    return Err(anyhow!(
+                        "unsupported version {} of lockfile {}, expected {}",
+                        lock.version,
+                        path.display(),
+                        VERSION
+                    ));
+                }
+                lock.remote
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            update,
+            remote,
+            used: HashSet::new(),
+            changed: false,
+        })
+    }
+
+    /// Checks a module fetched from `url` against the lockfile, recording it
+    /// if it is new or the lockfile is being updated.
+    pub fn check(&mut self, url: &Url, resolved: &Url, source: &[u8]) -> Result<(), anyhow::Error> {
+        let entry = LockEntry {
+            resolved: resolved.to_string(),
+            integrity: integrity(source),
+        };
+        self.used.insert(url.to_string());
+
+        match self.remote.get(url.as_str()) {
+            Some(locked) if *locked == entry => Ok(()),
+            Some(locked) if !self.update => Err(if locked.resolved != entry.resolved {
+                anyhow!(
+                    "{} resolved to {}, but {} locks it to {}",
+                    url,
+                    entry.resolved,
+                    self.path.display(),
+                    locked.resolved
+                )
+            } else {
+                anyhow!(
+                    "{} has changed since it was locked in {}: expected {}, got {}",
+                    url,
+                    self.path.display(),
+                    locked.integrity,
+                    entry.integrity
+                )
+            }),
+            _ => {
+                self.remote.insert(url.to_string(), entry);
+                self.changed = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Keeps the entry of a module used without being fetched, such as a
+    /// cached npm package, when updating.
+    pub fn keep(&mut self, url: &Url) {
+        self.used.insert(url.to_string());
+    }
+
+    /// Writes the lockfile if anything was added or updated. Updating also
+    /// drops the entries of modules no longer fetched.
+    pub fn save(&mut self) -> Result<(), anyhow::Error> {
+        if self.update {
+            let len = self.remote.len();
+            self.remote.retain(|url, _| self.used.contains(url));
+            self.changed |= self.remote.len() != len;
+        }
+        if !self.changed {
+            return Ok(());
+        }
+
+        let lock = LockFormat {
+            version: VERSION,
+            remote: self.remote.clone(),
+        };
+        let mut json = serde_json::to_string_pretty(&lock)?;
+        json.push('\n');
+        fs::write(&self.path, json)
+            .with_context(|| format!("could not write lockfile {}", self.path.display()))?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+fn integrity(source: &[u8]) -> String {
+    format!("blake2b256-{:x}", Blake2b::<consts::U32>::digest(source))
+}
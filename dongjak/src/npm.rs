@@ -0,0 +1,448 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Context};
+use deno_core::serde_json::{self, Value};
+use deno_graph::source::{ResolutionMode, ResolveError};
+use flate2::read::GzDecoder;
+use semver::{Version, VersionReq};
+use url::Url;
+
+use crate::lock::Lockfile;
+
+const REGISTRY: &str = "https://registry.npmjs.org/";
+
+/// Resolves `npm:` specifiers, such as `npm:preact@10/hooks`, to modules of
+/// packages downloaded from the npm registry into a cache on disk.
+///
+/// Only ES module packages are supported. Packages are unpacked as published,
+/// without install scripts, and bare imports inside them resolve to `npm:`
+/// specifiers of their dependencies.
+#[derive(Clone, Debug)]
+pub struct Npm {
+    /// Directory packages are unpacked into, as `<name>@<version>`.
+    cache: PathBuf,
+    client: reqwest::Client,
+}
+
+/// Parsed `npm:` specifier.
+struct PackageReq<'a> {
+    name: &'a str,
+    /// Version, range or dist tag, the latest version if unset.
+    version: Option<&'a str>,
+    /// Path inside the package, resolved through its `exports`.
+    subpath: Option<&'a str>,
+}
+
+impl Npm {
+    pub fn new(cache: &Path) -> Self {
+        Self {
+            cache: cache.to_path_buf(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// File URL of the module an `npm:` specifier points to, downloading its
+    /// package if no cached version satisfies it. Downloads are checked
+    /// against `lock`, if any, as `npm:<name>@<version>`.
+    pub async fn resolve(
+        &self,
+        specifier: &Url,
+        lock: Option<&Mutex<Lockfile>>,
+    ) -> Result<Url, anyhow::Error> {
+        let req = PackageReq::parse(specifier.path())
+            .with_context(|| format!("invalid npm specifier {}", specifier))?;
+
+        let dir = match self.cached(&req)? {
+            Some((version, dir)) => {
+                if let Some(lock) = lock {
+                    let package = Url::parse(&format!("npm:{}@{}", req.name, version))?;
+                    lock.lock().unwrap().keep(&package);
+                }
+                dir
+            }
+            None => self.download(&req, lock).await?,
+        };
+        let path =
+            entry(&dir, req.subpath).with_context(|| format!("could not resolve {}", specifier))?;
+        Url::from_file_path(&path).map_err(|_| anyhow!("invalid path {}", path.display()))
+    }
+
+    /// `npm:` specifier of a bare import, such as `preact/hooks`, made by a
+    /// module of a cached package, with the version range the package depends
+    /// on. `None` for other imports.
+    pub fn resolve_bare(&self, specifier: &str, referrer: &Url) -> Option<Url> {
+        if specifier.starts_with(['.', '/']) || Url::parse(specifier).is_ok() {
+            return None;
+        }
+        let referrer = referrer.to_file_path().ok()?;
+        let package = self.package_dir(&referrer)?;
+
+        let (name, subpath) = split_name(specifier);
+        let manifest = read_manifest(&package).ok()?;
+        let version = ["dependencies", "peerDependencies", "optionalDependencies"]
+            .iter()
+            .find_map(|key| manifest[key][name].as_str());
+
+        let mut npm = format!("npm:{}", name);
+        if let Some(version) = version {
+            npm.push('@');
+            npm.push_str(version);
+        }
+        if let Some(subpath) = subpath {
+            npm.push('/');
+            npm.push_str(subpath);
+        }
+        Url::parse(&npm).ok()
+    }
+
+    /// Directory of the cached package `path` is in.
+    fn package_dir(&self, path: &Path) -> Option<PathBuf> {
+        let relative = path.strip_prefix(&self.cache).ok()?;
+        let mut components = relative.components();
+        let first = components.next()?.as_os_str();
+        let dir = if first.to_string_lossy().starts_with('@') {
+            self.cache.join(first).join(components.next()?.as_os_str())
+        } else {
+            self.cache.join(first)
+        };
+        Some(dir)
+    }
+
+    /// Highest cached version of the package satisfying `req`, and its
+    /// directory.
+    fn cached(&self, req: &PackageReq) -> Result<Option<(Version, PathBuf)>, anyhow::Error> {
+        let (scope, name) = match req.name.split_once('/') {
+            Some((scope, name)) => (self.cache.join(scope), name),
+            None => (self.cache.clone(), req.name),
+        };
+        let Ok(entries) = fs::read_dir(&scope) else {
+            return Ok(None);
+        };
+
+        let range = match req.version {
+            Some(version) => match range(version) {
+                Some(range) => range,
+                // Dist tags move, so are always looked up
+                None => return Ok(None),
+            },
+            None => VersionReq::STAR,
+        };
+        let mut best: Option<(Version, PathBuf)> = None;
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(version) = file_name
+                .to_str()
+                .and_then(|file_name| file_name.strip_prefix(name))
+                .and_then(|version| version.strip_prefix('@'))
+                .and_then(|version| Version::parse(version).ok())
+            else {
+                continue;
+            };
+            if range.matches(&version) && best.as_ref().map_or(true, |(best, _)| version > *best) {
+                best = Some((version, entry.path()));
+            }
+        }
+        Ok(best)
+    }
+
+    /// Downloads and unpacks the version of the package matching `req`.
+    async fn download(
+        &self,
+        req: &PackageReq<'_>,
+        lock: Option<&Mutex<Lockfile>>,
+    ) -> Result<PathBuf, anyhow::Error> {
+        let registry = Url::parse(REGISTRY).unwrap();
+        let metadata: Value = self
+            .client
+            .get(registry.join(&req.name.replacen('/', "%2f", 1))?)
+            .header("accept", "application/vnd.npm.install-v1+json")
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("could not find package {} on npm", req.name))?
+            .json()
+            .await?;
+
+        let version = select_version(&metadata, req.version).ok_or_else(|| {
+            anyhow!(
+                "no version of {} matches {}",
+                req.name,
+                req.version.unwrap_or("latest")
+            )
+        })?;
+        let tarball = metadata["versions"][&version]["dist"]["tarball"]
+            .as_str()
+            .ok_or_else(|| anyhow!("{}@{} has no tarball", req.name, version))?;
+        let tarball = Url::parse(tarball)?;
+
+        tracing::info!("downloading {}@{}", req.name, version);
+        let bytes = self
+            .client
+            .get(tarball.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        if let Some(lock) = lock {
+            let package = Url::parse(&format!("npm:{}@{}", req.name, version))?;
+            lock.lock().unwrap().check(&package, &tarball, &bytes)?;
+        }
+
+        let dir = self.cache.join(format!("{}@{}", req.name, version));
+        // Unpacked next to the cache entry and moved into place, so other
+        // envs never see a partial package
+        let partial = self.cache.join(format!(
+            "{}@{}.partial-{}",
+            req.name,
+            version,
+            rand::random::<u32>()
+        ));
+        unpack(&bytes, &partial)
+            .with_context(|| format!("could not unpack {}@{}", req.name, version))?;
+        if fs::rename(&partial, &dir).is_err() {
+            // Another env got there first
+            fs::remove_dir_all(&partial)?;
+        }
+        Ok(dir)
+    }
+}
+
+impl deno_graph::source::Resolver for Npm {
+    fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &Url,
+        _mode: ResolutionMode,
+    ) -> Result<Url, ResolveError> {
+        match self.resolve_bare(specifier, referrer) {
+            Some(url) => Ok(url),
+            None => {
+                deno_graph::resolve_import(specifier, referrer).map_err(ResolveError::Specifier)
+            }
+        }
+    }
+}
+
+impl<'a> PackageReq<'a> {
+    /// Parses `name[@version][/subpath]`, where `name` may be scoped.
+    fn parse(specifier: &'a str) -> Result<Self, anyhow::Error> {
+        if specifier.is_empty() {
+            return Err(anyhow!("missing package name"));
+        }
+        let (package, subpath) = match specifier.strip_prefix('@') {
+            Some(scoped) => {
+                let end = scoped
+                    .find('/')
+                    .ok_or_else(|| anyhow!("scoped package has no name"))?;
+                match scoped[end + 1..].find('/') {
+                    Some(slash) => {
+                        let split = end + slash + 2;
+                        (&specifier[..split], Some(&specifier[split + 1..]))
+                    }
+                    None => (specifier, None),
+                }
+            }
+            None => match specifier.split_once('/') {
+                Some((package, subpath)) => (package, Some(subpath)),
+                None => (specifier, None),
+            },
+        };
+        // Version separator, after the scope's `@`
+        let (name, version) = match package[1..].find('@') {
+            Some(at) => (&package[..at + 1], Some(&package[at + 2..])),
+            None => (package, None),
+        };
+        if name.is_empty() || name.ends_with('/') {
+            return Err(anyhow!("missing package name"));
+        }
+
+        Ok(Self {
+            name,
+            version: version.filter(|version| !version.is_empty()),
+            subpath: subpath.filter(|subpath| !subpath.is_empty()),
+        })
+    }
+}
+
+/// Splits a bare specifier into its package name and subpath.
+fn split_name(specifier: &str) -> (&str, Option<&str>) {
+    let slashes = if specifier.starts_with('@') { 2 } else { 1 };
+    match specifier.match_indices('/').nth(slashes - 1) {
+        Some((split, _)) => (&specifier[..split], Some(&specifier[split + 1..])),
+        None => (specifier, None),
+    }
+}
+
+/// Version in `metadata` matching `version`, as a dist tag, an exact version
+/// or the highest version in a range, or the `latest` tag if unset.
+fn select_version(metadata: &Value, version: Option<&str>) -> Option<String> {
+    let tag = version.unwrap_or("latest");
+    if let Some(tagged) = metadata["dist-tags"][tag].as_str() {
+        return Some(tagged.into());
+    }
+
+    let range = range(tag)?;
+    metadata["versions"]
+        .as_object()?
+        .keys()
+        .filter_map(|version| Version::parse(version).ok())
+        .filter(|version| range.matches(version))
+        .max()
+        .map(|version| version.to_string())
+}
+
+/// Parses a version or range. A version matches only itself, as in npm,
+/// rather than compatible versions, as in Cargo. Ranges are the subset of
+/// npm's Cargo understands, such as `^1.2`, `~1.2.3`, `1.x` or `>=1.2, <2`.
+fn range(version: &str) -> Option<VersionReq> {
+    match Version::parse(version) {
+        Ok(version) => VersionReq::parse(&format!("={}", version)).ok(),
+        Err(_) => VersionReq::parse(version).ok(),
+    }
+}
+
+/// Unpacks a package tarball, whose files are all under a top-level
+/// directory, usually `package/`, into `dir`.
+fn unpack(tarball: &[u8], dir: &Path) -> Result<(), anyhow::Error> {
+    fs::create_dir_all(dir)?;
+    let mut archive = tar::Archive::new(GzDecoder::new(tarball));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let relative: PathBuf = entry.path()?.components().skip(1).collect();
+        // Refuses paths escaping `dir`
+        let valid = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if relative.as_os_str().is_empty() || !valid {
+            continue;
+        }
+
+        let out = dir.join(relative);
+        fs::create_dir_all(out.parent().unwrap())?;
+        entry.unpack(&out)?;
+    }
+    Ok(())
+}
+
+fn read_manifest(dir: &Path) -> Result<Value, anyhow::Error> {
+    let manifest = fs::read(dir.join("package.json"))?;
+    Ok(serde_json::from_slice(&manifest)?)
+}
+
+/// File loaded by importing `subpath` of the package in `dir`, or the
+/// package itself, following `exports` with the `import`, `module`,
+/// `browser` and `default` conditions, or else `module` and `main`.
+fn entry(dir: &Path, subpath: Option<&str>) -> Result<PathBuf, anyhow::Error> {
+    let manifest = read_manifest(dir)?;
+    let name = manifest["name"].as_str().unwrap_or("package");
+    let is_module = manifest["type"] == "module";
+    let key = match subpath {
+        Some(subpath) => format!("./{}", subpath),
+        None => ".".into(),
+    };
+
+    let (target, esm) = match &manifest["exports"] {
+        Value::Null => match subpath {
+            Some(subpath) => (Some(subpath.to_string()), is_module),
+            None => match manifest["module"].as_str() {
+                Some(module) => (Some(module.to_string()), true),
+                None => (manifest["main"].as_str().map(str::to_string), is_module),
+            },
+        },
+        exports => {
+            let exported = match exports {
+                Value::Object(map) if map.keys().any(|key| key.starts_with('.')) => {
+                    export_target(map, &key)
+                }
+                _ if key == "." => Some((exports.clone(), None)),
+                _ => None,
+            };
+            let (target, wildcard) =
+                exported.ok_or_else(|| anyhow!("{} does not export {}", name, key))?;
+            match condition(&target) {
+                Some((target, esm)) => {
+                    let target = match wildcard {
+                        Some(wildcard) => target.replace('*', &wildcard),
+                        None => target,
+                    };
+                    (Some(target), esm || is_module)
+                }
+                None => {
+                    return Err(anyhow!(
+                        "{} is CommonJS only, as it exports {} only to require, and only ES module packages are supported",
+                        name,
+                        key
+                    ))
+                }
+            }
+        }
+    };
+
+    let target = target.unwrap_or_else(|| "index.js".into());
+    let path = [
+        target.clone(),
+        format!("{}.js", target),
+        format!("{}.mjs", target),
+        format!("{}/index.js", target),
+    ]
+    .into_iter()
+    .map(|target| dir.join(target.trim_start_matches("./")))
+    .find(|path| path.is_file())
+    .ok_or_else(|| anyhow!("{} has no file {}", name, target))?;
+
+    let esm = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("mjs") => true,
+        Some("cjs") => false,
+        _ => esm,
+    };
+    if !esm {
+        return Err(anyhow!(
+            "{} is CommonJS, and only ES module packages are supported",
+            name
+        ));
+    }
+    Ok(path)
+}
+
+/// Target of `key` in an `exports` map, and what `*` matched if it was
+/// exported by a pattern such as `./*`.
+fn export_target(
+    exports: &serde_json::Map<String, Value>,
+    key: &str,
+) -> Option<(Value, Option<String>)> {
+    if let Some(target) = exports.get(key) {
+        return Some((target.clone(), None));
+    }
+    exports.iter().find_map(|(pattern, target)| {
+        let (prefix, suffix) = pattern.split_once('*')?;
+        let matched = key.strip_prefix(prefix)?.strip_suffix(suffix)?;
+        Some((target.clone(), Some(matched.to_string())))
+    })
+}
+
+/// Path a conditional export resolves to when imported, and whether it was
+/// picked for ES module imports specifically.
+fn condition(target: &Value) -> Option<(String, bool)> {
+    match target {
+        Value::String(path) => Some((path.clone(), false)),
+        Value::Array(targets) => targets.iter().find_map(condition),
+        Value::Object(conditions) => {
+            ["import", "module", "browser", "default"]
+                .iter()
+                .find_map(|name| {
+                    let (path, esm) = condition(conditions.get(*name)?)?;
+                    Some((path, esm || matches!(*name, "import" | "module")))
+                })
+        }
+        _ => None,
+    }
+}
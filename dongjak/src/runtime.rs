@@ -1,5 +1,6 @@
 use std::{
-    collections::HashMap,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
     rc::Rc,
     sync::{Arc, Mutex},
@@ -7,15 +8,33 @@ use std::{
 
 use anyhow::anyhow;
 use deno_ast::EmitOptions;
-use deno_core::{v8, Extension, JsRuntime, PollEventLoopOptions};
-use deno_graph::ModuleGraph;
+use deno_core::{serde_json, v8, Extension, JsRuntime, OpState, PollEventLoopOptions};
+use deno_graph::{
+    source::Resolver, BuildOptions, Module, ModuleEntryRef, ModuleGraph, WalkOptions,
+};
 use serde::de::DeserializeOwned;
+use tracing::instrument;
 use url::Url;
 
-use crate::loader::{transpile, Loader, LoaderOptions};
+use crate::{
+    loader::{transpile, Loader, LoaderOptions, Transform},
+    lock::Lockfile,
+};
 
 pub struct RuntimeOptions {
     pub jsx_import_source: String,
+    pub jsx_automatic: bool,
+    pub jsx_factory: Option<String>,
+    pub jsx_fragment_factory: Option<String>,
+    pub mdx_extensions: Vec<String>,
+    pub transforms: Vec<(String, Transform)>,
+    pub import_meta_env: serde_json::Map<String, serde_json::Value>,
+    /// Remote modules fetched at once.
+    pub max_fetches: usize,
+    /// Lockfile remote modules are checked against.
+    pub lock: Option<Arc<Mutex<Lockfile>>>,
+    /// Directory `npm:` packages are cached in.
+    pub npm_cache: Option<PathBuf>,
     pub extensions: Vec<Extension>,
 }
 
@@ -27,26 +46,46 @@ pub struct Runtime {
     graph: Arc<Mutex<ModuleGraph>>,
     pub graph_loader: Loader,
     pub functions: HashMap<String, Function>,
-    jsx_import_source: String,
+    loader_options: LoaderOptions,
 }
 
 impl Runtime {
     pub async fn add_root(&mut self, root: &Url) {
+        self.build_graph(vec![root.clone()]).await;
+    }
+
+    /// Adds `roots` and the modules they import to the graph, resolving bare
+    /// imports inside npm packages as the module loader does.
+    async fn build_graph(&mut self, roots: Vec<Url>) {
+        let npm = self.graph_loader.npm().cloned();
         self.graph
             .lock()
             .unwrap()
             .build(
-                vec![root.clone()],
+                roots,
                 &mut self.graph_loader,
-                Default::default(),
+                BuildOptions {
+                    resolver: npm.as_ref().map(|npm| npm as &dyn Resolver),
+                    ..Default::default()
+                },
             )
             .await;
     }
 
     pub fn new(root: &Path, options: RuntimeOptions) -> Self {
-        let loader = Loader::new(LoaderOptions {
-            jsx_import_source: options.jsx_import_source.clone(),
-        });
+        let loader_options = LoaderOptions {
+            jsx_import_source: options.jsx_import_source,
+            jsx_automatic: options.jsx_automatic,
+            jsx_factory: options.jsx_factory,
+            jsx_fragment_factory: options.jsx_fragment_factory,
+            mdx_extensions: options.mdx_extensions,
+            transforms: options.transforms,
+            import_meta_env: options.import_meta_env,
+            max_fetches: options.max_fetches,
+            lock: options.lock,
+            npm_cache: options.npm_cache,
+        };
+        let loader = Loader::new(loader_options.clone());
 
         let js_runtime = JsRuntime::new(deno_core::RuntimeOptions {
             module_loader: Some(Rc::new(loader.clone())),
@@ -62,18 +101,73 @@ impl Runtime {
             graph: Arc::new(Mutex::new(ModuleGraph::new(deno_graph::GraphKind::All))),
             graph_loader: loader,
             functions: HashMap::new(),
-            jsx_import_source: options.jsx_import_source,
+            loader_options,
         }
     }
 
+    /// Modules `root` imports, directly or not, and itself, as far as the
+    /// graph has been built.
+    pub fn dependencies(&self, root: &Url) -> HashSet<Url> {
+        self.graph
+            .lock()
+            .unwrap()
+            .walk(
+                &[root.clone()],
+                WalkOptions {
+                    check_js: true,
+                    follow_dynamic: true,
+                    follow_type_only: false,
+                },
+            )
+            .map(|(specifier, _)| specifier.clone())
+            .collect()
+    }
+
+    /// Size of the source of `root` and of each module it imports, as far as
+    /// the graph has been built.
+    pub fn source_sizes(&self, root: &Url) -> Vec<(Url, usize)> {
+        self.graph
+            .lock()
+            .unwrap()
+            .walk(
+                &[root.clone()],
+                WalkOptions {
+                    check_js: true,
+                    follow_dynamic: true,
+                    follow_type_only: false,
+                },
+            )
+            .filter_map(|(specifier, entry)| match entry {
+                ModuleEntryRef::Module(Module::Js(module)) => {
+                    Some((specifier.clone(), module.source.len()))
+                }
+                ModuleEntryRef::Module(Module::Json(module)) => {
+                    Some((specifier.clone(), module.source.len()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
     pub fn scope(&mut self) -> v8::HandleScope {
         self.js_runtime.handle_scope()
     }
 
+    /// State shared by ops, such as what extensions put in it.
+    pub fn op_state(&mut self) -> Rc<RefCell<OpState>> {
+        self.js_runtime.op_state()
+    }
+
     pub fn root(&self) -> &Path {
         &self.root
     }
 
+    /// Whether V8 was told to stop running JavaScript, such as after running
+    /// out of memory, so nothing more can be evaluated.
+    pub fn is_terminated(&mut self) -> bool {
+        self.js_runtime.v8_isolate().is_execution_terminating()
+    }
+
     pub async fn bundle(&mut self, url: &Url) -> Result<String, anyhow::Error> {
         let mut graph = self.graph.lock().unwrap().clone();
         graph.roots = vec![url.clone()];
@@ -99,7 +193,7 @@ impl Runtime {
         code: impl ToString,
         main: bool,
     ) -> Result<usize, anyhow::Error> {
-        let code = transpile(url, &code.to_string(), &self.jsx_import_source)?;
+        let code = transpile(url, &code.to_string(), &self.loader_options)?;
 
         let module = if main {
             self.js_runtime
@@ -117,19 +211,20 @@ impl Runtime {
         }
 
         self.graph_loader.inject(url.clone(), code);
-        self.graph
-            .lock()
-            .unwrap()
-            .build(
-                self.mods.iter().map(|(k, _)| k.clone()).collect(),
-                &mut self.graph_loader,
-                Default::default(),
-            )
+        self.build_graph(self.mods.iter().map(|(k, _)| k.clone()).collect())
             .await;
 
         Ok(module)
     }
 
+    /// Transpiles `code` and serves it as the module at `url` once imported,
+    /// without loading it.
+    pub fn inject(&self, url: &Url, code: &str) -> Result<(), anyhow::Error> {
+        let code = transpile(url, code, &self.loader_options)?;
+        self.graph_loader.inject(url.clone(), code);
+        Ok(())
+    }
+
     pub async fn load_from_url(&mut self, url: &Url, main: bool) -> Result<usize, anyhow::Error> {
         let module = if main {
             self.js_runtime.load_main_module(url, None).await?
@@ -142,14 +237,7 @@ impl Runtime {
             self.main_mod = Some((url.clone(), module));
         }
 
-        self.graph
-            .lock()
-            .unwrap()
-            .build(
-                self.mods.iter().map(|(k, _)| k.clone()).collect(),
-                &mut self.graph_loader,
-                Default::default(),
-            )
+        self.build_graph(self.mods.iter().map(|(k, _)| k.clone()).collect())
             .await;
 
         Ok(module)
@@ -192,6 +280,7 @@ impl Runtime {
         Ok(global)
     }
 
+    #[instrument(level = "debug", skip_all)]
     pub async fn call<T>(
         &mut self,
         func: &Function,
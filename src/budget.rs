@@ -0,0 +1,220 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::builder::format_bytes;
+
+/// File, relative to the root, the sizes of the last build are kept in, so
+/// outputs over budget show how much they grew.
+const SIZES_FILE: &str = ".areum/sizes.json";
+/// Number of modules listed for a bundle over budget.
+const TOP_MODULES: usize = 5;
+
+/// Sizes outputs of builds should stay under, such as `bundle = "150KB"`.
+/// Outputs over budget are warned about, or fail the build with
+/// `--strict-budgets`.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct BudgetsConfig {
+    /// Client bundle, written to `build.bundle_path`.
+    pub bundle: Option<String>,
+    /// HTML of each page.
+    pub page_html: Option<String>,
+    /// Global and page stylesheets together, each counted once.
+    pub total_css: Option<String>,
+}
+
+impl BudgetsConfig {
+    pub fn limits(&self) -> Result<Limits, anyhow::Error> {
+        let parse = |size: &Option<String>, name: &str| {
+            size.as_deref()
+                .map(|size| parse_size(size).with_context(|| format!("invalid budget {}", name)))
+                .transpose()
+        };
+        Ok(Limits {
+            bundle: parse(&self.bundle, "bundle")?,
+            page_html: parse(&self.page_html, "page_html")?,
+            total_css: parse(&self.total_css, "total_css")?,
+        })
+    }
+}
+
+/// Budgets in bytes.
+#[derive(Default, Clone, Copy)]
+pub struct Limits {
+    pub bundle: Option<u64>,
+    pub page_html: Option<u64>,
+    pub total_css: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.bundle.is_none() && self.page_html.is_none() && self.total_css.is_none()
+    }
+
+    /// Outputs of `sizes` over budget, compared to the `previous` build.
+    /// `modules` gives the modules of the bundle, largest first, and is only
+    /// called if the bundle is over budget.
+    pub fn check(
+        &self,
+        sizes: &Sizes,
+        previous: Option<&Sizes>,
+        modules: impl FnOnce() -> Vec<(Url, usize)>,
+    ) -> Vec<BudgetViolation> {
+        let mut violations = Vec::new();
+
+        if let Some(limit) = self.bundle.filter(|limit| sizes.bundle > *limit) {
+            violations.push(BudgetViolation {
+                budget: "bundle",
+                path: None,
+                bytes: sizes.bundle,
+                limit,
+                previous: previous.map(|previous| previous.bundle),
+                modules: modules()
+                    .into_iter()
+                    .take(TOP_MODULES)
+                    .map(|(url, bytes)| ModuleSize {
+                        url: url.to_string(),
+                        bytes: bytes as u64,
+                    })
+                    .collect(),
+            });
+        }
+
+        if let Some(limit) = self.page_html {
+            for (path, bytes) in sizes.pages.iter().filter(|(_, bytes)| **bytes > limit) {
+                violations.push(BudgetViolation {
+                    budget: "page_html",
+                    path: Some(path.clone()),
+                    bytes: *bytes,
+                    limit,
+                    previous: previous.and_then(|previous| previous.pages.get(path).copied()),
+                    modules: Vec::new(),
+                });
+            }
+        }
+
+        if let Some(limit) = self.total_css.filter(|limit| sizes.total_css > *limit) {
+            violations.push(BudgetViolation {
+                budget: "total_css",
+                path: None,
+                bytes: sizes.total_css,
+                limit,
+                previous: previous.map(|previous| previous.total_css),
+                modules: Vec::new(),
+            });
+        }
+
+        violations
+    }
+}
+
+/// Sizes of the outputs of a build, kept for the next one to compare with.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Sizes {
+    pub bundle: u64,
+    pub total_css: u64,
+    /// HTML of each page, by path in the output.
+    pub pages: BTreeMap<PathBuf, u64>,
+}
+
+impl Sizes {
+    /// Sizes of the last build of the site at `root`, if it kept them.
+    pub fn load(root: &Path) -> Option<Self> {
+        let sizes = fs::read(root.join(SIZES_FILE)).ok()?;
+        serde_json::from_slice(&sizes).ok()
+    }
+
+    pub fn save(&self, root: &Path) -> Result<(), anyhow::Error> {
+        let path = root.join(SIZES_FILE);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+/// Output of a build over its budget.
+#[derive(Serialize)]
+pub struct BudgetViolation {
+    /// `bundle`, `page_html` or `total_css`.
+    pub budget: &'static str,
+    /// Page over `page_html`, by path in the output.
+    pub path: Option<PathBuf>,
+    pub bytes: u64,
+    pub limit: u64,
+    /// Size in the previous build, if it was kept.
+    pub previous: Option<u64>,
+    /// Largest modules of a bundle over budget, by source size.
+    pub modules: Vec<ModuleSize>,
+}
+
+#[derive(Serialize)]
+pub struct ModuleSize {
+    pub url: String,
+    pub bytes: u64,
+}
+
+impl fmt::Display for BudgetViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "/{} ", path.display())?,
+            None => write!(f, "{} ", self.budget)?,
+        }
+        write!(
+            f,
+            "is {}, over the {} budget of {}",
+            format_bytes(self.bytes),
+            self.budget,
+            format_bytes(self.limit)
+        )?;
+        match self.previous {
+            Some(previous) if previous <= self.bytes => write!(
+                f,
+                " (+{} since the last build)",
+                format_bytes(self.bytes - previous)
+            )?,
+            Some(previous) => write!(
+                f,
+                " (-{} since the last build)",
+                format_bytes(previous - self.bytes)
+            )?,
+            None => {}
+        }
+        for module in &self.modules {
+            write!(f, "\n  {:>10}  {}", format_bytes(module.bytes), module.url)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a size such as `150KB`, `1.5 MB` or `512`, in bytes. Units are
+/// powers of 1024, as build reports print them.
+pub fn parse_size(size: &str) -> Result<u64, anyhow::Error> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow!("{:?} is not a size, such as \"150KB\"", size))?;
+    let scale = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" | "KIB" => 1024,
+        "MB" | "MIB" => 1024 * 1024,
+        _ => {
+            return Err(anyhow!(
+                "unknown unit {:?} in {:?}, expected B, KB or MB",
+                unit.trim(),
+                size
+            ))
+        }
+    };
+    Ok((number * scale as f64) as u64)
+}
@@ -1,81 +1,2517 @@
 use std::{
-    fs,
-    io::{self, Write},
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    io::Write,
+    mem,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
+
+use anyhow::{anyhow, Context};
+use blake2::{digest::consts, Blake2b, Digest};
+use deno_core::error::JsError;
+use flate2::{write::GzEncoder, Compression};
+use globset::GlobSet;
+use serde::Serialize;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
 use url::Url;
 
-use crate::{env::Env, src_fs::SrcFs};
+use crate::{
+    budget::{BudgetViolation, Sizes},
+    collection::Collections,
+    config::{Config, ConfigError},
+    css::{process_global_css, CssError},
+    env::{Env, Mode},
+    favicon::{self, Favicons},
+    git,
+    images::{ImageManifest, ImagePipeline},
+    links,
+    manifest::Manifest,
+    page::Page,
+    search::SearchIndex,
+    src_fs::{
+        canonicalize, decode_route, encode_route, file_path, file_url, CaseSensitivity, SrcChange,
+        SrcChangeKind, SrcFile, SrcFs, SrcFsOptions, SrcKind,
+    },
+    taxonomy::Taxonomies,
+    transform::PageTransform,
+};
+
+const STYLES_DIR: &str = "_areum";
+/// Number of pages listed in [`BuildReport::largest_pages`].
+const LARGEST_PAGES: usize = 5;
+/// Time changes are collected for before a watch rebuild, as editors often
+/// write several files at once.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(50);
 
 pub struct Builder {
     root: PathBuf,
+    config: Config,
     env: Env,
     src_fs: SrcFs,
+    taxonomies: Taxonomies,
+    collections: Collections,
+    /// Sources of the modules generated from pages the env was bootstrapped
+    /// with, by specifier.
+    data_modules: Vec<(&'static str, String)>,
+    images: ImagePipeline,
+    transforms: Vec<Box<dyn PageTransform>>,
+    on_event: Option<Box<dyn FnMut(&BuildEvent)>>,
+    options: BuilderOptions,
+    bootstrapped: bool,
+    /// Modules each rendered source imports, by its path, to find the pages a
+    /// change affects.
+    deps: BTreeMap<PathBuf, HashSet<Url>>,
+    /// Outputs of the last build pages are linked to.
+    outputs: Option<BuildOutputs>,
+}
+
+#[derive(Clone)]
+struct BuildOutputs {
+    global_hrefs: Vec<String>,
+    images: ImageManifest,
+    favicons: Favicons,
+}
+
+/// Page written by [`Builder::build_route`].
+pub struct RenderedPage {
+    pub route: String,
+    /// Output file of the page.
+    pub path: PathBuf,
+    pub html: String,
+}
+
+/// What a build would do, as reported by `areum build --dry-run`.
+#[derive(Default)]
+pub struct BuildPlan {
+    /// Outputs of pages that are missing, or whose sources, the modules they
+    /// import or the config changed since the last build. Builds render every
+    /// page, but only these would change.
+    pub pages: Vec<PathBuf>,
+    /// Generator sources, whose outputs are only known once evaluated.
+    pub generators: Vec<PathBuf>,
+    /// Outputs of assets that are missing or out of date.
+    pub assets: Vec<PathBuf>,
+    /// Existing outputs no source produces. Builds leave these in place.
+    pub stale: Vec<PathBuf>,
+}
+
+/// Summary of a finished build, printed after `areum build`.
+#[derive(Serialize, Default)]
+pub struct BuildReport {
+    /// Pages rendered from page sources.
+    pub pages: usize,
+    /// Pages rendered by generators.
+    pub generated_pages: usize,
+    /// Assets copied, leaving out up to date ones.
+    pub assets: usize,
+    /// Total size of the assets copied.
+    pub asset_bytes: u64,
+    pub bundle_bytes: u64,
+    pub bundle_gzip_bytes: u64,
+    /// Largest pages by HTML size, largest first.
+    pub largest_pages: Vec<PageSize>,
+    /// Outputs over the sizes in `[budgets]`.
+    pub over_budget: Vec<BudgetViolation>,
+    /// Pages skipped with [`BuilderOptions::continue_on_error`].
+    pub failures: Vec<PageFailure>,
+    /// Links to paths no output is written to, with
+    /// [`BuilderOptions::check_links`].
+    pub broken_links: Vec<BrokenLink>,
+}
+
+/// Link from a page to a path in the site nothing is written to.
+#[derive(Serialize)]
+pub struct BrokenLink {
+    /// Output of the page with the link.
+    pub page: PathBuf,
+    /// Path the link resolves to, without its query or fragment.
+    pub path: String,
+}
+
+/// Page left out of a build that continued on errors.
+#[derive(Serialize)]
+pub struct PageFailure {
+    /// Source of the page, if known.
+    pub file: Option<PathBuf>,
+    /// Error with its causes, including the JavaScript stack for errors
+    /// thrown while rendering.
+    pub error: String,
+}
+
+/// What a rebuild after a change wrote, printed by `areum build --watch`.
+#[derive(Default)]
+pub struct RebuildReport {
+    /// Pages rendered again.
+    pub pages: usize,
+    /// Assets copied.
+    pub assets: usize,
+    /// Whether the client bundle was written again.
+    pub bundled: bool,
+}
+
+impl From<BuildReport> for RebuildReport {
+    fn from(report: BuildReport) -> Self {
+        RebuildReport {
+            pages: report.pages + report.generated_pages,
+            assets: report.assets,
+            bundled: true,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PageSize {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// Event of a build, printed as a line of JSON by
+/// `areum build --message-format json`. `schemas/build-messages.schema.json`
+/// describes the stream.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum BuildEvent {
+    PageBuilt {
+        /// Output file of the page.
+        path: PathBuf,
+        route: String,
+        /// Time taken to render and write the page. Generated pages share
+        /// their generator's time.
+        ms: u64,
+    },
+    Error {
+        file: Option<PathBuf>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        line: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        column: Option<u32>,
+        message: String,
+        phase: BuildPhase,
+    },
+    Summary {
+        pages: usize,
+        assets: usize,
+        errors: usize,
+        total_ms: u64,
+    },
+}
+
+impl BuildEvent {
+    /// Error event of a failed build, locating it as well as the error allows.
+    pub fn error(err: &anyhow::Error) -> Self {
+        let (phase, file) = if let Some(err) = err.downcast_ref::<BuildError>() {
+            (err.phase, err.file.clone())
+        } else if let Some(err) = err.downcast_ref::<ConfigError>() {
+            (BuildPhase::Config, Some(err.path().to_path_buf()))
+        } else {
+            (BuildPhase::Other, None)
+        };
+        let (line, column) = match position(err, file.as_deref()) {
+            Some((line, column)) => (Some(line), Some(column)),
+            None => (None, None),
+        };
+
+        BuildEvent::Error {
+            file,
+            line,
+            column,
+            message: format!("{:#}", err),
+            phase,
+        }
+    }
+}
+
+/// Line and column of an error in its source, from a stylesheet's syntax
+/// error or the stack of a script's, preferring the frame in `file`.
+fn position(err: &anyhow::Error, file: Option<&Path>) -> Option<(u32, u32)> {
+    err.chain().find_map(|err| {
+        if let Some(err) = err.downcast_ref::<CssError>() {
+            return Some((err.line, err.column));
+        }
+        let err = err.downcast_ref::<JsError>()?;
+        let in_file = |name: &String| {
+            let path = Url::parse(name).ok()?.to_file_path().ok()?;
+            Some(file == Some(path.as_path()))
+        };
+        let mut frames = err
+            .frames
+            .iter()
+            .filter(|frame| frame.line_number.is_some() && frame.column_number.is_some());
+        let frame = frames
+            .clone()
+            .find(|frame| frame.file_name.as_ref().and_then(in_file) == Some(true))
+            .or_else(|| frames.next())?;
+        Some((frame.line_number? as u32, frame.column_number? as u32))
+    })
+}
+
+/// Step of a build an error happened in.
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum BuildPhase {
+    Config,
+    Css,
+    Render,
+    Write,
+    Assets,
+    Bundle,
+    Other,
+}
+
+/// Context of build errors, naming the phase and source they happened in.
+#[derive(Debug)]
+pub struct BuildError {
+    pub phase: BuildPhase,
+    pub file: Option<PathBuf>,
+}
+
+impl BuildError {
+    fn new(phase: BuildPhase, file: &Path) -> Self {
+        Self {
+            phase,
+            file: Some(file.to_path_buf()),
+        }
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.phase {
+            BuildPhase::Config => "load config",
+            BuildPhase::Css => "process stylesheet",
+            BuildPhase::Render => "render",
+            BuildPhase::Write => "write",
+            BuildPhase::Assets => "copy",
+            BuildPhase::Bundle => "bundle scripts",
+            BuildPhase::Other => "build",
+        };
+        match &self.file {
+            Some(file) => write!(f, "could not {} {}", action, file.display()),
+            None => write!(f, "could not {}", action),
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct BuilderOptions {
+    /// Renders components with invalid styles unstyled instead of failing,
+    /// overriding `css.lenient` and `css.strict` in the config.
+    pub lenient_css: bool,
+    /// Writes a search index, overriding `search.enabled` in the config.
+    pub search_index: bool,
+    /// Checks that the internal links of pages point to outputs, listing
+    /// the ones that do not in [`BuildReport::broken_links`].
+    pub check_links: bool,
+    /// Skips pages that fail to render or write, listing them in
+    /// [`BuildReport::failures`], instead of failing the build.
+    pub continue_on_error: bool,
+    /// Includes pages dated in the future, overriding `build.future` in the
+    /// config.
+    pub include_future: bool,
+    /// Seed of `randString`, overriding `build.seed` in the config.
+    pub seed: Option<u64>,
+    /// Records remote modules that changed in the lockfile instead of
+    /// failing.
+    pub update_lock: bool,
 }
 
 impl Builder {
-    pub async fn new(root: &Path) -> Result<Self, anyhow::Error> {
-        let root = fs::canonicalize(root)?;
-        let mut env = Env::new(&root)?;
-        env.bootstrap().await?;
+    pub async fn new(root: &Path, options: BuilderOptions) -> Result<Self, anyhow::Error> {
+        let root = canonicalize(root)?;
+        let mut config = Config::load(&root)?;
+        if options.lenient_css {
+            config.css.strict = Some(false);
+        }
+        config.search.enabled |= options.search_index;
+        config.build.future |= options.include_future;
+        config.imports.update_lock |= options.update_lock;
+        if let Some(seed) = options.seed {
+            config.build.seed = seed;
+        }
+        let env = Env::new(&root, &config, Mode::Build)?;
+
+        let src_fs = SrcFs::new(
+            &root,
+            SrcFsOptions {
+                drafts: config.build.drafts,
+                future: config.build.future,
+                ..config.src_fs_options()?
+            },
+        );
 
         Ok(Builder {
             env,
-            src_fs: SrcFs::new(&root),
+            src_fs,
+            taxonomies: Taxonomies::new(&root, config.taxonomies.clone()),
+            collections: Collections::new(&root, config.collections.clone()),
+            data_modules: Vec::new(),
+            images: ImagePipeline::new(&root, &config.images)?,
             root,
+            config,
+            transforms: Vec::new(),
+            on_event: None,
+            options,
+            bootstrapped: false,
+            deps: BTreeMap::new(),
+            outputs: None,
         })
     }
 
-    pub async fn build(&mut self, outdir: &Path) -> Result<(), anyhow::Error> {
-        self.src_fs.scan().await?;
+    /// Calls `f` with every event of a build as it happens.
+    pub fn with_events(mut self, f: impl FnMut(&BuildEvent) + 'static) -> Self {
+        self.on_event = Some(Box::new(f));
+        self
+    }
+
+    fn emit(&mut self, event: BuildEvent) {
+        if let Some(on_event) = &mut self.on_event {
+            on_event(&event);
+        }
+    }
+
+    /// Registers a transform run on every page, after those already registered.
+    pub fn with_transform(mut self, transform: Box<dyn PageTransform>) -> Self {
+        self.transforms.push(transform);
+        self
+    }
+
+    /// Bootstraps the env once, however many builds it is used for. Sources
+    /// must be scanned first, as generated modules list their pages.
+    async fn bootstrap(&mut self) -> Result<(), anyhow::Error> {
+        if !self.bootstrapped {
+            self.data_modules = self.collections.modules(&self.src_fs).await?;
+            for (specifier, code) in &self.data_modules {
+                self.env.set_module(specifier, code.clone());
+            }
+            self.env.set_commits(git::site_commits(&self.root));
+            self.env.bootstrap().await?;
+            self.bootstrapped = true;
+        }
+        Ok(())
+    }
+
+    /// Processes and writes the global stylesheets, returning their hrefs.
+    async fn write_global_styles(&self, outdir: &Path) -> Result<Vec<String>, anyhow::Error> {
+        let globals = self.config.css.global_styles()?;
+        let css_options = self.config.css.options()?;
+        let mut global_hrefs = Vec::new();
+        for src in self.src_fs.lock().await.iter_global_styles(&globals) {
+            let process = || {
+                let css = fs::read_to_string(&src.path)?;
+                process_global_css(&css, &src.path.to_string_lossy(), &css_options)
+            };
+            let css = process().with_context(|| BuildError::new(BuildPhase::Css, &src.path))?;
+            global_hrefs.push(write_stylesheet(&css, outdir)?);
+        }
+        Ok(global_hrefs)
+    }
+
+    /// Outputs the build writes itself, which no source may also write, with
+    /// what each is and how to avoid a collision with it.
+    fn reserved_outputs(
+        &self,
+        outdir: &Path,
+    ) -> Result<Vec<(PathBuf, &'static str)>, anyhow::Error> {
+        let mut reserved = vec![(
+            outdir.join(self.config.build.bundle_path()?),
+            "the client bundle; rename the source or move the bundle with build.bundle_path",
+        )];
+        if self.config.search.enabled {
+            reserved.push((
+                outdir.join(SearchIndex::FILE_NAME),
+                "the search index; rename the source",
+            ));
+        }
+        if self.config.favicon.icon.is_some() {
+            for file in [Favicons::ICO_FILE, Favicons::MANIFEST_FILE] {
+                reserved.push((
+                    outdir.join(file),
+                    "a generated icon file; remove the source or unset favicon.icon",
+                ));
+            }
+        }
+        Ok(reserved)
+    }
+
+    /// Where the client bundle is written, creating its directory.
+    fn bundle_out(&self, outdir: &Path) -> Result<PathBuf, anyhow::Error> {
+        let out = outdir.join(self.config.build.bundle_path()?);
+        fs::create_dir_all(out.parent().unwrap())?;
+        Ok(out)
+    }
+
+    /// Processes the images matching `[images]` globs.
+    async fn process_images(&self, outdir: &Path) -> Result<ImageManifest, anyhow::Error> {
+        let mut images = Vec::new();
+        let assets: Vec<SrcFile> = self.src_fs.lock().await.iter_assets().cloned().collect();
+        for asset in &assets {
+            if self.images.matches(asset.path.strip_prefix(&self.root)?) {
+                images.push((asset.path.clone(), self.src_fs.route(asset).await?));
+            }
+        }
+        self.images.process(images, outdir).await
+    }
+
+    /// Generates the icons configured by `[favicon]`, if any.
+    fn generate_favicons(&self, outdir: &Path) -> Result<Favicons, anyhow::Error> {
+        let serialization = self.config.html.serialization;
+        favicon::generate(&self.root, &self.config.favicon, serialization, outdir)
+    }
+
+    /// Writes the outputs every page may reference: global stylesheets,
+    /// processed images and generated icons.
+    async fn write_shared(&self, outdir: &Path) -> Result<BuildOutputs, anyhow::Error> {
+        let global_hrefs = self.write_global_styles(outdir).await?;
+        let images = self.process_images(outdir).await?;
+        let favicons = self.generate_favicons(outdir)?;
+
+        Ok(BuildOutputs {
+            global_hrefs,
+            images,
+            favicons,
+        })
+    }
+
+    /// Links a rendered page to the stylesheets, images and icons of the
+    /// build and writes it, returning its HTML.
+    fn write_page(
+        &self,
+        page: &mut Page,
+        outdir: &Path,
+        outputs: &BuildOutputs,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        page.link_global_styles(outputs.global_hrefs.to_vec());
+        page.images = outputs.images.clone();
+        page.favicons = outputs.favicons.clone();
+
+        if self.config.css.external {
+            let href = write_stylesheet(page.styles()?, outdir)?;
+            let critical = page.component_styles(&self.config.css.critical)?;
+            page.link_styles(href, Some(critical).filter(|css| !css.is_empty()));
+        }
+        if self.config.script.external && !page.script().is_empty() {
+            let src = write_script(page.script(), outdir)?;
+            page.link_script(src);
+        }
+
+        let src = file_path(&page.url)?;
+        let mut html = Vec::new();
+        page.render_with(&mut html, &self.transforms)
+            .with_context(|| BuildError::new(BuildPhase::Render, &src))?;
+
+        let out = outdir.join(&page.path).join("index.html");
+        fs::create_dir_all(out.parent().unwrap())?;
+        fs::write(&out, &html).with_context(|| BuildError::new(BuildPhase::Write, &out))?;
+        tracing::debug!(path = %out.display(), "wrote page");
+
+        for (path, contents) in &page.outputs {
+            let out = outdir.join(path);
+            fs::create_dir_all(out.parent().unwrap())?;
+            fs::write(&out, contents).with_context(|| BuildError::new(BuildPhase::Write, &out))?;
+            tracing::debug!(path = %out.display(), "wrote page output");
+        }
+        Ok(html)
+    }
+
+    pub async fn build(&mut self, outdir: &Path) -> Result<BuildReport, anyhow::Error> {
         fs::create_dir_all(outdir)?;
+        self.exclude_output(outdir).await;
+        self.src_fs.scan().await?;
+        self.src_fs.check_permalinks().await?;
+        self.bootstrap().await?;
+
+        let globals = self.config.css.global_styles()?;
+        let global_hrefs = self.write_global_styles(outdir).await?;
+        let last_manifest = Manifest::load(&self.root, outdir);
+        let mut manifest = Manifest::new(outdir);
+        let shared_sources = self.shared_sources(&globals).await;
 
+        let mut report = BuildReport::default();
         let mut pages = Vec::new();
+        // Time each page took to render, by index in `pages`
+        let mut times = Vec::new();
+        let render_error = |src: &Path| BuildError::new(BuildPhase::Render, src);
+        let continue_on_error = self.options.continue_on_error;
+        let mut failed = Vec::new();
 
-        for src in self.src_fs.lock().await.iter_pages() {
-            let url = Url::from_file_path(&src.path).unwrap();
-            let path = self.src_fs.site_path(src).await?;
-            let page = self.env.new_page(&url, &path).await?;
-            pages.push(page);
+        let srcs: Vec<SrcFile> = self.src_fs.lock().await.iter_pages().cloned().collect();
+        for src in &srcs {
+            let start = Instant::now();
+            let pages_ = self
+                .render_page(src)
+                .await
+                .with_context(|| render_error(&src.path));
+            let Some(mut pages_) = skip_failed(pages_, continue_on_error, &mut failed)? else {
+                continue;
+            };
+            times.extend(shared_times(start.elapsed(), pages_.len()));
+            pages.append(&mut pages_);
         }
 
-        for src in self.src_fs.lock().await.iter_generators() {
-            let url = Url::from_file_path(&src.path).unwrap();
-            let mut pages_ = self.env.new_pages(&url).await?;
+        let generators: Vec<SrcFile> = {
+            let guard = self.src_fs.lock().await;
+            guard.iter_generators().cloned().collect()
+        };
+        for src in &generators {
+            let start = Instant::now();
+            let url = file_url(&src.path)?;
+            let translations = self.src_fs.translations(src).await?;
+            let layouts = self.src_fs.layouts(&src.path).await?;
+            let pages_ = self
+                .env
+                .new_pages(&url, translations, &layouts)
+                .await
+                .with_context(|| render_error(&src.path));
+            let Some(mut pages_) = skip_failed(pages_, continue_on_error, &mut failed)? else {
+                continue;
+            };
+            self.deps
+                .insert(src.path.clone(), self.dependencies(&url, &layouts));
+            report.generated_pages += pages_.len();
+            times.extend(shared_times(start.elapsed(), pages_.len()));
             pages.append(&mut pages_);
         }
 
-        for mut page in pages {
+        for taxonomy in self.taxonomies.collect(&self.src_fs).await? {
+            let start = Instant::now();
+            let template = file_path(&taxonomy.template)?;
+            let layouts = self.src_fs.layouts(&template).await?;
+            let pages_ = self
+                .env
+                .new_pages_each(&taxonomy.template, &taxonomy.pages, &layouts)
+                .await
+                .with_context(|| render_error(&template));
+            let Some(mut pages_) = skip_failed(pages_, continue_on_error, &mut failed)? else {
+                continue;
+            };
+            let deps = self.dependencies(&taxonomy.template, &layouts);
+            self.deps.insert(template, deps);
+            report.generated_pages += pages_.len();
+            times.extend(shared_times(start.elapsed(), pages_.len()));
+            pages.append(&mut pages_);
+        }
+        report.pages = pages.len() - report.generated_pages;
+
+        // Every output is known before anything is written, so no source
+        // silently overwrites another
+        let mut outputs = Vec::new();
+        for page in &pages {
+            let src = file_path(page.url())?;
+            outputs.push((outdir.join(page.path()).join("index.html"), src.clone()));
+            for (path, _) in &page.outputs {
+                outputs.push((outdir.join(path), src.clone()));
+            }
+        }
+        for asset in self.copied_assets(&globals).await {
+            outputs.push((self.src_fs.out_fpath(&asset, outdir).await?, asset.path));
+        }
+        check_collisions(outputs, &self.reserved_outputs(outdir)?, &self.root)?;
+
+        // Pages reference images by their optimized variants and the
+        // generated icons, so these are written first
+        let shared = BuildOutputs {
+            global_hrefs,
+            images: self.process_images(outdir).await?,
+            favicons: self.generate_favicons(outdir)?,
+        };
+
+        let mut sizes = Vec::new();
+        // Page stylesheets are counted once however many pages share them
+        let mut stylesheets = HashSet::new();
+        let mut total_css = 0;
+        for href in &shared.global_hrefs {
+            total_css += fs::metadata(outdir.join(href.trim_start_matches('/')))?.len();
+        }
+        let mut search = self.config.search.enabled.then(SearchIndex::default);
+        // Modules of the pages written, whose components the bundle exports
+        let mut bundled_pages = Vec::new();
+        // Paths each page written links to, with `check_links`
+        let mut page_links = Vec::new();
+
+        for (mut page, time) in pages.into_iter().zip(times) {
+            let start = Instant::now();
+            let _span = tracing::debug_span!("write", page = %page.url).entered();
+            let html = self.write_page(&mut page, outdir, &shared);
+            let Some(html) = skip_failed(html, continue_on_error, &mut failed)? else {
+                continue;
+            };
             let out = outdir.join(&page.path).join("index.html");
-            fs::create_dir_all(out.parent().unwrap())?;
-            let f = fs::File::create(out)?;
+            let sources = self.page_sources(page.url(), &shared_sources);
+            manifest.insert(&self.root, &out, sources.clone());
+            for (path, _) in &page.outputs {
+                manifest.insert(&self.root, &outdir.join(path), sources.clone());
+            }
+            self.emit(BuildEvent::PageBuilt {
+                path: out,
+                route: encode_route(&page.path),
+                ms: (time + start.elapsed()).as_millis() as u64,
+            });
 
-            let mut w = io::BufWriter::new(f);
-            page.render(&mut w)?;
-            w.flush()?;
+            if let Some(search) = &mut search {
+                search.add(&page, self.config.search.max_text);
+            }
+            if self.options.check_links {
+                let out = outdir.join(&page.path).join("index.html");
+                let paths = links::internal_links(&html, &encode_route(&page.path))?;
+                page_links.push((out, paths));
+            }
 
-            self.env.bundler.push(format!(
-                r#"export {{ default as page{} }} from "{}"
-                "#,
-                page.id(),
-                page.url.to_string()
-            ));
+            sizes.push(PageSize {
+                path: page.path.clone(),
+                bytes: html.len() as u64,
+            });
+            let css = page.styles()?;
+            if stylesheets.insert(Blake2b::<consts::U16>::digest(css)) {
+                total_css += css.len() as u64;
+            }
+            bundled_pages.push(page.url);
+        }
+
+        for asset in &self.copied_assets(&globals).await {
+            let out = self.src_fs.out_fpath(asset, outdir).await?;
+            if !last_manifest.is_fresh(&self.root, &out) {
+                tracing::debug!(path = %asset.path.display(), "copying asset");
+                self.src_fs
+                    .copy(asset, outdir)
+                    .await
+                    .with_context(|| BuildError::new(BuildPhase::Assets, &asset.path))?;
+                report.assets += 1;
+                report.asset_bytes += asset.size;
+            }
+            manifest.insert(&self.root, &out, [asset.path.clone()]);
+        }
+
+        if let Some(search) = search {
+            fs::write(
+                outdir.join(SearchIndex::FILE_NAME),
+                serde_json::to_string(&search)?,
+            )?;
+        }
+
+        let bundled = self
+            .env
+            .bundle_pages(&bundled_pages)
+            .await
+            .context(BuildError {
+                phase: BuildPhase::Bundle,
+                file: None,
+            })?;
+        fs::write(self.bundle_out(outdir)?, &bundled)?;
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(bundled.as_bytes())?;
+        report.bundle_bytes = bundled.len() as u64;
+        report.bundle_gzip_bytes = gzip.finish()?.len() as u64;
+
+        let limits = self.config.budgets.limits()?;
+        if !limits.is_empty() {
+            let sizes = Sizes {
+                bundle: report.bundle_bytes,
+                total_css,
+                pages: sizes
+                    .iter()
+                    .map(|page| (page.path.clone(), page.bytes))
+                    .collect(),
+            };
+            report.over_budget = limits.check(&sizes, Sizes::load(&self.root).as_ref(), || {
+                self.env.bundled_modules(&bundled_pages)
+            });
+            for violation in &report.over_budget {
+                tracing::warn!("{}", violation);
+            }
+            if let Err(err) = sizes.save(&self.root) {
+                tracing::warn!("could not keep the sizes of the build: {:#}", err);
+            }
+        }
+
+        sizes.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+        sizes.truncate(LARGEST_PAGES);
+        report.largest_pages = sizes;
+
+        // Checked once everything is written, as pages link to each other
+        for (page, paths) in page_links {
+            for path in paths {
+                if !links::exists(outdir, &path) {
+                    let page = page.clone();
+                    report.broken_links.push(BrokenLink { page, path });
+                }
+            }
+        }
+
+        for err in failed {
+            tracing::error!("{:?}", err);
+            self.emit(BuildEvent::error(&err));
+            report.failures.push(PageFailure {
+                file: err
+                    .downcast_ref::<BuildError>()
+                    .and_then(|err| err.file.clone()),
+                error: format!("{:#}", err),
+            });
+        }
+
+        if let Err(err) = manifest.save(&self.root) {
+            tracing::warn!("could not keep the manifest of the build: {:#}", err);
+        }
+        self.env.save_lock()?;
+        self.outputs = Some(shared);
+        Ok(report)
+    }
+
+    /// Rebuilds what each change to the sources affects, calling `on_rebuild`
+    /// with the outcome and time taken, until the sources stop being watched
+    /// or the future is dropped.
+    ///
+    /// A failed rebuild leaves the previous outputs in place, and the next
+    /// change is tried again.
+    pub async fn watch(
+        &mut self,
+        outdir: &Path,
+        mut on_rebuild: impl FnMut(Result<RebuildReport, anyhow::Error>, Duration),
+    ) -> Result<(), anyhow::Error> {
+        let mut rx_change = self.src_fs.watch().await?;
+        let config = self.root.join(Config::FILE_NAME);
+        fs::create_dir_all(outdir)?;
+        let output = canonicalize(outdir)?;
+
+        loop {
+            let mut changes = Vec::new();
+            // Missed changes could be to anything
+            let mut lagged = false;
+            match rx_change.recv().await {
+                Ok(change) => changes.push(change),
+                Err(RecvError::Lagged(_)) => lagged = true,
+                Err(RecvError::Closed) => return Ok(()),
+            }
+            tokio::time::sleep(WATCH_DEBOUNCE).await;
+            loop {
+                match rx_change.try_recv() {
+                    Ok(change) => changes.push(change),
+                    Err(TryRecvError::Lagged(_)) => lagged = true,
+                    Err(_) => break,
+                }
+            }
+            // Outputs written inside the root are not sources
+            changes.retain(|change| !change.path.starts_with(&output));
+            if changes.is_empty() && !lagged {
+                continue;
+            }
+
+            let start = Instant::now();
+            let result = if changes.iter().any(|change| change.path == config) {
+                // An invalid config keeps the last valid one
+                match self.reload().await {
+                    Ok(()) => {
+                        rx_change = self.src_fs.watch().await?;
+                        self.build(outdir).await.map(RebuildReport::from)
+                    }
+                    Err(err) => Err(err),
+                }
+            } else if lagged {
+                self.full_rebuild(outdir).await
+            } else {
+                self.rebuild(outdir, &changes).await
+            };
+            on_rebuild(result, start.elapsed());
+        }
+    }
+
+    /// Reloads the config, starting over with new sources and a new env.
+    async fn reload(&mut self) -> Result<(), anyhow::Error> {
+        let reloaded = Builder::new(&self.root, self.options).await?;
+        *self = Builder {
+            transforms: mem::take(&mut self.transforms),
+            on_event: self.on_event.take(),
+            ..reloaded
+        };
+        Ok(())
+    }
+
+    /// Replaces the env, whose modules are cached once loaded, with a new one.
+    fn reset_env(&mut self) -> Result<(), anyhow::Error> {
+        self.env = Env::new(&self.root, &self.config, Mode::Build)?;
+        self.bootstrapped = false;
+        Ok(())
+    }
+
+    async fn full_rebuild(&mut self, outdir: &Path) -> Result<RebuildReport, anyhow::Error> {
+        self.reset_env()?;
+        self.build(outdir).await.map(RebuildReport::from)
+    }
+
+    /// Writes again what `changes` affect: pages importing changed modules,
+    /// added pages, taxonomy pages if any page changed, changed assets, and
+    /// the bundle if any module changed. Changes to global styles or
+    /// processed images, which any page may reference, rebuild everything.
+    ///
+    /// Outputs of removed sources are left in place, as builds leave them.
+    pub async fn rebuild(
+        &mut self,
+        outdir: &Path,
+        changes: &[SrcChange],
+    ) -> Result<RebuildReport, anyhow::Error> {
+        let Some(outputs) = self.outputs.clone() else {
+            return self.full_rebuild(outdir).await;
+        };
+
+        let globals = self.config.css.global_styles()?;
+        let everything = changes.iter().any(|change| {
+            let relative = change.path.strip_prefix(&self.root).unwrap_or(&change.path);
+            (change.src_kind == SrcKind::Css && globals.is_match(relative))
+                || self.images.matches(relative)
+                || self.config.favicon.icon.as_deref().map(Path::new) == Some(relative)
+        });
+        if everything {
+            return self.full_rebuild(outdir).await;
+        }
+
+        let is_module = |kind| {
+            matches!(
+                kind,
+                SrcKind::Jsx | SrcKind::Mdx | SrcKind::Md | SrcKind::Js
+            )
+        };
+        // The shell wraps every page, and layouts every page below them
+        let shell = changes.iter().any(|change| {
+            change.path.file_stem().map_or(false, |stem| {
+                stem == Env::DOCUMENT_NAME || stem == Env::APP_NAME
+            })
+        });
+        let layout_dirs: Vec<&Path> = changes
+            .iter()
+            .filter(|change| {
+                matches!(change.src_kind, SrcKind::Jsx | SrcKind::Mdx)
+                    && change
+                        .path
+                        .file_stem()
+                        .map_or(false, |stem| stem == SrcFile::LAYOUT_NAME)
+            })
+            .filter_map(|change| change.path.parent())
+            .collect();
+        let mut modules: HashSet<Url> = changes
+            .iter()
+            .filter(|change| is_module(change.src_kind))
+            .filter_map(|change| Url::from_file_path(&change.path).ok())
+            .collect();
+        // Generated modules change with pages and their frontmatter, so pages
+        // importing them are rendered again if what they would see changed
+        let data_modules = self.collections.modules(&self.src_fs).await?;
+        let data_changed = data_modules != self.data_modules;
+        for module in &data_modules {
+            if !self.data_modules.contains(module) {
+                modules.insert(Url::parse(module.0).unwrap());
+            }
+        }
+
+        let mut affected: HashSet<PathBuf> = self
+            .deps
+            .iter()
+            .filter(|(src, deps)| {
+                shell
+                    || !deps.is_disjoint(&modules)
+                    || layout_dirs.iter().any(|dir| src.starts_with(dir))
+            })
+            .map(|(src, _)| src.clone())
+            .collect();
+        // Only changes to modules the env has loaded need a new one
+        if !affected.is_empty() || data_changed {
+            self.reset_env()?;
+        }
+        for change in changes {
+            if change.kind == SrcChangeKind::Removed {
+                affected.remove(&change.path);
+                self.deps.remove(&change.path);
+            } else if is_module(change.src_kind) {
+                affected.insert(change.path.clone());
+            }
+        }
+        let pages_changed = changes
+            .iter()
+            .any(|change| matches!(change.src_kind, SrcKind::Jsx | SrcKind::Mdx | SrcKind::Md));
+        self.bootstrap().await?;
+
+        // The watcher updates sources as they change, so they are copied
+        // rather than locked while pages render
+        let files: Vec<SrcFile> = self.src_fs.lock().await.iter().cloned().collect();
+        let render_error = |src: &Path| BuildError::new(BuildPhase::Render, src);
+
+        // Everything is rendered before anything is written, so a failed
+        // rebuild leaves the previous outputs in place
+        let mut pages = Vec::new();
+        for src in files.iter().filter(|src| affected.contains(&src.path)) {
+            if src.is_page() {
+                let mut pages_ = self
+                    .render_page(src)
+                    .await
+                    .with_context(|| render_error(&src.path))?;
+                pages.append(&mut pages_);
+            } else if src.generator {
+                let url = file_url(&src.path)?;
+                let layouts = self.src_fs.layouts(&src.path).await?;
+                let translations = self.src_fs.translations(src).await?;
+                let mut pages_ = self
+                    .env
+                    .new_pages(&url, translations, &layouts)
+                    .await
+                    .with_context(|| render_error(&src.path))?;
+                pages.append(&mut pages_);
+                self.deps
+                    .insert(src.path.clone(), self.dependencies(&url, &layouts));
+            }
+        }
+
+        if pages_changed {
+            for taxonomy in self.taxonomies.collect(&self.src_fs).await? {
+                let template = file_path(&taxonomy.template)?;
+                let layouts = self.src_fs.layouts(&template).await?;
+                let mut pages_ = self
+                    .env
+                    .new_pages_each(&taxonomy.template, &taxonomy.pages, &layouts)
+                    .await
+                    .with_context(|| render_error(&template))?;
+                let deps = self.dependencies(&taxonomy.template, &layouts);
+                self.deps.insert(template, deps);
+                pages.append(&mut pages_);
+            }
+        }
+
+        let mut report = RebuildReport::default();
+        for mut page in pages {
+            let start = Instant::now();
+            let _span = tracing::debug_span!("write", page = %page.url).entered();
+            self.write_page(&mut page, outdir, &outputs)?;
+            self.emit(BuildEvent::PageBuilt {
+                path: outdir.join(&page.path).join("index.html"),
+                route: encode_route(&page.path),
+                ms: start.elapsed().as_millis() as u64,
+            });
+            report.pages += 1;
+        }
+
+        let assets: Vec<SrcFile> = self.src_fs.lock().await.iter_assets().cloned().collect();
+        for change in changes {
+            if change.kind == SrcChangeKind::Removed || is_module(change.src_kind) {
+                continue;
+            }
+            let Some(asset) = assets.iter().find(|src| src.path == change.path) else {
+                continue;
+            };
+            self.src_fs
+                .copy(asset, outdir)
+                .await
+                .with_context(|| BuildError::new(BuildPhase::Assets, &asset.path))?;
+            report.assets += 1;
+        }
+
+        if !modules.is_empty() {
+            // Pages are exported by source, so removed ones drop out
+            let urls = self
+                .deps
+                .keys()
+                .map(|src| file_url(src))
+                .collect::<Result<Vec<_>, _>>()?;
+            let bundled = self.env.bundle_pages(&urls).await.context(BuildError {
+                phase: BuildPhase::Bundle,
+                file: None,
+            })?;
+            fs::write(self.bundle_out(outdir)?, &bundled)?;
+            report.bundled = true;
+        }
+        self.env.save_lock()?;
+
+        Ok(report)
+    }
+
+    /// Renders and writes the page at `route` alone, regenerating it on
+    /// demand without a full [`Builder::build`].
+    ///
+    /// Sources are rescanned first, and the env is recreated if a module it
+    /// has loaded changed. Global styles and images are written as in a
+    /// build. The bundle and search index are left as the last build wrote
+    /// them, so a route new since then has no client script until the next
+    /// full build.
+    pub async fn build_route(
+        &mut self,
+        outdir: &Path,
+        route: &str,
+    ) -> Result<RenderedPage, anyhow::Error> {
+        self.exclude_output(outdir).await;
+        let prev = self.src_fs.snapshot().await;
+        self.src_fs.scan().await?;
+        let changes = self.src_fs.changed_since(&prev).await;
+        if self.bootstrapped && changes.iter().any(|change| self.is_loaded(change)) {
+            self.reset_env()?;
+        }
+        self.bootstrap().await?;
+        fs::create_dir_all(outdir)?;
+
+        let mut page = self.render_route(route).await?;
+        let outputs = self.write_shared(outdir).await?;
+        let html = self.write_page(&mut page, outdir, &outputs)?;
+
+        Ok(RenderedPage {
+            route: encode_route(&page.path),
+            path: outdir.join(&page.path).join("index.html"),
+            html: String::from_utf8(html)?,
+        })
+    }
+
+    /// Renders the page `src`, and again at the site paths it falls back to
+    /// in locales it is not translated into, recording its dependencies.
+    async fn render_page(&mut self, src: &SrcFile) -> Result<Vec<Page>, anyhow::Error> {
+        let url = file_url(&src.path)?;
+        let layouts = self.src_fs.layouts(&src.path).await?;
+        let mut paths = vec![(
+            self.src_fs.site_path(src).await?,
+            self.src_fs.translations(src).await?,
+        )];
+        paths.extend(self.src_fs.fallbacks(src).await?);
+
+        let mut pages = Vec::new();
+        for (path, translations) in paths {
+            pages.push(
+                self.env
+                    .new_page(&url, &path, translations, &layouts)
+                    .await?,
+            );
+        }
+        self.deps
+            .insert(src.path.clone(), self.dependencies(&url, &layouts));
+        Ok(pages)
+    }
+
+    /// Modules a page rendered from `url` inside `layouts` depends on,
+    /// including the layouts and what they import.
+    fn dependencies(&self, url: &Url, layouts: &[Url]) -> HashSet<Url> {
+        let mut deps = self.env.runtime.dependencies(url);
+        for layout in layouts {
+            deps.extend(self.env.runtime.dependencies(layout));
+        }
+        deps
+    }
+
+    /// Whether a changed source may be cached by the env.
+    fn is_loaded(&self, change: &SrcChange) -> bool {
+        let shell = change.path.file_stem().map_or(false, |stem| {
+            stem == Env::DOCUMENT_NAME || stem == Env::APP_NAME
+        });
+        let loaded = Url::from_file_path(&change.path)
+            .map_or(false, |url| self.env.runtime.graph_loader.is_loaded(&url));
+        shell || loaded
+    }
+
+    /// Renders the page at `route`, resolved as the server resolves requests.
+    async fn render_route(&mut self, route: &str) -> Result<Page, anyhow::Error> {
+        let relpath = decode_route(route).ok_or_else(|| anyhow!("invalid route {}", route))?;
+        let relpath = relpath.trim_matches('/');
+        let not_found = || anyhow!("could not find page {}", route);
+
+        if let Some(src) = self.src_fs.find(route, CaseSensitivity::Strict).await {
+            let url = file_url(&src.path)?;
+            let render_error = || BuildError::new(BuildPhase::Render, &src.path);
+            let translations = self.src_fs.translations(&src).await?;
+            let layouts = self.src_fs.layouts(&src.path).await?;
+            if src.is_page() {
+                let path = self.src_fs.site_path(&src).await?;
+                return self
+                    .env
+                    .new_page(&url, &path, translations, &layouts)
+                    .await
+                    .with_context(render_error);
+            }
+            if src.generator {
+                let pages = self
+                    .env
+                    .new_pages(&url, translations, &layouts)
+                    .await
+                    .with_context(render_error)?;
+                return pages
+                    .into_iter()
+                    .find(|page| page.path == Path::new(relpath))
+                    .ok_or_else(not_found);
+            }
+            return Err(not_found());
+        }
+
+        if let Some((src, path, translations)) = self.src_fs.find_fallback(route).await {
+            let url = file_url(&src.path)?;
+            let layouts = self.src_fs.layouts(&src.path).await?;
+            return self
+                .env
+                .new_page(&url, &path, translations, &layouts)
+                .await
+                .with_context(|| BuildError::new(BuildPhase::Render, &src.path));
+        }
+
+        if self.taxonomies.contains(Path::new(relpath)) {
+            for taxonomy in self.taxonomies.collect(&self.src_fs).await? {
+                let Some(props) = taxonomy.pages.get(relpath) else {
+                    continue;
+                };
+                let pages = BTreeMap::from([(relpath.to_string(), props.clone())]);
+                let template = file_path(&taxonomy.template)?;
+                let layouts = self.src_fs.layouts(&template).await?;
+                let mut pages = self
+                    .env
+                    .new_pages_each(&taxonomy.template, &pages, &layouts)
+                    .await
+                    .with_context(|| BuildError::new(BuildPhase::Render, &template))?;
+                return pages.pop().ok_or_else(not_found);
+            }
+        }
+
+        Err(not_found())
+    }
+
+    /// Assets copied as they are, leaving out global styles.
+    ///
+    /// Like other sources iterated over while awaiting, they are copied out of
+    /// the lock, as resolving their outputs takes it again and the watcher may
+    /// be waiting to update them.
+    async fn copied_assets(&self, globals: &GlobSet) -> Vec<SrcFile> {
+        let guard = self.src_fs.lock().await;
+        guard
+            .iter_assets()
+            .filter(|asset| !guard.is_global_style(asset, globals))
+            .cloned()
+            .collect()
+    }
+
+    /// Sources the output of every page is made from: the config and the
+    /// global stylesheets it links.
+    async fn shared_sources(&self, globals: &GlobSet) -> Vec<PathBuf> {
+        let config = self.root.join(Config::FILE_NAME);
+        let mut sources: Vec<PathBuf> = config.is_file().then_some(config).into_iter().collect();
+        let guard = self.src_fs.lock().await;
+        for src in guard.iter_global_styles(globals) {
+            sources.push(src.path.clone());
+        }
+        sources
+    }
+
+    /// Sources the output of the page of the module at `url` is made from:
+    /// the module, the modules it and its layouts import, and `shared`.
+    fn page_sources(&self, url: &Url, shared: &[PathBuf]) -> Vec<PathBuf> {
+        let src = url.to_file_path().ok();
+        let deps = src.as_ref().and_then(|src| self.deps.get(src));
+        let modules = deps
+            .into_iter()
+            .flatten()
+            .filter_map(|dep| dep.to_file_path().ok())
+            .filter(|path| path.is_file());
+        src.into_iter()
+            .chain(modules)
+            .chain(shared.iter().cloned())
+            .collect()
+    }
+
+    /// Leaves the output out of scans if it is inside the root, so earlier
+    /// outputs are not taken for sources.
+    async fn exclude_output(&self, outdir: &Path) {
+        if let Ok(output) = canonicalize(outdir) {
+            if output.starts_with(&self.root) {
+                self.src_fs.exclude(&output).await;
+            }
+        }
+    }
+
+    /// Works out what [`Builder::build`] would do without writing anything or
+    /// evaluating any pages.
+    pub async fn plan(&mut self, outdir: &Path) -> Result<BuildPlan, anyhow::Error> {
+        self.exclude_output(outdir).await;
+        self.src_fs.scan().await?;
+        self.src_fs.check_permalinks().await?;
+        let (pages, generators): (Vec<SrcFile>, Vec<SrcFile>) = {
+            let guard = self.src_fs.lock().await;
+            (
+                guard.iter_pages().cloned().collect(),
+                guard.iter_generators().cloned().collect(),
+            )
+        };
+
+        let mut plan = BuildPlan::default();
+        let mut outputs: HashSet<_> = self
+            .reserved_outputs(outdir)?
+            .into_iter()
+            .map(|(out, _)| out)
+            .collect();
+        let mut generated = Vec::new();
+        let manifest = Manifest::load(&self.root, outdir);
+
+        for src in &pages {
+            let mut outs = vec![self.src_fs.out_fpath(src, outdir).await?];
+            for (path, _) in self.src_fs.fallbacks(src).await? {
+                outs.push(outdir.join(path).join("index.html"));
+            }
+            for out in outs {
+                if !manifest.is_fresh(&self.root, &out) {
+                    plan.pages.push(out.clone());
+                }
+                outputs.insert(out);
+            }
+        }
+
+        for src in &generators {
+            let site_path = self.src_fs.site_path(src).await?;
+            generated.push(outdir.join(site_path.parent().unwrap_or(&site_path)));
+            plan.generators
+                .push(src.path.strip_prefix(&self.root)?.to_path_buf());
+        }
+        for name in self.config.taxonomies.keys() {
+            generated.push(outdir.join(name));
+        }
+
+        let globals = self.config.css.global_styles()?;
+        for src in &self.copied_assets(&globals).await {
+            let out = self.src_fs.out_fpath(src, outdir).await?;
+            if !manifest.is_fresh(&self.root, &out) {
+                plan.assets.push(out.clone());
+            }
+            outputs.insert(out);
         }
 
-        for asset in self.src_fs.lock().await.iter_assets() {
-            self.src_fs.copy(asset, outdir).await?;
+        if outdir.exists() {
+            let walker = ignore::WalkBuilder::new(outdir)
+                .standard_filters(false)
+                .build();
+            for entry in walker {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type().map_or(false, |t| t.is_file())
+                    && !outputs.contains(path)
+                    && !path.starts_with(outdir.join(STYLES_DIR))
+                    && !generated.iter().any(|dir| path.starts_with(dir))
+                {
+                    plan.stale.push(path.to_path_buf());
+                }
+            }
+            plan.stale.sort();
         }
 
-        self.env.bundler.push(format!(
-            r#"export {{ runScript }} from "{}""#,
-            &Url::from_file_path(self.root.join("/areum/jsx-runtime"))
-                .unwrap()
-                .to_string()
-        ));
+        Ok(plan)
+    }
+}
 
-        let bundled = self.env.bundle().await?;
-        fs::write(outdir.join("index.js"), bundled)?;
+impl fmt::Display for BuildReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "pages            {}", self.pages)?;
+        writeln!(f, "generated pages  {}", self.generated_pages)?;
+        writeln!(
+            f,
+            "assets copied    {} ({})",
+            self.assets,
+            format_bytes(self.asset_bytes)
+        )?;
+        writeln!(
+            f,
+            "bundle           {} ({} gzipped)",
+            format_bytes(self.bundle_bytes),
+            format_bytes(self.bundle_gzip_bytes)
+        )?;
 
+        if !self.failures.is_empty() {
+            writeln!(f, "failed pages     {}", self.failures.len())?;
+            for failure in &self.failures {
+                match &failure.file {
+                    Some(file) => writeln!(f, "  {}", file.display())?,
+                    None => writeln!(f, "  (unknown source)")?,
+                }
+            }
+        }
+
+        if !self.broken_links.is_empty() {
+            writeln!(f, "broken links     {}", self.broken_links.len())?;
+            for link in &self.broken_links {
+                writeln!(f, "  {}  in {}", link.path, link.page.display())?;
+            }
+        }
+
+        if !self.over_budget.is_empty() {
+            writeln!(f, "over budget      {}", self.over_budget.len())?;
+            for violation in &self.over_budget {
+                match &violation.path {
+                    Some(path) => writeln!(f, "  {}  /{}", violation.budget, path.display())?,
+                    None => writeln!(f, "  {}", violation.budget)?,
+                }
+            }
+        }
+
+        if !self.largest_pages.is_empty() {
+            writeln!(f, "largest pages")?;
+            for page in &self.largest_pages {
+                writeln!(
+                    f,
+                    "  {:>10}  /{}",
+                    format_bytes(page.bytes),
+                    page.path.display()
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for RebuildReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rebuilt {} pages, copied {} assets",
+            self.pages, self.assets
+        )?;
+        if self.bundled {
+            write!(f, ", bundled scripts")?;
+        }
         Ok(())
     }
 }
+
+/// Passes on a page's error, or keeps it in `failed` to report once the build
+/// is done when continuing on errors.
+fn skip_failed<T>(
+    result: Result<T, anyhow::Error>,
+    continue_on_error: bool,
+    failed: &mut Vec<anyhow::Error>,
+) -> Result<Option<T>, anyhow::Error> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if continue_on_error => {
+            failed.push(err);
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1048575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+    }
+}
+
+impl fmt::Display for BuildPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for page in &self.pages {
+            writeln!(f, "render    {}", page.display())?;
+        }
+        for generator in &self.generators {
+            writeln!(f, "generate  {}", generator.display())?;
+        }
+        for asset in &self.assets {
+            writeln!(f, "copy      {}", asset.display())?;
+        }
+        for stale in &self.stale {
+            writeln!(f, "stale     {}", stale.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a generator's render time evenly between its pages.
+fn shared_times(time: Duration, pages: usize) -> Vec<Duration> {
+    vec![time / pages.max(1) as u32; pages]
+}
+
+/// Fails if two sources write the same output, naming both, or if a source
+/// writes one of the `reserved` outputs of the build.
+fn check_collisions(
+    outputs: Vec<(PathBuf, PathBuf)>,
+    reserved: &[(PathBuf, &str)],
+    root: &Path,
+) -> Result<(), anyhow::Error> {
+    let relative = |path: &Path| path.strip_prefix(root).unwrap_or(path).to_path_buf();
+    let mut sources = HashMap::new();
+    for (out, src) in outputs {
+        if let Some((_, what)) = reserved.iter().find(|(reserved, _)| *reserved == out) {
+            return Err(anyhow!(
+                "{} would write {}, which is reserved for {}",
+                relative(&src).display(),
+                out.display(),
+                what
+            )
+            .context(BuildError::new(BuildPhase::Write, &src)));
+        }
+        if let Some(other) = sources.insert(out.clone(), src.clone()) {
+            return Err(anyhow!(
+                "{} and {} both write {}",
+                relative(&other).display(),
+                relative(&src).display(),
+                out.display()
+            )
+            .context(BuildError::new(BuildPhase::Write, &src)));
+        }
+    }
+    Ok(())
+}
+
+/// Writes a page script to a file named after its hash, returning its URL.
+fn write_script(script: &str, outdir: &Path) -> Result<String, anyhow::Error> {
+    let hash = bs58::encode(Blake2b::<consts::U16>::digest(script)).into_string();
+    let name = format!("{hash}.js");
+
+    let out = outdir.join(STYLES_DIR).join(&name);
+    if !out.exists() {
+        fs::create_dir_all(out.parent().unwrap())?;
+        fs::write(out, script)?;
+    }
+
+    Ok(format!("/{STYLES_DIR}/{name}"))
+}
+
+/// Writes a stylesheet to a content-addressed file, returning its URL.
+fn write_stylesheet(css: &str, outdir: &Path) -> Result<String, anyhow::Error> {
+    let hash = bs58::encode(Blake2b::<consts::U16>::digest(css)).into_string();
+    let name = format!("{hash}.css");
+
+    let out = outdir.join(STYLES_DIR).join(&name);
+    if !out.exists() {
+        fs::create_dir_all(out.parent().unwrap())?;
+        fs::write(out, css)?;
+    }
+
+    Ok(format!("/{STYLES_DIR}/{name}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::testing::Fixture;
+
+    const LAYOUT: &str = "export default ({ children }) => <main>{children}</main>";
+    /// Page whose style fails to parse on its second line.
+    const INVALID_STYLE: &str = "const Page = () => <p>Broken</p>;\n\
+        Page.style = \"p { color: red; }\\n.card[ { color: blue; }\";\n\
+        export default Page;";
+
+    #[tokio::test]
+    async fn layout_changes_rebuild_only_pages_below() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("blog/_layout.tsx", LAYOUT)
+            .unwrap()
+            .file("blog/post.tsx", "export default () => <p>Post</p>")
+            .unwrap()
+            .file("about.tsx", "export default () => <p>About</p>")
+            .unwrap();
+        let out = fixture.out_dir();
+        let mut builder = Builder::new(fixture.root(), BuilderOptions::default())
+            .await
+            .unwrap();
+        builder.build(&out).await.unwrap();
+        let modified = |path: &str| fs::metadata(out.join(path)).unwrap().modified().unwrap();
+        let (post, about) = (
+            modified("blog/post/index.html"),
+            modified("about/index.html"),
+        );
+
+        // Modification times may only have a resolution of a second
+        std::thread::sleep(Duration::from_millis(1100));
+        let layout = "export default ({ children }) => <article>{children}</article>";
+        fixture.file("blog/_layout.tsx", layout).unwrap();
+        let change = SrcChange {
+            path: builder.root.join("blog").join("_layout.tsx"),
+            kind: SrcChangeKind::Modified,
+            src_kind: SrcKind::Jsx,
+        };
+        let report = builder.rebuild(&out, &[change]).await.unwrap();
+
+        assert_eq!(report.pages, 1);
+        assert!(modified("blog/post/index.html") > post);
+        assert_eq!(modified("about/index.html"), about);
+        assert!(fixture
+            .output("blog/post/index.html")
+            .unwrap()
+            .contains("<article><p>Post</p></article>"));
+    }
+
+    #[tokio::test]
+    async fn plans_render_pages_whose_dependencies_changed() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("blog/_layout.tsx", LAYOUT)
+            .unwrap()
+            .file("blog/post.tsx", "export default () => <p>Post</p>")
+            .unwrap()
+            .file("about.tsx", "export default () => <p>About</p>")
+            .unwrap()
+            .file("logo.svg", "<svg></svg>")
+            .unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+
+        let plan = || async {
+            let mut site = Builder::new(fixture.root(), BuilderOptions::default())
+                .await
+                .unwrap();
+            site.plan(&fixture.out_dir()).await.unwrap()
+        };
+        let unchanged = plan().await;
+        assert!(unchanged.pages.is_empty());
+        assert!(unchanged.assets.is_empty());
+
+        fixture
+            .file(
+                "blog/_layout.tsx",
+                "export default ({ children }) => <div>{children}</div>",
+            )
+            .unwrap();
+        let changed = plan().await;
+        let out = fs::canonicalize(fixture.out_dir()).unwrap();
+        let pages: Vec<_> = changed
+            .pages
+            .iter()
+            .map(|page| fs::canonicalize(page).unwrap())
+            .collect();
+        assert_eq!(pages, [out.join("blog/post/index.html")]);
+        assert!(changed.assets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn resolves_style_imports_from_component_modules() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "components/shared.css",
+                ".shared { text-transform: uppercase; }",
+            )
+            .unwrap()
+            .file(
+                "components/button.tsx",
+                "const Button = () => <button class=\"shared\">Go</button>;\n\
+                 Button.style = '@import \"./shared.css\";';\n\
+                 export default Button;",
+            )
+            .unwrap()
+            .file("card.css", ".card { text-transform: lowercase; }")
+            .unwrap()
+            .file(
+                "index.tsx",
+                "import Button from \"./components/button.tsx\";\n\
+                 const Card = () => <div class=\"card\"><Button /></div>;\n\
+                 Card.style = '@import \"./card.css\";';\n\
+                 export default () => <html><head></head><body><Card /></body></html>;",
+            )
+            .unwrap();
+
+        let html = fixture.render("/").await.unwrap();
+        assert!(html.contains("uppercase"));
+        assert!(html.contains("lowercase"));
+    }
+
+    #[tokio::test]
+    async fn reports_broken_links() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "index.tsx",
+                "export default () => <nav><a href=\"/about\">About</a>\
+                 <a href=\"/missing\">Missing</a><img src=\"logo.svg\" /></nav>",
+            )
+            .unwrap()
+            .file("about.tsx", "export default () => <a href=\"..\">Home</a>")
+            .unwrap()
+            .file("logo.svg", "<svg></svg>")
+            .unwrap();
+        let options = BuilderOptions {
+            check_links: true,
+            ..Default::default()
+        };
+
+        let report = fixture.build(options).await.unwrap();
+        let broken: Vec<_> = report
+            .broken_links
+            .iter()
+            .map(|link| link.path.as_str())
+            .collect();
+        assert_eq!(broken, ["/missing"]);
+        assert!(report.broken_links[0].page.ends_with("index.html"));
+    }
+
+    #[tokio::test]
+    async fn inlines_critical_styles_of_linked_pages() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "areum.toml",
+                "[css]\nexternal = true\ncritical = [\"Header\"]\n",
+            )
+            .unwrap()
+            .file(
+                "index.tsx",
+                "const Header = () => <header>Header</header>;\n\
+                 Header.style = \"header { text-transform: uppercase; }\";\n\
+                 const Footer = () => <footer>Footer</footer>;\n\
+                 Footer.style = \"footer { text-transform: lowercase; }\";\n\
+                 export default () => \
+                 <html><head></head><body><Header /><Footer /></body></html>;",
+            )
+            .unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+
+        let html = fixture.output("index.html").unwrap();
+        let (_, inlined) = html.split_once("<style>").unwrap();
+        let (inlined, rest) = inlined.split_once("</style>").unwrap();
+        assert!(inlined.contains("uppercase"));
+        assert!(!inlined.contains("lowercase"));
+
+        let (_, href) = rest
+            .split_once(r#"<link rel="stylesheet" href="/"#)
+            .unwrap();
+        let (href, _) = href.split_once('"').unwrap();
+        let linked = fixture.output(href).unwrap();
+        assert!(linked.contains("uppercase") && linked.contains("lowercase"));
+    }
+
+    #[tokio::test]
+    async fn inlines_critical_styles_shared_with_other_components() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "areum.toml",
+                "[css]\nexternal = true\ncritical = [\"Header\"]\n",
+            )
+            .unwrap()
+            .file(
+                "index.tsx",
+                "const style = \"p { text-transform: uppercase; }\";\n\
+                 const Banner = () => <p>Banner</p>;\n\
+                 Banner.style = style;\n\
+                 const Header = () => <p>Header</p>;\n\
+                 Header.style = style;\n\
+                 export default () => <html><head></head><body>\
+                 <Banner /><Header /><Header /></body></html>;",
+            )
+            .unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+
+        // Header's style is Banner's, which came first on the page
+        let html = fixture.output("index.html").unwrap();
+        let (_, inlined) = html.split_once("<style>").unwrap();
+        let (inlined, _) = inlined.split_once("</style>").unwrap();
+        assert_eq!(inlined.matches("uppercase").count(), 1, "{}", inlined);
+    }
+
+    #[tokio::test]
+    async fn invalid_styles_fail_pages_only_in_strict_mode() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("areum.toml", "[css]\nstrict = false\n")
+            .unwrap()
+            .file("broken.tsx", INVALID_STYLE)
+            .unwrap();
+        let report = fixture.build(BuilderOptions::default()).await.unwrap();
+        assert_eq!(report.pages, 1);
+        let html = fixture.output("broken/index.html").unwrap();
+        assert!(html.contains("<p>Broken</p>"));
+        assert!(!html.contains("color:"));
+
+        fixture
+            .file("areum.toml", "[css]\nstrict = true\n")
+            .unwrap();
+        let err = fixture
+            .build(BuilderOptions::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(err.chain().any(|err| err.is::<CssError>()));
+        // The command line still wins over the config
+        let options = BuilderOptions {
+            lenient_css: true,
+            ..Default::default()
+        };
+        fixture.build(options).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn error_messages_locate_invalid_styles() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("index.tsx", "export default () => <p>Home</p>")
+            .unwrap()
+            .file("broken.tsx", INVALID_STYLE)
+            .unwrap();
+
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let lines_ = lines.clone();
+        let options = BuilderOptions {
+            continue_on_error: true,
+            ..Default::default()
+        };
+        let mut site = Builder::new(fixture.root(), options)
+            .await
+            .unwrap()
+            .with_events(move |event| {
+                lines_
+                    .borrow_mut()
+                    .push(serde_json::to_string(event).unwrap());
+            });
+        site.build(&fixture.out_dir()).await.unwrap();
+
+        let events: Vec<serde_json::Value> = lines
+            .borrow()
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let built = events.iter().filter(|event| event["type"] == "page-built");
+        assert_eq!(built.count(), 1);
+        let errors: Vec<_> = events
+            .iter()
+            .filter(|event| event["type"] == "error")
+            .collect();
+        assert_eq!(errors.len(), 1);
+        let error = errors[0];
+        assert_eq!(error["phase"], "render");
+        assert!(error["file"].as_str().unwrap().ends_with("broken.tsx"));
+        assert_eq!(error["line"], 2);
+        assert!(error["column"].as_u64().unwrap() >= 1);
+    }
+
+    #[tokio::test]
+    async fn untranslated_pages_fall_back_to_the_default_locale() {
+        let page = |text: &str| {
+            format!(
+                "export default ({{ locale }}) => \
+                 <html><head></head><body><p>{} {{locale}}</p></body></html>",
+                text
+            )
+        };
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("areum.toml", "[i18n]\nlocales = [\"en\", \"ko\"]\n")
+            .unwrap()
+            .file("en/about.tsx", page("About"))
+            .unwrap()
+            .file("ko/about.tsx", page("소개"))
+            .unwrap()
+            .file("en/contact.tsx", page("Contact"))
+            .unwrap();
+
+        let report = fixture.build(BuilderOptions::default()).await.unwrap();
+        assert_eq!(report.pages, 4);
+
+        let contact = fixture.output("ko/contact/index.html").unwrap();
+        assert!(contact.contains(r#"<html lang="ko">"#));
+        assert!(contact.contains("<p>Contact ko</p>"));
+        for html in [contact, fixture.output("en/contact/index.html").unwrap()] {
+            assert!(html.contains(r#"hreflang="en" href="/en/contact""#));
+            assert!(html.contains(r#"hreflang="ko" href="/ko/contact""#));
+            assert!(html.contains(r#"hreflang="x-default" href="/en/contact""#));
+        }
+        assert!(fixture
+            .output("ko/about/index.html")
+            .unwrap()
+            .contains("<p>소개 ko</p>"));
+    }
+
+    #[tokio::test]
+    async fn builds_generated_pages_reproducibly() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "blog/_.tsx",
+                "const post = (key, i) => {\n\
+                 const Post = () => <html><head></head><body><p>{key}</p></body></html>;\n\
+                 Post.style = `p { order: ${i}; }`;\n\
+                 Post.script = () => console.log(\"post\");\n\
+                 return [key, Post];\n\
+                 };\n\
+                 export default Object.fromEntries(\
+                 [\"e\", \"b\", \"h\", \"a\", \"d\", \"c\", \"g\", \"f\"].map(post));",
+            )
+            .unwrap();
+
+        let mut builds = Vec::new();
+        for _ in 0..2 {
+            let _ = fs::remove_dir_all(fixture.out_dir());
+            let report = fixture.build(BuilderOptions::default()).await.unwrap();
+            assert_eq!(report.generated_pages, 8);
+            let pages: Vec<_> = ["a", "b", "c", "d", "e", "f", "g", "h"]
+                .iter()
+                .map(|key| fixture.output(format!("blog/{}/index.html", key)).unwrap())
+                .collect();
+            builds.push((fixture.output("index.js").unwrap(), pages));
+        }
+        assert_eq!(builds[0], builds[1]);
+    }
+
+    #[tokio::test]
+    async fn never_routes_imported_underscore_components() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("_Foo.tsx", "export default () => <p>Foo</p>")
+            .unwrap()
+            .file(
+                "index.tsx",
+                "import Foo from \"./_Foo.tsx\";\nexport default () => <main><Foo /></main>",
+            )
+            .unwrap();
+
+        let report = fixture.build(BuilderOptions::default()).await.unwrap();
+        assert_eq!(report.pages, 1);
+        assert!(fixture
+            .output("index.html")
+            .unwrap()
+            .contains("<main><p>Foo</p></main>"));
+        let outputs: Vec<_> = fs::read_dir(fixture.out_dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert!(
+            outputs
+                .iter()
+                .all(|name| !name.to_string_lossy().starts_with("_Foo")),
+            "{:?}",
+            outputs
+        );
+        let err = fixture.render("/_Foo").await.unwrap_err();
+        assert!(format!("{:#}", err).contains("could not find page"));
+    }
+
+    #[tokio::test]
+    async fn bundles_generators_rendering_several_pages() {
+        let fixture = Fixture::new().unwrap();
+        let script = "const Post = () => <p>Post</p>;\n\
+            Post.script = () => console.log(\"post\");\n";
+        fixture
+            .file(
+                "blog/_.tsx",
+                format!("{}export default {{ first: Post, second: Post }};", script),
+            )
+            .unwrap()
+            .file("index.tsx", format!("{}export default Post;", script))
+            .unwrap();
+
+        // Each page of the generator would otherwise export its module again
+        let report = fixture.build(BuilderOptions::default()).await.unwrap();
+        assert_eq!((report.pages, report.generated_pages), (1, 2));
+        let bundle = fixture.output("index.js").unwrap();
+        assert_eq!(bundle.matches("console.log(").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn links_global_styles_into_every_page() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("styles/global.css", "body {\n  color: red;\n}\n")
+            .unwrap()
+            .file("index.tsx", "export default () => <p>Home</p>")
+            .unwrap()
+            .file("blog/post.tsx", "export default () => <p>Post</p>")
+            .unwrap();
+        let href = || {
+            let html = fixture.output("index.html").unwrap();
+            let (_, rest) = html.split_once(r#"<link rel="stylesheet" href=""#).unwrap();
+            rest.split_once('"').unwrap().0.to_string()
+        };
+
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        let first = href();
+        assert!(first.starts_with(&format!("/{}/", STYLES_DIR)), "{}", first);
+        assert_eq!(fixture.output(&first[1..]).unwrap(), "body{color:red}");
+        assert!(fixture
+            .output("blog/post/index.html")
+            .unwrap()
+            .contains(&first));
+        // Processed instead of copied as an asset
+        assert!(fixture.output("styles/global.css").is_err());
+
+        fixture
+            .file("styles/global.css", "body { color: blue; }")
+            .unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        let second = href();
+        assert_ne!(first, second);
+        assert_eq!(fixture.output(&second[1..]).unwrap(), "body{color:#00f}");
+    }
+
+    #[tokio::test]
+    async fn exposes_the_config_as_a_module() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "areum.toml",
+                "title = \"Areum\"\n\n[extra]\nauthor = \"Kim\"\n",
+            )
+            .unwrap()
+            .file(
+                "index.tsx",
+                "import config from \"areum:config\";\n\
+                 const Page = () => <p>{config.title} by {config.extra.author}</p>;\n\
+                 Page.script = () => console.log(config.title + \"!\");\n\
+                 export default Page;",
+            )
+            .unwrap();
+
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        let html = fixture.output("index.html").unwrap();
+        assert!(html.contains("<p>Areum by Kim</p>"), "{}", html);
+        // Client scripts get the same values bundled
+        let bundle = fixture.output("index.js").unwrap();
+        assert!(bundle.contains("Areum"), "{}", bundle);
+    }
+
+    #[tokio::test]
+    async fn fails_when_files_write_the_same_page() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("about.tsx", "export default () => <p>About</p>")
+            .unwrap()
+            .file("about/index.tsx", "export default () => <p>Index</p>")
+            .unwrap();
+
+        let err = fixture
+            .build(BuilderOptions::default())
+            .await
+            .err()
+            .unwrap();
+        let message = format!("{:#}", err);
+        assert!(message.contains("about.tsx"), "{}", message);
+        assert!(message.contains(&Path::new("about").join("index.tsx").display().to_string()));
+        assert!(message.contains("both write"));
+        assert!(fixture.output("about/index.html").is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_when_generators_write_a_file_page() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("blog/post.tsx", "export default () => <p>Post</p>")
+            .unwrap()
+            .file(
+                "blog/_.tsx",
+                "export default { post: () => <p>Generated</p>, other: () => <p>Other</p> }",
+            )
+            .unwrap();
+
+        let err = fixture
+            .build(BuilderOptions::default())
+            .await
+            .err()
+            .unwrap();
+        let message = format!("{:#}", err);
+        let post = Path::new("blog").join("post.tsx").display().to_string();
+        let generator = Path::new("blog").join("_.tsx").display().to_string();
+        assert!(message.contains(&post), "{}", message);
+        assert!(message.contains(&generator));
+        assert!(message.contains("both write"));
+    }
+
+    #[tokio::test]
+    async fn watch_rebuilds_changed_pages() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("index.tsx", "export default () => <p>Before</p>")
+            .unwrap();
+        let mut site = Builder::new(fixture.root(), BuilderOptions::default())
+            .await
+            .unwrap();
+        site.build(&fixture.out_dir()).await.unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let outdir = fixture.out_dir();
+        let watch = site.watch(&outdir, |result, _| {
+            let _ = tx.send(result.map(|report| report.pages));
+        });
+        let change = async {
+            // Gives the watcher time to start
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            fixture
+                .file("index.tsx", "export default () => <p>After</p>")
+                .unwrap();
+            tokio::time::timeout(Duration::from_secs(10), rx.recv()).await
+        };
+
+        let pages = tokio::select! {
+            watched = watch => panic!("watch stopped: {:?}", watched.err()),
+            rebuilt = change => rebuilt.unwrap().unwrap().unwrap(),
+        };
+        assert_eq!(pages, 1);
+        assert!(fixture
+            .output("index.html")
+            .unwrap()
+            .contains("<p>After</p>"));
+    }
+
+    #[tokio::test]
+    async fn builds_md_pages_without_jsx() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("post.md", "# Post\n\nUses <Foo> with {x} as written.\n")
+            .unwrap();
+
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        let html = fixture.output("post/index.html").unwrap();
+        assert!(html.contains("Post</h1>"), "{}", html);
+        assert!(html.contains("Uses <Foo> with {x} as written."), "{}", html);
+    }
+
+    #[tokio::test]
+    async fn excludes_drafts_unless_configured() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("blog/post.mdx", "# Post")
+            .unwrap()
+            .file("blog/draft.mdx", "---\ndraft: true\n---\n\n# Draft")
+            .unwrap();
+
+        let report = fixture.build(BuilderOptions::default()).await.unwrap();
+        assert_eq!(report.pages, 1);
+        assert!(fixture.output("blog/post/index.html").is_ok());
+        assert!(fixture.output("blog/draft/index.html").is_err());
+
+        // The dev server scans drafts whatever the build config
+        let config = Config::load(fixture.root()).unwrap();
+        let src_fs = SrcFs::new(fixture.root(), config.src_fs_options().unwrap());
+        src_fs.scan().await.unwrap();
+        assert!(src_fs
+            .find("/blog/draft", CaseSensitivity::Strict)
+            .await
+            .is_some());
+
+        fixture
+            .file("areum.toml", "[build]\ndrafts = true\n")
+            .unwrap();
+        fs::remove_dir_all(fixture.out_dir()).unwrap();
+        let report = fixture.build(BuilderOptions::default()).await.unwrap();
+        assert_eq!(report.pages, 2);
+        assert!(fixture
+            .output("blog/draft/index.html")
+            .unwrap()
+            .contains("Draft</h1>"));
+    }
+
+    #[tokio::test]
+    async fn excludes_future_posts_unless_included() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("blog/past.mdx", "---\ndate: 2001-02-03\n---\n\n# Past")
+            .unwrap()
+            .file("blog/future.mdx", "---\ndate: 9999-01-01\n---\n\n# Future")
+            .unwrap();
+
+        let report = fixture.build(BuilderOptions::default()).await.unwrap();
+        assert_eq!(report.pages, 1);
+        assert!(fixture.output("blog/past/index.html").is_ok());
+        assert!(fixture.output("blog/future/index.html").is_err());
+
+        // The dev server scans both
+        let config = Config::load(fixture.root()).unwrap();
+        let src_fs = SrcFs::new(fixture.root(), config.src_fs_options().unwrap());
+        src_fs.scan().await.unwrap();
+        for route in ["/blog/past", "/blog/future"] {
+            assert!(src_fs.find(route, CaseSensitivity::Strict).await.is_some());
+        }
+
+        let report = fixture
+            .build(BuilderOptions {
+                include_future: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(report.pages, 2);
+        assert!(fixture
+            .output("blog/future/index.html")
+            .unwrap()
+            .contains("Future</h1>"));
+    }
+
+    #[tokio::test]
+    async fn nests_layouts_of_parent_directories() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("_layout.tsx", LAYOUT)
+            .unwrap()
+            .file(
+                "blog/_layout.tsx",
+                "export default ({ children }) => <article>{children}</article>",
+            )
+            .unwrap()
+            .file("blog/post.tsx", "export default () => <p>Post</p>")
+            .unwrap()
+            .file("docs/intro.tsx", "export default () => <p>Intro</p>")
+            .unwrap();
+
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        assert!(fixture
+            .output("blog/post/index.html")
+            .unwrap()
+            .contains("<main><article><p>Post</p></article></main>"));
+        let intro = fixture.output("docs/intro/index.html").unwrap();
+        assert!(intro.contains("<main><p>Intro</p></main>"), "{}", intro);
+        assert!(!intro.contains("<article>"));
+        // Layouts are not pages themselves
+        assert!(fixture.output("_layout/index.html").is_err());
+    }
+
+    #[tokio::test]
+    async fn passes_routes_and_urls_to_pages() {
+        let page = "({ url, route, segments, is_index }) =>\n\
+            <p>{`${url} ${route} ${segments.join(\",\")} ${is_index}`}</p>";
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("areum.toml", "[links]\nhost = \"example.com\"\n")
+            .unwrap()
+            .file("index.tsx", format!("export default {page}"))
+            .unwrap()
+            .file("blog/post.tsx", format!("export default {page}"))
+            .unwrap()
+            .file(
+                "tags/_.tsx",
+                format!("const Tag = {page};\nexport default {{ rust: Tag }}"),
+            )
+            .unwrap();
+
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        for (output, props) in [
+            ("index.html", "https://example.com/ /  true"),
+            (
+                "blog/post/index.html",
+                "https://example.com/blog/post /blog/post blog,post false",
+            ),
+            (
+                "tags/rust/index.html",
+                "https://example.com/tags/rust /tags/rust tags,rust false",
+            ),
+        ] {
+            let html = fixture.output(output).unwrap();
+            assert!(html.contains(&format!("<p>{props}</p>")), "{}", html);
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_outputs_relative_to_their_module() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "blog/index.tsx",
+                "export const outputs = {\n\
+                   \"api/posts.json\": () => [{ title: \"Post\" }],\n\
+                   \"feed.txt\": async () => \"Feed\",\n\
+                 };\n\
+                 export default () => <p>Blog</p>",
+            )
+            .unwrap();
+
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        assert_eq!(
+            fixture.output("blog/api/posts.json").unwrap(),
+            "[\n  {\n    \"title\": \"Post\"\n  }\n]"
+        );
+        assert_eq!(fixture.output("blog/feed.txt").unwrap(), "Feed");
+        assert!(fixture
+            .output("blog/index.html")
+            .unwrap()
+            .contains("<p>Blog</p>"));
+
+        fixture
+            .file(
+                "blog/index.tsx",
+                "export const outputs = { \"../escape.txt\": () => \"\" };\n\
+                 export default () => <p>Blog</p>",
+            )
+            .unwrap();
+        let err = fixture
+            .build(BuilderOptions::default())
+            .await
+            .err()
+            .unwrap();
+        let message = format!("{:#}", err);
+        assert!(message.contains("without `..`"), "{}", message);
+        assert!(fixture.output("escape.txt").is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_when_sources_write_the_bundle() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("index.tsx", "export default () => <p>Home</p>")
+            .unwrap()
+            .file("index.js", "console.log(\"mine\");")
+            .unwrap();
+
+        let err = fixture
+            .build(BuilderOptions::default())
+            .await
+            .err()
+            .unwrap();
+        let message = format!("{:#}", err);
+        assert!(message.contains("index.js"), "{}", message);
+        assert!(message.contains("the client bundle"));
+        assert!(message.contains("build.bundle_path"));
+
+        fixture
+            .file("areum.toml", "[build]\nbundle_path = \"assets/areum.js\"\n")
+            .unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        assert_eq!(
+            fixture.output("index.js").unwrap(),
+            "console.log(\"mine\");"
+        );
+        assert!(fixture.output("assets/areum.js").is_ok());
+    }
+
+    #[tokio::test]
+    async fn reports_outputs_over_budget() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "areum.toml",
+                "[budgets]\nbundle = \"1B\"\npage_html = \"1B\"\ntotal_css = \"1MB\"\n",
+            )
+            .unwrap()
+            .file("index.tsx", "export default () => <p>Home</p>")
+            .unwrap()
+            .file("about.tsx", "export default () => <p>About</p>")
+            .unwrap();
+
+        let report = fixture.build(BuilderOptions::default()).await.unwrap();
+        let budgets: Vec<_> = report.over_budget.iter().map(|v| v.budget).collect();
+        assert_eq!(budgets, ["bundle", "page_html", "page_html"]);
+        let bundle = &report.over_budget[0];
+        assert_eq!(bundle.bytes, report.bundle_bytes);
+        assert_eq!(bundle.previous, None);
+        assert!(bundle
+            .to_string()
+            .ends_with("over the bundle budget of 1 B"));
+        assert_eq!(report.over_budget[2].path, Some(PathBuf::from("about")));
+        assert!(report.to_string().contains("over budget      3"));
+
+        // Later builds compare sizes with the last one
+        fixture
+            .file("index.tsx", "export default () => <p>Home, longer</p>")
+            .unwrap();
+        let report = fixture.build(BuilderOptions::default()).await.unwrap();
+        let home = report
+            .over_budget
+            .iter()
+            .find(|v| v.path.as_deref() == Some(Path::new("")))
+            .unwrap();
+        assert!(home.previous.unwrap() < home.bytes);
+        assert!(
+            home.to_string().ends_with("since the last build)"),
+            "{}",
+            home
+        );
+    }
+
+    #[tokio::test]
+    async fn seeds_random_strings_per_page() {
+        let page = "export default () => \
+            <p>{Deno.core.ops.randString(12)} {Deno.core.ops.hashString(\"areum\")}</p>";
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("a.tsx", page)
+            .unwrap()
+            .file("b.tsx", page)
+            .unwrap();
+        let strings = || {
+            ["a", "b"].map(|path| {
+                let html = fixture.output(format!("{path}/index.html")).unwrap();
+                let (_, rest) = html.split_once("<p>").unwrap();
+                rest.split_once("</p>").unwrap().0.to_string()
+            })
+        };
+
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        let first = strings();
+        let hash = bs58::encode(Blake2b::<consts::U6>::digest("areum")).into_string();
+        assert!(first[0].ends_with(&format!(" {hash}")), "{}", first[0]);
+        assert_ne!(first[0], first[1]);
+
+        // Other pages don't change what a page draws
+        fixture.file("c.tsx", page).unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        assert_eq!(strings(), first);
+
+        fixture.file("areum.toml", "[build]\nseed = 1\n").unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        let reseeded = strings();
+        assert_ne!(reseeded[0], first[0]);
+        assert!(reseeded[0].ends_with(&format!(" {hash}")));
+    }
+
+    #[tokio::test]
+    async fn events_describe_built_pages_and_errors() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("blog/post.tsx", "export default () => <p>Post</p>")
+            .unwrap()
+            .file(
+                "broken.tsx",
+                "export default () => {\n  throw new Error(\"boom\");\n};",
+            )
+            .unwrap();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let events_ = events.clone();
+        let options = BuilderOptions {
+            continue_on_error: true,
+            ..Default::default()
+        };
+        let mut site = Builder::new(fixture.root(), options)
+            .await
+            .unwrap()
+            .with_events(move |event| {
+                events_
+                    .borrow_mut()
+                    .push(serde_json::to_value(event).unwrap());
+            });
+        site.build(&fixture.out_dir()).await.unwrap();
+
+        let events = events.borrow();
+        let built = events
+            .iter()
+            .find(|event| event["type"] == "page-built")
+            .unwrap();
+        let built = built.as_object().unwrap();
+        let keys: Vec<_> = built.keys().map(String::as_str).collect();
+        assert_eq!(keys.len(), 4);
+        assert!(["type", "path", "route", "ms"]
+            .iter()
+            .all(|key| keys.contains(key)));
+        assert_eq!(built["route"], "/blog/post");
+        let path = PathBuf::from(built["path"].as_str().unwrap());
+        assert!(path.ends_with(Path::new("blog").join("post").join("index.html")));
+
+        let error = events
+            .iter()
+            .find(|event| event["type"] == "error")
+            .unwrap();
+        assert_eq!(error["phase"], "render");
+        assert!(error["file"].as_str().unwrap().ends_with("broken.tsx"));
+        assert!(error["message"].as_str().unwrap().contains("boom"));
+        assert!(
+            error["line"].is_u64() && error["column"].is_u64(),
+            "{}",
+            error
+        );
+
+        // Builds failing as a whole are a single error, of the config here
+        fixture.file("areum.toml", "[build\n").unwrap();
+        let err = fixture
+            .build(BuilderOptions::default())
+            .await
+            .err()
+            .unwrap();
+        let error = serde_json::to_value(BuildEvent::error(&err)).unwrap();
+        assert_eq!(error["phase"], "config");
+        assert!(error["file"].as_str().unwrap().ends_with("areum.toml"));
+    }
+
+    #[tokio::test]
+    async fn places_page_scripts_as_configured() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "index.tsx",
+                "const Page = () => <html><head></head><body><p>Home</p></body></html>;\n\
+                 Page.script = () => console.log(\"home\");\n\
+                 export default Page;",
+            )
+            .unwrap()
+            .file("about.tsx", "export default () => <p>About</p>")
+            .unwrap();
+        let script = || {
+            let html = fixture.output("index.html").unwrap();
+            let start = html.find("<script").unwrap();
+            let end = start + html[start..].find("</script>").unwrap();
+            let in_head = end < html.find("</head>").unwrap();
+            (html[start..end].to_string(), in_head, html)
+        };
+
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        let (inline, in_head, html) = script();
+        assert!(
+            inline.starts_with(r#"<script type="module">"#),
+            "{}",
+            inline
+        );
+        assert!(!in_head);
+        assert!(html.find("<p>Home</p>").unwrap() < html.find(&inline).unwrap());
+        assert!(!fixture
+            .output("about/index.html")
+            .unwrap()
+            .contains("<script"));
+
+        fixture
+            .file(
+                "areum.toml",
+                "[script]\nposition = \"head\"\nasync = true\n",
+            )
+            .unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        let (tag, in_head, _) = script();
+        assert!(
+            tag.starts_with(r#"<script type="module" async>"#),
+            "{}",
+            tag
+        );
+        assert!(in_head);
+
+        fixture
+            .file("areum.toml", "[script]\nexternal = true\ndefer = true\n")
+            .unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        let (tag, in_head, _) = script();
+        assert!(!in_head);
+        let src = tag
+            .strip_prefix(&format!(
+                r#"<script type="module" defer src="/{}/"#,
+                STYLES_DIR
+            ))
+            .unwrap();
+        let name = src.strip_suffix(r#"">"#).unwrap();
+        let external = fixture.output(Path::new(STYLES_DIR).join(name)).unwrap();
+        assert_eq!(
+            external,
+            inline.strip_prefix(r#"<script type="module">"#).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn serializes_pages_as_configured() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("styles/global.css", "body { margin: 0; }")
+            .unwrap()
+            .file(
+                "index.tsx",
+                "export default () => <html><head></head><body>\
+                 <p>a<br />b</p><input disabled /></body></html>",
+            )
+            .unwrap();
+
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        let html = fixture.output("index.html").unwrap();
+        assert!(html.starts_with("<!DOCTYPE html><html"), "{}", html);
+        assert!(html.contains("<p>a<br>b</p><input disabled>"), "{}", html);
+        assert!(!html.contains("/>"));
+
+        fixture
+            .file(
+                "areum.toml",
+                "[html]\nserialization = \"xhtml\"\n\
+                 doctype = '<?xml version=\"1.0\" encoding=\"UTF-8\"?><!DOCTYPE html>'\n",
+            )
+            .unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        let html = fixture.output("index.html").unwrap();
+        assert!(
+            html.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?><!DOCTYPE html><html"#),
+            "{}",
+            html
+        );
+        assert!(html.contains(r#"<p>a<br />b</p><input disabled="disabled" />"#));
+        let (_, link) = html.split_once(r#"<link rel="stylesheet""#).unwrap();
+        assert!(link.split_once('>').unwrap().0.ends_with(" /"), "{}", link);
+
+        fixture
+            .file("areum.toml", "[html]\ndoctype = \"\"\n")
+            .unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+        assert!(fixture.output("index.html").unwrap().starts_with("<html"));
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use globset::GlobSet;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    config,
+    env::Env,
+    excerpt, markdown,
+    src_fs::{to_slash, SrcFs, SrcKind},
+};
+
+/// Pages gathered under a name with their frontmatter, for index pages to
+/// list, such as `posts` for every page under `posts/`.
+#[derive(Deserialize, Clone)]
+pub struct CollectionConfig {
+    /// Globs, relative to the root, of the pages in the collection.
+    pub pages: Vec<String>,
+    /// Frontmatter key pages are sorted by. Pages without it come last, and
+    /// ties are sorted by route.
+    pub sort: Option<String>,
+    /// Whether pages are sorted in descending order, such as newest first.
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+impl CollectionConfig {
+    pub fn globs(&self) -> Result<GlobSet, anyhow::Error> {
+        config::glob_set(&self.pages, "collection")
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Collections {
+    root: PathBuf,
+    collections: BTreeMap<String, CollectionConfig>,
+}
+
+impl Collections {
+    pub fn new(root: &Path, collections: BTreeMap<String, CollectionConfig>) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            collections,
+        }
+    }
+
+    /// Every page, sorted by route. Only MDX and Markdown pages have
+    /// frontmatter, so other pages have empty `meta`.
+    async fn pages(&self, src_fs: &SrcFs) -> Result<Vec<(PathBuf, Value)>, anyhow::Error> {
+        let mut pages = Vec::new();
+
+        // Routes take the lock again, so it is released first
+        let srcs: Vec<_> = src_fs.lock().await.iter_pages().cloned().collect();
+        for src in &srcs {
+            let (meta, excerpt) = if matches!(src.kind, SrcKind::Mdx | SrcKind::Md) {
+                let code = fs::read_to_string(&src.path)?;
+                let frontmatter = markdown::frontmatter(&code).unwrap_or("{}");
+                let meta: Value = serde_yaml::from_str(frontmatter)
+                    .with_context(|| format!("invalid frontmatter in {}", src.path.display()))?;
+                (meta, excerpt::from_source(&code))
+            } else {
+                (json!({}), None)
+            };
+
+            let relative = src.path.strip_prefix(&self.root).unwrap_or(&src.path);
+            pages.push((
+                relative.to_path_buf(),
+                json!({
+                    "route": src_fs.route(src).await?,
+                    "source": to_slash(relative),
+                    "title": meta.get("title"),
+                    "excerpt": excerpt,
+                    "meta": meta,
+                }),
+            ));
+        }
+
+        pages.sort_by(|(_, a), (_, b)| a["route"].as_str().cmp(&b["route"].as_str()));
+        Ok(pages)
+    }
+
+    /// Sources of the modules generated from the site's pages:
+    /// `areum:collections`, which exports the pages of every collection and
+    /// `getCollection` to look one up by name, and `areum:pages`, which
+    /// exports every page.
+    pub async fn modules(
+        &self,
+        src_fs: &SrcFs,
+    ) -> Result<Vec<(&'static str, String)>, anyhow::Error> {
+        let pages = self.pages(src_fs).await?;
+        let all: Vec<&Value> = pages.iter().map(|(_, page)| page).collect();
+        Ok(vec![
+            (Env::COLLECTIONS_MODULE, module(&self.collect(&pages)?)),
+            (Env::PAGES_MODULE, pages_module(&all)),
+        ])
+    }
+
+    /// Gathers the pages of every collection out of `pages`, sorted as
+    /// configured.
+    fn collect(&self, pages: &[(PathBuf, Value)]) -> Result<Map<String, Value>, anyhow::Error> {
+        let mut collected = Map::new();
+
+        for (name, config) in &self.collections {
+            let globs = config.globs()?;
+            let mut pages: Vec<Value> = pages
+                .iter()
+                .filter(|(relative, _)| globs.is_match(relative))
+                .map(|(_, page)| page.clone())
+                .collect();
+
+            if let Some(key) = &config.sort {
+                pages.sort_by(|a, b| match (a["meta"].get(key), b["meta"].get(key)) {
+                    (Some(a), Some(b)) if config.reverse => compare(a, b).reverse(),
+                    (Some(a), Some(b)) => compare(a, b),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                });
+            } else if config.reverse {
+                pages.reverse();
+            }
+
+            collected.insert(name.clone(), Value::Array(pages));
+        }
+
+        Ok(collected)
+    }
+}
+
+/// Source of the `areum:pages` module exporting `pages`.
+pub fn pages_module(pages: &[&Value]) -> String {
+    format!("export default Object.freeze({});", json!(pages))
+}
+
+/// Source of the `areum:collections` module exporting `collections`.
+pub fn module(collections: &Map<String, Value>) -> String {
+    format!(
+        r#"const collections = Object.freeze({});
+export default collections;
+export function getCollection(name, filter) {{
+  const pages = collections[name];
+  if (pages === undefined) {{
+    throw new Error(`unknown collection ${{JSON.stringify(name)}}, configure it as [collections.${{name}}] in areum.toml`);
+  }}
+  return filter ? pages.filter(filter) : [...pages];
+}}
+"#,
+        Value::Object(collections.clone())
+    )
+}
+
+/// Orders numbers by value and everything else, including dates written as
+/// `YYYY-MM-DD`, by its text.
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => text(a).cmp(&text(b)),
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
@@ -0,0 +1,595 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    path::{Component, Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use url::Url;
+
+use crate::{
+    budget::BudgetsConfig,
+    collection::CollectionConfig,
+    css::CssOptions,
+    dom::{JsonProps, Serialization},
+    excerpt::ExcerptConfig,
+    favicon::FaviconConfig,
+    src_fs::{CaseSensitivity, Locales, SrcFsOptions, SrcKinds, TrailingSlash},
+    taxonomy::TaxonomyConfig,
+};
+
+/// Site configuration, read from `areum.toml` in the site root.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub build: BuildConfig,
+    pub css: CssConfig,
+    pub script: ScriptConfig,
+    pub imports: ImportsConfig,
+    pub jsx: JsxConfig,
+    pub html: HtmlConfig,
+    pub theme: ThemeConfig,
+    pub i18n: Locales,
+    pub links: LinksConfig,
+    pub search: SearchConfig,
+    pub excerpts: ExcerptConfig,
+    pub images: ImagesConfig,
+    pub budgets: BudgetsConfig,
+    pub favicon: FaviconConfig,
+    pub taxonomies: BTreeMap<String, TaxonomyConfig>,
+    pub collections: BTreeMap<String, CollectionConfig>,
+    pub sources: SourcesConfig,
+    /// Extra extension to source kind mappings, e.g. `markdown = "mdx"`.
+    pub extensions: HashMap<String, String>,
+    /// The whole file as parsed, including tables Areum does not use itself.
+    #[serde(skip)]
+    pub values: toml::Table,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Path to an HTML file, relative to the root, used for error responses.
+    pub error_page: Option<String>,
+    /// Inline HTML used for error responses. Ignored if `error_page` is set.
+    pub error_template: Option<String>,
+    pub trailing_slash: TrailingSlash,
+    pub case_sensitivity: CaseSensitivity,
+    /// Number of envs rendering pages in parallel. Each has its own V8
+    /// isolate, so memory use grows with it.
+    pub workers: Option<usize>,
+    /// `user:password` required with HTTP basic auth for every request.
+    pub auth: Option<String>,
+    /// Token required for every request, as a bearer token or a `token`
+    /// query parameter.
+    pub auth_token: Option<String>,
+    /// Seconds the server waits for requests and envs to finish when shutting
+    /// down, before exiting with an error.
+    pub shutdown_timeout: Option<u64>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct BuildConfig {
+    /// Whether builds include pages with `draft: true` in their frontmatter,
+    /// which only the dev server renders otherwise.
+    pub drafts: bool,
+    /// Whether builds include pages with a `date` in the future, which only
+    /// the dev server renders otherwise.
+    pub future: bool,
+    /// Seed `randString` is reseeded with for every page, so builds of the
+    /// same site give the same strings.
+    pub seed: u64,
+    /// Path, relative to the output, the client bundle page scripts import is
+    /// written to. `index.js` by default.
+    pub bundle_path: Option<String>,
+    /// Directory, relative to the root, builds are written to. `dist` by
+    /// default.
+    #[serde(alias = "outDir")]
+    pub out_dir: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct CssConfig {
+    /// Whether builds write page styles to content-addressed files under
+    /// `_areum/` and link them, rather than inlining them into each page.
+    pub external: bool,
+    /// Globs, relative to the root, of stylesheets linked into every page.
+    pub global: Vec<String>,
+    /// Names of components whose styles are still inlined into pages when
+    /// `external` is set, so what renders first needs no request.
+    pub critical: Vec<String>,
+    /// Browserslist query for the browsers styles are prefixed and lowered
+    /// for. Styles are left as they are if unset.
+    pub targets: Option<String>,
+    pub minify: bool,
+    /// Whether builds render components with invalid styles unstyled, with a
+    /// warning, instead of failing. Always on for the dev server.
+    pub lenient: bool,
+    /// Whether invalid styles fail their page, in builds and the dev server
+    /// alike. Overrides `lenient` if set.
+    pub strict: Option<bool>,
+}
+
+impl Default for CssConfig {
+    fn default() -> Self {
+        Self {
+            external: false,
+            global: vec!["styles/*.css".into()],
+            critical: Vec::new(),
+            targets: None,
+            minify: true,
+            lenient: false,
+            strict: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct SourcesConfig {
+    /// Whether scans follow symlinks, such as to content directories shared
+    /// in a monorepo. Symlinks to targets outside the root are skipped.
+    pub follow_symlinks: bool,
+    /// Whether assets under a path segment starting with `_`, such as
+    /// `_assets/logo.png`, are copied to the output and served.
+    pub underscore_assets: bool,
+}
+
+impl Default for SourcesConfig {
+    fn default() -> Self {
+        Self {
+            follow_symlinks: true,
+            underscore_assets: false,
+        }
+    }
+}
+
+/// How the client script of each page is included.
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+pub struct ScriptConfig {
+    pub position: ScriptPosition,
+    /// Whether builds write page scripts to content-addressed files under
+    /// `_areum/` and load them, rather than inlining them into each page.
+    pub external: bool,
+    /// Whether external scripts are loaded with `defer`.
+    pub defer: bool,
+    /// Whether scripts are run with `async`, as soon as they have loaded.
+    #[serde(rename = "async")]
+    pub async_: bool,
+}
+
+/// Where page scripts are added.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScriptPosition {
+    /// At the end of `<head>`.
+    Head,
+    /// At the end of `<body>`.
+    #[default]
+    BodyEnd,
+}
+
+/// How remote `https:` imports are fetched.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ImportsConfig {
+    /// Remote modules each env fetches at once, so a burst of imports does not
+    /// overwhelm a CDN.
+    pub max_fetches: usize,
+    /// Whether builds record remote modules that no longer match `areum.lock`
+    /// instead of failing. Set by `areum build --update-lock`.
+    #[serde(skip)]
+    pub update_lock: bool,
+}
+
+impl Default for ImportsConfig {
+    fn default() -> Self {
+        Self {
+            max_fetches: 8,
+            update_lock: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct JsxConfig {
+    /// Module JSX is compiled to import its runtime from, as
+    /// `<import_source>/jsx-runtime`. Pages are still rendered by Areum's own
+    /// runtime, so a custom one must produce the same elements.
+    pub import_source: String,
+    pub runtime: JsxRuntime,
+    /// Function classic runtime JSX calls to create elements.
+    pub factory: Option<String>,
+    /// Fragment component of the classic runtime.
+    pub fragment_factory: Option<String>,
+}
+
+impl Default for JsxConfig {
+    fn default() -> Self {
+        Self {
+            import_source: "/areum".into(),
+            runtime: JsxRuntime::default(),
+            factory: None,
+            fragment_factory: None,
+        }
+    }
+}
+
+/// How JSX is compiled. Files can override it with a `@jsxRuntime` pragma.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JsxRuntime {
+    /// Imports `jsx` from `<import_source>/jsx-runtime`.
+    #[default]
+    Automatic,
+    /// Calls the factory in scope, as `React.createElement` by default.
+    Classic,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct HtmlConfig {
+    /// How array and object props are written as attributes.
+    pub json_props: JsonProps,
+    /// HTML shown in a `<noscript>` at the start of the body of pages with a
+    /// client script, for visitors without JavaScript.
+    pub noscript: Option<String>,
+    pub serialization: Serialization,
+    /// Doctype pages start with, `<!DOCTYPE html>` by default. Empty for none.
+    pub doctype: Option<String>,
+}
+
+impl HtmlConfig {
+    pub fn doctype(&self) -> &str {
+        self.doctype.as_deref().unwrap_or("<!DOCTYPE html>")
+    }
+}
+
+/// CSS custom properties for light and dark color schemes, injected into every
+/// page's global styles.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct ThemeConfig {
+    /// Properties set on `:root`, with or without the leading `--`.
+    pub light: BTreeMap<String, String>,
+    /// Properties overriding `light` when the user prefers a dark scheme.
+    pub dark: BTreeMap<String, String>,
+    /// Class on the root element that applies `dark` regardless of the
+    /// preferred scheme, for manual toggles.
+    pub dark_class: Option<String>,
+}
+
+/// How links leaving the site are rendered.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct LinksConfig {
+    /// Host of the site, so absolute links to it are not treated as external.
+    pub host: Option<String>,
+    /// Whether external links get `rel="noopener noreferrer"`.
+    pub noopener: bool,
+    /// Whether external links without a `target` open in a new tab.
+    pub new_tab: bool,
+}
+
+impl Default for LinksConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            noopener: true,
+            new_tab: false,
+        }
+    }
+}
+
+impl LinksConfig {
+    /// Whether `href` points to another site. Relative links and anchors are
+    /// internal.
+    pub fn is_external(&self, href: &str) -> bool {
+        let href = match href.strip_prefix("//") {
+            Some(rest) => format!("https://{}", rest),
+            None => href.to_string(),
+        };
+        Url::parse(&href).map_or(false, |url| {
+            matches!(url.scheme(), "http" | "https") && url.host_str() != self.host.as_deref()
+        })
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// Whether builds write a search index of every page.
+    pub enabled: bool,
+    /// Characters of each page's text indexed, bounding the index's size.
+    pub max_text: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_text: 10_000,
+        }
+    }
+}
+
+/// Image optimization for builds. Off unless `globs` match something.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ImagesConfig {
+    /// Globs, relative to the root, of the images processed.
+    pub globs: Vec<String>,
+    /// Widths variants are resized to. Images are never upscaled.
+    pub widths: Vec<u32>,
+    /// Whether AVIF variants are written alongside WebP ones.
+    pub avif: bool,
+    /// Encoding quality, from 1 to 100.
+    pub quality: u8,
+    /// `sizes` attribute of images given a `srcset`.
+    pub sizes: String,
+    /// Images processed at once. Defaults to the available parallelism.
+    pub parallelism: Option<usize>,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            globs: Vec::new(),
+            widths: vec![480, 960, 1600],
+            avif: false,
+            quality: 80,
+            sizes: "100vw".into(),
+            parallelism: None,
+        }
+    }
+}
+
+/// Context of errors caused by an invalid config, so they can be told apart.
+#[derive(Debug)]
+pub struct ConfigError(PathBuf);
+
+impl ConfigError {
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid config {}", self.0.display())
+    }
+}
+
+impl Config {
+    pub const FILE_NAME: &'static str = "areum.toml";
+
+    /// Loads and validates the config, failing with a [`ConfigError`].
+    pub fn load(root: &Path) -> Result<Self, anyhow::Error> {
+        let path = root.join(Self::FILE_NAME);
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let load = || {
+            let contents = fs::read_to_string(&path)?;
+            let mut config: Config = toml::from_str(&contents)?;
+            config.values = toml::from_str(&contents)?;
+
+            config.src_kinds()?;
+            config.css.global_styles()?;
+            config.images.globs()?;
+            for collection in config.collections.values() {
+                collection.globs()?;
+            }
+            config.css.options()?;
+            config.budgets.limits()?;
+            config.build.bundle_path()?;
+            config.server.error_template(root)?;
+            if let Some(locale) = &config.i18n.default_locale {
+                if !config.i18n.locales.contains(locale) {
+                    return Err(anyhow!("default locale {} is not in locales", locale));
+                }
+            }
+
+            Ok::<_, anyhow::Error>(config)
+        };
+
+        load().context(ConfigError(path.clone()))
+    }
+
+    pub fn src_kinds(&self) -> Result<SrcKinds, anyhow::Error> {
+        SrcKinds::new(&self.extensions)
+    }
+
+    /// Options scanning the site's sources with, as builds and the dev server
+    /// scan them.
+    pub fn src_fs_options(&self) -> Result<SrcFsOptions, anyhow::Error> {
+        Ok(SrcFsOptions {
+            follow_symlinks: self.sources.follow_symlinks,
+            underscore_assets: self.sources.underscore_assets,
+            kinds: self.src_kinds()?,
+            locales: self.i18n.clone(),
+            ..Default::default()
+        })
+    }
+
+    /// Source of the `areum:config` module, which exports the config's values.
+    pub fn module(&self) -> Result<String, anyhow::Error> {
+        Ok(format!(
+            "export default Object.freeze({});",
+            serde_json::to_string(&self.values)?
+        ))
+    }
+}
+
+impl BuildConfig {
+    /// Directory builds of the site at `root` are written to: `out` if given,
+    /// else the configured one, resolved against `root` unless absolute.
+    pub fn out_dir(&self, root: &Path, out: Option<&Path>) -> PathBuf {
+        let out = out.unwrap_or(Path::new(self.out_dir.as_deref().unwrap_or("dist")));
+        root.join(out)
+    }
+
+    /// Configured bundle path, checked to be a `.js` file inside the output.
+    pub fn bundle_path(&self) -> Result<&str, anyhow::Error> {
+        let path = self.bundle_path.as_deref().unwrap_or("index.js");
+        let valid = Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !valid || !path.ends_with(".js") {
+            return Err(anyhow!(
+                "bundle_path {:?} must be a relative path to a .js file, such as \"assets/areum.js\"",
+                path
+            ));
+        }
+        Ok(path)
+    }
+}
+
+impl ServerConfig {
+    /// Configured number of workers, defaulting to the available parallelism,
+    /// at most 4.
+    pub fn workers(&self) -> usize {
+        self.workers
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get().min(4)))
+            .max(1)
+    }
+
+    /// Configured shutdown timeout, 5 seconds by default.
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout.unwrap_or(5))
+    }
+
+    /// Resolves the configured error template, if any.
+    pub fn error_template(&self, root: &Path) -> Result<Option<String>, anyhow::Error> {
+        if let Some(page) = &self.error_page {
+            let path = root.join(page);
+            let template = fs::read_to_string(&path)
+                .with_context(|| format!("could not read error page {}", path.display()))?;
+            Ok(Some(template))
+        } else {
+            Ok(self.error_template.clone())
+        }
+    }
+}
+
+impl CssConfig {
+    pub fn options(&self) -> Result<CssOptions, anyhow::Error> {
+        Ok(CssOptions {
+            targets: match &self.targets {
+                Some(query) => CssOptions::targets(query)?,
+                None => Default::default(),
+            },
+            minify: self.minify,
+            dev: false,
+            lenient: self.strict.map_or(self.lenient, |strict| !strict),
+        })
+    }
+
+    pub fn global_styles(&self) -> Result<GlobSet, anyhow::Error> {
+        glob_set(&self.global, "global stylesheet")
+    }
+}
+
+impl ImagesConfig {
+    pub fn globs(&self) -> Result<GlobSet, anyhow::Error> {
+        glob_set(&self.globs, "image")
+    }
+
+    pub fn parallelism(&self) -> usize {
+        self.parallelism
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+            .max(1)
+    }
+
+    /// Settings affecting processed images, so changing them invalidates
+    /// cached variants.
+    pub fn cache_key(&self) -> String {
+        format!("{:?} {} {}", self.widths, self.avif, self.quality)
+    }
+}
+
+pub(crate) fn glob_set(patterns: &[String], what: &str) -> Result<GlobSet, anyhow::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("invalid {} glob {}", what, pattern))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+impl ThemeConfig {
+    /// Global stylesheet defining the theme, empty if no properties are set.
+    pub fn css(&self) -> String {
+        let mut css = String::new();
+
+        if !self.light.is_empty() {
+            css += &format!(":root{{{}}}", declarations(&self.light));
+        }
+
+        if !self.dark.is_empty() {
+            let dark = declarations(&self.dark);
+            css += &format!("@media (prefers-color-scheme: dark){{:root{{{}}}}}", dark);
+            if let Some(class) = &self.dark_class {
+                css += &format!(":root.{}{{{}}}", class, dark);
+            }
+        }
+
+        css
+    }
+}
+
+fn declarations(properties: &BTreeMap<String, String>) -> String {
+    properties
+        .iter()
+        .map(|(name, value)| format!("--{}:{};", name.trim_start_matches("--"), value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::{Builder, BuilderOptions},
+        testing::Fixture,
+    };
+
+    #[tokio::test]
+    async fn builds_into_the_configured_out_dir_of_the_root() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("areum.toml", "[build]\noutDir = \"public\"\n")
+            .unwrap()
+            .file("index.tsx", "export default () => <p>Home</p>")
+            .unwrap();
+        let root = fixture.root();
+        assert_ne!(std::env::current_dir().unwrap(), root);
+
+        let build = Config::load(root).unwrap().build;
+        let out = build.out_dir(root, None);
+        assert_eq!(out, root.join("public"));
+        assert_eq!(
+            build.out_dir(root, Some(Path::new("out"))),
+            root.join("out")
+        );
+        let absolute = std::env::temp_dir().join("out");
+        assert_eq!(build.out_dir(root, Some(&absolute)), absolute);
+
+        let mut site = Builder::new(root, BuilderOptions::default()).await.unwrap();
+        site.build(&out).await.unwrap();
+        assert!(root.join("public/index.html").is_file());
+        assert!(!root.join("dist").exists());
+    }
+}
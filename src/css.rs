@@ -0,0 +1,1091 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::Infallible,
+    fmt, fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context};
+use blake2::{digest::consts, Blake2b, Digest};
+use lightningcss::{
+    css_modules,
+    error::PrinterError,
+    properties::{
+        animation::AnimationName,
+        contain::{Container, ContainerNameList},
+        Property,
+    },
+    rules::{
+        container::{ContainerName, ContainerRule},
+        keyframes::{KeyframesName, KeyframesRule},
+        CssRule,
+    },
+    selector::{Component, PseudoClass, Selector},
+    stylesheet::{MinifyOptions, ParserFlags, ParserOptions, PrinterOptions, StyleSheet},
+    targets::{Browsers, Targets},
+    traits::ToCss,
+    values::{ident::CustomIdent, string::CSSString, url::Url as CssUrl},
+    visitor::{Visit, VisitTypes, Visitor},
+};
+use parcel_sourcemap::SourceMap;
+use url::Url;
+
+use crate::{images::ImageManifest, src_fs::encode_route};
+
+/// How stylesheets are printed, shared by every kind of stylesheet. Minified
+/// by default, as for builds.
+#[derive(Clone, Copy)]
+pub struct CssOptions {
+    /// Browsers to add vendor prefixes for and lower newer syntax to, such as
+    /// flattening nested rules. Used when minifying and printing.
+    pub targets: Targets,
+    pub minify: bool,
+    /// Whether component styles are printed for debugging, unminified with
+    /// source maps and under classes named after their components.
+    pub dev: bool,
+    /// Whether components with invalid styles are rendered unstyled with a
+    /// warning, rather than failing the page.
+    pub lenient: bool,
+}
+
+/// A syntax error in a component's style.
+#[derive(Debug)]
+pub struct CssError {
+    /// URL of the module defining the style.
+    pub module: String,
+    pub component: Option<String>,
+    /// Position within the style, starting at 1.
+    pub line: u32,
+    pub column: u32,
+    pub message: String,
+    snippet: String,
+}
+
+impl CssError {
+    fn new<T: fmt::Display>(err: lightningcss::error::Error<T>, style: &str, module: &Url) -> Self {
+        let (line, column) = err
+            .loc
+            .map_or((1, 1), |loc| (loc.line + 1, loc.column.max(1)));
+        let text = style.lines().nth(line as usize - 1).unwrap_or_default();
+        let snippet = format!(
+            "{:>4} | {}\n     | {}^",
+            line,
+            text,
+            " ".repeat(column as usize - 1)
+        );
+
+        CssError {
+            module: module.to_string(),
+            component: None,
+            line,
+            column,
+            message: err.kind.to_string(),
+            snippet,
+        }
+    }
+}
+
+impl fmt::Display for CssError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in style of {} ({}) at {}:{}\n{}",
+            self.message,
+            self.component.as_deref().unwrap_or("component"),
+            self.module,
+            self.line,
+            self.column,
+            self.snippet
+        )
+    }
+}
+
+impl std::error::Error for CssError {}
+
+impl Default for CssOptions {
+    fn default() -> Self {
+        Self {
+            targets: Targets::default(),
+            minify: true,
+            dev: false,
+            lenient: false,
+        }
+    }
+}
+
+impl CssOptions {
+    /// Parses a browserslist query, such as `>= 0.5%, not dead`.
+    pub fn targets(query: &str) -> Result<Targets, anyhow::Error> {
+        let browsers = Browsers::from_browserslist(query.split(',').map(str::trim))
+            .with_context(|| format!("invalid browserslist query {}", query))?;
+        Ok(browsers.map(Targets::from).unwrap_or_default())
+    }
+
+    fn print(&self, stylesheet: &mut StyleSheet) -> Result<String, anyhow::Error> {
+        stylesheet.minify(MinifyOptions {
+            targets: self.targets,
+            ..Default::default()
+        })?;
+
+        let css = stylesheet.to_css(PrinterOptions {
+            minify: self.minify,
+            targets: self.targets,
+            ..Default::default()
+        })?;
+
+        Ok(css.code)
+    }
+}
+
+/// Class styles are scoped under before their class is known.
+const SCOPE_PLACEHOLDER: &str = "__areum_scope__";
+
+/// Class name for a scoped stylesheet, derived from its contents so identical
+/// styles share a class.
+pub fn scope_class(style: &str) -> String {
+    let hash = Blake2b::<consts::U12>::digest(style);
+    format!("s{}", bs58::encode(hash).into_string())
+}
+
+/// A component's style once scoped.
+#[derive(Clone)]
+pub struct ScopedStyle {
+    /// Class the style is scoped under.
+    pub class: String,
+    pub css: String,
+    /// Rules taken out of `css` for pages to put ahead of every style.
+    pub hoisted: Hoisted,
+}
+
+/// `@import`, `@font-face` and `@keyframes` rules taken out of the top level
+/// of styles. Pages include each once at the top of their styles, as imports
+/// must come before any other rule, and font faces and keyframes are global
+/// whatever style declares them.
+#[derive(Clone, Default)]
+pub struct Hoisted {
+    pub imports: Vec<String>,
+    pub font_faces: Vec<String>,
+    /// Named after their contents by [`KeyframesVisitor`], so identical ones
+    /// are the same rule.
+    pub keyframes: Vec<String>,
+}
+
+impl Hoisted {
+    /// Adds the rules of `other` that are not already included.
+    pub fn extend(&mut self, other: Hoisted) {
+        for (rules, new) in [
+            (&mut self.imports, other.imports),
+            (&mut self.font_faces, other.font_faces),
+            (&mut self.keyframes, other.keyframes),
+        ] {
+            for rule in new {
+                if !rules.contains(&rule) {
+                    rules.push(rule);
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.imports.is_empty() && self.font_faces.is_empty() && self.keyframes.is_empty()
+    }
+
+    /// Imports followed by font faces and keyframes.
+    pub fn css(&self) -> String {
+        self.imports.concat() + &self.font_faces.concat() + &self.keyframes.concat()
+    }
+}
+
+/// Scoped styles by component scope and a hash of their inputs, shared by the
+/// pages an env renders so components recurring across pages are processed
+/// once.
+#[derive(Clone, Default)]
+pub struct CssCache(Arc<Mutex<HashMap<(String, String), ScopedStyle>>>);
+
+impl CssCache {
+    /// Returns the cached style for `key`, or computes it with `f`.
+    ///
+    /// The lock is not held while computing, so styles can be processed in
+    /// parallel. Errors are not cached.
+    pub fn get_or_try_insert_with(
+        &self,
+        key: (String, String),
+        f: impl FnOnce() -> Result<ScopedStyle, anyhow::Error>,
+    ) -> Result<ScopedStyle, anyhow::Error> {
+        if let Some(scoped) = self.0.lock().unwrap().get(&key) {
+            return Ok(scoped.clone());
+        }
+
+        let scoped = f()?;
+        self.0.lock().unwrap().insert(key, scoped.clone());
+        Ok(scoped)
+    }
+}
+
+/// Where relative `url()` references in a component's style resolve from.
+pub struct AssetBase<'a> {
+    /// URL of the module defining the style.
+    pub base: &'a Url,
+    /// Site root, which referenced files must be inside of.
+    pub root: &'a Path,
+    /// Optimized images, referenced instead of their originals.
+    pub images: &'a ImageManifest,
+}
+
+impl AssetBase<'_> {
+    /// Root-relative route of the asset `reference` points to, or `None` if it
+    /// is not a file in the site.
+    fn route(&self, reference: &str) -> Option<String> {
+        let path = self.base.join(reference).ok()?.to_file_path().ok()?;
+        let relative = path.strip_prefix(self.root).ok()?;
+        if !path.is_file() {
+            return None;
+        }
+
+        let route = encode_route(relative);
+        match self.images.get(&route).and_then(|image| image.fallback()) {
+            Some(variant) => Some(variant.href()),
+            None => Some(route),
+        }
+    }
+}
+
+/// Scopes a component's style: selectors at any depth, including inside
+/// `@media`, `@supports` and `@container`, and container names.
+///
+/// Keyframes the style declares are renamed as [`KeyframesVisitor`] names
+/// them, along with the animations using them. Other at-rules pass through
+/// untouched, so `@font-face`, `@layer` and `@property` names stay global, as
+/// do keyframes declared elsewhere.
+struct CssVisitor<'a> {
+    scope: String,
+    assets: &'a AssetBase<'a>,
+    /// New names of the keyframes the style declares.
+    keyframes: HashMap<String, String>,
+}
+
+impl CssVisitor<'_> {
+    fn scope_ident(&self, ident: &mut CustomIdent) {
+        ident.0 = format!("{}_{}", ident.0, self.scope).into();
+    }
+
+    fn rename_animation(&self, name: &mut AnimationName) {
+        let (AnimationName::Ident(CustomIdent(name)) | AnimationName::String(CSSString(name))) =
+            name
+        else {
+            return;
+        };
+        if let Some(renamed) = self.keyframes.get(&**name) {
+            *name = renamed.clone().into();
+        }
+    }
+}
+
+/// Names the keyframes declared in a style after themselves, as `spin` becomes
+/// `spin_<hash>`, so identical keyframes of different components are included
+/// once and different ones of the same name do not override each other.
+#[derive(Default)]
+struct KeyframesVisitor(HashMap<String, String>);
+
+impl<'i> Visitor<'i> for KeyframesVisitor {
+    type Error = PrinterError;
+
+    fn visit_types(&self) -> VisitTypes {
+        lightningcss::visit_types!(RULES)
+    }
+
+    fn visit_rule(&mut self, rule: &mut CssRule<'i>) -> Result<(), Self::Error> {
+        if let CssRule::Keyframes(keyframes) = rule {
+            let hash = scope_class(&keyframes.to_css_string(PrinterOptions::default())?);
+            let (KeyframesName::Ident(CustomIdent(name)) | KeyframesName::Custom(CSSString(name))) =
+                &keyframes.name;
+            let renamed = format!("{}_{}", name, hash);
+            self.0.insert(name.to_string(), renamed);
+        }
+
+        rule.visit_children(self)
+    }
+}
+
+impl<'a, 'i> lightningcss::visitor::Visitor<'i> for CssVisitor<'a> {
+    type Error = Infallible;
+
+    fn visit_types(&self) -> lightningcss::visitor::VisitTypes {
+        lightningcss::visit_types!(SELECTORS | URLS | RULES | PROPERTIES)
+    }
+
+    fn visit_rule(&mut self, rule: &mut CssRule<'i>) -> Result<(), Self::Error> {
+        if let CssRule::Container(ContainerRule {
+            name: Some(ContainerName(name)),
+            ..
+        }) = rule
+        {
+            self.scope_ident(name);
+        }
+        if let CssRule::Keyframes(KeyframesRule {
+            name: KeyframesName::Ident(CustomIdent(name)) | KeyframesName::Custom(CSSString(name)),
+            ..
+        }) = rule
+        {
+            if let Some(renamed) = self.keyframes.get(&**name) {
+                *name = renamed.clone().into();
+            }
+        }
+
+        rule.visit_children(self)
+    }
+
+    fn visit_property(&mut self, property: &mut Property<'i>) -> Result<(), Self::Error> {
+        if let Property::ContainerName(ContainerNameList::Names(names))
+        | Property::Container(Container {
+            name: ContainerNameList::Names(names),
+            ..
+        }) = property
+        {
+            for name in names.iter_mut() {
+                self.scope_ident(name);
+            }
+        }
+        match property {
+            Property::AnimationName(names, _) => {
+                for name in names.iter_mut() {
+                    self.rename_animation(name);
+                }
+            }
+            Property::Animation(animations, _) => {
+                for animation in animations.iter_mut() {
+                    self.rename_animation(&mut animation.name);
+                }
+            }
+            _ => {}
+        }
+
+        property.visit_children(self)
+    }
+
+    fn visit_url(&mut self, url: &mut CssUrl<'i>) -> Result<(), Self::Error> {
+        // Absolute, root-relative and fragment references are left as they are
+        if url.url.starts_with(['/', '#']) || Url::parse(&url.url).is_ok() {
+            return Ok(());
+        }
+
+        match self.assets.route(&url.url) {
+            Some(route) => url.url = route.into(),
+            None => tracing::warn!(
+                "could not resolve url({}) in style of scope {}, defined in {}",
+                url.url,
+                self.scope,
+                self.assets.base
+            ),
+        }
+
+        Ok(())
+    }
+
+    fn visit_selector(&mut self, selector: &mut Selector<'i>) -> Result<(), Self::Error> {
+        let mut complex = Vec::new();
+        let mut compound = Vec::new();
+        let mut it = selector.iter();
+
+        loop {
+            if let Some(component) = it.next() {
+                match component {
+                    Component::NonTSPseudoClass(PseudoClass::Global { selector }) => {
+                        complex.extend(selector.iter_raw_parse_order_from(0).map(Clone::clone));
+                        compound.clear();
+
+                        if let Some(combinator) = it.next_sequence() {
+                            complex.push(Component::Combinator(combinator));
+                        } else {
+                            break;
+                        }
+                    }
+                    _ => {
+                        compound.push(component.clone());
+                    }
+                }
+            } else {
+                complex.push(Component::Class(self.scope.clone().into()));
+                complex.extend(compound.iter().rev().map(Clone::clone));
+                compound.clear();
+
+                if let Some(combinator) = it.next_sequence() {
+                    complex.push(Component::Combinator(combinator));
+                } else {
+                    break;
+                }
+            }
+        }
+
+        complex.reverse();
+        *selector = complex.try_into()?;
+
+        Ok(())
+    }
+}
+
+/// Scopes a component's style.
+///
+/// The class is derived from the processed CSS, so styles only share a class
+/// if they are identical once imports and URLs are resolved. In dev mode, it
+/// is prefixed with `name`, or the module's file name, like `Button_sXXXX`.
+pub fn scope_css(
+    style: &str,
+    name: Option<&str>,
+    assets: &AssetBase,
+    options: &CssOptions,
+) -> Result<ScopedStyle, anyhow::Error> {
+    let named = |err: anyhow::Error| match err.downcast::<CssError>() {
+        Ok(err) => CssError {
+            component: name.map(String::from),
+            ..err
+        }
+        .into(),
+        Err(err) => err,
+    };
+
+    if options.dev {
+        let name = name
+            .filter(|name| !name.is_empty())
+            .or_else(|| assets.base.path_segments()?.last()?.split('.').next())
+            .unwrap_or("Component");
+        let name: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+
+        // Hashed before processing, so the source map stays accurate
+        let class = format!(
+            "{}_{}",
+            name,
+            scope_class(&format!("{}{}", assets.base, style))
+        );
+        let (css, hoisted) = process_css(style, &class, assets, options).map_err(named)?;
+        return Ok(ScopedStyle {
+            class,
+            css,
+            hoisted,
+        });
+    }
+
+    let (css, hoisted) = process_css(style, SCOPE_PLACEHOLDER, assets, options).map_err(named)?;
+    // Hoisted rules are part of the style, so only identical ones share a class
+    let class = scope_class(&(hoisted.css() + &css));
+    let css = css.replace(SCOPE_PLACEHOLDER, &class);
+    Ok(ScopedStyle {
+        class,
+        css,
+        hoisted,
+    })
+}
+
+/// Scopes a component's style under the class `unique`, rewriting relative
+/// `url()` references to the routes of the assets they point to, and takes
+/// out the rules pages hoist.
+pub fn process_css(
+    style: &str,
+    unique: &str,
+    assets: &AssetBase,
+    options: &CssOptions,
+) -> Result<(String, Hoisted), anyhow::Error> {
+    let mut stylesheet = StyleSheet::parse(
+        &style,
+        ParserOptions {
+            filename: assets.base.to_string(),
+            flags: ParserFlags::NESTING,
+            css_modules: Some(css_modules::Config {
+                pattern: css_modules::Pattern {
+                    segments: vec![css_modules::Segment::Local].into(),
+                },
+                dashed_idents: false,
+            }),
+            ..Default::default()
+        },
+    )
+    .map_err(|err| CssError::new(err, style, assets.base))?;
+
+    let mut keyframes = KeyframesVisitor::default();
+    stylesheet.visit(&mut keyframes)?;
+
+    // Rescope stylesheet with unique ID class
+    let visitor = &mut CssVisitor {
+        scope: unique.to_string(),
+        assets,
+        keyframes: keyframes.0,
+    };
+    stylesheet.visit(visitor)?;
+
+    let hoisted = hoist(&mut stylesheet, options)?;
+    let css = if options.dev {
+        print_with_source_map(&mut stylesheet, style, assets.base, options)?
+    } else {
+        options.print(&mut stylesheet)?
+    };
+    Ok((css, hoisted))
+}
+
+/// Takes the `@import`, `@font-face` and `@keyframes` rules out of the top
+/// level of `stylesheet`, printed.
+fn hoist(stylesheet: &mut StyleSheet, options: &CssOptions) -> Result<Hoisted, anyhow::Error> {
+    let print = |rule: &CssRule| {
+        rule.to_css_string(PrinterOptions {
+            minify: options.minify && !options.dev,
+            targets: options.targets,
+            ..Default::default()
+        })
+    };
+
+    let mut hoisted = Hoisted::default();
+    let mut rules = Vec::new();
+    for rule in std::mem::take(&mut stylesheet.rules.0) {
+        match rule {
+            CssRule::Import(_) => hoisted.imports.push(print(&rule)?),
+            CssRule::FontFace(_) => hoisted.font_faces.push(print(&rule)?),
+            CssRule::Keyframes(_) => hoisted.keyframes.push(print(&rule)?),
+            rule => rules.push(rule),
+        }
+    }
+    stylesheet.rules.0 = rules;
+    Ok(hoisted)
+}
+
+/// Prints a stylesheet unminified, with an inline source map pointing into
+/// `source`, the style as written in the module at `url`.
+fn print_with_source_map(
+    stylesheet: &mut StyleSheet,
+    source: &str,
+    url: &Url,
+    options: &CssOptions,
+) -> Result<String, anyhow::Error> {
+    stylesheet.minify(MinifyOptions {
+        targets: options.targets,
+        ..Default::default()
+    })?;
+
+    let mut map = SourceMap::new("/");
+    let index = map.add_source(url.as_str());
+    map.set_source_content(index as usize, source)?;
+
+    let css = stylesheet.to_css(PrinterOptions {
+        minify: false,
+        source_map: Some(&mut map),
+        targets: options.targets,
+        ..Default::default()
+    })?;
+
+    Ok(format!(
+        "{}\n/*# sourceMappingURL={} */\n",
+        css.code,
+        map.to_data_url(None)?
+    ))
+}
+
+/// Processes a global stylesheet, leaving its selectors as they are.
+pub fn process_global_css(
+    css: &str,
+    filename: &str,
+    options: &CssOptions,
+) -> Result<String, anyhow::Error> {
+    let mut stylesheet = StyleSheet::parse(
+        css,
+        ParserOptions {
+            filename: filename.to_string(),
+            flags: ParserFlags::NESTING,
+            ..Default::default()
+        },
+    )
+    .map_err(|e| anyhow!("{} in {}", e, filename))?;
+
+    options.print(&mut stylesheet)
+}
+
+/// File name suffix of stylesheets compiled as CSS modules.
+pub const MODULE_SUFFIX: &str = ".module.css";
+
+/// Compiles a CSS module into a JavaScript module.
+///
+/// The default export maps each class name to its hashed name, and holds the
+/// compiled stylesheet under `Symbol.for("areum.stylesheet")`, where it is
+/// picked up when assigned as a component's `style`.
+pub fn css_module(url: &Url, css: &str, options: &CssOptions) -> Result<String, anyhow::Error> {
+    let mut stylesheet = StyleSheet::parse(
+        css,
+        ParserOptions {
+            filename: url.path().to_string(),
+            flags: ParserFlags::NESTING,
+            css_modules: Some(css_modules::Config::default()),
+            ..Default::default()
+        },
+    )
+    .map_err(|e| anyhow!("{} in {}", e, url))?;
+
+    stylesheet.minify(MinifyOptions {
+        targets: options.targets,
+        ..Default::default()
+    })?;
+    let result = stylesheet.to_css(PrinterOptions {
+        minify: options.minify,
+        targets: options.targets,
+        ..Default::default()
+    })?;
+
+    let classes: BTreeMap<String, String> = result
+        .exports
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, export)| {
+            let composed = export
+                .composes
+                .into_iter()
+                .map(|reference| match reference {
+                    css_modules::CssModuleReference::Local { name }
+                    | css_modules::CssModuleReference::Global { name }
+                    | css_modules::CssModuleReference::Dependency { name, .. } => name,
+                });
+            let names: Vec<String> = std::iter::once(export.name).chain(composed).collect();
+            (name, names.join(" "))
+        })
+        .collect();
+
+    Ok(format!(
+        r#"const styles = {};
+Object.defineProperty(styles, Symbol.for("areum.stylesheet"), {{ value: {} }});
+export default Object.freeze(styles);
+"#,
+        serde_json::to_string(&classes)?,
+        serde_json::to_string(&result.code)?
+    ))
+}
+
+/// Inlines the local `@import` rules at the top of a stylesheet, resolving
+/// them relative to `base`, the URL of the module defining the style.
+///
+/// Imports with media queries are wrapped in `@media`. Remote imports are kept
+/// as they are, ahead of the inlined rules.
+pub fn inline_imports(style: &str, base: &Url) -> Result<String, anyhow::Error> {
+    let mut kept = String::new();
+    let mut inlined = String::new();
+    inline_imports_into(style, base, &mut HashSet::new(), &mut kept, &mut inlined)?;
+    Ok(kept + &inlined)
+}
+
+fn inline_imports_into(
+    style: &str,
+    base: &Url,
+    seen: &mut HashSet<Url>,
+    kept: &mut String,
+    inlined: &mut String,
+) -> Result<(), anyhow::Error> {
+    let mut rest = style;
+
+    loop {
+        let trimmed = skip_whitespace_and_comments(rest);
+        let Some(statement) = trimmed.strip_prefix("@import") else {
+            rest = trimmed;
+            break;
+        };
+
+        let end = statement_end(statement)
+            .with_context(|| format!("unterminated @import in style defined in {}", base))?;
+        let prelude = statement[..end].trim();
+        rest = &statement[end + 1..];
+
+        let (specifier, media) = parse_import_prelude(prelude)
+            .with_context(|| format!("invalid @import {} in {}", prelude, base))?;
+        let url = base.join(specifier)?;
+
+        if url.scheme() != "file" {
+            kept.push_str(&format!("@import {};", prelude));
+            continue;
+        }
+
+        // Each file is only inlined once, which also breaks import cycles
+        if !seen.insert(url.clone()) {
+            continue;
+        }
+
+        let path = url
+            .to_file_path()
+            .map_err(|_| anyhow!("invalid file URL {}", url))?;
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("could not read {} imported in {}", path.display(), base))?;
+
+        let mut nested = String::new();
+        inline_imports_into(&contents, &url, seen, kept, &mut nested)?;
+
+        if media.is_empty() {
+            inlined.push_str(&nested);
+        } else {
+            inlined.push_str(&format!("@media {} {{{}}}", media, nested));
+        }
+        inlined.push('\n');
+    }
+
+    inlined.push_str(rest);
+    Ok(())
+}
+
+fn skip_whitespace_and_comments(mut css: &str) -> &str {
+    loop {
+        css = css.trim_start();
+        match css
+            .strip_prefix("/*")
+            .and_then(|c| c.find("*/").map(|end| &c[end + 2..]))
+        {
+            Some(after) => css = after,
+            None => return css,
+        }
+    }
+}
+
+/// Finds the `;` ending a statement, skipping over strings and parentheses.
+fn statement_end(css: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut depth = 0;
+
+    for (i, c) in css.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ';') if depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Splits an `@import` prelude into its URL and media query list.
+fn parse_import_prelude(prelude: &str) -> Option<(&str, &str)> {
+    let (specifier, rest) = if let Some(inner) = prelude.strip_prefix("url(") {
+        let close = inner.find(')')?;
+        let specifier = inner[..close]
+            .trim()
+            .trim_matches(|c| c == '"' || c == '\'');
+        (specifier, &inner[close + 1..])
+    } else {
+        let quote = prelude.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let close = prelude[1..].find(quote)? + 1;
+        (&prelude[1..close], &prelude[close + 1..])
+    };
+
+    let media = rest.trim();
+    if media.starts_with("layer") || media.starts_with("supports(") {
+        return None;
+    }
+
+    Some((specifier, media))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+    use super::*;
+
+    fn scope(style: &str, options: &CssOptions) -> ScopedStyle {
+        scope_in("file:///site/button.tsx", style, None, options)
+    }
+
+    /// Scopes `style` as defined by the component `name` in the module at
+    /// `module`, in a site at `/site`.
+    fn scope_in(
+        module: &str,
+        style: &str,
+        name: Option<&str>,
+        options: &CssOptions,
+    ) -> ScopedStyle {
+        let base = Url::parse(module).unwrap();
+        let images = ImageManifest::default();
+        let assets = AssetBase {
+            base: &base,
+            root: Path::new("/site"),
+            images: &images,
+        };
+        scope_css(style, name, &assets, options).unwrap()
+    }
+
+    fn chrome(version: u32) -> CssOptions {
+        let browsers = Browsers {
+            chrome: Some(version << 16),
+            ..Default::default()
+        };
+        CssOptions {
+            targets: browsers.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the first rule of `css` has another inside it.
+    fn is_nested(css: &str) -> bool {
+        let end = css.find('}').unwrap();
+        css[..end].matches('{').count() > 1
+    }
+
+    #[test]
+    fn scope_classes_are_derived_from_contents() {
+        let style = "button { color: red; }";
+        let scoped = scope(style, &CssOptions::default());
+        // Pinned, so classes stay the same across runs and releases
+        assert_eq!(scoped.class, "s2Hf3RjuGiEHm3dAyS");
+        assert_eq!(scoped.css, "button.s2Hf3RjuGiEHm3dAyS{color:red}");
+
+        let other = scope("button { color: blue; }", &CssOptions::default());
+        assert_ne!(other.class, scoped.class);
+    }
+
+    #[test]
+    fn identical_styles_of_different_components_share_a_class() {
+        let style = "button { color: red; }";
+        let options = CssOptions::default();
+        let button = scope_in("file:///site/button.tsx", style, Some("Button"), &options);
+        let link = scope_in("file:///site/nav/link.tsx", style, Some("Link"), &options);
+        assert_eq!(button.class, link.class);
+        assert_eq!(button.css, link.css);
+    }
+
+    #[test]
+    fn dev_styles_are_readable_with_source_maps() {
+        let style = ".card {\n  color: red;\n}";
+        let dev = CssOptions {
+            dev: true,
+            ..Default::default()
+        };
+        let module = "file:///site/components/card.tsx";
+        let named = scope_in(module, style, Some("Card"), &dev);
+        assert!(named.class.starts_with("Card_s"), "{}", named.class);
+        // Without a name, the class is named after the module's file
+        let unnamed = scope_in(module, style, None, &dev);
+        assert!(unnamed.class.starts_with("card_s"), "{}", unnamed.class);
+
+        let css = &named.css;
+        assert!(
+            css.contains(&format!(".card.{} {{\n", named.class)),
+            "{}",
+            css
+        );
+        let (_, map) = css.split_once("sourceMappingURL=data:").unwrap();
+        let (_, map) = map.split_once("base64,").unwrap();
+        let map = map.trim_end().trim_end_matches("*/").trim_end();
+        let map: serde_json::Value = serde_json::from_slice(&BASE64.decode(map).unwrap()).unwrap();
+        let source = map["sources"][0].as_str().unwrap();
+        assert!(source.ends_with("components/card.tsx"), "{}", source);
+        assert_eq!(map["sourcesContent"], serde_json::json!([style]));
+        assert!(!map["mappings"].as_str().unwrap().is_empty());
+
+        // Builds keep the short class and minified CSS
+        let prod = scope_in(module, style, Some("Card"), &CssOptions::default());
+        assert!(prod.class.starts_with('s'), "{}", prod.class);
+        assert_eq!(prod.css, format!(".card.{}{{color:red}}", prod.class));
+    }
+
+    #[test]
+    fn caches_styles_across_pages() {
+        let cache = CssCache::default();
+        let calls = std::cell::Cell::new(0);
+        let process = || {
+            calls.set(calls.get() + 1);
+            Ok(scope("button { color: red; }", &CssOptions::default()))
+        };
+        let key = || ("scope".to_string(), "hash".to_string());
+
+        let first = cache.get_or_try_insert_with(key(), process).unwrap();
+        // Pages get clones of the env's cache
+        let second = cache
+            .clone()
+            .get_or_try_insert_with(key(), process)
+            .unwrap();
+        assert_eq!(calls.get(), 1);
+        assert_eq!((&first.class, &first.css), (&second.class, &second.css));
+
+        let other = ("scope".to_string(), "other".to_string());
+        cache.get_or_try_insert_with(other, process).unwrap();
+        assert_eq!(calls.get(), 2);
+
+        // Errors are not cached, so the style is processed again next time
+        let failed = ("failed".to_string(), "hash".to_string());
+        assert!(cache
+            .get_or_try_insert_with(failed.clone(), || Err(anyhow!("invalid")))
+            .is_err());
+        cache.get_or_try_insert_with(failed, process).unwrap();
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn rewrites_urls_to_asset_routes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = crate::src_fs::canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(root.join("components").join("shared")).unwrap();
+        fs::write(root.join("components").join("shared").join("bg.png"), "").unwrap();
+
+        let base = crate::src_fs::file_url(&root.join("components").join("card").join("card.tsx"))
+            .unwrap();
+        let images = ImageManifest::default();
+        let assets = AssetBase {
+            base: &base,
+            root: &root,
+            images: &images,
+        };
+        let style = ".card { background: url(../shared/bg.png); }\n\
+                     .missing { background: url(../missing.png); }\n\
+                     .remote { background: url(https://example.com/bg.png); }\n\
+                     .absolute { background: url(/bg.png); }";
+        let css = scope_css(style, None, &assets, &CssOptions::default())
+            .unwrap()
+            .css;
+        // Resolved from the module, so pages at any depth can use it
+        assert!(css.contains("url(/components/shared/bg.png)"), "{}", css);
+        assert!(css.contains("url(../missing.png)"), "{}", css);
+        assert!(css.contains("url(https://example.com/bg.png)"), "{}", css);
+        assert!(css.contains("url(/bg.png)"), "{}", css);
+    }
+
+    #[test]
+    fn processes_global_styles_without_scoping() {
+        let css = "body {\n  color: red;\n  & main { margin: 0 auto; }\n}\n";
+        let processed = process_global_css(css, "styles/global.css", &chrome(100)).unwrap();
+        assert_eq!(processed, "body{color:red}body main{margin:0 auto}");
+    }
+
+    #[test]
+    fn scopes_rules_in_media_and_supports() {
+        let style = "@media (min-width: 40rem) { .card { color: red; } }\n\
+                     @supports (display: grid) { .grid { display: grid; } }";
+        let scoped = scope(style, &CssOptions::default());
+        let css = &scoped.css;
+        assert!(css.contains("@media ("), "{}", css);
+        assert!(css.contains("@supports ("), "{}", css);
+        assert!(
+            css.contains(&format!(".card.{}{{", scoped.class)),
+            "{}",
+            css
+        );
+        assert!(
+            css.contains(&format!(".grid.{}{{", scoped.class)),
+            "{}",
+            css
+        );
+    }
+
+    #[test]
+    fn scopes_container_names_with_their_queries() {
+        let style = ".sidebar { container: sidebar / inline-size; }\n\
+                     @container sidebar (min-width: 20rem) { .title { color: red; } }\n\
+                     @container (min-width: 40rem) { .body { color: blue; } }";
+        let scoped = scope(style, &CssOptions::default());
+        let css = &scoped.css;
+        let name = format!("sidebar_{}", scoped.class);
+        // The query still matches the container once both are renamed
+        assert!(
+            css.contains(&format!("container:{}/inline-size", name)),
+            "{}",
+            css
+        );
+        assert!(css.contains(&format!("@container {} (", name)), "{}", css);
+        assert!(!css.contains("sidebar "), "{}", css);
+        assert!(
+            css.contains(&format!(".title.{}{{", scoped.class)),
+            "{}",
+            css
+        );
+        // Unnamed queries match the nearest container, so are left as they are
+        assert!(css.contains("@container ("), "{}", css);
+        assert!(
+            css.contains(&format!(".body.{}{{", scoped.class)),
+            "{}",
+            css
+        );
+    }
+
+    #[test]
+    fn flattens_nesting_for_old_targets() {
+        let style = ".card { color: red; & .title { color: blue; } }";
+
+        let old = scope(style, &chrome(100));
+        assert!(!is_nested(&old.css), "{}", old.css);
+        assert!(old.css.contains(".title"));
+
+        let modern = scope(style, &chrome(130));
+        assert!(is_nested(&modern.css), "{}", modern.css);
+    }
+
+    #[test]
+    fn lowers_colors_for_old_targets() {
+        let style = ".card { color: lab(50% 40 20); }";
+        let browsers = Browsers {
+            safari: Some(12 << 16),
+            ..Default::default()
+        };
+        let old = CssOptions {
+            targets: browsers.into(),
+            ..Default::default()
+        };
+        let css = scope(style, &old).css;
+        assert!(!css.contains("lab("), "{}", css);
+        assert!(!css.contains('\n'), "{}", css);
+
+        let css = scope(style, &CssOptions::default()).css;
+        assert!(css.contains("lab("), "{}", css);
+    }
+
+    #[test]
+    fn hoists_shared_rules_once() {
+        let shared = "@import \"https://example.com/fonts.css\";\n\
+                      @font-face { font-family: Inter; src: url(/inter.woff2); }\n\
+                      @keyframes spin { to { transform: rotate(1turn); } }\n";
+        let button = scope(
+            &format!("{}.button {{ animation: spin 1s; }}", shared),
+            &CssOptions::default(),
+        );
+        let spinner = scope(
+            &format!("{}.spinner {{ animation: spin 2s linear; }}", shared),
+            &CssOptions::default(),
+        );
+        assert_ne!(button.class, spinner.class);
+
+        let mut hoisted = Hoisted::default();
+        hoisted.extend(button.hoisted);
+        hoisted.extend(spinner.hoisted);
+        let css = hoisted.css();
+        assert_eq!(css.matches("@import").count(), 1, "{}", css);
+        assert_eq!(css.matches("@font-face").count(), 1, "{}", css);
+        assert_eq!(css.matches("@keyframes").count(), 1, "{}", css);
+        assert!(css.starts_with("@import"), "{}", css);
+
+        // Both components animate with the one hoisted rule
+        let (_, name) = hoisted.keyframes[0].split_once("@keyframes ").unwrap();
+        let name = name.split('{').next().unwrap().trim();
+        assert!(name.starts_with("spin_"), "{}", name);
+        for css in [&button.css, &spinner.css] {
+            assert!(css.contains(name), "{}", css);
+            assert!(!css.contains("@keyframes"), "{}", css);
+        }
+    }
+
+    #[test]
+    fn keeps_different_keyframes_of_the_same_name_apart() {
+        let fast = scope(
+            "@keyframes fade { from { opacity: 0; } }\n.a { animation: fade 1s; }",
+            &CssOptions::default(),
+        );
+        let slow = scope(
+            "@keyframes fade { from { opacity: 0.5; } }\n.b { animation: fade 1s; }",
+            &CssOptions::default(),
+        );
+        let mut hoisted = Hoisted::default();
+        hoisted.extend(fast.hoisted);
+        hoisted.extend(slow.hoisted);
+        assert_eq!(hoisted.keyframes.len(), 2);
+        assert_ne!(hoisted.keyframes[0], hoisted.keyframes[1]);
+    }
+}
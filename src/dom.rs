@@ -1,10 +1,20 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
 use anyhow::anyhow;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 
 type PropValue = serde_json::Value;
 
+/// Elements without an end tag in HTML.
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+fn is_void(tag: &str) -> bool {
+    VOID_ELEMENTS.contains(&tag)
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Props(HashMap<String, PropValue>);
 
@@ -50,13 +60,13 @@ impl Props {
     }
 }
 
-impl ToString for Props {
-    fn to_string(&self) -> String {
+impl Props {
+    fn serialize(&self, serializer: &mut PropSerializer) -> String {
         let mut stringified = self
             .0
             .iter()
             .filter(|(key, _)| !key.starts_with("_"))
-            .map(|kv| Prop::from(kv).to_string())
+            .map(|kv| Prop::from(kv).serialize(serializer))
             .collect::<Vec<_>>()
             .join(" ");
 
@@ -76,8 +86,98 @@ impl From<(&String, &serde_json::Value)> for Prop {
     }
 }
 
-impl ToString for Prop {
-    fn to_string(&self) -> String {
+/// How array and object props are serialized into attributes.
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+#[serde(default)]
+pub struct JsonProps {
+    /// Whether JSON is indented, rather than compact.
+    pub pretty: bool,
+    /// Size in bytes above which JSON is moved out of the attribute into a
+    /// `<script type="application/json">` at the end of the body. The
+    /// attribute is then `#` followed by the script's id.
+    pub script_threshold: Option<usize>,
+}
+
+/// How pages are serialized.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Serialization {
+    /// Void elements without an end tag, such as `<br>`.
+    #[default]
+    Html,
+    /// Void elements self-closed, such as `<br />`, and boolean attributes
+    /// set to their name, as XML parsers need.
+    Xhtml,
+}
+
+impl Serialization {
+    /// End of the start tag of a void element.
+    pub fn void_end(self) -> &'static str {
+        match self {
+            Self::Html => ">",
+            Self::Xhtml => " />",
+        }
+    }
+}
+
+/// Serializes props, collecting the JSON moved into scripts.
+pub struct PropSerializer {
+    options: JsonProps,
+    serialization: Serialization,
+    /// Ids and contents of the JSON scripts, in order.
+    pub scripts: Vec<(String, String)>,
+}
+
+impl PropSerializer {
+    pub fn new(options: JsonProps, serialization: Serialization) -> Self {
+        Self {
+            options,
+            serialization,
+            scripts: Vec::new(),
+        }
+    }
+
+    /// Attribute value of a JSON prop, either the JSON itself or a reference
+    /// to the script holding it.
+    fn json(&mut self, value: &PropValue) -> String {
+        let json = if self.options.pretty {
+            serde_json::to_string_pretty(value)
+        } else {
+            serde_json::to_string(value)
+        }
+        .unwrap_or_default();
+
+        match self.options.script_threshold {
+            Some(threshold) if json.len() > threshold => {
+                let id = format!("areum-json-{}", self.scripts.len());
+                // Only `</script` could end the script early
+                self.scripts.push((id.clone(), json.replace("</", r"<\/")));
+                format!("#{}", id)
+            }
+            _ => escape_attribute(&json),
+        }
+    }
+
+    /// `<script>` tags holding the collected JSON.
+    pub fn scripts_html(&self) -> String {
+        self.scripts
+            .iter()
+            .map(|(id, json)| {
+                format!(
+                    r#"<script type="application/json" id="{}">{}</script>"#,
+                    id, json
+                )
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn escape_attribute(value: &str) -> String {
+    value.replace('&', "&amp;").replace('"', "&quot;")
+}
+
+impl Prop {
+    fn serialize(&self, serializer: &mut PropSerializer) -> String {
         let mut stringified = String::new();
 
         fn push_prefix(str: &mut String, key: &str) {
@@ -86,6 +186,11 @@ impl ToString for Prop {
         }
 
         match &self.1 {
+            PropValue::Bool(true) if serializer.serialization == Serialization::Xhtml => {
+                push_prefix(&mut stringified, &self.0);
+                stringified.push_str(&self.0);
+                stringified.push('"');
+            }
             PropValue::Bool(true) => stringified.push_str(&self.0),
             PropValue::Number(num) => {
                 push_prefix(&mut stringified, &self.0);
@@ -97,13 +202,10 @@ impl ToString for Prop {
                 stringified.push_str(&str);
                 stringified.push('"');
             }
-            PropValue::Array(_) => {
+            PropValue::Array(_) | PropValue::Object(_) => {
                 push_prefix(&mut stringified, &self.0);
-                stringified.push_str(r#"[Array]""#)
-            }
-            PropValue::Object(_) => {
-                stringified.push_str(&self.0);
-                stringified.push_str(r#"[Object]""#)
+                stringified.push_str(&serializer.json(&self.1));
+                stringified.push('"');
             }
             _ => {}
         }
@@ -112,16 +214,161 @@ impl ToString for Prop {
     }
 }
 
+/// Children as the JSX runtime sends them. It drops `null`, `undefined` and
+/// boolean children and turns numbers into text, so conditionals render
+/// nothing. Numbers from other runtimes are turned into text here.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum Children<T> {
     Elements(Vec<Self>),
     Element(T),
+    #[serde(deserialize_with = "text")]
     Text(String),
 }
 
+/// Deserializes a string, or a number as JavaScript would print it.
+fn text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    struct TextVisitor;
+
+    impl<'de> de::Visitor<'de> for TextVisitor {
+        type Value = String;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a string or number")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<String, E> {
+            Ok(v.to_string())
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<String, E> {
+            Ok(v.to_string())
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<String, E> {
+            Ok(v.to_string())
+        }
+
+        fn visit_f64<E: de::Error>(self, v: f64) -> Result<String, E> {
+            // V8 passes every number as a double, so whole numbers print
+            // without a fraction, as `String(42)` does
+            Ok(if v == 0.0 {
+                "0".into()
+            } else if v.fract() == 0.0 && v.abs() < 1e21 {
+                format!("{:.0}", v)
+            } else if v.is_nan() {
+                "NaN".into()
+            } else if v.is_infinite() {
+                if v > 0.0 { "Infinity" } else { "-Infinity" }.into()
+            } else {
+                v.to_string()
+            })
+        }
+    }
+
+    deserializer.deserialize_any(TextVisitor)
+}
+
+impl<T> Children<T> {
+    /// Elements among the children, in order, flattening nested lists.
+    pub fn iter_elements(&self) -> impl Iterator<Item = &T> {
+        self.leaves().filter_map(|leaf| match leaf {
+            Children::Element(element) => Some(element),
+            _ => None,
+        })
+    }
+
+    /// Text among the children, in order, flattening nested lists. Text of
+    /// the elements' own children is left out.
+    pub fn iter_text(&self) -> impl Iterator<Item = &str> {
+        self.leaves().filter_map(|leaf| match leaf {
+            Children::Text(text) => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Elements and text, flattening nested lists.
+    fn leaves(&self) -> Leaves<'_, T> {
+        Leaves(vec![self])
+    }
+
+    /// Converts every element, keeping the structure of the children.
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> Children<U> {
+        self.map_with(&mut f)
+    }
+
+    fn map_with<U>(self, f: &mut impl FnMut(T) -> U) -> Children<U> {
+        match self {
+            Children::Elements(children) => Children::Elements(
+                children
+                    .into_iter()
+                    .map(|child| child.map_with(f))
+                    .collect(),
+            ),
+            Children::Element(element) => Children::Element(f(element)),
+            Children::Text(text) => Children::Text(text),
+        }
+    }
+
+    /// Text of the children and all their descendants, concatenated as
+    /// `textContent` would be. `children_of` gets an element's children.
+    ///
+    /// Text children holding HTML, such as compiled Markdown, are kept as is.
+    pub fn text_content_with<'a>(
+        &'a self,
+        children_of: &impl Fn(&'a T) -> Option<&'a Children<T>>,
+    ) -> String {
+        let mut text = String::new();
+        for leaf in self.leaves() {
+            match leaf {
+                Children::Text(t) => text.push_str(t),
+                Children::Element(element) => {
+                    if let Some(children) = children_of(element) {
+                        text.push_str(&children.text_content_with(children_of));
+                    }
+                }
+                Children::Elements(_) => {}
+            }
+        }
+        text
+    }
+}
+
+/// Depth-first iterator over the elements and text of nested children.
+struct Leaves<'a, T>(Vec<&'a Children<T>>);
+
+impl<'a, T> Iterator for Leaves<'a, T> {
+    type Item = &'a Children<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.0.pop()? {
+                Children::Elements(children) => self.0.extend(children.iter().rev()),
+                leaf => return Some(leaf),
+            }
+        }
+    }
+}
+
+impl Children<boxed::BoxedElement> {
+    /// Text of the children and all their descendants, concatenated. Cached
+    /// subtrees are left out, as only the arena resolves them.
+    pub fn text_content(&self) -> String {
+        self.text_content_with(&|element| match element {
+            boxed::BoxedElement::Intrinsic { children, .. }
+            | boxed::BoxedElement::Virtual { children, .. } => children.as_deref(),
+            boxed::BoxedElement::Cached { .. } | boxed::BoxedElement::Invalid { .. } => None,
+        })
+    }
+}
+
 pub mod arena {
-    use super::{boxed::BoxedElement, Children, Props};
+    use anyhow::anyhow;
+
+    use super::{
+        boxed::{BoxedElement, Subtrees},
+        Children, PropSerializer, Props,
+    };
 
     pub struct Arena {
         arena: Vec<ArenaElement>,
@@ -131,6 +378,11 @@ pub mod arena {
         pub fn new() -> Self {
             Arena { arena: Vec::new() }
         }
+
+        /// Text of `children` and all their descendants, concatenated.
+        pub fn text_content(&self, children: &Children<ArenaId>) -> String {
+            children.text_content_with(&|id| self[*id].children())
+        }
     }
 
     impl std::ops::Index<ArenaId> for Arena {
@@ -165,6 +417,9 @@ pub mod arena {
             scope: String,
 
             style: Option<String>,
+            source: Option<String>,
+            stylesheet: Option<String>,
+            name: Option<String>,
         },
     }
 
@@ -204,22 +459,30 @@ pub mod arena {
             }
         }
 
-        pub fn to_string(&self, arena: &Arena) -> String {
+        pub fn to_string(&self, arena: &Arena, serializer: &mut PropSerializer) -> String {
             match self {
+                Self::Intrinsic { props, tag, .. } if super::is_void(tag) => {
+                    // Void elements cannot have children, so any are dropped
+                    let end = serializer.serialization.void_end();
+                    format!("<{}{}{}", tag, props.serialize(serializer), end)
+                }
                 Self::Intrinsic {
                     props,
                     children,
                     tag,
                     ..
                 } => {
+                    let children = children
+                        .as_ref()
+                        .map_or("".into(), |c| c.to_string(arena, serializer));
                     format!(
                         "<{tag}{1}>{0}</{tag}>",
-                        children.clone().map_or("".into(), |c| c.to_string(arena)),
-                        props.to_string(),
+                        children,
+                        props.serialize(serializer)
                     )
                 }
                 Self::Virtual { children, .. } => match children {
-                    Some(children) => children.to_string(arena),
+                    Some(children) => children.to_string(arena, serializer),
                     None => "".into(),
                 },
             }
@@ -227,11 +490,14 @@ pub mod arena {
     }
 
     impl ArenaElement {
+        /// Adds `boxed` and its descendants to `arena`, resolving cached
+        /// subtrees from `subtrees`.
         pub fn from_boxed(
             arena: &mut Arena,
             boxed: &BoxedElement,
-            _parent: Option<ArenaId>,
-        ) -> ArenaId {
+            subtrees: &Subtrees,
+            parent: Option<ArenaId>,
+        ) -> Result<ArenaId, anyhow::Error> {
             let element = match boxed {
                 BoxedElement::Intrinsic {
                     props,
@@ -249,54 +515,70 @@ pub mod arena {
                     children: _,
                     scope,
                     style,
+                    source,
+                    stylesheet,
+                    name,
+                    cache: _,
                 } => ArenaElement::Virtual {
                     props: props.clone(),
                     children: None,
                     scope: scope.clone(),
                     style: style.clone(),
+                    source: source.clone(),
+                    stylesheet: stylesheet.clone(),
+                    name: name.clone(),
                 },
+                BoxedElement::Cached { id } => {
+                    let boxed = subtrees
+                        .get(*id)
+                        .ok_or_else(|| anyhow!("could not find cached component {}", id))?;
+                    return ArenaElement::from_boxed(arena, boxed, subtrees, parent);
+                }
+                BoxedElement::Invalid { error } => return Err(anyhow!("{}", error)),
             };
 
             arena.arena.push(element);
             let id = ArenaId(arena.arena.len() - 1);
 
             if boxed.children().is_none() {
-                return id;
+                return Ok(id);
             }
 
             fn from_boxed_children(
                 arena: &mut Arena,
                 children: &Children<BoxedElement>,
+                subtrees: &Subtrees,
                 parent: Option<ArenaId>,
-            ) -> Children<ArenaId> {
-                match children {
+            ) -> Result<Children<ArenaId>, anyhow::Error> {
+                Ok(match children {
                     Children::Text(text) => Children::Text(text.clone()),
                     Children::Element(el) => {
-                        Children::Element(ArenaElement::from_boxed(arena, el, parent))
+                        Children::Element(ArenaElement::from_boxed(arena, el, subtrees, parent)?)
                     }
                     Children::Elements(els) => Children::Elements(
                         els.iter()
-                            .map(|el| from_boxed_children(arena, el, parent))
-                            .collect(),
+                            .map(|el| from_boxed_children(arena, el, subtrees, parent))
+                            .collect::<Result<_, _>>()?,
                     ),
-                }
+                })
             }
 
-            let children = from_boxed_children(arena, &boxed.children().unwrap(), Some(id));
+            let children =
+                from_boxed_children(arena, &boxed.children().unwrap(), subtrees, Some(id))?;
             *arena[id].children_mut() = Some(children);
 
-            id
+            Ok(id)
         }
     }
 
     impl Children<ArenaId> {
-        fn to_string(&self, arena: &Arena) -> String {
+        fn to_string(&self, arena: &Arena, serializer: &mut PropSerializer) -> String {
             match self {
-                Children::Element(el) => arena[*el].to_string(arena),
+                Children::Element(el) => arena[*el].to_string(arena, serializer),
                 Children::Text(text) => text.clone(),
                 Children::Elements(els) => els
                     .iter()
-                    .map(|el| el.to_string(arena))
+                    .map(|el| el.to_string(arena, serializer))
                     .collect::<Vec<_>>()
                     .join(""),
             }
@@ -305,12 +587,24 @@ pub mod arena {
 }
 
 pub mod boxed {
+    use std::collections::HashMap;
+
     use super::{Children, Props};
     use serde::{Deserialize, Serialize};
+    use serde_json::{json, Map, Value};
+
+    /// Version of the elements the JSX runtime renders, which it exports as
+    /// `protocol`. Checked when the runtime is loaded, so a runtime producing
+    /// other elements fails then rather than on every page.
+    pub const PROTOCOL: i64 = 1;
 
+    /// Element rendered by the JSX runtime. Elements are read leniently, so
+    /// malformed ones become [`BoxedElement::Invalid`] instead of failing
+    /// deep in deserialization.
     #[derive(Serialize, Deserialize, Debug, Clone)]
     #[serde(tag = "kind")]
     #[serde(rename_all = "lowercase")]
+    #[serde(from = "RawElement")]
     pub enum BoxedElement {
         Intrinsic {
             props: Props,
@@ -325,7 +619,138 @@ pub mod boxed {
             scope: String,
 
             style: Option<String>,
+            /// URL of the module the component was defined in, when known.
+            source: Option<String>,
+            /// Compiled CSS module stylesheet, included without rescoping.
+            stylesheet: Option<String>,
+            /// Name of the component function.
+            name: Option<String>,
+            /// Id the subtree is kept under, for components with
+            /// `cache = true`.
+            cache: Option<u32>,
         },
+        /// Subtree of a component with `cache = true`, sent in full before.
+        Cached { id: u32 },
+        /// Element the runtime rendered that is not one of the above, failing
+        /// the page with `error` once it is added to an arena.
+        Invalid { error: String },
+    }
+
+    /// Any object among an element's children, checked for the fields its
+    /// kind needs once read.
+    #[derive(Deserialize)]
+    struct RawElement {
+        kind: Option<Value>,
+        props: Option<Props>,
+        children: Option<Box<Children<BoxedElement>>>,
+        scope: Option<String>,
+        tag: Option<String>,
+        style: Option<String>,
+        source: Option<String>,
+        stylesheet: Option<String>,
+        name: Option<String>,
+        cache: Option<u32>,
+        id: Option<u32>,
+    }
+
+    impl From<RawElement> for BoxedElement {
+        fn from(raw: RawElement) -> Self {
+            raw.into_element()
+                .unwrap_or_else(|error| BoxedElement::Invalid { error })
+        }
+    }
+
+    impl RawElement {
+        fn into_element(self) -> Result<BoxedElement, String> {
+            let kind = match &self.kind {
+                Some(Value::String(kind)) => kind.clone(),
+                Some(kind) => {
+                    return Err(self.error(format!("element kind {} is not a string", kind)))
+                }
+                None => return Err(self.error("element without a kind".into())),
+            };
+            let required = match kind.as_str() {
+                "intrinsic" => vec![
+                    ("props", self.props.is_some()),
+                    ("scope", self.scope.is_some()),
+                    ("tag", self.tag.is_some()),
+                ],
+                "virtual" => vec![
+                    ("props", self.props.is_some()),
+                    ("scope", self.scope.is_some()),
+                ],
+                "cached" => vec![("id", self.id.is_some())],
+                _ => return Err(self.error(format!("unknown element kind `{}`", kind))),
+            };
+            let missing: Vec<&str> = required
+                .into_iter()
+                .filter(|(_, present)| !present)
+                .map(|(field, _)| field)
+                .collect();
+            if !missing.is_empty() {
+                return Err(self.error(format!(
+                    "{} element without `{}`",
+                    kind,
+                    missing.join("`, `")
+                )));
+            }
+
+            Ok(match kind.as_str() {
+                "intrinsic" => BoxedElement::Intrinsic {
+                    props: self.props.unwrap(),
+                    children: self.children,
+                    scope: self.scope.unwrap(),
+                    tag: self.tag.unwrap(),
+                },
+                "virtual" => BoxedElement::Virtual {
+                    props: self.props.unwrap(),
+                    children: self.children,
+                    scope: self.scope.unwrap(),
+                    style: self.style,
+                    source: self.source,
+                    stylesheet: self.stylesheet,
+                    name: self.name,
+                    cache: self.cache,
+                },
+                _ => BoxedElement::Cached {
+                    id: self.id.unwrap(),
+                },
+            })
+        }
+
+        /// `message`, followed by the component that rendered the element, if
+        /// known, and the fields it has.
+        fn error(&self, message: String) -> String {
+            let mut error = message;
+            if let Some(name) = self.name.as_deref().filter(|name| !name.is_empty()) {
+                error += &format!(" produced by component {}", name);
+            }
+            if let Some(source) = &self.source {
+                error += &format!(" at {}", source);
+            }
+
+            let mut partial = Map::new();
+            let strings = [
+                ("kind", self.kind.clone()),
+                ("tag", self.tag.clone().map(Value::String)),
+                ("name", self.name.clone().map(Value::String)),
+                ("scope", self.scope.clone().map(Value::String)),
+            ];
+            for (field, value) in strings {
+                if let Some(value) = value {
+                    partial.insert(field.into(), value);
+                }
+            }
+            if let Some(props) = &self.props {
+                let mut keys: Vec<&String> = props.0.keys().collect();
+                keys.sort();
+                partial.insert("props".into(), json!(keys));
+            }
+            if self.children.is_some() {
+                partial.insert("children".into(), json!("..."));
+            }
+            format!("{}: {}", error, Value::Object(partial))
+        }
     }
 
     impl BoxedElement {
@@ -333,6 +758,7 @@ pub mod boxed {
             match self {
                 Self::Intrinsic { props, .. } => props.clone(),
                 Self::Virtual { props, .. } => props.clone(),
+                Self::Cached { .. } | Self::Invalid { .. } => Props(HashMap::new()),
             }
         }
 
@@ -340,6 +766,7 @@ pub mod boxed {
             match self {
                 Self::Intrinsic { children, .. } => children.clone(),
                 Self::Virtual { children, .. } => children.clone(),
+                Self::Cached { .. } | Self::Invalid { .. } => None,
             }
         }
 
@@ -347,7 +774,200 @@ pub mod boxed {
             match self {
                 Self::Intrinsic { scope, .. } => scope.clone(),
                 Self::Virtual { scope, .. } => scope.clone(),
+                Self::Cached { .. } | Self::Invalid { .. } => String::new(),
+            }
+        }
+    }
+
+    /// Subtrees of components with `cache = true`, by id, kept for the life
+    /// of the env as the runtime only sends each in full once.
+    #[derive(Default)]
+    pub struct Subtrees(HashMap<u32, BoxedElement>);
+
+    impl Subtrees {
+        /// Keeps the cached subtrees sent in full within `boxed`.
+        pub fn collect(&mut self, boxed: &BoxedElement) {
+            let children = match boxed {
+                BoxedElement::Virtual {
+                    cache: Some(id),
+                    children,
+                    ..
+                } => {
+                    self.0.entry(*id).or_insert_with(|| boxed.clone());
+                    children
+                }
+                BoxedElement::Intrinsic { children, .. }
+                | BoxedElement::Virtual { children, .. } => children,
+                BoxedElement::Cached { .. } | BoxedElement::Invalid { .. } => return,
+            };
+            if let Some(children) = children {
+                for child in children.iter_elements() {
+                    self.collect(child);
+                }
             }
         }
+
+        pub fn get(&self, id: u32) -> Option<&BoxedElement> {
+            self.0.get(&id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{
+        arena::{Arena, ArenaElement},
+        boxed::{BoxedElement, Subtrees},
+        *,
+    };
+
+    /// `a`, then `1` and `b` in a nested list, then `2`.
+    fn nested() -> Children<u32> {
+        Children::Elements(vec![
+            Children::Text("a".into()),
+            Children::Elements(vec![Children::Element(1), Children::Text("b".into())]),
+            Children::Element(2),
+        ])
+    }
+
+    #[test]
+    fn iterates_over_nested_children_in_order() {
+        let children = nested();
+        assert_eq!(children.iter_elements().collect::<Vec<_>>(), [&1, &2]);
+        assert_eq!(children.iter_text().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(Children::Text::<u32>("c".into()).iter_elements().count(), 0);
+    }
+
+    #[test]
+    fn maps_elements_keeping_the_structure() {
+        let mapped = nested().map(|n| n * 10);
+        assert_eq!(mapped.iter_elements().collect::<Vec<_>>(), [&10, &20]);
+        assert_eq!(mapped.iter_text().collect::<Vec<_>>(), ["a", "b"]);
+        assert!(matches!(
+            &mapped,
+            Children::Elements(children) if matches!(children[1], Children::Elements(_))
+        ));
+    }
+
+    #[test]
+    fn concatenates_text_content_of_descendants() {
+        let children = nested();
+        let texts = [
+            (1, Children::Text("one".into())),
+            (2, Children::Text("two".into())),
+        ];
+        let text = children.text_content_with(&|n| {
+            texts
+                .iter()
+                .find(|(m, _)| m == n)
+                .map(|(_, children)| children)
+        });
+        assert_eq!(text, "aonebtwo");
+
+        let children: Children<BoxedElement> = serde_json::from_value(json!([
+            "Hello, ",
+            {
+                "kind": "intrinsic",
+                "tag": "b",
+                "props": {},
+                "scope": "",
+                "children": ["<i>wor</i>", "ld"]
+            },
+            42
+        ]))
+        .unwrap();
+        assert_eq!(children.text_content(), "Hello, <i>wor</i>ld42");
+
+        let boxed = BoxedElement::Intrinsic {
+            props: Props(HashMap::new()),
+            children: Some(Box::new(children)),
+            scope: String::new(),
+            tag: "p".into(),
+        };
+        let mut arena = Arena::new();
+        let id = ArenaElement::from_boxed(&mut arena, &boxed, &Subtrees::default(), None).unwrap();
+        let children = arena[id].children().unwrap();
+        assert_eq!(arena.text_content(children), "Hello, <i>wor</i>ld42");
+
+        // Only the arena resolves cached subtrees
+        let cached: Children<BoxedElement> =
+            serde_json::from_value(json!(["a", { "kind": "cached", "id": 1 }])).unwrap();
+        assert_eq!(cached.text_content(), "a");
+    }
+
+    fn boxed(value: serde_json::Value) -> BoxedElement {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn error(value: serde_json::Value) -> String {
+        match boxed(value) {
+            BoxedElement::Invalid { error } => error,
+            element => panic!("expected an invalid element, got {:?}", element),
+        }
+    }
+
+    #[test]
+    fn reads_elements_by_kind() {
+        let element = boxed(json!({
+            "kind": "virtual",
+            "props": { "title": "Hi" },
+            "scope": "s",
+            "name": "Card",
+            "cache": 3
+        }));
+        assert!(matches!(
+            element,
+            BoxedElement::Virtual { ref name, cache: Some(3), .. } if name.as_deref() == Some("Card")
+        ));
+        assert!(matches!(
+            boxed(json!({ "kind": "cached", "id": 3 })),
+            BoxedElement::Cached { id: 3 }
+        ));
+        assert!(matches!(
+            boxed(json!({ "kind": "intrinsic", "tag": "p", "props": {}, "scope": "" })),
+            BoxedElement::Intrinsic { ref tag, children: None, .. } if tag == "p"
+        ));
+    }
+
+    #[test]
+    fn reads_malformed_elements_as_invalid() {
+        let unknown =
+            error(json!({ "kind": "fragment", "name": "List", "source": "file:///list.tsx" }));
+        assert_eq!(
+            unknown,
+            "unknown element kind `fragment` produced by component List at file:///list.tsx: \
+             {\"kind\":\"fragment\",\"name\":\"List\"}"
+        );
+
+        let missing = error(json!({ "tag": "p", "props": { "b": 1, "a": 2 }, "children": [] }));
+        assert!(
+            missing.starts_with("element without a kind: {"),
+            "{}",
+            missing
+        );
+        assert!(missing.contains(r#""props":["a","b"]"#));
+        assert!(missing.contains(r#""children":"...""#));
+
+        assert!(error(json!({ "kind": 1 })).starts_with("element kind 1 is not a string"));
+        assert!(error(json!({ "kind": "intrinsic", "props": {} }))
+            .starts_with("intrinsic element without `scope`, `tag`"));
+
+        // Invalid elements fail only once added to an arena
+        let page = boxed(json!({
+            "kind": "intrinsic",
+            "tag": "main",
+            "props": {},
+            "scope": "",
+            "children": [{ "kind": "cached" }]
+        }));
+        let err = ArenaElement::from_boxed(&mut Arena::new(), &page, &Subtrees::default(), None)
+            .unwrap_err();
+        assert!(
+            err.to_string().starts_with("cached element without `id`"),
+            "{}",
+            err
+        );
     }
 }
@@ -1,41 +1,203 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap},
+    fs,
     io::Write,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::{Arc, Mutex},
 };
 
+use anyhow::{anyhow, Context};
 use blake2::{digest::consts, Blake2b, Digest};
-use deno_core::{op2, v8};
-use dongjak::runtime::{Runtime, RuntimeOptions};
-use rand::{distributions::Alphanumeric, Rng};
+use deno_core::{op2, v8, OpState};
+use dongjak::{
+    loader::Transform,
+    lock::Lockfile,
+    runtime::{Runtime, RuntimeOptions},
+};
+use rand::{distributions::Alphanumeric, rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::instrument;
 // use sha2::{Digest, Sha256};
 use url::Url;
 
 use crate::{
+    collection,
+    config::{Config, HtmlConfig, JsxRuntime, LinksConfig, ScriptConfig},
+    css::{self, CssCache, CssOptions},
     dom::{
         arena::{Arena, ArenaElement},
-        boxed::BoxedElement,
+        boxed::{self, BoxedElement, Subtrees},
     },
-    page::{Page, PageProps},
+    excerpt,
+    git::{self, Commit},
+    markdown,
+    page::{LastModified, Page, PageProps},
+    src_fs::{self, file_path, file_url, SrcKind, SrcKinds, Translations},
+    taxonomy,
 };
 
+/// Page rendered by the loader, with the files its module exports as
+/// `outputs`.
+#[derive(Deserialize)]
+struct LoadedPage {
+    node: BoxedElement,
+    outputs: BTreeMap<String, String>,
+}
+
+/// Pages rendered by a generator, by site path, with the files it exports as
+/// `outputs`.
+#[derive(Deserialize)]
+struct LoadedPages {
+    pages: BTreeMap<String, BoxedElement>,
+    outputs: BTreeMap<String, String>,
+}
+
 pub struct Env {
     pub runtime: Runtime,
     pub bundler: Bundler,
+    theme: String,
+    css: CssOptions,
+    css_cache: CssCache,
+    /// Subtrees of components with `cache = true` rendered so far.
+    subtrees: Subtrees,
+    links: LinksConfig,
+    script: ScriptConfig,
+    html: HtmlConfig,
+    kinds: SrcKinds,
+    excerpt_length: usize,
+    shell: Shell,
+    config_module: String,
+    /// Sources of the generated `areum:` modules besides the config, by
+    /// specifier.
+    data_modules: BTreeMap<&'static str, String>,
+    /// Last commit of every file, by path relative to the root.
+    commits: BTreeMap<String, Commit>,
+    /// Seed `randString` is reseeded with for every page in builds. Random in
+    /// the dev server.
+    seed: Option<u64>,
+    query: BTreeMap<String, String>,
+    /// Lockfile remote modules are checked against in builds.
+    lock: Option<Arc<Mutex<Lockfile>>>,
+    /// URL of the client bundle page scripts import in builds.
+    bundle_href: String,
+}
+
+/// Whether pages are rendered for the dev server or for a build.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Dev,
+    Build,
+}
+
+/// URLs of the components every page is rendered inside of, if present.
+#[derive(Serialize)]
+struct Shell {
+    document: Option<String>,
+    app: Option<String>,
 }
 
 impl Env {
     pub const LOADER_FN_KEY: &'static str = "load";
     pub const GENERATOR_LOADER_FN_KEY: &'static str = "loadGenerator";
+    pub const EACH_LOADER_FN_KEY: &'static str = "loadEach";
+    pub const GENERATOR_LIST_FN_KEY: &'static str = "listGenerator";
+    /// File name, without extension, of the component rendering the HTML shell
+    /// around every page.
+    pub const DOCUMENT_NAME: &'static str = "_document";
+    /// File name, without extension, of the component wrapping every page,
+    /// inside the document.
+    pub const APP_NAME: &'static str = "_app";
+    /// Specifier of the module exporting the site config.
+    pub const CONFIG_MODULE: &'static str = "areum:config";
+    /// Specifier of the module exporting the pages of every collection.
+    pub const COLLECTIONS_MODULE: &'static str = "areum:collections";
+    /// Specifier of the module exporting every page.
+    pub const PAGES_MODULE: &'static str = "areum:pages";
+    /// Specifier of the module exporting the last commit of every file.
+    pub const GIT_MODULE: &'static str = "areum:git";
+    /// File, relative to the root, builds lock remote modules in.
+    pub const LOCK_FILE: &'static str = "areum.lock";
+    /// Directory, relative to the root, `npm:` packages are cached in.
+    pub const NPM_CACHE_DIR: &'static str = ".areum/cache/npm";
+    /// Prefixes of environment variables exposed to pages and their scripts
+    /// through `import.meta.env`.
+    pub const PUBLIC_ENV_PREFIXES: [&'static str; 2] = ["AREUM_", "VITE_"];
+
+    /// Creates an env rendering the site at `root`. [`Env::bootstrap`] must
+    /// be called once before any page is rendered.
+    pub fn new(root: &Path, config: &Config, mode: Mode) -> Result<Self, anyhow::Error> {
+        let kinds = config.src_kinds()?;
+        let options = config.css.options()?;
+        let css = CssOptions {
+            dev: mode == Mode::Dev,
+            lenient: options.lenient || (mode == Mode::Dev && config.css.strict.is_none()),
+            ..options
+        };
+        let find = |name: &str| {
+            kinds
+                .extensions(SrcKind::Jsx)
+                .into_iter()
+                .map(|ext| root.join(format!("{}.{}", name, ext)))
+                .find(|path| path.is_file())
+                .map(|path| file_url(&path).map(|url| url.to_string()))
+                .transpose()
+        };
+        let shell = Shell {
+            document: find(Self::DOCUMENT_NAME)?,
+            app: find(Self::APP_NAME)?,
+        };
+
+        let seed = match mode {
+            Mode::Build => Some(config.build.seed),
+            Mode::Dev => None,
+        };
+        // The dev server fetches remote modules without checking them
+        let lock = match mode {
+            Mode::Build => Some(Arc::new(Mutex::new(Lockfile::load(
+                &root.join(Self::LOCK_FILE),
+                config.imports.update_lock,
+            )?))),
+            Mode::Dev => None,
+        };
+
+        let mut transforms = vec![(
+            css::MODULE_SUFFIX.into(),
+            Arc::new(move |url: &Url, code: &str| css::css_module(url, code, &css)) as Transform,
+        )];
+        for ext in kinds.extensions(SrcKind::Md) {
+            let jsx_import_source = config.jsx.import_source.clone();
+            transforms.push((
+                format!(".{}", ext),
+                Arc::new(move |_: &Url, code: &str| markdown::md_module(code, &jsx_import_source))
+                    as Transform,
+            ));
+        }
+
+        // The marker ending excerpts is an HTML comment, which MDX rejects
+        for ext in kinds.extensions(SrcKind::Mdx) {
+            transforms.push((
+                format!(".{}", ext),
+                Arc::new(|_: &Url, code: &str| Ok(excerpt::strip_marker(code))) as Transform,
+            ));
+        }
 
-    pub fn new(root: &Path) -> Result<Self, anyhow::Error> {
         let runtime = Runtime::new(
             root,
             RuntimeOptions {
-                jsx_import_source: "/areum".into(),
+                jsx_import_source: config.jsx.import_source.clone(),
+                jsx_automatic: config.jsx.runtime == JsxRuntime::Automatic,
+                jsx_factory: config.jsx.factory.clone(),
+                jsx_fragment_factory: config.jsx.fragment_factory.clone(),
+                mdx_extensions: kinds.extensions(SrcKind::Mdx),
+                transforms,
+                import_meta_env: import_meta_env(mode),
+                max_fetches: config.imports.max_fetches,
+                lock: lock.clone(),
+                npm_cache: Some(root.join(Self::NPM_CACHE_DIR)),
                 extensions: vec![
-                    rand_extension::init_ops_and_esm(),
+                    rand_extension::init_ops_and_esm(seed),
                     print_extension::init_ops_and_esm(),
                 ],
             },
@@ -44,92 +206,337 @@ impl Env {
         Ok(Env {
             runtime,
             bundler: Bundler::new(),
+            theme: config.theme.css(),
+            css,
+            css_cache: CssCache::default(),
+            subtrees: Subtrees::default(),
+            links: config.links.clone(),
+            script: config.script,
+            html: config.html.clone(),
+            kinds,
+            excerpt_length: config.excerpts.length,
+            shell,
+            config_module: config.module()?,
+            data_modules: BTreeMap::from([
+                (
+                    Self::COLLECTIONS_MODULE,
+                    collection::module(&Default::default()),
+                ),
+                (Self::PAGES_MODULE, collection::pages_module(&[])),
+                (Self::GIT_MODULE, git::module(&Default::default())),
+            ]),
+            commits: BTreeMap::new(),
+            seed,
+            query: BTreeMap::new(),
+            lock,
+            bundle_href: format!("/{}", config.build.bundle_path()?),
         })
     }
 
-    pub async fn new_page(&mut self, url: &Url, path: &Path) -> Result<Page, anyhow::Error> {
-        self.runtime.add_root(url).await;
+    /// Writes the lockfile if remote modules were added to it or updated.
+    pub fn save_lock(&self) -> Result<(), anyhow::Error> {
+        match &self.lock {
+            Some(lock) => lock.lock().unwrap().save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Sets the query parameters pages are rendered with, as `props.query`,
+    /// until set again. Empty unless set, as in builds.
+    pub fn set_query(&mut self, query: BTreeMap<String, String>) {
+        self.query = query;
+    }
 
-        let props = PageProps {
-            path: path.to_string_lossy().into(),
-            generator: format!("Areum {}", env!("CARGO_PKG_VERSION")),
+    /// Sets the source of a generated module, such as
+    /// [`Env::COLLECTIONS_MODULE`], which is empty until then. Modules are not
+    /// reloaded, so this must be called before [`Env::bootstrap`].
+    pub fn set_module(&mut self, specifier: &'static str, code: String) {
+        self.data_modules.insert(specifier, code);
+    }
+
+    /// Sets the last commit of every file, by path relative to the root, for
+    /// [`Env::GIT_MODULE`] and the `last_modified` page prop. Must be called
+    /// before [`Env::bootstrap`], as [`Env::set_module`].
+    pub fn set_commits(&mut self, commits: BTreeMap<String, Commit>) {
+        self.set_module(Self::GIT_MODULE, git::module(&commits));
+        self.commits = commits;
+    }
+
+    /// Reseeds `randString` for the page at `url` in builds, so it gives the
+    /// same strings every build, whatever other pages are rendered.
+    fn reseed(&mut self, url: &Url) {
+        let Some(seed) = self.seed else {
+            return;
+        };
+        // Relative, so builds of the same site elsewhere match
+        let path = url.to_file_path().unwrap_or_default();
+        let relative = path.strip_prefix(self.runtime.root()).unwrap_or(&path);
+        let hash = Blake2b::<consts::U8>::digest(src_fs::to_slash(relative));
+        let page = u64::from_le_bytes(hash.as_slice().try_into().unwrap());
+        self.runtime
+            .op_state()
+            .borrow_mut()
+            .put(RandState(StdRng::seed_from_u64(seed ^ page)));
+    }
+
+    /// Last change to the source at `url`, from its last commit, or else its
+    /// modification time if it has not been committed.
+    fn last_modified(&self, url: &Url) -> Option<LastModified> {
+        let path = url.to_file_path().ok()?;
+        let relative = path.strip_prefix(self.runtime.root()).ok()?;
+        if let Some(commit) = self.commits.get(&src_fs::to_slash(relative)) {
+            return Some(LastModified {
+                date: commit.date.clone(),
+                author: Some(commit.author.clone()),
+                hash: Some(commit.hash.clone()),
+            });
+        }
+
+        let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok()?;
+        Some(LastModified {
+            date: OffsetDateTime::from(modified).format(&Rfc3339).ok()?,
+            author: None,
+            hash: None,
+        })
+    }
+
+    /// Renders the page at `url` inside `layouts`, outermost first, unless it
+    /// opts out with `export const layout = false`.
+    #[instrument(name = "render", level = "debug", skip_all, fields(page = %url))]
+    pub async fn new_page(
+        &mut self,
+        url: &Url,
+        path: &Path,
+        translations: Translations,
+        layouts: &[Url],
+    ) -> Result<Page, anyhow::Error> {
+        self.runtime.add_root(url).await;
+        self.reseed(url);
+
+        let mut props = PageProps {
+            locale: translations.locale,
+            default_locale: translations.default_locale,
+            alternates: translations.alternates,
+            excerpt: match self.kinds.kind(url.path()) {
+                SrcKind::Mdx | SrcKind::Md => {
+                    excerpt::from_source(&fs::read_to_string(file_path(url)?)?)
+                }
+                _ => None,
+            },
+            query: self.query.clone(),
+            last_modified: self.last_modified(url),
+            ..PageProps::new(path, self.links.host.as_deref())
         };
 
         let mut arena = Arena::new();
-        let boxed: BoxedElement = self
+        let LoadedPage {
+            node: boxed,
+            outputs,
+        } = self
             .runtime
-            .call_by_name(Env::LOADER_FN_KEY, &[&url.to_string(), &props])
+            .call_by_name(
+                Env::LOADER_FN_KEY,
+                &[&url.to_string(), &props, &self.shell, &layout_urls(layouts)],
+            )
             .await?;
+        let outputs = self.outputs(url, outputs)?;
 
-        let dom = ArenaElement::from_boxed(&mut arena, &boxed, None);
+        self.subtrees.collect(&boxed);
+        let dom = tracing::debug_span!("from_boxed")
+            .in_scope(|| ArenaElement::from_boxed(&mut arena, &boxed, &self.subtrees, None))?;
 
-        let hash = Blake2b::<consts::U6>::digest(url.to_string());
-        let id = bs58::encode(hash).into_string();
+        let id = Env::page_id(url);
 
         let script = format!(
             r#"
-        import {{ page{} as Page, run }} from "/index.js"
+        import {{ page{} as Page, run }} from "{}"
         run(Page, {{}})
         "#,
-            id
+            id, self.bundle_href
         );
 
-        let page = Page {
-            path: path.to_path_buf(),
-            url: url.clone(),
+        if props.excerpt.is_none() {
+            props.excerpt = excerpt::from_page(&arena, dom, self.excerpt_length);
+        }
+
+        let mut page = Page::new(
+            path.to_path_buf(),
+            url.clone(),
             arena,
             dom,
-            style: String::new(),
-            scopes: HashSet::new(),
             script,
             id,
             props,
-        };
+        );
+        self.configure(&mut page);
+        page.outputs = outputs;
 
         Ok(page)
     }
 
-    pub async fn new_pages(&mut self, url: &Url) -> Result<Vec<Page>, anyhow::Error> {
+    /// Renders a generator's pages. Only the locale of `translations` applies,
+    /// as generated pages have no known translations.
+    #[instrument(name = "render", level = "debug", skip_all, fields(page = %url))]
+    pub async fn new_pages(
+        &mut self,
+        url: &Url,
+        translations: Translations,
+        layouts: &[Url],
+    ) -> Result<Vec<Page>, anyhow::Error> {
         self.runtime.add_root(url).await;
+        self.reseed(url);
+        let path = self.module_dir(url)?;
 
-        let path = url
-            .to_file_path()
-            .unwrap()
-            .strip_prefix(self.runtime.root())?
-            .parent()
-            .unwrap()
-            .to_path_buf();
-
+        // Generated pages have no source of their own, so they share the
+        // generator's
+        let last_modified = self.last_modified(url);
         let props_temp = PageProps {
-            path: path.to_string_lossy().into(),
-            generator: format!("Areum {}", env!("CARGO_PKG_VERSION")),
+            locale: translations.locale.clone(),
+            default_locale: translations.default_locale.clone(),
+            query: self.query.clone(),
+            last_modified: last_modified.clone(),
+            ..PageProps::new(&path, self.links.host.as_deref())
         };
 
-        let boxeds: HashMap<String, BoxedElement> = self
+        // Sorted by path, so pages are built and bundled in the same order
+        // every time
+        let LoadedPages { pages, outputs } = self
             .runtime
             .call_by_name(
                 Env::GENERATOR_LOADER_FN_KEY,
-                &[&url.to_string(), &props_temp],
+                &[
+                    &url.to_string(),
+                    &props_temp,
+                    &self.shell,
+                    &layout_urls(layouts),
+                ],
             )
             .await?;
+        let outputs = self.outputs(url, outputs)?;
 
+        let mut pages = self.generated_pages(url, pages, &translations, last_modified)?;
+        // Outputs are the generator's, so they are written once, with its
+        // first page
+        if let Some(page) = pages.first_mut() {
+            page.outputs = outputs;
+        }
+        Ok(pages)
+    }
+
+    /// Site paths of the pages the generator at `url` generates, sorted,
+    /// without rendering them.
+    pub async fn generated_paths(&mut self, url: &Url) -> Result<Vec<String>, anyhow::Error> {
+        self.runtime.add_root(url).await;
+        let root = src_fs::to_slash(&self.module_dir(url)?);
+        let mut paths: Vec<String> = self
+            .runtime
+            .call_by_name(Env::GENERATOR_LIST_FN_KEY, &[&url.to_string(), &root])
+            .await?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Directory of the module at `url`, relative to the root, which the
+    /// paths a generator generates and the outputs of a page are joined to.
+    fn module_dir(&self, url: &Url) -> Result<PathBuf, anyhow::Error> {
+        module_dir(self.runtime.root(), url)
+    }
+
+    /// Site paths of the `outputs` the module at `url` exports, which must be
+    /// relative to its directory and stay inside it.
+    fn outputs(
+        &self,
+        url: &Url,
+        outputs: BTreeMap<String, String>,
+    ) -> Result<Vec<(PathBuf, String)>, anyhow::Error> {
+        let dir = self.module_dir(url)?;
+        outputs
+            .into_iter()
+            .map(|(path, contents)| {
+                let relative = Path::new(&path);
+                let normal = relative
+                    .components()
+                    .all(|c| matches!(c, std::path::Component::Normal(_)));
+                if path.is_empty() || !normal {
+                    return Err(anyhow!(
+                        "output {:?} of {} must be a relative path without `..`",
+                        path,
+                        url
+                    ));
+                }
+                Ok((dir.join(relative), contents))
+            })
+            .collect()
+    }
+
+    /// Renders the component at `url` once for each of `pages`, a map of site
+    /// paths to props added to the page props.
+    #[instrument(name = "render", level = "debug", skip_all, fields(page = %url))]
+    pub async fn new_pages_each(
+        &mut self,
+        url: &Url,
+        pages: &BTreeMap<String, serde_json::Value>,
+        layouts: &[Url],
+    ) -> Result<Vec<Page>, anyhow::Error> {
+        self.runtime.add_root(url).await;
+        self.reseed(url);
+
+        let props_temp = PageProps {
+            query: self.query.clone(),
+            ..PageProps::new(Path::new(""), self.links.host.as_deref())
+        };
+
+        let boxeds: BTreeMap<String, BoxedElement> = self
+            .runtime
+            .call_by_name(
+                Env::EACH_LOADER_FN_KEY,
+                &[
+                    &url.to_string(),
+                    &props_temp,
+                    &self.shell,
+                    pages,
+                    &layout_urls(layouts),
+                ],
+            )
+            .await?;
+
+        self.generated_pages(url, boxeds, &Translations::default(), None)
+    }
+
+    fn generated_pages(
+        &mut self,
+        url: &Url,
+        boxeds: BTreeMap<String, BoxedElement>,
+        translations: &Translations,
+        last_modified: Option<LastModified>,
+    ) -> Result<Vec<Page>, anyhow::Error> {
+        // Pages may refer to cached subtrees sent in full with any other page
+        // of the batch
+        for boxed in boxeds.values() {
+            self.subtrees.collect(boxed);
+        }
         boxeds
             .into_iter()
             .map(|(path, boxed)| {
                 let mut arena = Arena::new();
-                let dom = ArenaElement::from_boxed(&mut arena, &boxed, None);
+                let dom = tracing::debug_span!("from_boxed").in_scope(|| {
+                    ArenaElement::from_boxed(&mut arena, &boxed, &self.subtrees, None)
+                })?;
 
-                let hash = Blake2b::<consts::U6>::digest(url.to_string());
-                let id = bs58::encode(hash).into_string();
+                let id = Env::page_id(url);
 
                 let props = PageProps {
-                    path: path.clone(),
-                    generator: format!("Areum {}", env!("CARGO_PKG_VERSION")),
+                    excerpt: excerpt::from_page(&arena, dom, self.excerpt_length),
+                    locale: translations.locale.clone(),
+                    default_locale: translations.default_locale.clone(),
+                    query: self.query.clone(),
+                    last_modified: last_modified.clone(),
+                    ..PageProps::new(Path::new(&path), self.links.host.as_deref())
                 };
 
                 let script = format!(
                     r#"
-            import {{ page{} as Page, runScript }} from "/index.js"
+            import {{ page{} as Page, runScript }} from "{}"
             if (!("Deno" in window)) {{
                 if (Page.script) {{
                     Page.script()
@@ -137,59 +544,142 @@ impl Env {
                 runScript(Page())
             }}
             "#,
-                    id
+                    id, self.bundle_href
                 );
 
-                Ok(Page {
-                    path: PathBuf::from_str(&path)?,
-                    url: url.clone(),
+                let mut page = Page::new(
+                    PathBuf::from_str(&path)?,
+                    url.clone(),
                     arena,
                     dom,
-                    style: String::new(),
-                    scopes: HashSet::new(),
                     script,
                     id,
                     props,
-                })
+                );
+                self.configure(&mut page);
+                Ok(page)
             })
             .collect()
     }
 
-    pub async fn bundle(&mut self) -> Result<String, anyhow::Error> {
-        let mut unique: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(8)
-            .map(char::from)
-            .collect();
-        unique.insert_str(0, "__");
-        unique.push_str(".ts");
+    /// Id of the page rendered from the module at `url`, naming its export
+    /// in the client bundle.
+    pub fn page_id(url: &Url) -> String {
+        let hash = Blake2b::<consts::U6>::digest(url.to_string());
+        bs58::encode(hash).into_string()
+    }
 
-        let url = Url::from_file_path(self.runtime.root().join(unique)).unwrap();
+    fn configure(&self, page: &mut Page) {
+        page.global_style = self.theme.clone();
+        page.css_options = self.css;
+        page.css_cache = self.css_cache.clone();
+        page.links = self.links.clone();
+        page.script_config = self.script;
+        page.json_props = self.html.json_props;
+        page.noscript = self.html.noscript.clone();
+        page.serialization = self.html.serialization;
+        page.doctype = self.html.doctype().to_string();
+        page.root = self.runtime.root().to_path_buf();
+    }
 
-        self.runtime
-            .graph_loader
-            .inject(url.clone(), self.bundler.code.clone());
+    /// Bundles the client scripts of the pages rendered from the modules at
+    /// `urls`, as builds write them to `build.bundle_path`, which page
+    /// scripts import their components from. Replaces whatever was pushed to
+    /// the bundler.
+    pub async fn bundle_pages<'a>(
+        &mut self,
+        urls: impl IntoIterator<Item = &'a Url>,
+    ) -> Result<String, anyhow::Error> {
+        self.bundler.clear();
+        // Generators render several pages from one module, exported once
+        for url in urls {
+            self.bundler
+                .push_export(&format!("page{}", Env::page_id(url)), url)?;
+        }
+        self.bundler.push(format!(
+            r#"export {{ runScript }} from "{}""#,
+            file_url(&self.runtime.root().join("/areum/jsx-runtime"))?
+        ));
+        self.bundle().await
+    }
+
+    /// Modules bundled for the pages of `urls`, largest source first.
+    pub fn bundled_modules<'a>(
+        &self,
+        urls: impl IntoIterator<Item = &'a Url>,
+    ) -> Vec<(Url, usize)> {
+        let mut modules = HashMap::new();
+        for url in urls {
+            modules.extend(self.runtime.source_sizes(url));
+        }
+        let mut modules: Vec<_> = modules.into_iter().collect();
+        modules.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        modules
+    }
+
+    /// Bundles the code pushed to the bundler and the modules it imports.
+    pub async fn bundle(&mut self) -> Result<String, anyhow::Error> {
+        // Named by its code, so the same bundle gets the same entry every time
+        let code = self.bundler.code();
+        let hash = Blake2b::<consts::U6>::digest(&code);
+        let name = format!("__{}.ts", bs58::encode(hash).into_string());
+        let url = file_url(&self.runtime.root().join(name))?;
+
+        self.runtime.graph_loader.inject(url.clone(), code);
         self.runtime.add_root(&url).await;
         let bundled = self.runtime.bundle(&url).await?;
 
         Ok(bundled)
     }
 
+    /// Fails unless the JSX runtime renders elements of the protocol this
+    /// version of Areum reads.
+    async fn check_protocol(&mut self, jsx_mod: usize) -> Result<(), anyhow::Error> {
+        let protocol = self
+            .runtime
+            .export::<v8::Integer>(jsx_mod, "protocol")
+            .await
+            .context("JSX runtime does not export the version of its elements")?;
+        let protocol = v8::Local::new(&mut self.runtime.scope(), protocol).value();
+        if protocol != boxed::PROTOCOL {
+            return Err(anyhow!(
+                "JSX runtime renders elements of protocol {}, but Areum {} reads protocol {}, reinstall Areum so both come from the same version",
+                protocol,
+                env!("CARGO_PKG_VERSION"),
+                boxed::PROTOCOL
+            ));
+        }
+        Ok(())
+    }
+
     pub async fn bootstrap(&mut self) -> Result<(), anyhow::Error> {
+        // Shared by the module loader and the graph loader, so pages and
+        // bundles see the same config
+        self.runtime.graph_loader.inject(
+            Url::parse(Self::CONFIG_MODULE).unwrap(),
+            self.config_module.clone(),
+        );
+        for (specifier, code) in &self.data_modules {
+            self.runtime
+                .graph_loader
+                .inject(Url::parse(specifier).unwrap(), code.clone());
+        }
+
         let jsx_mod = self
             .runtime
             .load_from_string(
-                &Url::from_file_path(self.runtime.root().join("/areum/jsx-runtime")).unwrap(),
+                &file_url(&self.runtime.root().join("/areum/jsx-runtime"))?,
                 include_str!("ts/jsx-runtime.ts"),
                 false,
             )
             .await?;
         self.runtime.eval(jsx_mod).await?;
+        self.check_protocol(jsx_mod).await?;
 
         let loader_mod = self
             .runtime
             .load_from_string(
-                &Url::from_file_path(self.runtime.root().join("__loader.ts")).unwrap(),
+                &file_url(&self.runtime.root().join("__loader.ts"))?,
                 include_str!("ts/loader.ts"),
                 false,
             )
@@ -213,18 +703,64 @@ impl Env {
             generator_loader.into(),
         );
 
+        let each_loader = self
+            .runtime
+            .export::<v8::Function>(loader_mod, Self::EACH_LOADER_FN_KEY)
+            .await?;
+        self.runtime
+            .functions
+            .insert(Self::EACH_LOADER_FN_KEY.into(), each_loader.into());
+
+        let generator_list = self
+            .runtime
+            .export::<v8::Function>(loader_mod, Self::GENERATOR_LIST_FN_KEY)
+            .await?;
+        self.runtime
+            .functions
+            .insert(Self::GENERATOR_LIST_FN_KEY.into(), generator_list.into());
+
         Ok(())
     }
 }
 
+/// Vite-like `import.meta.env`, with the mode and public environment
+/// variables. Scripts are bundled with the same values, so they end up in the
+/// client bundle too.
+fn import_meta_env(mode: Mode) -> serde_json::Map<String, serde_json::Value> {
+    let mut env: serde_json::Map<_, _> = std::env::vars()
+        .filter(|(key, _)| {
+            Env::PUBLIC_ENV_PREFIXES
+                .iter()
+                .any(|prefix| key.starts_with(prefix))
+        })
+        .map(|(key, value)| (key, value.into()))
+        .collect();
+    let name = match mode {
+        Mode::Dev => "development",
+        Mode::Build => "production",
+    };
+    env.insert("MODE".into(), name.into());
+    env.insert("DEV".into(), (mode == Mode::Dev).into());
+    env.insert("PROD".into(), (mode == Mode::Build).into());
+    env
+}
+
+fn layout_urls(layouts: &[Url]) -> Vec<String> {
+    layouts.iter().map(Url::to_string).collect()
+}
+
 pub struct Bundler {
     code: String,
+    /// Modules whose default export is exported, by the name it is exported
+    /// as.
+    exports: BTreeMap<String, Url>,
 }
 
 impl Bundler {
     pub fn new() -> Self {
         Bundler {
             code: String::new(),
+            exports: BTreeMap::new(),
         }
     }
 
@@ -232,15 +768,66 @@ impl Bundler {
         self.code.push_str(code.as_ref())
     }
 
+    /// Exports the default export of the module at `url` as `id`. Exporting
+    /// the same module as the same id again does nothing, but exporting
+    /// another module as an id already taken fails.
+    pub fn push_export(&mut self, id: &str, url: &Url) -> Result<(), anyhow::Error> {
+        if !is_identifier(id) {
+            return Err(anyhow!(
+                "cannot export {} as {:?}, which is not a JavaScript identifier",
+                url,
+                id
+            ));
+        }
+        match self.exports.get(id) {
+            Some(exported) if exported == url => Ok(()),
+            Some(exported) => Err(anyhow!(
+                "{} and {} would both be exported from the bundle as {}",
+                exported,
+                url,
+                id
+            )),
+            None => {
+                self.exports.insert(id.to_string(), url.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// Code pushed, followed by the exports sorted by name, so the same
+    /// pages give the same bundle in any order.
+    pub fn code(&self) -> String {
+        let mut code = self.code.clone();
+        for (id, url) in &self.exports {
+            code += &format!("export {{ default as {} }} from \"{}\";\n", id, url);
+        }
+        code
+    }
+
     pub fn clear(&mut self) {
-        self.code.clear()
+        self.code.clear();
+        self.exports.clear();
     }
 }
 
+fn is_identifier(id: &str) -> bool {
+    let mut chars = id.chars();
+    chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+/// Generator `randString` draws from, seeded in builds.
+struct RandState(StdRng);
+
 #[op2]
 #[string]
-fn randString(n: u32) -> String {
-    rand::thread_rng()
+fn randString(state: &mut OpState, n: u32) -> String {
+    state
+        .borrow_mut::<RandState>()
+        .0
+        .by_ref()
         .sample_iter(&Alphanumeric)
         .take(n as usize)
         .map(char::from)
@@ -250,6 +837,13 @@ fn randString(n: u32) -> String {
 deno_core::extension!(
     rand_extension,
     ops = [randString, hashString],
+    options = { seed: Option<u64> },
+    state = |state, options| {
+        state.put(RandState(match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }));
+    },
     docs = "Extension providing operations for randomness",
 );
 
@@ -262,7 +856,7 @@ fn hashString(#[string] str: String) -> String {
 
 deno_core::extension!(
     print_extension,
-    ops = [print, join_path],
+    ops = [print, join_path, slugify, encode_route],
     docs = "Extension providing printing",
 );
 
@@ -281,5 +875,91 @@ pub fn print(#[string] msg: &str, is_err: bool) -> Result<(), anyhow::Error> {
 #[op2]
 #[string]
 pub fn join_path(#[string] root: &str, #[string] to_join: &str) -> String {
-    Path::new(root).join(to_join).to_string_lossy().to_string()
+    join_site_path(root, to_join)
+}
+
+/// Joins site paths, which pages see, always with `/`.
+fn join_site_path(root: &str, to_join: &str) -> String {
+    match root.trim_end_matches('/') {
+        "" => to_join.into(),
+        root => format!("{}/{}", root, to_join),
+    }
+}
+
+/// Directory of the module at `url`, relative to `root`.
+fn module_dir(root: &Path, url: &Url) -> Result<PathBuf, anyhow::Error> {
+    let path = url
+        .to_file_path()
+        .map_err(|_| anyhow!("{} is not a file", url))?;
+    let relative = path
+        .strip_prefix(root)
+        .with_context(|| format!("{} is not inside {}", path.display(), root.display()))?;
+    Ok(relative.parent().unwrap_or(Path::new("")).to_path_buf())
+}
+
+/// Slug of a taxonomy term, as used in its route.
+#[op2]
+#[string]
+pub fn slugify(#[string] text: &str) -> String {
+    taxonomy::slugify(text)
+}
+
+/// Route of a site path, percent-encoded as in page props.
+#[op2]
+#[string]
+pub fn encode_route(#[string] path: &str) -> String {
+    src_fs::encode_route(Path::new(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn site_paths_are_joined_with_slashes() {
+        assert_eq!(join_site_path("", "post"), "post");
+        assert_eq!(join_site_path("blog", "post"), "blog/post");
+        assert_eq!(join_site_path("blog/", "2024/post"), "blog/2024/post");
+    }
+
+    #[test]
+    fn module_dirs_are_relative_to_the_root() {
+        let root = std::env::temp_dir().join("site");
+        let generator = file_url(&root.join("blog").join("_.tsx")).unwrap();
+        let dir = module_dir(&root, &generator).unwrap();
+        assert_eq!(src_fs::to_slash(&dir), "blog");
+        let generator = file_url(&root.join("_.tsx")).unwrap();
+        assert_eq!(module_dir(&root, &generator).unwrap(), Path::new(""));
+
+        let outside = file_url(&std::env::temp_dir().join("other").join("_.tsx")).unwrap();
+        assert!(module_dir(&root, &outside).is_err());
+        let remote = Url::parse("https://example.com/_.tsx").unwrap();
+        assert!(module_dir(&root, &remote).is_err());
+    }
+
+    #[test]
+    fn bundles_export_each_module_once_in_sorted_order() {
+        let root = std::env::temp_dir().join("site");
+        let generator = file_url(&root.join("blog").join("_.tsx")).unwrap();
+        let index = file_url(&root.join("index.tsx")).unwrap();
+
+        let mut bundler = Bundler::new();
+        bundler.push_export("pageB", &generator).unwrap();
+        bundler.push_export("pageA", &index).unwrap();
+        // Generators export once however many pages they render
+        bundler.push_export("pageB", &generator).unwrap();
+        assert_eq!(
+            bundler.code(),
+            format!(
+                "export {{ default as pageA }} from \"{}\";\n\
+                 export {{ default as pageB }} from \"{}\";\n",
+                index, generator
+            )
+        );
+
+        let err = bundler.push_export("pageA", &generator).unwrap_err();
+        assert!(err.to_string().contains("would both be exported"));
+        let err = bundler.push_export("page-a", &index).unwrap_err();
+        assert!(err.to_string().contains("not a JavaScript identifier"));
+    }
 }
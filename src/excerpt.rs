@@ -0,0 +1,234 @@
+use pulldown_cmark::{Event, Parser, Tag};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    dom::{
+        arena::{Arena, ArenaElement, ArenaId},
+        Children,
+    },
+    markdown,
+};
+
+/// Marks the end of a Markdown or MDX page's excerpt.
+pub const MORE_MARKER: &str = "<!--more-->";
+
+/// Tags whose text is never part of an automatic excerpt.
+const SKIPPED_TAGS: [&str; 16] = [
+    "head", "nav", "script", "style", "noscript", "template", "svg", "pre", "code", "h1", "h2",
+    "h3", "h4", "h5", "h6", "title",
+];
+/// Tags inside words, which are removed without leaving a space.
+const INLINE_TAGS: [&str; 11] = [
+    "a", "b", "del", "em", "i", "mark", "s", "small", "span", "strong", "u",
+];
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ExcerptConfig {
+    /// Characters of text automatic excerpts are cut to.
+    pub length: usize,
+}
+
+impl Default for ExcerptConfig {
+    fn default() -> Self {
+        Self { length: 160 }
+    }
+}
+
+/// Excerpt a Markdown or MDX page gives itself: the `description` in its
+/// frontmatter, or else its content above [`MORE_MARKER`] as plain text.
+pub fn from_source(code: &str) -> Option<String> {
+    let description = markdown::frontmatter(code)
+        .and_then(|frontmatter| serde_yaml::from_str::<Value>(frontmatter).ok())
+        .and_then(|meta| meta.get("description")?.as_str().map(str::to_string));
+    if let Some(description) = description {
+        return Some(description.trim().to_string());
+    }
+
+    let body = markdown::strip_frontmatter(code);
+    let (above, _) = body.split_once(MORE_MARKER)?;
+    Some(plain_text(above))
+}
+
+/// Removes [`MORE_MARKER`], which is not valid MDX, from MDX source.
+pub fn strip_marker(code: &str) -> String {
+    code.replace(MORE_MARKER, "")
+}
+
+/// Markdown as plain text, without emphasis, links or raw HTML and JSX.
+fn plain_text(markdown: &str) -> String {
+    let mut text = String::new();
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            Event::End(Tag::Paragraph | Tag::Heading(..) | Tag::Item) => text.push(' '),
+            _ => {}
+        }
+    }
+    collapse_whitespace(&text)
+}
+
+/// Automatic excerpt of a rendered page: its first `length` characters of
+/// visible text, leaving out headings and code.
+pub fn from_page(arena: &Arena, dom: ArenaId, length: usize) -> Option<String> {
+    let mut text = String::new();
+    collect(arena, dom, &mut text);
+    let text = collapse_whitespace(&text);
+    (!text.is_empty()).then(|| truncate(&text, length))
+}
+
+fn collect(arena: &Arena, id: ArenaId, text: &mut String) {
+    let element = &arena[id];
+    if let ArenaElement::Intrinsic { tag, .. } = element {
+        if SKIPPED_TAGS.contains(&tag.as_str()) {
+            return;
+        }
+    }
+    collect_children(arena, element.children(), text);
+}
+
+fn collect_children(arena: &Arena, children: Option<&Children<ArenaId>>, text: &mut String) {
+    match children {
+        Some(Children::Element(id)) => collect(arena, *id, text),
+        Some(Children::Elements(children)) => {
+            for child in children {
+                collect_children(arena, Some(child), text);
+            }
+        }
+        Some(Children::Text(html)) => {
+            text.push(' ');
+            text.push_str(&visible_text(html));
+        }
+        None => {}
+    }
+}
+
+/// Text of an HTML fragment, as text children may hold compiled Markdown,
+/// outside of tags and of skipped elements.
+fn visible_text(html: &str) -> String {
+    if !html.contains('<') {
+        return html.to_string();
+    }
+
+    let mut text = String::new();
+    let mut skipped = 0usize;
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        if skipped == 0 {
+            text.push_str(&rest[..start]);
+        }
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if SKIPPED_TAGS.contains(&name.as_str()) {
+            if tag.starts_with('/') {
+                skipped = skipped.saturating_sub(1);
+            } else if !tag.ends_with('/') {
+                skipped += 1;
+            }
+        }
+        if !INLINE_TAGS.contains(&name.as_str()) {
+            text.push(' ');
+        }
+        rest = &rest[start + end + 1..];
+    }
+    if skipped == 0 {
+        text.push_str(rest);
+    }
+    text
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Cuts `text` to at most `length` characters at a word boundary, so neither
+/// words nor HTML entities are split.
+fn truncate(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return text.to_string();
+    }
+
+    let cut: String = text.chars().take(length + 1).collect();
+    // The character after the cut tells whether it falls inside a word
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(end) => &cut[..end],
+        None => "",
+    };
+    format!("{}…", cut)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::dom::boxed::{BoxedElement, Subtrees};
+
+    fn element(tag: &str, children: Value) -> Value {
+        json!({ "kind": "intrinsic", "tag": tag, "props": {}, "scope": "", "children": children })
+    }
+
+    #[test]
+    fn prefers_the_description_to_content_above_the_marker() {
+        let page = "---\ndescription: \" Described \"\n---\n\nAbove\n\n<!--more-->\n\nBelow";
+        assert_eq!(from_source(page).as_deref(), Some("Described"));
+
+        let page = "# Title\n\nSome *emphasis* and [a link](/x).\n\n<!--more-->\n\nBelow";
+        assert_eq!(
+            from_source(page).as_deref(),
+            Some("Title Some emphasis and a link.")
+        );
+        assert_eq!(strip_marker(page), page.replace("<!--more-->", ""));
+
+        assert_eq!(from_source("# Title\n\nNo marker"), None);
+    }
+
+    #[test]
+    fn takes_visible_text_of_pages() {
+        let dom = element(
+            "main",
+            json!([
+                element("h1", json!("Title")),
+                element(
+                    "p",
+                    json!(["Some ", element("strong", json!("bold")), " text"])
+                ),
+                element("pre", json!("let code;")),
+                "<p>Compiled <em>mark</em>down</p><script>run()</script>",
+            ]),
+        );
+        let boxed: BoxedElement = serde_json::from_value(dom).unwrap();
+        let mut arena = Arena::new();
+        let dom = ArenaElement::from_boxed(&mut arena, &boxed, &Subtrees::default(), None).unwrap();
+
+        assert_eq!(
+            from_page(&arena, dom, 160).as_deref(),
+            Some("Some bold text Compiled markdown")
+        );
+        assert_eq!(from_page(&arena, dom, 12).as_deref(), Some("Some bold…"));
+
+        let boxed: BoxedElement = serde_json::from_value(element("h1", json!("Only"))).unwrap();
+        let mut arena = Arena::new();
+        let dom = ArenaElement::from_boxed(&mut arena, &boxed, &Subtrees::default(), None).unwrap();
+        assert_eq!(from_page(&arena, dom, 160), None);
+    }
+
+    #[test]
+    fn truncates_at_word_boundaries() {
+        assert_eq!(truncate("one two three", 13), "one two three");
+        assert_eq!(truncate("one two three", 7), "one two…");
+        assert_eq!(truncate("one two three", 6), "one…");
+        assert_eq!(truncate("unbreakable", 4), "…");
+        assert_eq!(truncate("é à ü", 3), "é à…");
+    }
+}
@@ -0,0 +1,400 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use blake2::{digest::consts, Blake2b, Digest};
+use image::{
+    codecs::ico::{IcoEncoder, IcoFrame},
+    imageops::{self, FilterType},
+    ColorType, DynamicImage, ImageFormat,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::dom::{
+    arena::{Arena, ArenaElement, ArenaId},
+    escape_attribute, Serialization,
+};
+
+/// Directory, relative to the root, generated icons are cached in, by the hash
+/// of their source.
+const CACHE_DIR: &str = ".areum/favicons";
+/// Directory, relative to the output, PNG icons are written to.
+const OUT_DIR: &str = "_areum/icons";
+/// Sizes of the PNG icons. The web manifest lists the 192 and 512 pixel ones.
+const PNG_SIZES: [u32; 4] = [16, 32, 192, 512];
+const MANIFEST_SIZES: [u32; 2] = [192, 512];
+const APPLE_TOUCH_ICON_SIZE: u32 = 180;
+/// Sizes held in `favicon.ico`.
+const ICO_SIZES: [u32; 3] = [16, 32, 48];
+
+/// Icons and web manifest generated from a single image, as configured by
+/// `[favicon]`. Off unless `icon` is set.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct FaviconConfig {
+    /// Image, relative to the root, icons are generated from. Square images
+    /// work best; others are centered on a transparent square.
+    pub icon: Option<String>,
+    /// Name of the site in the web manifest.
+    pub name: Option<String>,
+    /// Name shown where there is little space, such as under home screen
+    /// icons. Defaults to `name`.
+    pub short_name: Option<String>,
+    /// Color of the browser's interface around the site, in the web manifest
+    /// and a `theme-color` meta tag.
+    pub theme_color: Option<String>,
+    /// Color of the splash screen of the installed site.
+    pub background_color: Option<String>,
+}
+
+/// Tags of the generated icons, injected into the `<head>` of every page.
+/// Each is left out of pages declaring their own.
+#[derive(Clone, Default)]
+pub struct Favicons {
+    /// `<link>`s to the icons, left out if a page links an icon.
+    pub(crate) icons: String,
+    /// `<link>` to the web manifest.
+    pub(crate) manifest: String,
+    /// `theme-color` meta tag, if configured.
+    pub(crate) theme_color: String,
+}
+
+impl Favicons {
+    /// File, relative to the output, holding the ICO icon browsers look for.
+    pub const ICO_FILE: &'static str = "favicon.ico";
+    /// File, relative to the output, the web manifest is written to.
+    pub const MANIFEST_FILE: &'static str = "site.webmanifest";
+
+    /// Tags to inject into a page.
+    pub(crate) fn tags(&self) -> String {
+        format!("{}{}{}", self.icons, self.manifest, self.theme_color)
+    }
+
+    /// Leaves out the tags the page under `id` declares its own of.
+    pub(crate) fn leave_out_declared(&mut self, arena: &Arena, id: ArenaId) {
+        let element = &arena[id];
+        if let ArenaElement::Intrinsic { tag, props, .. } = element {
+            let attribute = |name| props.get(name).and_then(Value::as_str).unwrap_or_default();
+            match tag.as_str() {
+                "link" => {
+                    let rel = attribute("rel").to_ascii_lowercase();
+                    let has_rel = |value| rel.split_ascii_whitespace().any(|rel| rel == value);
+                    if has_rel("icon") || has_rel("apple-touch-icon") {
+                        self.icons.clear();
+                    }
+                    if has_rel("manifest") {
+                        self.manifest.clear();
+                    }
+                }
+                "meta" if attribute("name").eq_ignore_ascii_case("theme-color") => {
+                    self.theme_color.clear();
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(children) = element.children() {
+            for child in children.iter_elements() {
+                self.leave_out_declared(arena, *child);
+            }
+        }
+    }
+}
+
+/// Generates the icons and web manifest configured by `config` into `outdir`,
+/// returning the tags linking them, serialized as `serialization`. Icons are
+/// reused from the cache while the source image is unchanged.
+pub fn generate(
+    root: &Path,
+    config: &FaviconConfig,
+    serialization: Serialization,
+    outdir: &Path,
+) -> Result<Favicons, anyhow::Error> {
+    let Some(icon) = &config.icon else {
+        return Ok(Favicons::default());
+    };
+    let path = root.join(icon);
+    let bytes = fs::read(&path)
+        .with_context(|| format!("could not read favicon.icon {}", path.display()))?;
+    let key = bs58::encode(Blake2b::<consts::U16>::digest(&bytes)).into_string();
+
+    let cache = root.join(CACHE_DIR).join(&key);
+    let mut files: Vec<String> = PNG_SIZES.iter().map(|size| png_name(*size)).collect();
+    files.push(png_name(APPLE_TOUCH_ICON_SIZE));
+    files.push(Favicons::ICO_FILE.into());
+    if !files.iter().all(|file| cache.join(file).is_file()) {
+        encode_icons(&bytes, &cache)
+            .with_context(|| format!("could not generate icons from {}", path.display()))?;
+    }
+
+    // PNGs are named by the hash of their source, as browsers cache icons
+    // for long
+    let href = |size: u32| format!("/{}/{}-{}.png", OUT_DIR, png_stem(size), key);
+    fs::create_dir_all(outdir.join(OUT_DIR))?;
+    for size in PNG_SIZES.into_iter().chain([APPLE_TOUCH_ICON_SIZE]) {
+        let dest = outdir.join(href(size).trim_start_matches('/'));
+        if !dest.exists() {
+            fs::copy(cache.join(png_name(size)), dest)?;
+        }
+    }
+    fs::copy(
+        cache.join(Favicons::ICO_FILE),
+        outdir.join(Favicons::ICO_FILE),
+    )?;
+    fs::write(
+        outdir.join(Favicons::MANIFEST_FILE),
+        serde_json::to_string_pretty(&manifest(config, &href))?,
+    )?;
+
+    let end = serialization.void_end();
+    let ico_sizes: Vec<String> = ICO_SIZES
+        .iter()
+        .map(|size| format!("{}x{}", size, size))
+        .collect();
+    let mut icons = format!(
+        r#"<link rel="icon" href="/{}" sizes="{}"{}"#,
+        Favicons::ICO_FILE,
+        ico_sizes.join(" "),
+        end
+    );
+    for size in [16, 32] {
+        icons += &format!(
+            r#"<link rel="icon" type="image/png" sizes="{}x{}" href="{}"{}"#,
+            size,
+            size,
+            href(size),
+            end
+        );
+    }
+    icons += &format!(
+        r#"<link rel="apple-touch-icon" sizes="{}x{}" href="{}"{}"#,
+        APPLE_TOUCH_ICON_SIZE,
+        APPLE_TOUCH_ICON_SIZE,
+        href(APPLE_TOUCH_ICON_SIZE),
+        end
+    );
+
+    Ok(Favicons {
+        icons,
+        manifest: format!(
+            r#"<link rel="manifest" href="/{}"{}"#,
+            Favicons::MANIFEST_FILE,
+            end
+        ),
+        theme_color: config
+            .theme_color
+            .as_deref()
+            .map(|color| {
+                format!(
+                    r#"<meta name="theme-color" content="{}"{}"#,
+                    escape_attribute(color),
+                    end
+                )
+            })
+            .unwrap_or_default(),
+    })
+}
+
+/// Web manifest listing the icons, at the hrefs given by `href`.
+fn manifest(config: &FaviconConfig, href: &dyn Fn(u32) -> String) -> Value {
+    let mut manifest = Map::new();
+    let mut set = |key: &str, value: &Option<String>| {
+        if let Some(value) = value {
+            manifest.insert(key.into(), value.clone().into());
+        }
+    };
+    set("name", &config.name);
+    set(
+        "short_name",
+        &config.short_name.clone().or(config.name.clone()),
+    );
+    set("theme_color", &config.theme_color);
+    set("background_color", &config.background_color);
+    manifest.insert(
+        "icons".into(),
+        MANIFEST_SIZES
+            .iter()
+            .map(|size| {
+                json!({
+                    "src": href(*size),
+                    "sizes": format!("{}x{}", size, size),
+                    "type": "image/png",
+                })
+            })
+            .collect(),
+    );
+    Value::Object(manifest)
+}
+
+fn png_stem(size: u32) -> String {
+    if size == APPLE_TOUCH_ICON_SIZE {
+        "apple-touch-icon".into()
+    } else {
+        format!("icon-{}", size)
+    }
+}
+
+fn png_name(size: u32) -> String {
+    format!("{}.png", png_stem(size))
+}
+
+/// Writes every icon generated from the image in `bytes` to `cache`.
+fn encode_icons(bytes: &[u8], cache: &Path) -> Result<(), anyhow::Error> {
+    let image = image::load_from_memory(bytes)?;
+    fs::create_dir_all(cache)?;
+
+    for size in PNG_SIZES.into_iter().chain([APPLE_TOUCH_ICON_SIZE]) {
+        square(&image, size).save_with_format(cache.join(png_name(size)), ImageFormat::Png)?;
+    }
+
+    let frames = ICO_SIZES
+        .iter()
+        .map(|size| {
+            let icon = square(&image, *size).to_rgba8();
+            IcoFrame::as_png(icon.as_raw(), *size, *size, ColorType::Rgba8)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let file = fs::File::create(cache.join(Favicons::ICO_FILE))?;
+    IcoEncoder::new(file)
+        .encode_images(&frames)
+        .map_err(|err| anyhow!(err))
+}
+
+/// `image` resized to fit a `size` pixel square, centered on a transparent one
+/// if it is not square itself.
+fn square(image: &DynamicImage, size: u32) -> DynamicImage {
+    let resized = image.resize(size, size, FilterType::Lanczos3);
+    let mut icon = DynamicImage::new_rgba8(size, size);
+    let x = (size - resized.width()) / 2;
+    let y = (size - resized.height()) / 2;
+    imageops::overlay(&mut icon, &resized, x as i64, y as i64);
+    icon
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageOutputFormat, Rgba, RgbaImage};
+
+    use super::*;
+    use crate::{builder::BuilderOptions, testing::Fixture};
+
+    /// PNG of a `width` by `height` opaque image of `color`.
+    fn png(width: u32, height: u32, color: [u8; 3]) -> Vec<u8> {
+        let [r, g, b] = color;
+        let image = RgbaImage::from_pixel(width, height, Rgba([r, g, b, 255]));
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[tokio::test]
+    async fn generates_icons_and_a_manifest_linked_from_pages() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "areum.toml",
+                "[favicon]\nicon = \"logo.png\"\nname = \"Site\"\ntheme_color = \"#336699\"\n",
+            )
+            .unwrap()
+            .file("logo.png", png(64, 32, [255, 0, 0]))
+            .unwrap()
+            .file("index.tsx", "export default () => <p>Home</p>")
+            .unwrap()
+            .file(
+                "about.tsx",
+                "export default () => <html><head><link rel=\"icon\" href=\"/mine.svg\" />\
+                 </head><body><p>About</p></body></html>",
+            )
+            .unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+
+        let manifest: Value =
+            serde_json::from_str(&fixture.output(Favicons::MANIFEST_FILE).unwrap()).unwrap();
+        assert_eq!(manifest["name"], "Site");
+        assert_eq!(manifest["short_name"], "Site");
+        assert_eq!(manifest["theme_color"], "#336699");
+        assert!(manifest.get("background_color").is_none());
+        let icons = manifest["icons"].as_array().unwrap();
+        assert_eq!(icons.len(), 2);
+        assert_eq!(icons[1]["sizes"], "512x512");
+
+        // Wider images are centered on a transparent square
+        let src = icons[1]["src"].as_str().unwrap();
+        let icon = image::open(fixture.out_dir().join(&src[1..]))
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(icon.dimensions(), (512, 512));
+        assert_eq!(icon.get_pixel(0, 0)[3], 0);
+        assert_eq!(*icon.get_pixel(256, 256), Rgba([255, 0, 0, 255]));
+        let ico = image::open(fixture.out_dir().join(Favicons::ICO_FILE)).unwrap();
+        assert_eq!(ico.width(), 48);
+
+        let home = fixture.output("index.html").unwrap();
+        assert!(home.contains(r#"<link rel="icon" href="/favicon.ico" sizes="16x16 32x32 48x48">"#));
+        assert!(home.contains(r#"<link rel="manifest" href="/site.webmanifest">"#));
+        assert!(home.contains(r##"<meta name="theme-color" content="#336699">"##));
+        // Pages linking their own icon keep it and the rest of the tags
+        let about = fixture.output("about/index.html").unwrap();
+        assert!(!about.contains("/favicon.ico"), "{}", about);
+        assert!(about.contains("/mine.svg"));
+        assert!(about.contains(r#"<link rel="manifest""#));
+    }
+
+    #[test]
+    fn reuses_cached_icons_until_the_image_changes() {
+        let fixture = Fixture::new().unwrap();
+        fixture.file("logo.png", png(32, 32, [0, 0, 255])).unwrap();
+        let config = FaviconConfig {
+            icon: Some("logo.png".into()),
+            ..Default::default()
+        };
+        let generate_into = |outdir: &str, serialization| {
+            generate(
+                fixture.root(),
+                &config,
+                serialization,
+                &fixture.root().join(outdir),
+            )
+            .unwrap()
+        };
+
+        let first = generate_into("first", Serialization::Xhtml);
+        assert!(first.icons.ends_with(" />"), "{}", first.icons);
+        assert!(first.theme_color.is_empty());
+        let cached: Vec<_> = fs::read_dir(fixture.root().join(CACHE_DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(cached.len(), 1);
+
+        // Cached icons are copied as they are
+        fs::write(cached[0].join(png_name(16)), "cached").unwrap();
+        let second = generate_into("second", Serialization::Html);
+        assert!(second.icons.ends_with('>') && !second.icons.ends_with("/>"));
+        let icon16 = fs::read_dir(fixture.root().join("second").join(OUT_DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| {
+                path.file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .starts_with("icon-16-")
+            })
+            .unwrap();
+        assert_eq!(fs::read_to_string(icon16).unwrap(), "cached");
+
+        fixture.file("logo.png", png(32, 32, [0, 255, 0])).unwrap();
+        let third = generate_into("third", Serialization::Html);
+        assert_ne!(second.icons, third.icons);
+        assert_eq!(
+            fs::read_dir(fixture.root().join(CACHE_DIR))
+                .unwrap()
+                .count(),
+            2
+        );
+    }
+}
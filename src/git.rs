@@ -0,0 +1,91 @@
+use std::{collections::BTreeMap, path::Path, process::Command};
+
+use anyhow::anyhow;
+use serde::Serialize;
+use serde_json::json;
+
+/// Separates commits in the log read by [`last_commits`].
+const COMMIT_SEPARATOR: char = '\x1e';
+/// Separates the fields of a commit.
+const FIELD_SEPARATOR: char = '\x1f';
+
+#[derive(Serialize, Clone)]
+pub struct Commit {
+    pub hash: String,
+    /// Author date, in RFC 3339.
+    pub date: String,
+    pub author: String,
+    /// Subject line of the message.
+    pub message: String,
+}
+
+/// Last commit touching each file under `root`, by path relative to it with
+/// `/` separators. Reads the whole history once, newest first.
+pub fn last_commits(root: &Path) -> Result<BTreeMap<String, Commit>, anyhow::Error> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args([
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "--relative",
+            "--name-only",
+        ])
+        .arg(format!(
+            "--format={}%H{}%aI{}%an{}%s",
+            COMMIT_SEPARATOR, FIELD_SEPARATOR, FIELD_SEPARATOR, FIELD_SEPARATOR
+        ))
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let mut commits = BTreeMap::new();
+    let log = String::from_utf8_lossy(&output.stdout);
+    for commit in log
+        .split(COMMIT_SEPARATOR)
+        .filter(|commit| !commit.is_empty())
+    {
+        let mut lines = commit.lines();
+        let fields: Vec<&str> = lines
+            .next()
+            .unwrap_or_default()
+            .split(FIELD_SEPARATOR)
+            .collect();
+        let [hash, date, author, message] = fields[..] else {
+            continue;
+        };
+        let commit = Commit {
+            hash: hash.into(),
+            date: date.into(),
+            author: author.into(),
+            message: message.into(),
+        };
+
+        for path in lines.filter(|line| !line.is_empty()) {
+            if !commits.contains_key(path) {
+                commits.insert(path.to_string(), commit.clone());
+            }
+        }
+    }
+
+    Ok(commits)
+}
+
+/// Source of the `areum:git` module exporting `commits`.
+pub fn module(commits: &BTreeMap<String, Commit>) -> String {
+    format!("export default Object.freeze({});", json!(commits))
+}
+
+/// Last commits of the files of the site at `root`, or none if it is not in a
+/// Git repository or Git is not installed.
+pub fn site_commits(root: &Path) -> BTreeMap<String, Commit> {
+    last_commits(root).unwrap_or_else(|err| {
+        tracing::debug!("could not read Git history: {:#}", err);
+        BTreeMap::new()
+    })
+}
@@ -0,0 +1,350 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context};
+use blake2::{digest::consts, Blake2b, Digest};
+use globset::GlobSet;
+use image::{
+    codecs::{
+        avif::AvifEncoder,
+        webp::{WebPEncoder, WebPQuality},
+    },
+    imageops::FilterType,
+    DynamicImage,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Semaphore, task};
+
+use crate::config::ImagesConfig;
+
+/// Directory, relative to the root, processed images are cached in.
+const CACHE_DIR: &str = ".areum/images";
+/// Directory, relative to the output, variants are written to.
+const OUT_DIR: &str = "_areum/images";
+
+/// Optimized variants of the images a build processed, by the route of their
+/// original.
+#[derive(Clone, Default)]
+pub struct ImageManifest {
+    images: Arc<HashMap<String, ProcessedImage>>,
+    /// `sizes` given to images with a `srcset`.
+    pub(crate) sizes: String,
+}
+
+impl ImageManifest {
+    pub fn get(&self, route: &str) -> Option<&ProcessedImage> {
+        self.images.get(route)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ProcessedImage {
+    /// Size of the original.
+    pub width: u32,
+    pub height: u32,
+    /// Variants, narrowest first.
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Variant {
+    /// File name, the same in the cache and the output.
+    pub name: String,
+    pub width: u32,
+    pub format: ImageFormat,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Webp,
+    Avif,
+}
+
+impl ImageFormat {
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+        }
+    }
+}
+
+impl Variant {
+    pub fn href(&self) -> String {
+        format!("/{}/{}", OUT_DIR, self.name)
+    }
+}
+
+impl ProcessedImage {
+    /// `srcset` of the variants in `format`, empty if there are none.
+    pub fn srcset(&self, format: ImageFormat) -> String {
+        self.variants
+            .iter()
+            .filter(|variant| variant.format == format)
+            .map(|variant| format!("{} {}w", variant.href(), variant.width))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Widest WebP variant, used where only one can be referenced, as in CSS.
+    pub fn fallback(&self) -> Option<&Variant> {
+        self.variants
+            .iter()
+            .filter(|variant| variant.format == ImageFormat::Webp)
+            .last()
+    }
+}
+
+/// Resizes and re-encodes the images matching `[images]` globs.
+pub struct ImagePipeline {
+    config: ImagesConfig,
+    globs: GlobSet,
+    cache: PathBuf,
+}
+
+impl ImagePipeline {
+    pub fn new(root: &Path, config: &ImagesConfig) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            config: config.clone(),
+            globs: config.globs()?,
+            cache: root.join(CACHE_DIR),
+        })
+    }
+
+    /// Whether the image at `relative`, relative to the root, is processed.
+    pub fn matches(&self, relative: &Path) -> bool {
+        self.globs.is_match(relative)
+    }
+
+    /// Processes `images`, pairs of source paths and routes, on blocking
+    /// threads, and writes their variants to `outdir`.
+    pub async fn process(
+        &self,
+        images: Vec<(PathBuf, String)>,
+        outdir: &Path,
+    ) -> Result<ImageManifest, anyhow::Error> {
+        let permits = Arc::new(Semaphore::new(self.config.parallelism()));
+        let mut tasks = Vec::new();
+        for (path, route) in images {
+            let permit = permits.clone().acquire_owned().await?;
+            let config = self.config.clone();
+            let (cache, outdir) = (self.cache.clone(), outdir.to_path_buf());
+            tasks.push(task::spawn_blocking(move || {
+                let _permit = permit;
+                let processed = process_image(&path, &config, &cache, &outdir)
+                    .with_context(|| format!("could not process image {}", path.display()))?;
+                Ok::<_, anyhow::Error>((route, processed))
+            }));
+        }
+
+        let mut images = HashMap::new();
+        for task in tasks {
+            let (route, processed) = task.await??;
+            images.insert(route, processed);
+        }
+
+        Ok(ImageManifest {
+            images: Arc::new(images),
+            sizes: self.config.sizes.clone(),
+        })
+    }
+}
+
+/// Processes an image, or reuses its variants from the cache if the image and
+/// settings are unchanged.
+fn process_image(
+    path: &Path,
+    config: &ImagesConfig,
+    cache: &Path,
+    outdir: &Path,
+) -> Result<ProcessedImage, anyhow::Error> {
+    let bytes = fs::read(path)?;
+    let mut hasher = Blake2b::<consts::U16>::new();
+    hasher.update(&bytes);
+    hasher.update(config.cache_key());
+    let key = bs58::encode(hasher.finalize()).into_string();
+
+    let manifest = cache.join(format!("{}.json", key));
+    let cached = fs::read(&manifest)
+        .ok()
+        .and_then(|json| serde_json::from_slice::<ProcessedImage>(&json).ok())
+        .filter(|image| {
+            image
+                .variants
+                .iter()
+                .all(|variant| cache.join(&variant.name).is_file())
+        });
+
+    let processed = match cached {
+        Some(processed) => processed,
+        None => {
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy())
+                .unwrap_or_default();
+            let processed = encode_variants(&bytes, &stem, &key, config, cache)?;
+            fs::write(&manifest, serde_json::to_vec(&processed)?)?;
+            processed
+        }
+    };
+
+    let out = outdir.join(OUT_DIR);
+    fs::create_dir_all(&out)?;
+    for variant in &processed.variants {
+        let dest = out.join(&variant.name);
+        if !dest.exists() {
+            fs::copy(cache.join(&variant.name), dest)?;
+        }
+    }
+
+    Ok(processed)
+}
+
+fn encode_variants(
+    bytes: &[u8],
+    stem: &str,
+    key: &str,
+    config: &ImagesConfig,
+    cache: &Path,
+) -> Result<ProcessedImage, anyhow::Error> {
+    let image = image::load_from_memory(bytes)?;
+    let (width, height) = (image.width(), image.height());
+
+    // Images are never upscaled, so small ones get a single variant at their
+    // own width
+    let mut widths: Vec<_> = config
+        .widths
+        .iter()
+        .copied()
+        .filter(|w| *w < width)
+        .collect();
+    widths.sort_unstable();
+    widths.dedup();
+    if widths.is_empty() {
+        widths.push(width);
+    }
+
+    let mut formats = vec![ImageFormat::Webp];
+    if config.avif {
+        formats.push(ImageFormat::Avif);
+    }
+
+    fs::create_dir_all(cache)?;
+    let mut variants = Vec::new();
+    for format in formats {
+        for &w in &widths {
+            let resized = if w == width {
+                image.clone()
+            } else {
+                image.resize(w, u32::MAX, FilterType::Lanczos3)
+            };
+            let name = format!("{}-{}-{}.{}", stem, key, w, format.extension());
+            let mut file = io::BufWriter::new(fs::File::create(cache.join(&name))?);
+            encode(&resized, format, config.quality, &mut file)?;
+            variants.push(Variant {
+                name,
+                width: w,
+                format,
+            });
+        }
+    }
+
+    Ok(ProcessedImage {
+        width,
+        height,
+        variants,
+    })
+}
+
+fn encode(
+    image: &DynamicImage,
+    format: ImageFormat,
+    quality: u8,
+    w: &mut impl io::Write,
+) -> Result<(), anyhow::Error> {
+    // Encoders only take 8-bit RGB(A)
+    let image = DynamicImage::ImageRgba8(image.to_rgba8());
+    match format {
+        ImageFormat::Webp => image.write_with_encoder(WebPEncoder::new_with_quality(
+            w,
+            WebPQuality::lossy(quality),
+        )),
+        ImageFormat::Avif => {
+            image.write_with_encoder(AvifEncoder::new_with_speed_quality(w, 8, quality))
+        }
+    }
+    .map_err(|err| anyhow!(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageOutputFormat, RgbImage};
+
+    use super::*;
+    use crate::{builder::BuilderOptions, testing::Fixture};
+
+    #[tokio::test]
+    async fn writes_variants_and_srcset_of_large_images() {
+        let photo = RgbImage::from_fn(2000, 1000, |x, y| image::Rgb([x as u8, y as u8, 128]));
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgb8(photo)
+            .write_to(&mut Cursor::new(&mut jpeg), ImageOutputFormat::Jpeg(90))
+            .unwrap();
+
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("areum.toml", "[images]\nglobs = [\"content/**/*.jpg\"]\n")
+            .unwrap()
+            .file("content/photo.jpg", jpeg)
+            .unwrap()
+            .file(
+                "index.tsx",
+                r#"export default () => <img src="/content/photo.jpg" alt="Photo" />;"#,
+            )
+            .unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+
+        let mut variants: Vec<_> = fs::read_dir(fixture.out_dir().join(OUT_DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        // Names end in the variant's width
+        variants.sort_by_key(|name| {
+            let width = name.trim_end_matches(".webp").rsplit('-').next().unwrap();
+            width.parse::<u32>().unwrap()
+        });
+        assert_eq!(variants.len(), 3);
+        for (name, width) in variants.iter().zip([480, 960, 1600]) {
+            assert!(name.starts_with("photo-"));
+            assert!(name.ends_with(&format!("-{}.webp", width)));
+            let variant = image::open(fixture.out_dir().join(OUT_DIR).join(name)).unwrap();
+            assert_eq!((variant.width(), variant.height()), (width, width / 2));
+        }
+
+        let html = fixture.output("index.html").unwrap();
+        let srcset = variants
+            .iter()
+            .zip([480, 960, 1600])
+            .map(|(name, width)| format!("/{}/{} {}w", OUT_DIR, name, width))
+            .collect::<Vec<_>>()
+            .join(", ");
+        assert!(html.contains(&format!(r#"srcset="{}""#, srcset)));
+        assert!(html.contains(r#"sizes="100vw""#));
+        assert!(html.contains(r#"width="2000" height="1000""#));
+        assert!(html.contains(r#"src="/content/photo.jpg""#));
+    }
+}
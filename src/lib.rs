@@ -1,6 +1,41 @@
+mod budget;
 pub mod builder;
+mod collection;
+mod config;
+mod css;
 mod dom;
 mod env;
+mod excerpt;
+mod favicon;
+mod git;
+mod images;
+mod links;
+mod manifest;
+mod markdown;
 pub mod page;
+pub mod profile;
+mod render;
+mod search;
 pub mod server;
 mod src_fs;
+mod taxonomy;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod transform;
+
+pub use config::ConfigError;
+pub use render::{render_source, RenderOptions};
+pub use src_fs::SrcKind;
+
+// Library surface for rendering without the CLI: scan a site with `SrcFs`,
+// render its pages with an `Env`, and bundle their scripts. These are kept
+// stable; other public items may change with the CLI.
+pub use config::Config;
+pub use dom::{
+    arena::{Arena, ArenaElement, ArenaId},
+    boxed::BoxedElement,
+    Children, Props,
+};
+pub use env::{Env, Mode};
+pub use page::Page;
+pub use src_fs::{CaseSensitivity, SrcFile, SrcFs, SrcFsOptions, Translations};
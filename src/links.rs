@@ -0,0 +1,91 @@
+use std::{cell::RefCell, path::Path};
+
+use lol_html::{element, HtmlRewriter, Settings};
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+/// Origin internal links are resolved against, standing in for the site's.
+const BASE: &str = "http://areum.invalid";
+
+/// Paths in the site the `href` and `src` attributes of a page at `route`
+/// link to, decoded and without their query or fragment. Links to other
+/// origins, including `mailto:` and `data:` ones, are left out.
+pub fn internal_links(html: &[u8], route: &str) -> Result<Vec<String>, anyhow::Error> {
+    // Pages are written to `index.html` in a directory named after them
+    let base = Url::parse(BASE)?.join(&format!("{}/", route.trim_end_matches('/')))?;
+    let links = RefCell::new(Vec::new());
+
+    let mut rewriter = HtmlRewriter::new(
+        Settings {
+            element_content_handlers: vec![element!("[href], [src]", |el| {
+                for name in ["href", "src"] {
+                    if let Some(path) = el.get_attribute(name).and_then(|href| target(&base, &href))
+                    {
+                        links.borrow_mut().push(path);
+                    }
+                }
+                Ok(())
+            })],
+            ..Default::default()
+        },
+        |_: &[u8]| {},
+    );
+    rewriter.write(html)?;
+    rewriter.end()?;
+
+    let mut links = links.into_inner();
+    links.sort();
+    links.dedup();
+    Ok(links)
+}
+
+/// Whether an output in `outdir` is served at `path`, as a file or a page.
+pub fn exists(outdir: &Path, path: &str) -> bool {
+    let out = outdir.join(path.trim_start_matches('/'));
+    out.is_file() || out.join("index.html").is_file()
+}
+
+/// Path in the site `href` resolves to, if it is on the site's origin.
+fn target(base: &Url, href: &str) -> Option<String> {
+    let url = base.join(href.trim()).ok()?;
+    if url.origin() != base.origin() {
+        return None;
+    }
+    let path = percent_decode_str(url.path()).decode_utf8().ok()?;
+    Some(path.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_internal_links() {
+        let html = br##"<a href="/about">About</a><a href="../tags/rust?page=2#top">Rust</a>
+            <a href="https://example.com/">Example</a><a href="mailto:me@example.com">Mail</a>
+            <a href="#comments">Comments</a><img src="cat%20photo.jpg">"##;
+        let links = internal_links(html, "/blog/post").unwrap();
+        assert_eq!(
+            links,
+            [
+                "/about",
+                "/blog/post/",
+                "/blog/post/cat photo.jpg",
+                "/blog/tags/rust"
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_outputs_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("about")).unwrap();
+        std::fs::write(dir.path().join("about/index.html"), "").unwrap();
+        std::fs::write(dir.path().join("logo.svg"), "").unwrap();
+
+        assert!(exists(dir.path(), "/about"));
+        assert!(exists(dir.path(), "/about/"));
+        assert!(exists(dir.path(), "/logo.svg"));
+        assert!(!exists(dir.path(), "/contact"));
+    }
+}
@@ -1,16 +1,47 @@
-use std::path::PathBuf;
+use std::{
+    cell::Cell,
+    fmt,
+    path::{Path, PathBuf},
+    process::{self, ExitCode},
+    rc::Rc,
+    time::Instant,
+};
 
 use anyhow::anyhow;
 use areum::{
-    builder::Builder,
-    server::{Command, Server},
+    builder::{BuildEvent, BuildReport, Builder, BuilderOptions},
+    profile::Profiler,
+    server::{Command, Server, ServerOptions},
+    Config, ConfigError,
+};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use tokio::{signal, sync::broadcast};
+use tracing_subscriber::{
+    fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
 };
-use clap::{Parser, Subcommand};
-use notify::{event::ModifyKind, Event, EventKind, RecursiveMode, Watcher};
-use tokio::signal;
+
+/// Exit code for an invalid `areum.toml`.
+const EXIT_CONFIG: u8 = 3;
+/// Exit code for a failed build.
+const EXIT_BUILD: u8 = 4;
+/// Exit code for a build with broken links, with `--check-links`.
+const EXIT_LINKS: u8 = 5;
+/// Exit code when interrupted twice, without waiting for shutdown.
+const EXIT_INTERRUPTED: i32 = 130;
+/// Environment variable overriding the log filter set by `-v` and `-q`.
+const LOG_ENV: &str = "AREUM_LOG";
 
 #[derive(Parser)]
 struct Cli {
+    /// Only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Log more detail, -vv for module loads and timings
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+    /// Format of the logs printed to stderr
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
     #[command(subcommand)]
     command: Commands,
 }
@@ -18,55 +49,231 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Build {
-        #[arg(short, long, default_value = "dist")]
-        out: PathBuf,
+        /// Output directory, relative to the input unless absolute. Defaults
+        /// to `build.out_dir` in the config, or `dist`
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+        /// Print what would be built without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Print the build report as JSON
+        #[arg(long)]
+        json: bool,
+        /// Render components with invalid styles unstyled instead of failing
+        #[arg(long)]
+        lenient_css: bool,
+        /// Write a search index of every page to search-index.json
+        #[arg(long)]
+        search_index: bool,
+        /// Fail if pages link to paths in the site nothing is written to
+        #[arg(long)]
+        check_links: bool,
+        /// Skip pages that fail to render and report them once the build is
+        /// done, still exiting with an error
+        #[arg(long)]
+        continue_on_error: bool,
+        /// Include pages with a `date` in the future in their frontmatter
+        #[arg(long)]
+        include_future: bool,
+        /// Seed `randString` with this instead of `build.seed` in the config
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Fail the build if outputs are over the sizes in `[budgets]`,
+        /// rather than warning about them
+        #[arg(long)]
+        strict_budgets: bool,
+        /// Record remote modules that changed since they were locked in
+        /// areum.lock instead of failing, and drop ones no longer imported
+        #[arg(long)]
+        update_lock: bool,
+        /// Print build events to stdout as newline-delimited JSON, described
+        /// by schemas/build-messages.schema.json, and the report to stderr
+        #[arg(long, value_enum, default_value_t = MessageFormat::Human)]
+        message_format: MessageFormat,
+        /// Print how long each phase of the build took, in total and for the
+        /// slowest pages
+        #[arg(long)]
+        profile: bool,
+        /// Also write the profile as a Chrome trace, for Perfetto
+        #[arg(long, value_name = "PATH")]
+        profile_out: Option<PathBuf>,
+        /// Keep running after the build, rebuilding the pages a change
+        /// affects, until interrupted
+        #[arg(long, conflicts_with_all = ["dry_run", "json", "profile", "profile_out", "strict_budgets"])]
+        watch: bool,
         input: Option<PathBuf>,
     },
     Serve {
         #[arg(short, long, default_value = "0.0.0.0:8000")]
         address: String,
+        /// Require HTTP basic auth with these credentials
+        #[arg(long, value_name = "USER:PASSWORD")]
+        auth: Option<String>,
+        /// Require this token, as a bearer token or a `token` query parameter
+        #[arg(long, value_name = "TOKEN")]
+        auth_token: Option<String>,
         input: Option<PathBuf>,
     },
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum MessageFormat {
+    Human,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
 #[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
+    let building = matches!(cli.command, Commands::Build { .. });
+    let profiler = init_logging(&cli);
+
+    match run(cli, profiler).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            tracing::error!("{:?}", err);
+            if err.downcast_ref::<ConfigError>().is_some() {
+                ExitCode::from(EXIT_CONFIG)
+            } else if err.downcast_ref::<BrokenLinks>().is_some() {
+                ExitCode::from(EXIT_LINKS)
+            } else if building {
+                ExitCode::from(EXIT_BUILD)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
+
+/// Logs to stderr, leaving stdout to reports. Returns the profiler if the
+/// build is profiled.
+fn init_logging(cli: &Cli) -> Option<Profiler> {
+    let filter = EnvFilter::try_from_env(LOG_ENV)
+        .unwrap_or_else(|_| EnvFilter::new(log_filter(cli.quiet, cli.verbose)));
+
+    // Closing spans logs how long page renders and module loads took
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr);
+    let fmt = match cli.log_format {
+        LogFormat::Text => fmt.boxed(),
+        LogFormat::Json => fmt.json().boxed(),
+    };
+
+    // Without profiling, spans below the log level are never created
+    let profiler = match &cli.command {
+        Commands::Build {
+            profile,
+            profile_out,
+            ..
+        } if *profile || profile_out.is_some() => Some(Profiler::default()),
+        _ => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(fmt.with_filter(filter))
+        .with(
+            profiler
+                .clone()
+                .map(|profiler| profiler.with_filter(Profiler::targets())),
+        )
+        .init();
+    profiler
+}
+
+/// Log filter set by `-q` and the number of `-v`s, unless overridden by
+/// [`LOG_ENV`].
+fn log_filter(quiet: bool, verbose: u8) -> &'static str {
+    match (quiet, verbose) {
+        (true, _) => "error",
+        (false, 0) => "warn,areum=info,dongjak=info",
+        (false, 1) => "warn,areum=debug,dongjak=debug",
+        (false, _) => "info,areum=trace,dongjak=trace",
+    }
+}
+
+async fn run(cli: Cli, profiler: Option<Profiler>) -> Result<(), anyhow::Error> {
     match cli.command {
-        Commands::Build { out, input } => {
+        Commands::Build {
+            out,
+            dry_run,
+            json,
+            lenient_css,
+            search_index,
+            check_links,
+            continue_on_error,
+            include_future,
+            seed,
+            strict_budgets,
+            update_lock,
+            message_format,
+            profile_out,
+            watch,
+            input,
+            ..
+        } => {
             let root = input.unwrap_or(std::env::current_dir()?);
-            let mut site = Builder::new(&root).await?;
-            site.build(&out).await?;
+            let out = Config::load(&root)?.build.out_dir(&root, out.as_deref());
+            let options = BuilderOptions {
+                lenient_css,
+                search_index,
+                check_links,
+                continue_on_error,
+                include_future,
+                seed,
+                update_lock,
+            };
+            if watch {
+                if message_format == MessageFormat::Json {
+                    return Err(anyhow!("--watch does not support --message-format json"));
+                }
+                return build_and_watch(&root, &out, options, cli.quiet).await;
+            }
+            if message_format == MessageFormat::Json && !dry_run {
+                let report = build_with_messages(&root, &out, options, cli.quiet).await?;
+                finish_profile(profiler, profile_out.as_deref())?;
+                check_failures(&report)?;
+                check_budgets(&report, strict_budgets)?;
+                return check_links_found(&report);
+            }
+
+            let mut site = Builder::new(&root, options).await?;
+            if dry_run {
+                let plan = site.plan(&out).await?;
+                if !cli.quiet {
+                    print!("{}", plan);
+                }
+            } else {
+                let report = site.build(&out).await?;
+                if !cli.quiet {
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&report)?);
+                    } else {
+                        print!("{}", report);
+                    }
+                }
+                finish_profile(profiler, profile_out.as_deref())?;
+                check_failures(&report)?;
+                check_budgets(&report, strict_budgets)?;
+                check_links_found(&report)?;
+            }
         }
-        Commands::Serve { address, input } => {
+        Commands::Serve {
+            address,
+            auth,
+            auth_token,
+            input,
+        } => {
             let root = input.unwrap_or(std::env::current_dir()?);
-            let (server, tx) = Server::new(&root)?;
-
-            let tx_ = tx.clone();
-            let mut watcher =
-                notify::recommended_watcher(move |res: Result<Event, notify::Error>| match res {
-                    Ok(event) => match event.kind {
-                        EventKind::Create(_)
-                        | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_))
-                        | EventKind::Remove(_) => {
-                            tx_.send(Command::Restart).or(Err("")).unwrap();
-                        }
-                        _ => {}
-                    },
-                    Err(e) => println!("watch error: {:?}", e),
-                })?;
-            watcher.watch(&root, RecursiveMode::Recursive)?;
-
-            tokio::spawn(async move {
-                signal::ctrl_c()
-                    .await
-                    .map_err(|err| anyhow!(err))
-                    .and_then(|_| {
-                        tx.send(Command::Stop)
-                            .unwrap_or_else(|_| panic!("error sending to channel"));
-                        Ok(())
-                    })
-            });
+            let (server, tx) = Server::new(&root, ServerOptions { auth, auth_token })?;
+
+            tokio::spawn(handle_interrupts(tx));
 
             server.serve(&address).await?;
         }
@@ -74,3 +281,212 @@ async fn main() -> Result<(), anyhow::Error> {
 
     Ok(())
 }
+
+/// Stops the server gracefully on the first Ctrl-C, and exits at once on the
+/// second in case shutdown is stuck.
+async fn handle_interrupts(tx: broadcast::Sender<Command>) -> Result<(), anyhow::Error> {
+    signal::ctrl_c().await?;
+    tracing::info!("shutting down, press Ctrl-C again to exit now");
+    // Nothing is listening if the server already stopped on its own
+    if tx.send(Command::Stop).is_err() {
+        tracing::debug!("server already stopped");
+    }
+    signal::ctrl_c().await?;
+    tracing::warn!("exiting without waiting for shutdown");
+    process::exit(EXIT_INTERRUPTED)
+}
+
+/// Builds, printing events to stdout as JSON lines and the report to stderr.
+/// A summary is printed even if the build fails.
+async fn build_with_messages(
+    root: &Path,
+    out: &Path,
+    options: BuilderOptions,
+    quiet: bool,
+) -> Result<BuildReport, anyhow::Error> {
+    let start = Instant::now();
+    let pages = Rc::new(Cell::new(0));
+
+    let pages_ = pages.clone();
+    let result = async {
+        let mut site = Builder::new(root, options)
+            .await?
+            .with_events(move |event| {
+                if let BuildEvent::PageBuilt { .. } = event {
+                    pages_.set(pages_.get() + 1);
+                }
+                print_event(event);
+            });
+        site.build(out).await
+    }
+    .await;
+
+    let (assets, errors) = match &result {
+        Ok(report) => (report.assets, report.failures.len()),
+        Err(err) => {
+            print_event(&BuildEvent::error(err));
+            (0, 1)
+        }
+    };
+    print_event(&BuildEvent::Summary {
+        pages: pages.get(),
+        assets,
+        errors,
+        total_ms: start.elapsed().as_millis() as u64,
+    });
+
+    let report = result?;
+    if !quiet {
+        eprint!("{}", report);
+    }
+    Ok(report)
+}
+
+/// Fails a build that skipped pages with `--continue-on-error`.
+fn check_failures(report: &BuildReport) -> Result<(), anyhow::Error> {
+    match report.failures.len() {
+        0 => Ok(()),
+        1 => Err(anyhow!("1 page failed to build")),
+        n => Err(anyhow!("{} pages failed to build", n)),
+    }
+}
+
+/// Fails a `--strict-budgets` build with outputs over budget.
+fn check_budgets(report: &BuildReport, strict: bool) -> Result<(), anyhow::Error> {
+    match report.over_budget.len() {
+        0 => Ok(()),
+        _ if !strict => Ok(()),
+        1 => Err(anyhow!("1 output is over budget")),
+        n => Err(anyhow!("{} outputs are over budget", n)),
+    }
+}
+
+/// Broken links found by a `--check-links` build.
+#[derive(Debug)]
+struct BrokenLinks(usize);
+
+impl fmt::Display for BrokenLinks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            1 => write!(f, "1 link is broken"),
+            n => write!(f, "{} links are broken", n),
+        }
+    }
+}
+
+impl std::error::Error for BrokenLinks {}
+
+/// Fails a `--check-links` build with broken links.
+fn check_links_found(report: &BuildReport) -> Result<(), anyhow::Error> {
+    match report.broken_links.len() {
+        0 => Ok(()),
+        n => Err(BrokenLinks(n).into()),
+    }
+}
+
+/// Builds, then rebuilds on every change until interrupted. Failed builds are
+/// logged without exiting, leaving the previous output in place.
+async fn build_and_watch(
+    root: &Path,
+    out: &Path,
+    options: BuilderOptions,
+    quiet: bool,
+) -> Result<(), anyhow::Error> {
+    let mut site = Builder::new(root, options).await?;
+    match site.build(out).await {
+        Ok(report) if !quiet => print!("{}", report),
+        Ok(_) => {}
+        Err(err) => tracing::error!("{:?}", err),
+    }
+    tracing::info!("watching {} for changes", root.display());
+
+    let watch = site.watch(out, |result, elapsed| match result {
+        Ok(report) if !quiet => println!("{} in {:.0?}", report, elapsed),
+        Ok(_) => {}
+        Err(err) => tracing::error!("{:?}", err),
+    });
+    tokio::select! {
+        watched = watch => watched,
+        _ = signal::ctrl_c() => Ok(()),
+    }
+}
+
+/// Prints the profile of a build to stderr and writes its trace, if profiled.
+fn finish_profile(profiler: Option<Profiler>, out: Option<&Path>) -> Result<(), anyhow::Error> {
+    let Some(profiler) = profiler else {
+        return Ok(());
+    };
+
+    let profile = profiler.finish();
+    eprint!("{}", profile);
+    if let Some(out) = out {
+        profile.write_trace(out)?;
+    }
+    Ok(())
+}
+
+fn print_event(event: &BuildEvent) {
+    println!("{}", serde_json::to_string(event).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn verbosity_sets_the_log_filter() {
+        let filter = |args: &[&str]| {
+            let cli = Cli::try_parse_from(args).unwrap();
+            log_filter(cli.quiet, cli.verbose)
+        };
+        assert_eq!(filter(&["areum", "build"]), "warn,areum=info,dongjak=info");
+        // Global, so also taken after the subcommand
+        assert_eq!(
+            filter(&["areum", "build", "-v"]),
+            "warn,areum=debug,dongjak=debug"
+        );
+        assert_eq!(
+            filter(&["areum", "-vv", "serve"]),
+            "info,areum=trace,dongjak=trace"
+        );
+        assert_eq!(filter(&["areum", "serve", "-q"]), "error");
+
+        let err = Cli::try_parse_from(["areum", "-q", "build", "-v"])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        // Every filter parses
+        for verbose in 0..3 {
+            EnvFilter::try_new(log_filter(false, verbose)).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_builds_over_budget_only_when_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let write = |path: &str, contents: &str| fs::write(root.join(path), contents).unwrap();
+        write("index.tsx", "export default () => <p>Home</p>");
+        write("about.tsx", "export default () => <p>About</p>");
+        let build = |budget: &str| {
+            write(
+                "areum.toml",
+                &format!("[budgets]\npage_html = \"{budget}\"\n"),
+            );
+            async move {
+                let mut site = Builder::new(root, BuilderOptions::default()).await?;
+                site.build(&root.join("dist")).await
+            }
+        };
+
+        let report = build("1B").await.unwrap();
+        check_budgets(&report, false).unwrap();
+        let err = check_budgets(&report, true).unwrap_err();
+        assert_eq!(err.to_string(), "2 outputs are over budget");
+
+        let report = build("1MB").await.unwrap();
+        check_budgets(&report, true).unwrap();
+    }
+}
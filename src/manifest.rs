@@ -0,0 +1,144 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use blake2::{digest::consts, Blake2b, Digest};
+use serde::{Deserialize, Serialize};
+
+/// File, relative to the root, the manifest of the last build is kept in.
+const MANIFEST_FILE: &str = ".areum/manifest.json";
+
+/// Sources each output of a build was made from, with a hash of their
+/// contents, so later builds know which outputs are out of date.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    /// Output directory of the build, as canonicalized.
+    outdir: PathBuf,
+    /// Outputs by their path in the output directory.
+    outputs: BTreeMap<PathBuf, Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Paths relative to the root.
+    sources: Vec<PathBuf>,
+    hash: String,
+}
+
+impl Manifest {
+    /// Empty manifest of a build into `outdir`.
+    pub fn new(outdir: &Path) -> Self {
+        Manifest {
+            outdir: fs::canonicalize(outdir).unwrap_or_else(|_| outdir.to_path_buf()),
+            outputs: BTreeMap::new(),
+        }
+    }
+
+    /// Manifest of the last build of the site at `root`, or an empty one if
+    /// it had none or built into another directory.
+    pub fn load(root: &Path, outdir: &Path) -> Self {
+        let empty = Self::new(outdir);
+        let manifest = fs::read(root.join(MANIFEST_FILE))
+            .ok()
+            .and_then(|manifest| serde_json::from_slice::<Self>(&manifest).ok());
+        match manifest {
+            Some(manifest) if manifest.outdir == empty.outdir => manifest,
+            _ => empty,
+        }
+    }
+
+    pub fn save(&self, root: &Path) -> Result<(), anyhow::Error> {
+        let path = root.join(MANIFEST_FILE);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Records that the output written to `out` was made from `sources`,
+    /// which are hashed as they are now.
+    pub fn insert(&mut self, root: &Path, out: &Path, sources: impl IntoIterator<Item = PathBuf>) {
+        let Some(out) = self.relative(out) else {
+            return;
+        };
+        let mut sources: Vec<PathBuf> = sources
+            .into_iter()
+            .map(|src| {
+                src.strip_prefix(root)
+                    .map_or(src.clone(), Path::to_path_buf)
+            })
+            .collect();
+        sources.sort();
+        sources.dedup();
+        let Some(hash) = hash_sources(root, &sources) else {
+            return;
+        };
+        self.outputs.insert(out, Entry { sources, hash });
+    }
+
+    /// Whether the output at `out` exists and none of the sources it was
+    /// made from have changed since.
+    pub fn is_fresh(&self, root: &Path, out: &Path) -> bool {
+        let entry = self.relative(out).and_then(|out| self.outputs.get(&out));
+        entry.map_or(false, |entry| {
+            hash_sources(root, &entry.sources).as_ref() == Some(&entry.hash)
+        })
+    }
+
+    /// Path of an existing output in the output directory.
+    fn relative(&self, out: &Path) -> Option<PathBuf> {
+        let out = fs::canonicalize(out).ok()?;
+        Some(out.strip_prefix(&self.outdir).ok()?.to_path_buf())
+    }
+}
+
+/// Hash of the paths and contents of `sources`, or `None` if one cannot be
+/// read.
+fn hash_sources(root: &Path, sources: &[PathBuf]) -> Option<String> {
+    let mut hasher = Blake2b::<consts::U16>::new();
+    for src in sources {
+        hasher.update(src.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        let contents = fs::read(root.join(src)).ok()?;
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(contents);
+    }
+    Some(bs58::encode(hasher.finalize()).into_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_sources_make_outputs_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let outdir = root.join("dist");
+        fs::create_dir_all(&outdir).unwrap();
+        fs::write(root.join("page.tsx"), "page").unwrap();
+        fs::write(root.join("_layout.tsx"), "layout").unwrap();
+        let out = outdir.join("page/index.html");
+        let sources = || [root.join("page.tsx"), root.join("_layout.tsx")];
+
+        let mut manifest = Manifest::new(&outdir);
+        // Outputs that were never written are not recorded
+        manifest.insert(&root, &out, sources());
+        assert!(!manifest.is_fresh(&root, &out));
+
+        fs::create_dir_all(out.parent().unwrap()).unwrap();
+        fs::write(&out, "<p>page</p>").unwrap();
+        manifest.insert(&root, &out, sources());
+        assert!(manifest.is_fresh(&root, &out));
+
+        manifest.save(&root).unwrap();
+        let loaded = Manifest::load(&root, &outdir);
+        assert!(loaded.is_fresh(&root, &out));
+        assert!(Manifest::load(&root, &root).outputs.is_empty());
+
+        fs::write(root.join("_layout.tsx"), "changed").unwrap();
+        assert!(!loaded.is_fresh(&root, &out));
+        assert!(!loaded.is_fresh(&root, &outdir.join("other/index.html")));
+    }
+}
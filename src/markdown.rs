@@ -0,0 +1,50 @@
+use pulldown_cmark::{html, Options, Parser};
+
+/// Compiles plain Markdown into a page module rendering it as HTML.
+///
+/// Unlike MDX, raw HTML and braces are kept as they are. Frontmatter is
+/// skipped, as it is for MDX.
+pub fn md_module(code: &str, jsx_import_source: &str) -> Result<String, anyhow::Error> {
+    let parser = Parser::new_ext(
+        strip_frontmatter(code),
+        Options::ENABLE_TABLES
+            | Options::ENABLE_FOOTNOTES
+            | Options::ENABLE_STRIKETHROUGH
+            | Options::ENABLE_TASKLISTS,
+    );
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, parser);
+
+    // Text children are output without escaping
+    Ok(format!(
+        r#"import {{ Fragment, jsx }} from "{}/jsx-runtime";
+export default () => jsx(Fragment, {{ children: {} }});
+"#,
+        jsx_import_source,
+        serde_json::to_string(&rendered)?
+    ))
+}
+
+/// Skips a leading `---` delimited YAML block.
+pub fn strip_frontmatter(code: &str) -> &str {
+    split_frontmatter(code).map_or(code, |(_, rest)| rest)
+}
+
+/// The leading `---` delimited YAML block of Markdown or MDX, if any.
+pub fn frontmatter(code: &str) -> Option<&str> {
+    split_frontmatter(code).map(|(frontmatter, _)| frontmatter)
+}
+
+fn split_frontmatter(code: &str) -> Option<(&str, &str)> {
+    let rest = code.strip_prefix("---\n")?;
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+
+    None
+}
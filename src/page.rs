@@ -1,20 +1,30 @@
-use std::{collections::HashSet, convert::Infallible, io, path::PathBuf};
-
-use anyhow::anyhow;
-
-use lightningcss::{
-    css_modules,
-    selector::{Component, PseudoClass, Selector},
-    stylesheet::{ParserFlags, ParserOptions, PrinterOptions, StyleSheet},
-    visitor::Visit,
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
 };
-use lol_html::{element, html_content::ContentType, text, HtmlRewriter};
+
+use lol_html::{element, html_content::ContentType, HtmlRewriter};
+use rayon::prelude::*;
 use serde::Serialize;
 use url::Url;
 
-use crate::dom::{
-    arena::{Arena, ArenaElement, ArenaId},
-    Children,
+use crate::{
+    config::{LinksConfig, ScriptConfig, ScriptPosition},
+    css::{
+        inline_imports, scope_class, scope_css, AssetBase, CssCache, CssOptions, Hoisted,
+        ScopedStyle,
+    },
+    dom::{
+        arena::{Arena, ArenaElement, ArenaId},
+        Children, JsonProps, PropSerializer, Serialization,
+    },
+    favicon::Favicons,
+    images::ImageManifest,
+    src_fs::{encode_route, to_slash},
+    transform::{
+        ElementHandler, ExternalLinks, Katex, PageTransform, ResponsiveImages, TransformCtx,
+    },
 };
 
 pub struct Page {
@@ -23,23 +33,352 @@ pub struct Page {
     pub(crate) arena: Arena,
     pub(crate) dom: ArenaId,
     pub(crate) style: String,
+    /// Parts `style` is made of, inlined separately in dev mode so each keeps
+    /// its source map.
+    style_blocks: Vec<String>,
+    /// Scoped CSS of each named component on the page, by its name.
+    component_styles: Vec<(String, String)>,
+    pub(crate) global_style: String,
+    pub(crate) css_options: CssOptions,
+    pub(crate) css_cache: CssCache,
+    pub(crate) links: LinksConfig,
+    /// Optimized images, which `<img>` tags and style URLs are pointed to.
+    pub(crate) images: ImageManifest,
+    /// Tags of the generated icons, injected unless the page has its own.
+    pub(crate) favicons: Favicons,
+    pub(crate) json_props: JsonProps,
+    /// Scripts holding JSON props too large for attributes, set as the page
+    /// is serialized.
+    json_scripts: String,
+    pub(crate) root: PathBuf,
     pub(crate) scopes: HashSet<String>,
+    pub(crate) classes: HashMap<String, String>,
     pub(crate) script: String,
+    pub(crate) script_config: ScriptConfig,
+    /// HTML of the `<noscript>` added to pages with a script.
+    pub(crate) noscript: Option<String>,
+    pub(crate) serialization: Serialization,
+    /// Doctype the page starts with, if any.
+    pub(crate) doctype: String,
+    /// URL of the file holding `script`, loaded in place of inlining it.
+    script_src: Option<String>,
     pub(crate) id: String,
-    pub(crate) props: PageProps
+    pub(crate) props: PageProps,
+    /// Files the page's module exports as `outputs`, by site path.
+    pub(crate) outputs: Vec<(PathBuf, String)>,
+    processed: bool,
+    stylesheet: Option<Stylesheet>,
+    global_stylesheets: Vec<String>,
+}
+
+/// External stylesheet linked in place of inlining the page's styles.
+struct Stylesheet {
+    href: String,
+    critical: Option<String>,
+}
+
+/// Injects the page's styles and script.
+struct Inject;
+
+impl PageTransform for Inject {
+    fn element_handlers<'a>(
+        &'a self,
+        page: &'a Page,
+        _ctx: &'a TransformCtx,
+    ) -> Vec<ElementHandler<'a>> {
+        vec![
+            element!("html", |el| {
+                if let Some(locale) = &page.props.locale {
+                    el.set_attribute("lang", locale)?;
+                }
+                Ok(())
+            }),
+            element!("body", |el| {
+                // Static pages work without JavaScript as they are
+                let noscript = page.noscript.as_ref().filter(|_| !page.script.is_empty());
+                if let Some(noscript) = noscript {
+                    el.prepend(
+                        &format!("<noscript>{}</noscript>", noscript),
+                        ContentType::Html,
+                    );
+                }
+                el.append(&page.json_scripts, ContentType::Html);
+                if page.script_config.position == ScriptPosition::BodyEnd {
+                    el.append(&script_tag(page), ContentType::Html);
+                }
+                Ok(())
+            }),
+            element!("head", |el| {
+                let end = page.serialization.void_end();
+                let mut tag = page.favicons.tags();
+                tag += &alternate_links(&page.props, end);
+                tag += &page
+                    .global_stylesheets
+                    .iter()
+                    .map(|href| format!(r#"<link rel="stylesheet" href="{}"{}"#, href, end))
+                    .collect::<String>();
+                tag += &match &page.stylesheet {
+                    Some(Stylesheet { href, critical }) => {
+                        let critical = match critical {
+                            Some(css) => format!("<style>{}</style>", css),
+                            None => String::new(),
+                        };
+                        format!(
+                            r#"{}<link rel="stylesheet" href="{}"{}"#,
+                            critical, href, end
+                        )
+                    }
+                    None if page.css_options.dev => page
+                        .style_blocks
+                        .iter()
+                        .map(|block| format!("<style>{}</style>", block))
+                        .collect(),
+                    None => format!("<style>{}</style>", page.style),
+                };
+                if page.script_config.position == ScriptPosition::Head {
+                    tag += &script_tag(page);
+                }
+                el.append(&tag, ContentType::Html);
+                Ok(())
+            }),
+        ]
+    }
+}
+
+/// The page's script, inlined or loaded from its file, or nothing if it has
+/// none.
+fn script_tag(page: &Page) -> String {
+    if page.script.is_empty() {
+        return String::new();
+    }
+
+    let config = page.script_config;
+    let flag = |name: &str| match page.serialization {
+        Serialization::Html => format!(" {}", name),
+        Serialization::Xhtml => format!(r#" {}="{}""#, name, name),
+    };
+    let mut attrs = String::from(r#" type="module""#);
+    if config.async_ {
+        attrs += &flag("async");
+    }
+    match &page.script_src {
+        Some(src) => {
+            if config.defer {
+                attrs += &flag("defer");
+            }
+            format!(r#"<script{} src="{}"></script>"#, attrs, src)
+        }
+        None => format!("<script{}>{}</script>", attrs, page.script),
+    }
+}
+
+/// `hreflang` links to a page's translations, if it has any besides itself.
+/// `end` closes each link, as [`Serialization::void_end`] gives.
+fn alternate_links(props: &PageProps, end: &str) -> String {
+    if props.alternates.len() < 2 {
+        return String::new();
+    }
+
+    let link = |locale: &str, href: &str| {
+        format!(
+            r#"<link rel="alternate" hreflang="{}" href="{}"{}"#,
+            locale, href, end
+        )
+    };
+    let mut links: String = props
+        .alternates
+        .iter()
+        .map(|(locale, href)| link(locale, href))
+        .collect();
+    let default = props.default_locale.as_ref();
+    if let Some(href) = default.and_then(|locale| props.alternates.get(locale)) {
+        links += &link("x-default", href);
+    }
+    links
+}
+
+/// Component style collected from the page, before it is processed.
+enum PendingStyle {
+    Scoped {
+        scope: String,
+        style: String,
+        source: Option<String>,
+        name: Option<String>,
+    },
+    /// Already scoped by its class names, so only deduplicated
+    Module(String),
 }
 
 #[derive(Serialize)]
 pub struct PageProps {
+    /// Site path, such as `blog/post`, empty for the root index.
     pub path: String,
+    /// Absolute URL of the page, if `links.host` is set.
+    pub url: Option<String>,
+    /// Route of the page from the root, such as `/blog/post`.
+    pub route: String,
+    /// Segments of the site path, such as `["blog", "post"]`, for
+    /// breadcrumbs.
+    pub segments: Vec<String>,
+    /// Whether the page is the root index.
+    pub is_index: bool,
+    /// When the page's source last changed, if it has one.
+    pub last_modified: Option<LastModified>,
     pub generator: String,
+    pub locale: Option<String>,
+    /// Locale to fall back to for pages without a translation.
+    pub default_locale: Option<String>,
+    /// Routes of the page's translations by locale, for language switchers.
+    pub alternates: BTreeMap<String, String>,
+    /// Summary of the page, from its frontmatter `description` or the content
+    /// above `<!--more-->`. Otherwise taken from its text once rendered, so
+    /// only available to the page itself in the first two cases.
+    pub excerpt: Option<String>,
+    /// Query parameters of the request the dev server renders the page for.
+    /// Always empty in builds, which render each page once for every query.
+    pub query: BTreeMap<String, String>,
+}
+
+impl PageProps {
+    /// Props of the page at the site path `path`, served on `host` if set,
+    /// without a locale, excerpt or query.
+    pub(crate) fn new(path: &Path, host: Option<&str>) -> Self {
+        let route = encode_route(path);
+        let segments: Vec<String> = path
+            .iter()
+            .map(|segment| segment.to_string_lossy().into_owned())
+            .collect();
+        PageProps {
+            path: to_slash(path),
+            url: host.map(|host| format!("https://{}{}", host, route)),
+            route,
+            is_index: segments.is_empty(),
+            segments,
+            generator: format!("Areum {}", env!("CARGO_PKG_VERSION")),
+            locale: None,
+            default_locale: None,
+            alternates: BTreeMap::new(),
+            excerpt: None,
+            query: BTreeMap::new(),
+            last_modified: None,
+        }
+    }
+}
+
+/// Last change to a page's source, from its last commit if it has one, or else
+/// its modification time.
+#[derive(Serialize, Clone)]
+pub struct LastModified {
+    /// Date of the change, in RFC 3339.
+    pub date: String,
+    /// Author of the commit, unknown for modification times.
+    pub author: Option<String>,
+    /// Hash of the commit, unknown for modification times.
+    pub hash: Option<String>,
 }
 
 impl Page {
+    pub(crate) fn new(
+        path: PathBuf,
+        url: Url,
+        arena: Arena,
+        dom: ArenaId,
+        script: String,
+        id: String,
+        props: PageProps,
+    ) -> Self {
+        Page {
+            path,
+            url,
+            arena,
+            dom,
+            style: String::new(),
+            style_blocks: Vec::new(),
+            component_styles: Vec::new(),
+            global_style: String::new(),
+            css_options: CssOptions::default(),
+            css_cache: CssCache::default(),
+            links: LinksConfig::default(),
+            images: ImageManifest::default(),
+            favicons: Favicons::default(),
+            json_props: JsonProps::default(),
+            json_scripts: String::new(),
+            root: PathBuf::new(),
+            scopes: HashSet::new(),
+            classes: HashMap::new(),
+            script,
+            script_config: ScriptConfig::default(),
+            noscript: None,
+            serialization: Serialization::default(),
+            doctype: "<!DOCTYPE html>".into(),
+            script_src: None,
+            id,
+            props,
+            outputs: Vec::new(),
+            processed: false,
+            stylesheet: None,
+            global_stylesheets: Vec::new(),
+        }
+    }
+
     pub fn id(&self) -> String {
         self.id.clone()
     }
 
+    /// Global styles followed by the scoped CSS of every component on the page,
+    /// as inlined by default.
+    pub fn styles(&mut self) -> Result<&str, anyhow::Error> {
+        self.process()?;
+        Ok(&self.style)
+    }
+
+    /// Scoped CSS of the components named in `components` that are on the
+    /// page, such as to inline as `critical` in [`Page::link_styles`]. CSS
+    /// shared by several of them is included once.
+    pub fn component_styles(&mut self, components: &[String]) -> Result<String, anyhow::Error> {
+        self.process()?;
+        let mut styles: Vec<&str> = Vec::new();
+        for (name, css) in &self.component_styles {
+            if components.contains(name) && !styles.contains(&css.as_str()) {
+                styles.push(css);
+            }
+        }
+        Ok(styles.concat())
+    }
+
+    /// Links the page's styles from `href` instead of inlining them.
+    ///
+    /// `critical` is still inlined, so above-the-fold rules can render before
+    /// the stylesheet loads. `href` should serve the output of [`Page::styles`].
+    pub fn link_styles(&mut self, href: String, critical: Option<String>) {
+        self.stylesheet = Some(Stylesheet { href, critical });
+    }
+
+    /// The page's client script, inlined at the end of `<body>` by default.
+    pub fn script(&self) -> &str {
+        &self.script
+    }
+
+    /// Loads the page's script from `src` instead of inlining it. `src` should
+    /// serve the output of [`Page::script`].
+    pub fn link_script(&mut self, src: String) {
+        self.script_src = Some(src);
+    }
+
+    /// Links stylesheets shared by every page, ahead of the page's own styles
+    /// so component styles take precedence.
+    pub fn link_global_styles(&mut self, hrefs: Vec<String>) {
+        self.global_stylesheets = hrefs;
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
     pub fn render_to_string(&mut self) -> Result<String, anyhow::Error> {
         let mut output = Vec::new();
         self.render(&mut output)?;
@@ -47,50 +386,48 @@ impl Page {
     }
 
     pub fn render(&mut self, writer: &mut impl io::Write) -> Result<(), anyhow::Error> {
+        self.render_with(writer, &[])
+    }
+
+    /// Renders the page, running `transforms` as documented on
+    /// [`PageTransform`].
+    pub fn render_with(
+        &mut self,
+        writer: &mut impl io::Write,
+        transforms: &[Box<dyn PageTransform>],
+    ) -> Result<(), anyhow::Error> {
         self.process()?;
+        let _span = tracing::debug_span!("rewrite").entered();
 
-        let mut html = self.arena[self.dom].to_string(&self.arena);
+        let ctx = TransformCtx {
+            root: self.root.clone(),
+            dev: self.css_options.dev,
+        };
+        for transform in transforms {
+            transform.transform(self, &ctx)?;
+        }
+        let mut favicons = std::mem::take(&mut self.favicons);
+        favicons.leave_out_declared(&self.arena, self.dom);
+        self.favicons = favicons;
+
+        let mut serializer = PropSerializer::new(self.json_props, self.serialization);
+        let mut html = self.arena[self.dom].to_string(&self.arena, &mut serializer);
+        self.json_scripts = serializer.scripts_html();
+
+        html.insert_str(0, &self.doctype);
 
-        html.insert_str(0, "<!DOCTYPE html>");
+        let builtins: [&dyn PageTransform; 4] =
+            [&Katex, &ExternalLinks, &ResponsiveImages, &Inject];
+        let page = &*self;
+        let element_content_handlers = builtins
+            .into_iter()
+            .chain(transforms.iter().map(|transform| &**transform))
+            .flat_map(|transform| transform.element_handlers(page, &ctx))
+            .collect();
 
         let mut rewriter = HtmlRewriter::new(
             lol_html::Settings {
-                element_content_handlers: vec![
-                    text!(".language-math.math-inline", |t| {
-                        if !t.last_in_text_node() {
-                            let rendered = katex::render(t.as_str().trim())?;
-                            t.replace(&rendered, ContentType::Html);
-                        }
-                        Ok(())
-                    }),
-                    text!(".language-math.math-display", |t| {
-                        if !t.last_in_text_node() {
-                            let opts = katex::Opts::builder().display_mode(true).build()?;
-                            let rendered = katex::render_with_opts(t.as_str(), opts)?;
-                            t.replace(&rendered, ContentType::Html);
-                        }
-                        Ok(())
-                    }),
-                    element!(".language-math.math-display", |el| {
-                        el.remove_and_keep_content();
-                        Ok(())
-                    }),
-                    element!(".language-math.math-inline", |el| {
-                        el.remove_and_keep_content();
-                        Ok(())
-                    }),
-                    element!("body", |el| {
-                        let tag = format!(r#"<script type="module">{}</script>"#, self.script);
-                        el.append(&tag, ContentType::Html);
-                        Ok(())
-                    }),
-                    element!("head", |el| {
-                        let tag = format!("<style>{}</style>", self.style);
-                        el.append(&tag, ContentType::Html);
-                        Ok(())
-                    }),
-                ],
-
+                element_content_handlers,
                 ..Default::default()
             },
             |c: &[u8]| {
@@ -104,8 +441,26 @@ impl Page {
     }
 
     fn process(&mut self) -> Result<(), anyhow::Error> {
-        self.process_scopes(self.dom)?;
-        self.process_styles(self.dom)?;
+        if self.processed {
+            return Ok(());
+        }
+
+        // Global styles come first so scoped styles can override them
+        self.style_blocks = vec![self.global_style.clone()];
+
+        // Styles are processed before scopes, as they determine the class for
+        // each scope
+        let hoisted = tracing::debug_span!("styles").in_scope(|| {
+            let mut pending = Vec::new();
+            self.collect_styles(self.dom, &mut pending, &mut HashSet::new())?;
+            self.process_styles(&pending)
+        })?;
+        if !hoisted.is_empty() {
+            self.style_blocks.insert(0, hoisted.css());
+        }
+        tracing::debug_span!("scopes").in_scope(|| self.process_scopes(self.dom))?;
+        self.style = self.style_blocks.concat();
+        self.processed = true;
         Ok(())
     }
 
@@ -114,22 +469,14 @@ impl Page {
         children: &Children<ArenaId>,
         f: &mut impl FnMut(&mut Self, ArenaId) -> Result<bool, anyhow::Error>,
     ) -> Result<(), anyhow::Error> {
-        match children {
-            Children::Element(child) => {
-                let propagate = f(self, *child)?;
-                if propagate {
-                    if let Some(grandchild) = self.arena[*child].clone().children() {
-                        self.walk_children(grandchild, f)?;
-                    }
+        for child in children.iter_elements() {
+            let propagate = f(self, *child)?;
+            if propagate {
+                if let Some(grandchild) = self.arena[*child].clone().children() {
+                    self.walk_children(grandchild, f)?;
                 }
             }
-            Children::Elements(children) => {
-                for child in children {
-                    self.walk_children(child, f)?;
-                }
-            }
-            _ => {}
-        };
+        }
 
         Ok(())
     }
@@ -138,10 +485,11 @@ impl Page {
         let element = self.arena[id].clone();
 
         if let ArenaElement::Intrinsic { ref scope, .. } = element {
-            let unique = format!("s{scope}");
-            self.arena[id]
-                .props_mut()
-                .append_string_space_separated("class".into(), unique.clone())?;
+            if let Some(class) = self.classes.get(scope).cloned() {
+                self.arena[id]
+                    .props_mut()
+                    .append_string_space_separated("class".into(), class)?;
+            }
         }
 
         if let Some(children) = element.children() {
@@ -154,111 +502,131 @@ impl Page {
         Ok(())
     }
 
-    fn process_styles(&mut self, id: ArenaId) -> Result<(), anyhow::Error> {
+    /// Collects component styles in document order, once per scope and
+    /// component name, so each component's CSS is known even where it shares
+    /// a scope with another.
+    fn collect_styles(
+        &mut self,
+        id: ArenaId,
+        pending: &mut Vec<PendingStyle>,
+        seen: &mut HashSet<(String, Option<String>)>,
+    ) -> Result<(), anyhow::Error> {
         let element = self.arena[id].clone();
 
         if let ArenaElement::Virtual {
             style: Some(ref style),
             ref scope,
+            ref source,
+            ref name,
             ..
         } = element
         {
-            let unique = format!("s{scope}");
-            if self.scopes.insert(unique.clone()) {
-                self.style += &process_css(&style, &unique)?;
+            if seen.insert((scope.clone(), name.clone())) {
+                pending.push(PendingStyle::Scoped {
+                    scope: scope.clone(),
+                    style: style.clone(),
+                    source: source.clone(),
+                    name: name.clone(),
+                });
             }
         }
 
+        if let ArenaElement::Virtual {
+            stylesheet: Some(ref stylesheet),
+            ..
+        } = element
+        {
+            pending.push(PendingStyle::Module(stylesheet.clone()));
+        }
+
         if let Some(children) = element.children() {
             self.walk_children(children, &mut |self_, id| {
-                self_.process_styles(id)?;
+                self_.collect_styles(id, pending, seen)?;
                 Ok(false)
             })?;
         }
 
         Ok(())
     }
-}
-
-struct CssVisitor {
-    scope: String,
-}
-
-impl<'i> lightningcss::visitor::Visitor<'i> for CssVisitor {
-    type Error = Infallible;
-
-    fn visit_types(&self) -> lightningcss::visitor::VisitTypes {
-        lightningcss::visit_types!(SELECTORS)
-    }
 
-    fn visit_selector(&mut self, selector: &mut Selector<'i>) -> Result<(), Self::Error> {
-        let mut complex = Vec::new();
-        let mut compound = Vec::new();
-        let mut it = selector.iter();
-
-        loop {
-            if let Some(component) = it.next() {
-                match component {
-                    Component::NonTSPseudoClass(PseudoClass::Global { selector }) => {
-                        complex.extend(selector.iter_raw_parse_order_from(0).map(Clone::clone));
-                        compound.clear();
-
-                        if let Some(combinator) = it.next_sequence() {
-                            complex.push(Component::Combinator(combinator));
+    /// Processes collected styles in parallel, adding them in the order they
+    /// were collected so output stays deterministic. Returns the rules of
+    /// every style to hoist, each once.
+    fn process_styles(&mut self, pending: &[PendingStyle]) -> Result<Hoisted, anyhow::Error> {
+        let (url, root, options) = (&self.url, &self.root, &self.css_options);
+        let images = &self.images;
+        let cache = &self.css_cache;
+        let results: Vec<_> = pending
+            .par_iter()
+            .map(|pending| -> Result<ScopedStyle, anyhow::Error> {
+                match pending {
+                    PendingStyle::Scoped {
+                        scope,
+                        style,
+                        source,
+                        name,
+                    } => {
+                        let base = match source {
+                            Some(source) => Url::parse(source)?,
+                            None => url.clone(),
+                        };
+                        let style = if style.contains("@import") {
+                            inline_imports(style, &base)?
                         } else {
-                            break;
+                            style.clone()
+                        };
+
+                        // Scoped CSS depends on where the style is from, as that
+                        // resolves its URLs, and in dev on the component's name
+                        let name = name.as_deref();
+                        let inputs = format!("{}\n{}\n{}", base, name.unwrap_or_default(), style);
+                        let key = (scope.clone(), scope_class(&inputs));
+                        cache.get_or_try_insert_with(key, || {
+                            let assets = AssetBase {
+                                base: &base,
+                                root,
+                                images,
+                            };
+                            scope_css(&style, name, &assets, options)
+                        })
+                    }
+                    PendingStyle::Module(stylesheet) => Ok(ScopedStyle {
+                        class: scope_class(stylesheet),
+                        css: stylesheet.clone(),
+                        hoisted: Hoisted::default(),
+                    }),
+                }
+            })
+            .collect();
+
+        let mut hoisted = Hoisted::default();
+        for (pending, result) in pending.iter().zip(results) {
+            match result {
+                Ok(scoped) => {
+                    // Components with identical processed styles share a class
+                    // and a single copy of the CSS
+                    if let PendingStyle::Scoped { scope, name, .. } = pending {
+                        self.classes.insert(scope.clone(), scoped.class.clone());
+                        // Kept for every component, even those sharing a
+                        // class with one seen before
+                        if let Some(name) = name {
+                            let style = (name.clone(), scoped.css.clone());
+                            if !self.component_styles.contains(&style) {
+                                self.component_styles.push(style);
+                            }
                         }
                     }
-                    _ => {
-                        compound.push(component.clone());
+                    if self.scopes.insert(scoped.class) {
+                        self.style_blocks.push(scoped.css);
+                        hoisted.extend(scoped.hoisted);
                     }
                 }
-            } else {
-                complex.push(Component::Class(self.scope.clone().into()));
-                complex.extend(compound.iter().rev().map(Clone::clone));
-                compound.clear();
-
-                if let Some(combinator) = it.next_sequence() {
-                    complex.push(Component::Combinator(combinator));
-                } else {
-                    break;
-                }
+                // Without a class, the component's elements are left unstyled
+                Err(err) if self.css_options.lenient => tracing::warn!("{:#}", err),
+                Err(err) => return Err(err),
             }
         }
 
-        complex.reverse();
-        *selector = complex.try_into()?;
-
-        Ok(())
+        Ok(hoisted)
     }
 }
-
-fn process_css(style: &str, unique: &str) -> Result<String, anyhow::Error> {
-    let mut stylesheet = StyleSheet::parse(
-        &style,
-        ParserOptions {
-            flags: ParserFlags::NESTING,
-            css_modules: Some(css_modules::Config {
-                pattern: css_modules::Pattern {
-                    segments: vec![css_modules::Segment::Local].into(),
-                },
-                dashed_idents: false,
-            }),
-            ..Default::default()
-        },
-    )
-    .map_err(|e| anyhow!(e.to_string()))?;
-
-    // Rescope stylesheet with unique ID class
-    let visitor = &mut CssVisitor {
-        scope: unique.to_string(),
-    };
-    stylesheet.visit(visitor)?;
-
-    let css = stylesheet.to_css(PrinterOptions {
-        minify: true,
-        ..Default::default()
-    })?;
-
-    Ok(css.code)
-}
@@ -0,0 +1,349 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tracing::{
+    field::{Field, Visit},
+    span, Level, Subscriber,
+};
+use tracing_subscriber::{
+    filter::Targets,
+    layer::{Context, Layer},
+    registry::LookupSpan,
+};
+
+/// Pages listed in a profile's breakdown, slowest first.
+const SLOWEST_PAGES: usize = 10;
+
+/// Layer timing the phases of a build, as recorded by the spans Areum and
+/// dongjak open around them, for `areum build --profile`.
+///
+/// Phases are attributed to the page whose span, with a `page` field, they
+/// ran in. Time is counted as self time, leaving out nested phases, so
+/// phases add up to the total.
+#[derive(Clone, Default)]
+pub struct Profiler(Arc<Mutex<Profile>>);
+
+#[derive(Default)]
+pub struct Profile {
+    spans: Vec<SpanRecord>,
+    threads: HashMap<ThreadId, usize>,
+    start: Option<Instant>,
+}
+
+struct SpanRecord {
+    phase: &'static str,
+    page: Option<String>,
+    start: Instant,
+    end: Instant,
+    self_time: Duration,
+    thread: usize,
+}
+
+/// Timing of an open span, kept in its extensions.
+struct SpanTiming {
+    page: Option<String>,
+    first_enter: Option<Instant>,
+    entered: Option<Instant>,
+    last_exit: Option<Instant>,
+    busy: Duration,
+    children: Duration,
+}
+
+impl Profiler {
+    /// Spans the profiler records, regardless of the log level.
+    pub fn targets() -> Targets {
+        Targets::new()
+            .with_target("areum", Level::DEBUG)
+            .with_target("dongjak", Level::DEBUG)
+    }
+
+    /// Takes the spans recorded so far.
+    pub fn finish(&self) -> Profile {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl<S> Layer<S> for Profiler
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut visitor = PageVisitor(None);
+        attrs.record(&mut visitor);
+        let page = visitor.0.or_else(|| {
+            let parent = span.parent()?;
+            let extensions = parent.extensions();
+            extensions.get::<SpanTiming>()?.page.clone()
+        });
+
+        self.0
+            .lock()
+            .unwrap()
+            .start
+            .get_or_insert_with(Instant::now);
+        span.extensions_mut().insert(SpanTiming {
+            page,
+            first_enter: None,
+            entered: None,
+            last_exit: None,
+            busy: Duration::ZERO,
+            children: Duration::ZERO,
+        });
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<SpanTiming>() {
+                let now = Instant::now();
+                timing.first_enter.get_or_insert(now);
+                timing.entered = Some(now);
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<SpanTiming>() {
+                let now = Instant::now();
+                if let Some(entered) = timing.entered.take() {
+                    timing.busy += now - entered;
+                }
+                timing.last_exit = Some(now);
+            }
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(timing) = span.extensions_mut().remove::<SpanTiming>() else {
+            return;
+        };
+        // Spans that were never entered took no time
+        let (Some(start), Some(end)) = (timing.first_enter, timing.last_exit) else {
+            return;
+        };
+
+        if let Some(parent) = span.parent() {
+            if let Some(parent) = parent.extensions_mut().get_mut::<SpanTiming>() {
+                parent.children += timing.busy;
+            }
+        }
+
+        let mut profile = self.0.lock().unwrap();
+        let threads = profile.threads.len();
+        let thread = *profile
+            .threads
+            .entry(thread::current().id())
+            .or_insert(threads);
+        profile.spans.push(SpanRecord {
+            phase: span.name(),
+            page: timing.page,
+            start,
+            end,
+            self_time: timing.busy.saturating_sub(timing.children),
+            thread,
+        });
+    }
+}
+
+struct PageVisitor(Option<String>);
+
+impl Visit for PageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "page" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "page" {
+            self.0 = Some(value.to_string());
+        }
+    }
+}
+
+/// Event of the Chrome tracing format, which Perfetto and `chrome://tracing`
+/// open.
+#[derive(Serialize)]
+struct TraceEvent<'a> {
+    name: &'a str,
+    cat: &'a str,
+    ph: &'a str,
+    /// Microseconds since the first span.
+    ts: u128,
+    dur: u128,
+    pid: u32,
+    tid: usize,
+    args: BTreeMap<&'a str, &'a str>,
+}
+
+impl Profile {
+    /// Self time of each phase, in total and by page.
+    fn phases(
+        &self,
+    ) -> (
+        BTreeMap<&str, Duration>,
+        BTreeMap<&str, BTreeMap<&str, Duration>>,
+    ) {
+        let mut totals = BTreeMap::new();
+        let mut pages = BTreeMap::new();
+        for span in &self.spans {
+            *totals.entry(span.phase).or_default() += span.self_time;
+            if let Some(page) = &span.page {
+                *pages
+                    .entry(page.as_str())
+                    .or_insert_with(BTreeMap::new)
+                    .entry(span.phase)
+                    .or_default() += span.self_time;
+            }
+        }
+        (totals, pages)
+    }
+
+    /// Writes every span as a Chrome tracing complete event.
+    pub fn write_trace(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let Some(origin) = self.start else {
+            return Ok(fs::write(path, r#"{"traceEvents":[]}"#)?);
+        };
+
+        let events: Vec<_> = self
+            .spans
+            .iter()
+            .map(|span| TraceEvent {
+                name: span.phase,
+                cat: "areum",
+                ph: "X",
+                ts: span.start.saturating_duration_since(origin).as_micros(),
+                dur: (span.end - span.start).as_micros(),
+                pid: 1,
+                tid: span.thread,
+                args: span
+                    .page
+                    .as_deref()
+                    .map(|page| BTreeMap::from([("page", page)]))
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        fs::write(
+            path,
+            serde_json::to_string(&serde_json::json!({ "traceEvents": events }))?,
+        )?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (totals, pages) = self.phases();
+        let total: Duration = totals.values().sum();
+        let share = |time: &Duration| {
+            if total.is_zero() {
+                0.0
+            } else {
+                time.as_secs_f64() / total.as_secs_f64() * 100.0
+            }
+        };
+
+        writeln!(f, "phase         time     share")?;
+        for (phase, time) in &totals {
+            writeln!(
+                f,
+                "{:<12} {:>8}  {:>5.1}%",
+                phase,
+                format_ms(time),
+                share(time)
+            )?;
+        }
+        writeln!(f, "{:<12} {:>8}", "total", format_ms(&total))?;
+
+        let mut pages: Vec<_> = pages
+            .into_iter()
+            .map(|(page, phases)| (page, phases.values().sum::<Duration>(), phases))
+            .collect();
+        pages.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+        if !pages.is_empty() {
+            writeln!(f, "slowest pages")?;
+        }
+        for (page, time, phases) in pages.iter().take(SLOWEST_PAGES) {
+            writeln!(f, "  {:>8}  {}", format_ms(time), page)?;
+            let phases = phases
+                .iter()
+                .map(|(phase, time)| format!("{} {}", phase, format_ms(time)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(f, "            {}", phases)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn format_ms(time: &Duration) -> String {
+    format!("{:.1}ms", time.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{
+        builder::BuilderOptions,
+        src_fs::{canonicalize, file_url},
+        testing::Fixture,
+    };
+
+    #[tokio::test]
+    async fn traces_every_page() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("index.tsx", "export default () => <p>Home</p>")
+            .unwrap()
+            .file("about.tsx", "export default () => <p>About</p>")
+            .unwrap()
+            .file("blog/post.mdx", "# Post")
+            .unwrap();
+
+        let profiler = Profiler::default();
+        let subscriber =
+            tracing_subscriber::registry().with(profiler.clone().with_filter(Profiler::targets()));
+        {
+            let _guard = tracing::subscriber::set_default(subscriber);
+            fixture.build(BuilderOptions::default()).await.unwrap();
+        }
+        let profile = profiler.finish();
+
+        let out = fixture.root().join("trace.json");
+        profile.write_trace(&out).unwrap();
+        let trace: serde_json::Value = serde_json::from_slice(&fs::read(&out).unwrap()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        let breakdown = profile.to_string();
+
+        let root = canonicalize(fixture.root()).unwrap();
+        for page in ["index.tsx", "about.tsx", "blog/post.mdx"] {
+            let url = file_url(&root.join(page)).unwrap().to_string();
+            for phase in ["render", "write"] {
+                let traced = events
+                    .iter()
+                    .any(|event| event["name"] == phase && event["args"]["page"] == url.as_str());
+                assert!(traced, "no {} span for {}", phase, url);
+            }
+            assert!(breakdown.contains(&url), "{}", breakdown);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+
+use crate::{
+    config::Config,
+    env::{Env, Mode},
+    src_fs::{file_url, SrcKind, Translations},
+};
+
+/// Root modules rendered by [`render_source`] are resolved against. Nothing is
+/// read from it, but it must be absolute for modules to have file URLs.
+#[cfg(not(windows))]
+const RENDER_ROOT: &str = "/__areum_render__";
+#[cfg(windows)]
+const RENDER_ROOT: &str = r"C:\__areum_render__";
+
+#[derive(Default)]
+pub struct RenderOptions {
+    /// Modules the source may import, by path relative to the source, such as
+    /// `components/button.tsx`.
+    pub modules: Vec<(PathBuf, String)>,
+    /// Whether to include the page's client script, bundled, as a build would.
+    pub bundle: bool,
+}
+
+/// Renders a page from source, without a site on disk, as a build would
+/// render it with the default config.
+///
+/// Meant for testing components and embedding Areum.
+pub async fn render_source(
+    source: &str,
+    kind: SrcKind,
+    options: RenderOptions,
+) -> Result<String, anyhow::Error> {
+    let ext = match kind {
+        SrcKind::Jsx => "tsx",
+        SrcKind::Mdx => "mdx",
+        SrcKind::Md => "md",
+        _ => return Err(anyhow!("{:?} sources cannot be rendered as pages", kind)),
+    };
+
+    let root = Path::new(RENDER_ROOT);
+    let config = Config::default();
+    let mut env = Env::new(root, &config, Mode::Build)?;
+    env.bootstrap().await?;
+
+    for (path, code) in &options.modules {
+        env.runtime.inject(&file_url(&root.join(path))?, code)?;
+    }
+    let url = file_url(&root.join(format!("index.{}", ext)))?;
+    env.runtime.inject(&url, source)?;
+
+    let mut page = env
+        .new_page(&url, Path::new(""), Translations::default(), &[])
+        .await?;
+
+    if options.bundle {
+        env.bundler.push(format!(
+            r#"import {{ run }} from "{}"
+            import Page from "{}"
+            run(Page, {{}})
+            "#,
+            file_url(&root.join(format!("{}/jsx-runtime", config.jsx.import_source)))?,
+            url
+        ));
+        page.script = env.bundle().await?;
+    } else {
+        page.script.clear();
+    }
+
+    page.render_to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn renders_imported_modules() {
+        let options = RenderOptions {
+            modules: vec![(
+                Path::new("components").join("greeting.tsx"),
+                "export const Greeting = ({ name }) => <p>Hello, {name}</p>".into(),
+            )],
+            ..Default::default()
+        };
+        let source = r#"
+            import { Greeting } from "./components/greeting.tsx";
+            export default () => <Greeting name="Areum" />;
+        "#;
+        let html = render_source(source, SrcKind::Jsx, options).await.unwrap();
+        assert!(html.contains("<p>Hello, Areum</p>"));
+    }
+
+    #[tokio::test]
+    async fn bundles_only_when_asked() {
+        // Scripts are added at the end of the body
+        let source = "export default () => <html><body><p>Hello</p></body></html>";
+        let html = render_source(source, SrcKind::Jsx, RenderOptions::default())
+            .await
+            .unwrap();
+        assert!(!html.contains("<script"));
+
+        let options = RenderOptions {
+            bundle: true,
+            ..Default::default()
+        };
+        let html = render_source(source, SrcKind::Jsx, options).await.unwrap();
+        assert!(html.contains(r#"<script type="module">"#));
+    }
+
+    #[tokio::test]
+    async fn scopes_style_functions_by_their_css() {
+        let source = r#"
+            const Button = ({ children }) => <button>{children}</button>;
+            Button.style = ({ align }) => `button { text-align: ${align}; }`;
+            export default () => <html><head></head><body>
+                <Button align="left">A</Button>
+                <Button align="right">B</Button>
+                <Button align="left">C</Button>
+            </body></html>;
+        "#;
+        let html = render_source(source, SrcKind::Jsx, RenderOptions::default())
+            .await
+            .unwrap();
+
+        let classes: Vec<_> = html
+            .split(r#"<button class=""#)
+            .skip(1)
+            .map(|rest| rest.split_once('"').unwrap().0)
+            .collect();
+        assert_eq!(classes.len(), 3);
+        // Identical CSS shares a scope, so only two rules are written
+        assert_eq!(classes[0], classes[2]);
+        assert_ne!(classes[0], classes[1]);
+        assert!(html.contains(&format!("button.{}{{text-align:left}}", classes[0])));
+        assert!(html.contains(&format!("button.{}{{text-align:right}}", classes[1])));
+        assert_eq!(html.matches("text-align:").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn shares_identical_styles_of_different_components() {
+        let source = r#"
+            const Button = ({ children }) => <button>{children}</button>;
+            Button.style = "a, button { color: red; }";
+            // Written differently, but the same once processed
+            const Link = ({ children }) => <a href="/">{children}</a>;
+            Link.style = "a,button{color:#f00}";
+            export default () => <html><head></head><body>
+                <Button>A</Button><Link>B</Link>
+            </body></html>;
+        "#;
+        let html = render_source(source, SrcKind::Jsx, RenderOptions::default())
+            .await
+            .unwrap();
+
+        let class = |tag: &str| {
+            let (_, rest) = html.split_once(&format!(r#"<{} "#, tag)).unwrap();
+            let (_, rest) = rest.split_once(r#"class=""#).unwrap();
+            rest.split_once('"').unwrap().0.to_string()
+        };
+        // Both stay styled, by the one copy of the CSS
+        assert_eq!(class("button"), class("a"));
+        assert_eq!(html.matches("color:red").count(), 1, "{}", html);
+    }
+
+    #[tokio::test]
+    async fn renders_classic_runtime_jsx() {
+        let source = r#"
+            /** @jsxRuntime classic */
+            /** @jsx h */
+            /** @jsxFrag Fragment */
+            import { jsx, Fragment } from "/areum/jsx-runtime";
+            const h = (type, props, ...children) => jsx(type, { ...props, children });
+            export default () => <><p class="greeting">Hello</p></>;
+        "#;
+        let html = render_source(source, SrcKind::Jsx, RenderOptions::default())
+            .await
+            .unwrap();
+        assert!(
+            html.contains(r#"<p class="greeting">Hello</p>"#),
+            "{}",
+            html
+        );
+    }
+
+    #[tokio::test]
+    async fn exposes_import_meta_to_pages() {
+        std::env::set_var("AREUM_RENDER_TEST_GREETING", "Hello");
+        let source = r#"
+            const env = import.meta.env;
+            export default () => <ul>
+                <li>{env.MODE} {String(env.DEV)} {String(env.PROD)}</li>
+                <li>{env.AREUM_RENDER_TEST_GREETING}</li>
+                <li>{String(env.PATH)}</li>
+                <li>{import.meta.url}</li>
+            </ul>;
+        "#;
+        let html = render_source(source, SrcKind::Jsx, RenderOptions::default())
+            .await
+            .unwrap();
+        assert!(html.contains("<li>production false true</li>"), "{}", html);
+        assert!(html.contains("<li>Hello</li>"), "{}", html);
+        // Only public variables are exposed
+        assert!(html.contains("<li>undefined</li>"), "{}", html);
+        let url = file_url(&Path::new(RENDER_ROOT).join("index.tsx")).unwrap();
+        assert!(html.contains(&format!("<li>{}</li>", url)), "{}", html);
+    }
+
+    #[tokio::test]
+    async fn renders_nothing_for_falsy_children() {
+        let source = r#"
+            const List = ({ items }) => <div>
+                {false && <p>Hidden</p>}
+                {null}{undefined}{true}
+                {items.length && <ul>{items.map((item) => <li>{item}</li>)}</ul>}
+            </div>;
+            export default () => <main><List items={[]} /><List items={["a"]} /></main>;
+        "#;
+        let html = render_source(source, SrcKind::Jsx, RenderOptions::default())
+            .await
+            .unwrap();
+        assert!(!html.contains("Hidden"));
+        assert!(!html.contains("false") && !html.contains("true"));
+        assert!(!html.contains("null") && !html.contains("undefined"));
+        // Zero is a number like any other, so an empty list renders it as
+        // it would in React
+        assert!(html.contains("<div>0</div>"));
+        assert!(html.contains("<div><ul><li>a</li></ul></div>"));
+    }
+
+    #[tokio::test]
+    async fn renders_number_children_as_text() {
+        let source = r#"
+            const count = 3;
+            export default () => <p>{42} {count} items at {1.5} {-7n}</p>;
+        "#;
+        let html = render_source(source, SrcKind::Jsx, RenderOptions::default())
+            .await
+            .unwrap();
+        assert!(html.contains("<p>42 3 items at 1.5 -7</p>"), "{}", html);
+    }
+
+    #[tokio::test]
+    async fn renders_markdown() {
+        let html = render_source("Some *text*", SrcKind::Md, RenderOptions::default())
+            .await
+            .unwrap();
+        assert!(html.contains("<p>Some <em>text</em></p>"));
+    }
+
+    #[tokio::test]
+    async fn rejects_other_kinds() {
+        let rendered = render_source("p {}", SrcKind::Css, RenderOptions::default()).await;
+        assert!(rendered.is_err());
+    }
+
+    #[tokio::test]
+    async fn explains_what_pages_export_instead_of_components() {
+        let error = |source: &'static str| async move {
+            let err = render_source(source, SrcKind::Jsx, RenderOptions::default())
+                .await
+                .unwrap_err();
+            format!("{:#}", err)
+        };
+
+        let err = error("export const Page = () => <p>Hi</p>;").await;
+        assert!(
+            err.contains("has no default export, pages must export a component function"),
+            "{}",
+            err
+        );
+        let err = error("export default 42;").await;
+        assert!(
+            err.contains("exports a number 42 by default, pages must export a component function"),
+            "{}",
+            err
+        );
+        let err = error("export default <p>Hi</p>;").await;
+        assert!(err.contains("by default, pages must export"), "{}", err);
+
+        let err = error("export default async () => <p>Hi</p>;").await;
+        assert!(
+            err.contains("cannot render a promise, as async components return"),
+            "{}",
+            err
+        );
+    }
+}
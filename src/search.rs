@@ -0,0 +1,145 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::{
+    dom::{
+        arena::{Arena, ArenaElement, ArenaId},
+        Children,
+    },
+    page::Page,
+    src_fs::encode_route,
+};
+
+/// Elements whose text is not searchable.
+const SKIPPED_TAGS: [&str; 7] = [
+    "head", "nav", "script", "style", "noscript", "template", "svg",
+];
+/// Attribute excluding an element's text from the index.
+const NOSEARCH_ATTR: &str = "data-nosearch";
+/// Characters of a page's text kept as its excerpt.
+const EXCERPT_LEN: usize = 160;
+
+/// Client-side search index, written to `search-index.json`.
+///
+/// `terms` maps each lowercased term to the indices in `pages` of the pages
+/// containing it, in ascending order.
+#[derive(Serialize, Default)]
+pub struct SearchIndex {
+    pages: Vec<SearchPage>,
+    terms: BTreeMap<String, BTreeSet<usize>>,
+}
+
+#[derive(Serialize)]
+struct SearchPage {
+    route: String,
+    title: Option<String>,
+    excerpt: String,
+}
+
+impl SearchIndex {
+    pub const FILE_NAME: &'static str = "search-index.json";
+
+    /// Indexes the visible text of a rendered page, up to `max_text`
+    /// characters of it.
+    pub fn add(&mut self, page: &Page, max_text: usize) {
+        let mut text = PageText::default();
+        text.collect(&page.arena, page.dom);
+
+        let body: String = text.body.split_whitespace().collect::<Vec<_>>().join(" ");
+        let body: String = body.chars().take(max_text).collect();
+
+        let id = self.pages.len();
+        for term in tokenize(&body) {
+            self.terms.entry(term).or_default().insert(id);
+        }
+
+        self.pages.push(SearchPage {
+            route: encode_route(&page.path),
+            title: text.title.map(|title| title.trim().to_string()),
+            excerpt: page
+                .props
+                .excerpt
+                .clone()
+                .unwrap_or_else(|| body.chars().take(EXCERPT_LEN).collect()),
+        });
+    }
+}
+
+#[derive(Default)]
+struct PageText {
+    title: Option<String>,
+    body: String,
+}
+
+impl PageText {
+    fn collect(&mut self, arena: &Arena, id: ArenaId) {
+        let element = &arena[id];
+        if let ArenaElement::Intrinsic { tag, props, .. } = element {
+            if tag == "title" && self.title.is_none() {
+                let mut title = PageText::default();
+                title.collect_children(arena, element.children());
+                self.title = Some(title.body);
+                return;
+            }
+            if tag == "head" {
+                // Only the title is wanted from the head
+                let mut head = PageText::default();
+                head.collect_children(arena, element.children());
+                self.title = self.title.take().or(head.title);
+                return;
+            }
+            if SKIPPED_TAGS.contains(&tag.as_str()) || props.get(NOSEARCH_ATTR).is_some() {
+                return;
+            }
+        }
+
+        self.collect_children(arena, element.children());
+    }
+
+    fn collect_children(&mut self, arena: &Arena, children: Option<&Children<ArenaId>>) {
+        match children {
+            Some(Children::Element(id)) => self.collect(arena, *id),
+            Some(Children::Elements(children)) => {
+                for child in children {
+                    self.collect_children(arena, Some(child));
+                }
+            }
+            Some(Children::Text(text)) => {
+                self.body.push(' ');
+                self.body.push_str(&strip_tags(text));
+            }
+            None => {}
+        }
+    }
+}
+
+/// Text outside of tags, as text children may hold HTML, such as compiled
+/// Markdown.
+fn strip_tags(html: &str) -> String {
+    if !html.contains('<') {
+        return html.to_string();
+    }
+
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Lowercased words of at least two characters.
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().nth(1).is_some())
+        .map(str::to_lowercase)
+}
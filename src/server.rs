@@ -1,178 +1,518 @@
 use std::{
-    path::{Path, PathBuf},
+    collections::{BTreeMap, HashMap},
+    future::IntoFuture,
+    io,
+    path::{Component, Path, PathBuf},
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, Mutex as StdMutex, RwLock},
     thread::{self, JoinHandle},
+    time::Duration,
 };
 
 use anyhow::{anyhow, Context};
 use axum::{
-    extract::Request,
-    http::{header, StatusCode},
+    body::Body,
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        Request, State,
+    },
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
     response::{Html, IntoResponse, Response},
-    routing, Router,
+    routing, Json, Router,
 };
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use dongjak::loader::Loader;
+use globset::GlobSet;
+use lol_html::{element, html_content::ContentType};
+use serde::Serialize;
 
-use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
-use url::Url;
+use tokio::sync::{
+    broadcast::{
+        self,
+        error::{RecvError, TryRecvError},
+    },
+    mpsc, oneshot, watch, Mutex,
+};
+use tokio_util::io::ReaderStream;
+use tracing::Instrument;
+use url::{form_urlencoded, Url};
 
 use crate::{
-    env::Env,
+    collection::Collections,
+    config::Config,
+    env::{Env, Mode},
+    git::{self, Commit},
     page::Page,
-    src_fs::{SrcFs, SrcKind},
+    src_fs::{
+        canonicalize, decode_route, encode_route, file_path, file_url, to_slash, CaseSensitivity,
+        SrcChange, SrcFile, SrcFs, SrcKind, TrailingSlash, Translations,
+    },
+    taxonomy::Taxonomies,
+    transform::{ElementHandler, PageTransform, TransformCtx},
 };
 
 pub struct Server {
     router: Router,
     src_fs: SrcFs,
     rx_cmd: broadcast::Receiver<Command>,
+    transforms: Transforms,
+    /// Envs left to stop, once they have been told to.
+    rx_stopped: watch::Receiver<Option<usize>>,
+    shutdown_timeout: Duration,
 }
 
+/// Transforms run on every page, shared with the request handlers.
+type Transforms = Arc<RwLock<Vec<Box<dyn PageTransform>>>>;
+
 #[derive(Clone, Copy)]
 pub enum Command {
     Stop,
     Restart,
 }
 
-struct Message {
+/// State of the envs, sent to browsers over [`STATUS_PATH`] as JSON tagged by
+/// `status`.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum Status {
+    /// Envs are restarting after a change, so requests wait.
+    Restarting,
+    /// Every env has bootstrapped.
+    Ready,
+    /// An env could not bootstrap.
+    Failed { message: String },
+}
+
+/// Handle to the current env's job channel, swapped out on restart.
+///
+/// The channel is gated: while an env starts up there is no sender, and
+/// requests wait for the env to become ready instead of failing.
+#[derive(Clone)]
+struct JobSender(watch::Receiver<Option<mpsc::Sender<Message>>>);
+
+impl JobSender {
+    async fn get(&mut self) -> Result<mpsc::Sender<Message>, anyhow::Error> {
+        let tx = self
+            .0
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| anyhow!("server is shutting down"))?;
+        Ok(tx.clone().unwrap())
+    }
+}
+
+/// Envs sharing one job queue, each rendering on its own thread with its own
+/// V8 isolate. Whichever env is free takes the next job.
+struct EnvPool {
+    envs: Vec<EnvHandle>,
+    tx_job: mpsc::Sender<Message>,
+    /// Module loaders of the envs, tracking which modules each has loaded.
+    loaders: Vec<Loader>,
+    /// Sources of the modules generated from pages the envs started with, by
+    /// specifier.
+    data_modules: Vec<(&'static str, String)>,
+}
+
+impl EnvPool {
+    /// Whether a change could affect what the envs have loaded, so they must
+    /// be restarted. Other changes, like to images, only need a rescan.
+    fn needs_restart(&self, root: &Path, change: &SrcChange) -> bool {
+        // Envs that failed to start may have failed on anything
+        if self.loaders.len() < self.envs.len() {
+            return true;
+        }
+
+        let loaded = Url::from_file_path(&change.path)
+            .map_or(false, |url| self.loaders.iter().any(|l| l.is_loaded(&url)));
+
+        // The shell components are looked up when an env is created
+        let shell = change.path.file_stem().map_or(false, |stem| {
+            stem == Env::DOCUMENT_NAME || stem == Env::APP_NAME
+        });
+
+        // Envs are created with the config, and export it as `areum:config`
+        let config = change.path == root.join(Config::FILE_NAME);
+
+        loaded || shell || config
+    }
+}
+
+/// An env running on its own thread.
+struct EnvHandle {
+    thread: JoinHandle<()>,
+    tx_stop: mpsc::Sender<bool>,
+    /// Resolves to the env's loader once it has bootstrapped, or the error it
+    /// could not with.
+    rx_ready: oneshot::Receiver<Result<Loader, String>>,
+}
+
+enum Message {
+    Render(Render),
+    /// Lists the paths each generator generates, by generator, without
+    /// rendering them.
+    ListRoutes {
+        generators: Vec<Url>,
+        responder: oneshot::Sender<Result<Vec<Vec<String>>, anyhow::Error>>,
+    },
+}
+
+struct Render {
     url: Url,
     path: PathBuf,
+    /// Layouts wrapping the module at `url`, outermost first.
+    layouts: Vec<Url>,
+    /// Query parameters of the request, passed to the page as props.
+    query: BTreeMap<String, String>,
     responder: oneshot::Sender<Result<Page, anyhow::Error>>,
-    generator: bool,
+    job: Job,
 }
 
-fn spawn_env(root: &PathBuf) -> (JoinHandle<()>, mpsc::Sender<Message>, mpsc::Sender<bool>) {
-    let (tx_job, mut rx_job) = mpsc::channel(16);
+/// How the module at a message's URL renders its page.
+enum Job {
+    Page(Translations),
+    Generator(Translations),
+    /// Rendered once per path with extra props, as taxonomy pages are.
+    Each(BTreeMap<String, serde_json::Value>),
+}
+
+/// Spawns an env, which runs until it is stopped or its job queue is closed
+/// and drained.
+fn spawn_env(
+    root: &PathBuf,
+    config: &Config,
+    modules: &[(&'static str, String)],
+    commits: &BTreeMap<String, Commit>,
+    rx_job: Arc<Mutex<mpsc::Receiver<Message>>>,
+) -> EnvHandle {
     let (tx_stop, mut rx_stop) = mpsc::channel::<bool>(1);
+    let (tx_ready, rx_ready) = oneshot::channel();
     let root = root.clone();
+    let config = config.clone();
+    let modules = modules.to_vec();
+    let commits = commits.clone();
 
     let join_handle = thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
-        let mut env: Env = Env::new(&root).unwrap();
 
         let future = async {
-            env.bootstrap().await?;
+            let bootstrap = async {
+                let mut env = Env::new(&root, &config, Mode::Dev)?;
+                for (specifier, code) in modules {
+                    env.set_module(specifier, code);
+                }
+                env.set_commits(commits);
+                env.bootstrap().await.context("could not bootstrap env")?;
+                Ok::<_, anyhow::Error>(env)
+            };
+            let mut env = match bootstrap.await {
+                Ok(env) => {
+                    let _ = tx_ready.send(Ok(env.runtime.graph_loader.clone()));
+                    env
+                }
+                Err(err) => {
+                    let _ = tx_ready.send(Err(format!("{:#}", err)));
+                    return Err(err);
+                }
+            };
 
             loop {
                 tokio::select! {
-                    Some(Message { responder, url, path, generator }) = rx_job.recv() => {
-                        let mut page = if generator {
-                            match env.new_pages(&url).await {
-                                Ok(pages) => {
-                                    println!("{:?}", path);
-                                    pages.into_iter().find(|page| page.path == path).context("could not find page")?
-                                },
-                                Err(err) => {
-                                    responder.send(Err(anyhow!("{}", err))).unwrap_or_else(|_| panic!("error sending to channel"));
-                                    return Err(err);
+                    // The queue is only locked while waiting for a job
+                    Some(message) = async {
+                        rx_job.lock().await.recv().await
+                    } => match message {
+                        Message::ListRoutes { generators, responder } => {
+                            // Errors are only the listing's, so the env keeps running
+                            let routes = async {
+                                let mut routes = Vec::new();
+                                for url in &generators {
+                                    let paths = env.generated_paths(url).await;
+                                    routes.push(paths.with_context(|| format!("could not list {}", url))?);
                                 }
+                                Ok::<_, anyhow::Error>(routes)
+                            };
+                            let _ = responder.send(routes.await);
+                        }
+                        Message::Render(Render { responder, url, path, layouts, query, job }) => {
+                            env.set_query(query);
+                            let find = |pages: Vec<Page>| {
+                                pages.into_iter().find(|page| page.path == path).context("could not find page")
+                            };
+                            let rendered = async {
+                                let mut page = match job {
+                                    Job::Page(translations) => env.new_page(&url, &path, translations, &layouts).await?,
+                                    Job::Generator(translations) => env.new_pages(&url, translations, &layouts).await.and_then(find)?,
+                                    Job::Each(pages) => env.new_pages_each(&url, &pages, &layouts).await.and_then(find)?,
+                                };
+                                page.script = bundle_page(&mut env, &root, &url, &path).await?;
+                                Ok::<_, anyhow::Error>(page)
+                            };
+                            let rendered = rendered.await;
+
+                            // Errors are the page's, so the env keeps serving others
+                            // unless V8 can no longer run anything
+                            let terminated = rendered.is_err() && env.runtime.is_terminated();
+                            if responder.send(rendered).is_err() {
+                                tracing::debug!("dropped render of {}, as its request was cancelled", url);
                             }
-                        } else {
-                            match env.new_page(&url, &path).await {
-                                Ok(page) => page,
-                                Err(err) => {
-                                    responder.send(Err(anyhow!("{}", err))).unwrap_or_else(|_| panic!("error sending to channel"));
-                                    return Err(err);
-                               }
+                            if terminated {
+                                return Err(anyhow!("JavaScript execution was terminated while rendering {}", url));
                             }
-                        };
-
-                        env.bundler.clear();
-                        env.bundler.push(format!(
-                            r#"import {{ run }} from "{}"
-                            "#,
-                            &Url::from_file_path(root.join("/areum/jsx-runtime"))
-                                .unwrap()
-                                .to_string()
-                        ));
-                        env.bundler.push(format!(
-                            r#"
-                            import {{ default as mod }} from "{}"
-
-                            let Page;
-                            if (typeof mod === "function") {{
-                                Page = mod;
-                            }} else {{
-                                Page = mod["{}"];
-                            }}
-
-                            run(Page, {{}})
-                            "#,
-                            url.to_string(),
-                            path.to_string_lossy()
-                        ));
-
-                        page.script = env.bundle().await?;
-
-                        responder.send(Ok(page)).unwrap_or_else(|_| panic!("error sending to channel"));
+                        }
                     },
-                    _ = rx_stop.recv() => {
+                    Some(_) = rx_stop.recv() => {
                         break;
                     }
+                    else => break,
                 }
             }
 
             Ok::<(), anyhow::Error>(())
         };
 
+        // Requests fail on their own, so this is the only place the error
+        // is reported
         if let Err(err) = rt.block_on(future) {
-            eprintln!("{}", err);
+            tracing::error!("env stopped: {:#}", err);
         };
     });
 
-    (join_handle, tx_job, tx_stop)
+    EnvHandle {
+        thread: join_handle,
+        tx_stop,
+        rx_ready,
+    }
+}
+
+/// Bundles the client script of the page at `path`, rendered from `url`.
+async fn bundle_page(
+    env: &mut Env,
+    root: &Path,
+    url: &Url,
+    path: &Path,
+) -> Result<String, anyhow::Error> {
+    env.bundler.clear();
+    env.bundler.push(format!(
+        r#"import {{ run }} from "{}"
+        "#,
+        file_url(&root.join("/areum/jsx-runtime"))?
+    ));
+    env.bundler.push(page_script(url, path));
+
+    env.bundle().await
+}
+
+/// Script running the page at `path`, which generators export under its site
+/// path, from the module at `url`.
+fn page_script(url: &Url, path: &Path) -> String {
+    format!(
+        r#"
+        import {{ default as mod }} from "{}"
+
+        let Page;
+        if (typeof mod === "function") {{
+            Page = mod;
+        }} else {{
+            Page = mod["{}"];
+        }}
+
+        run(Page, {{}})
+        "#,
+        url,
+        to_slash(path)
+    )
+}
+
+#[derive(Default)]
+pub struct ServerOptions {
+    /// `user:password` required with HTTP basic auth, overriding
+    /// `server.auth` in the config.
+    pub auth: Option<String>,
+    /// Bearer or query token required, overriding `server.auth_token` in the
+    /// config.
+    pub auth_token: Option<String>,
 }
 
 impl Server {
-    pub fn new(root: &Path) -> Result<(Self, broadcast::Sender<Command>), anyhow::Error> {
-        let root = root.to_path_buf().canonicalize()?;
-        let src_fs = SrcFs::new(&root);
+    pub fn new(
+        root: &Path,
+        options: ServerOptions,
+    ) -> Result<(Self, broadcast::Sender<Command>), anyhow::Error> {
+        let root = canonicalize(root)?;
+        let config = Config::load(&root)?;
+        let auth = Auth::new(
+            options.auth.or(config.server.auth.clone()),
+            options.auth_token.or(config.server.auth_token.clone()),
+        )?;
+        let src_fs = SrcFs::new(&root, config.src_fs_options()?);
+        let error_template = ErrorTemplate::new(config.server.error_template(&root)?);
+        let global_styles = config.css.global_styles()?;
+        let policy = RoutePolicy {
+            trailing_slash: config.server.trailing_slash,
+            case_sensitivity: config.server.case_sensitivity,
+        };
 
-        let (mut handle, tx_job, mut tx_stop) = spawn_env(&root);
+        let workers = config.server.workers();
+        let (tx_gate, rx_gate) = watch::channel(None);
+        let tx_job = JobSender(rx_gate);
+        // Pages the dev server renders show its status
+        let transforms: Transforms = Arc::new(RwLock::new(vec![Box::new(StatusClient)]));
+        let site = Site {
+            src_fs: src_fs.clone(),
+            policy,
+            global_styles,
+            taxonomies: Taxonomies::new(&root, config.taxonomies.clone()),
+            transforms: transforms.clone(),
+            routes: Arc::new(StdMutex::new(None)),
+            outputs: Arc::new(StdMutex::new(HashMap::new())),
+        };
+        let new_handler = |site: Site, tx_job: JobSender, error_template: ErrorTemplate| {
+            move |request| handle(request, site, tx_job, error_template)
+        };
 
-        let tx_job = Arc::new(Mutex::new(tx_job));
-        let new_handler = |src_fs: SrcFs, tx_job: Arc<Mutex<mpsc::Sender<Message>>>| {
-            |request| get_page(request, src_fs, tx_job)
+        let (tx_cmd, rx_cmd) = broadcast::channel(16);
+        let (tx_status, _) = broadcast::channel(16);
+        let status_socket = StatusSocket {
+            tx_status: tx_status.clone(),
+            tx_cmd: tx_cmd.clone(),
         };
 
         let router = Router::new();
+        let router = router.route(
+            STATUS_PATH,
+            routing::get(status_socket_handler).with_state(status_socket),
+        );
         let router = router.route(
             "/",
-            routing::get(new_handler(src_fs.clone(), tx_job.clone())),
+            routing::get(new_handler(
+                site.clone(),
+                tx_job.clone(),
+                error_template.clone(),
+            )),
         );
         let router = router.route(
             "/*path",
-            routing::get(new_handler(src_fs.clone(), tx_job.clone())),
+            routing::get(new_handler(site, tx_job.clone(), error_template.clone())),
         );
+        // Layered over every route, so nothing is served without credentials
+        let router = match auth {
+            Some(auth) => router.layer(middleware::from_fn_with_state(Arc::new(auth), authorize)),
+            None => router,
+        };
 
-        let (tx_cmd, rx_cmd) = broadcast::channel(16);
+        let (tx_stopped, rx_stopped) = watch::channel(None);
+        let tx_stopped = Arc::new(tx_stopped);
+        let shutdown_timeout = config.server.shutdown_timeout();
 
         let mut rx_cmd_ = tx_cmd.subscribe();
         let src_fs_ = src_fs.clone();
+        let routes = site.routes.clone();
+        let outputs = site.outputs.clone();
         tokio::spawn(async move {
+            let mut config = config;
+            // Generated modules list the sources, which `serve` may not have
+            // scanned yet
+            if let Err(err) = src_fs_.scan().await {
+                tracing::error!("{:#}", err);
+            }
+            let data_modules = generate_modules(&root, &config, &src_fs_, &[]).await;
+            let mut pool =
+                start_pool(&root, &config, data_modules, workers, &tx_gate, &tx_status).await;
+            // Without a watcher, as when out of inotify watches, requests
+            // are still served, just not reloaded on changes
+            let mut rx_change = match src_fs_.watch().await {
+                Ok(rx_change) => Some(rx_change),
+                Err(err) => {
+                    tracing::error!("could not watch for changes: {:#}", err);
+                    None
+                }
+            };
+
             loop {
-                match rx_cmd_.recv().await.unwrap() {
-                    Command::Restart => {
-                        let _ = tx_stop.send(true).await;
-                        let (handle_, tx_job_, tx_stop_) = spawn_env(&root);
-                        src_fs_.scan().await.unwrap();
-
-                        *tx_job.lock().await = tx_job_;
-                        drop(tx_stop);
-                        handle.join().unwrap();
-
-                        handle = handle_;
-                        tx_stop = tx_stop_;
+                // `SrcFs` is already up to date, so only changes to loaded
+                // modules need fresh envs
+                let restart = tokio::select! {
+                    cmd = rx_cmd_.recv() => match cmd {
+                        Ok(Command::Restart) => {
+                            let prev = src_fs_.snapshot().await;
+                            if let Err(err) = src_fs_.scan().await {
+                                tracing::error!("{:#}", err);
+                            }
+                            src_fs_
+                                .changed_since(&prev)
+                                .await
+                                .into_iter()
+                                .any(|change| pool.needs_restart(&root, &change))
+                        }
+                        // Missed commands could be restarts
+                        Err(RecvError::Lagged(_)) => true,
+                        Ok(Command::Stop) | Err(RecvError::Closed) => {
+                            for env in &pool.envs {
+                                let _ = env.tx_stop.try_send(true);
+                            }
+
+                            drop(tx_gate);
+                            drop(pool.tx_job);
+                            // Joined on threads of their own, so a stuck env
+                            // cannot keep the process from exiting once the
+                            // shutdown times out
+                            tx_stopped.send_replace(Some(pool.envs.len()));
+                            for env in pool.envs {
+                                drop(env.tx_stop);
+                                let tx_stopped = tx_stopped.clone();
+                                thread::spawn(move || {
+                                    let _ = env.thread.join();
+                                    tx_stopped.send_modify(|left| *left = left.map(|n| n - 1));
+                                });
+                            }
+
+                            break;
+                        }
+                    },
+                    change = next_change(&mut rx_change) => {
+                        // Coalesce a burst of changes into a single restart.
+                        // Missed changes could be to anything.
+                        let mut restart = change.map_or(true, |c| pool.needs_restart(&root, &c));
+                        while let Some(rx_change) = &mut rx_change {
+                            match rx_change.try_recv() {
+                                Ok(change) => restart |= pool.needs_restart(&root, &change),
+                                Err(TryRecvError::Lagged(_)) => restart = true,
+                                Err(_) => break,
+                            }
+                        }
+                        restart
                     }
-                    Command::Stop => {
-                        let _ = tx_stop.send(true).await;
+                };
 
-                        drop(tx_job);
-                        drop(tx_stop);
-                        handle.join().unwrap();
+                // Generated modules change with pages and their frontmatter,
+                // which envs do not load as modules
+                let data_modules =
+                    generate_modules(&root, &config, &src_fs_, &pool.data_modules).await;
+                let restart = restart || data_modules != pool.data_modules;
 
-                        break;
+                if restart {
+                    tracing::info!("restarting envs");
+                    let _ = tx_status.send(Status::Restarting);
+                    routes.lock().unwrap().take();
+                    outputs.lock().unwrap().clear();
+                    // Closing the gate drops the old pool's job sender, so its
+                    // envs exit after finishing the requests they already
+                    // have, while new requests wait for the new pool
+                    tx_gate.send_replace(None);
+                    drop(pool);
+
+                    // Only envs pick up config changes. An invalid config
+                    // keeps the last valid one.
+                    match Config::load(&root) {
+                        Ok(config_) => config = config_,
+                        Err(err) => tracing::error!("{:#}", err),
                     }
+
+                    let data_modules =
+                        generate_modules(&root, &config, &src_fs_, &data_modules).await;
+                    pool = start_pool(&root, &config, data_modules, workers, &tx_gate, &tx_status)
+                        .await;
                 }
             }
         });
@@ -181,91 +521,828 @@ impl Server {
             router,
             rx_cmd,
             src_fs,
+            transforms,
+            rx_stopped,
+            shutdown_timeout,
         };
         Ok((server, tx_cmd))
     }
 
-    pub async fn serve(self, address: &str) -> Result<(), anyhow::Error> {
+    /// Registers a transform run on every page, after those already registered.
+    pub fn with_transform(self, transform: Box<dyn PageTransform>) -> Self {
+        self.transforms.write().unwrap().push(transform);
+        self
+    }
+
+    /// Serves until stopped with [`Command::Stop`]. Shutting down fails if
+    /// open requests and envs take longer than `server.shutdown_timeout`.
+    pub async fn serve(mut self, address: &str) -> Result<(), anyhow::Error> {
         self.src_fs.scan().await?;
-        let listener = tokio::net::TcpListener::bind(address).await?;
-        axum::serve(listener, self.router)
+        let listener = tokio::net::TcpListener::bind(address)
+            .await
+            .map_err(|err| bind_error(address, err))?;
+
+        let (tx_stopping, rx_stopping) = oneshot::channel();
+        let mut rx_cmd = self.rx_cmd.resubscribe();
+        let serve = axum::serve(listener, self.router)
             .with_graceful_shutdown(async move {
                 loop {
-                    match self.rx_cmd.resubscribe().recv().await.unwrap() {
-                        Command::Stop => {
-                            break;
-                        }
-                        _ => {}
+                    match rx_cmd.recv().await {
+                        Ok(Command::Stop) | Err(RecvError::Closed) => break,
+                        // Stopping is never missed, as lagging only drops the
+                        // oldest commands
+                        Ok(Command::Restart) | Err(RecvError::Lagged(_)) => {}
                     }
                 }
+                let _ = tx_stopping.send(());
             })
-            .await?;
-        Ok(())
+            .into_future();
+        tokio::pin!(serve);
+
+        tokio::select! {
+            result = &mut serve => return Ok(result?),
+            Ok(()) = rx_stopping => {}
+        }
+
+        let mut served = false;
+        let stopped = async {
+            (&mut serve).await?;
+            served = true;
+            // Envs stop once they are done with the requests they have
+            self.rx_stopped.wait_for(|left| *left == Some(0)).await?;
+            Ok::<_, anyhow::Error>(())
+        };
+        let result = tokio::time::timeout(self.shutdown_timeout, stopped).await;
+        match result {
+            Ok(result) => result,
+            Err(_) => {
+                let mut stuck = Vec::new();
+                if !served {
+                    stuck.push("open requests".to_string());
+                }
+                match *self.rx_stopped.borrow() {
+                    Some(0) => {}
+                    Some(1) => stuck.push("1 env".into()),
+                    Some(n) => stuck.push(format!("{} envs", n)),
+                    None => stuck.push("envs".into()),
+                }
+                Err(anyhow!(
+                    "{} did not shut down within {:?}",
+                    stuck.join(" and "),
+                    self.shutdown_timeout
+                ))
+            }
+        }
     }
 }
 
-async fn get_page(
-    request: Request,
+/// Explains the usual reasons the server cannot listen on `address`.
+fn bind_error(address: &str, err: io::Error) -> anyhow::Error {
+    match err.kind() {
+        io::ErrorKind::AddrInUse => anyhow!(
+            "{} is already in use, stop whatever is using it or pick another address with --address",
+            address
+        ),
+        io::ErrorKind::PermissionDenied => anyhow!(
+            "not allowed to listen on {}, ports below 1024 usually need elevated privileges, pick another with --address",
+            address
+        ),
+        _ => anyhow!(err).context(format!("could not listen on {}", address)),
+    }
+}
+
+/// Spawns a pool of envs and opens the gate to it once they are ready,
+/// reporting whether they bootstrapped on `tx_status`.
+async fn start_pool(
+    root: &PathBuf,
+    config: &Config,
+    data_modules: Vec<(&'static str, String)>,
+    workers: usize,
+    tx_gate: &watch::Sender<Option<mpsc::Sender<Message>>>,
+    tx_status: &broadcast::Sender<Status>,
+) -> EnvPool {
+    let (tx_job, rx_job) = mpsc::channel(16);
+    let rx_job = Arc::new(Mutex::new(rx_job));
+
+    // Git history is read once per pool rather than by every env
+    let commits = git::site_commits(root);
+    let mut envs = (0..workers)
+        .map(|_| spawn_env(root, config, &data_modules, &commits, rx_job.clone()))
+        .collect::<Vec<_>>();
+
+    // If bootstrapping failed, requests fail on the closed channel instead of
+    // waiting forever
+    let mut loaders = Vec::new();
+    let mut error = None;
+    for env in &mut envs {
+        match (&mut env.rx_ready).await {
+            Ok(Ok(loader)) => loaders.push(loader),
+            Ok(Err(err)) => error = error.or(Some(err)),
+            Err(_) => error = error.or(Some("env stopped while bootstrapping".into())),
+        }
+    }
+    tx_gate.send_replace(Some(tx_job.clone()));
+    // Nobody may be listening
+    let _ = tx_status.send(match error {
+        Some(message) => Status::Failed { message },
+        None => Status::Ready,
+    });
+
+    EnvPool {
+        envs,
+        tx_job,
+        loaders,
+        data_modules,
+    }
+}
+
+/// Sources of the modules generated from the current pages. If they cannot
+/// be generated, such as while frontmatter is half written, the error is
+/// logged and `last` kept.
+async fn generate_modules(
+    root: &Path,
+    config: &Config,
+    src_fs: &SrcFs,
+    last: &[(&'static str, String)],
+) -> Vec<(&'static str, String)> {
+    let collections = Collections::new(root, config.collections.clone());
+    collections.modules(src_fs).await.unwrap_or_else(|err| {
+        tracing::error!("could not collect pages: {:#}", err);
+        last.to_vec()
+    })
+}
+
+/// Next change the watcher sends, or never if there is no watcher.
+async fn next_change(
+    rx_change: &mut Option<broadcast::Receiver<SrcChange>>,
+) -> Result<SrcChange, RecvError> {
+    match rx_change {
+        Some(rx_change) => rx_change.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+const RENDER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Cookie a `token` query parameter is kept in, so pages opened with one can
+/// load their scripts and styles.
+const TOKEN_COOKIE: &str = "areum_token";
+
+/// Credentials the server requires, either of which lets a request through.
+struct Auth {
+    /// Expected `Authorization` header value for basic auth.
+    basic: Option<String>,
+    token: Option<String>,
+    /// Token as kept in [`TOKEN_COOKIE`], percent-encoded.
+    cookie: Option<String>,
+}
+
+impl Auth {
+    fn new(basic: Option<String>, token: Option<String>) -> Result<Option<Self>, anyhow::Error> {
+        if let Some(basic) = &basic {
+            if !basic.contains(':') {
+                return Err(anyhow!("auth must be given as user:password"));
+            }
+        }
+        if token.as_deref() == Some("") {
+            return Err(anyhow!("auth token must not be empty"));
+        }
+        if basic.is_none() && token.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Auth {
+            basic: basic.map(|basic| format!("Basic {}", BASE64.encode(basic))),
+            cookie: token
+                .as_ref()
+                .map(|token| url::form_urlencoded::byte_serialize(token.as_bytes()).collect()),
+            token,
+        }))
+    }
+
+    fn allows(&self, request: &Request) -> bool {
+        let authorization = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .map(HeaderValue::as_bytes)
+            .unwrap_or_default();
+
+        if let Some(basic) = &self.basic {
+            if constant_time_eq(authorization, basic.as_bytes()) {
+                return true;
+            }
+        }
+
+        let Some(token) = &self.token else {
+            return false;
+        };
+        if let Some(bearer) = authorization.strip_prefix(b"Bearer ") {
+            if constant_time_eq(bearer, token.as_bytes()) {
+                return true;
+            }
+        }
+        let cookie = self.cookie.as_deref().unwrap_or_default();
+        let from_cookie = request
+            .headers()
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|cookie| cookie.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .any(|(name, value)| {
+                name == TOKEN_COOKIE && constant_time_eq(value.as_bytes(), cookie.as_bytes())
+            });
+        from_cookie || self.query_token(request)
+    }
+
+    /// Whether the request has the token as a query parameter.
+    fn query_token(&self, request: &Request) -> bool {
+        let (Some(token), Some(query)) = (&self.token, request.uri().query()) else {
+            return false;
+        };
+        url::form_urlencoded::parse(query.as_bytes()).any(|(key, value)| {
+            key == "token" && constant_time_eq(value.as_bytes(), token.as_bytes())
+        })
+    }
+}
+
+/// Compares secrets in time depending only on their lengths, so timing does
+/// not reveal how much of a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Rejects requests without valid credentials with a 401 challenge. Only the
+/// path is logged, never the credentials or query.
+async fn authorize(State(auth): State<Arc<Auth>>, request: Request, next: Next) -> Response {
+    if auth.allows(&request) {
+        let query_token = auth.query_token(&request);
+        let mut response = next.run(request).await;
+        if let (true, Some(cookie)) = (query_token, &auth.cookie) {
+            let cookie = format!(
+                "{}={}; Path=/; HttpOnly; SameSite=Strict",
+                TOKEN_COOKIE, cookie
+            );
+            if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+                response.headers_mut().append(header::SET_COOKIE, cookie);
+            }
+        }
+        return response;
+    }
+
+    tracing::debug!(path = %request.uri().path(), "unauthorized request");
+    let mut response = (StatusCode::UNAUTHORIZED, "unauthorized").into_response();
+    let headers = response.headers_mut();
+    if auth.basic.is_some() {
+        headers.append(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static(r#"Basic realm="areum", charset="UTF-8""#),
+        );
+    }
+    if auth.token.is_some() {
+        headers.append(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static(r#"Bearer realm="areum""#),
+        );
+    }
+    response
+}
+
+/// Channels a status WebSocket listens on.
+#[derive(Clone)]
+struct StatusSocket {
+    tx_status: broadcast::Sender<Status>,
+    /// Sockets close on [`Command::Stop`], so they do not hold up shutting
+    /// down.
+    tx_cmd: broadcast::Sender<Command>,
+}
+
+async fn status_socket_handler(
+    State(socket): State<StatusSocket>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    // Subscribed before upgrading, so no status sent in between is missed
+    let rx_status = socket.tx_status.subscribe();
+    let rx_cmd = socket.tx_cmd.subscribe();
+    upgrade.on_upgrade(move |ws| send_status(ws, rx_status, rx_cmd))
+}
+
+/// Sends every status to `ws` until it closes or the server stops.
+async fn send_status(
+    mut ws: WebSocket,
+    mut rx_status: broadcast::Receiver<Status>,
+    mut rx_cmd: broadcast::Receiver<Command>,
+) {
+    loop {
+        tokio::select! {
+            status = rx_status.recv() => {
+                let status = match status {
+                    Ok(status) => status,
+                    // Later statuses supersede missed ones
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                let json = serde_json::to_string(&status).unwrap();
+                if ws.send(WsMessage::Text(json)).await.is_err() {
+                    break;
+                }
+            }
+            cmd = rx_cmd.recv() => match cmd {
+                Ok(Command::Restart) | Err(RecvError::Lagged(_)) => {}
+                Ok(Command::Stop) | Err(RecvError::Closed) => break,
+            },
+            // Browsers send nothing but pings and closing frames
+            message = ws.recv() => match message {
+                Some(Ok(WsMessage::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = ws.send(WsMessage::Close(None)).await;
+}
+
+/// Client showing the status of the envs in a corner of every page, from
+/// [`STATUS_PATH`].
+const STATUS_CLIENT: &str = include_str!("ts/status-client.js");
+
+struct StatusClient;
+
+impl PageTransform for StatusClient {
+    fn element_handlers<'a>(
+        &'a self,
+        _page: &'a Page,
+        _ctx: &'a TransformCtx,
+    ) -> Vec<ElementHandler<'a>> {
+        vec![element!("body", |el| {
+            el.append(
+                &format!("<script>{}</script>", STATUS_CLIENT),
+                ContentType::Html,
+            );
+            Ok(())
+        })]
+    }
+}
+
+/// How requested routes are matched against files.
+#[derive(Clone, Copy)]
+struct RoutePolicy {
+    trailing_slash: TrailingSlash,
+    case_sensitivity: CaseSensitivity,
+}
+
+/// What every request is handled with.
+#[derive(Clone)]
+struct Site {
     src_fs: SrcFs,
-    tx: Arc<Mutex<mpsc::Sender<Message>>>,
-) -> Result<impl IntoResponse, ServerError> {
+    policy: RoutePolicy,
+    global_styles: GlobSet,
+    taxonomies: Taxonomies,
+    transforms: Transforms,
+    /// Listing of `/__areum/routes`, kept until envs restart.
+    routes: Arc<StdMutex<Option<Arc<Vec<RouteEntry>>>>>,
+    /// Files the pages rendered so far export as `outputs`, by site path,
+    /// kept until envs restart.
+    outputs: Arc<StdMutex<HashMap<String, String>>>,
+}
+
+async fn handle(
+    request: Request,
+    site: Site,
+    tx: JobSender,
+    error_template: ErrorTemplate,
+) -> Response {
+    let span = tracing::debug_span!("request", path = %request.uri().path());
+    match route(request, &site, tx).instrument(span.clone()).await {
+        Ok(response) => response,
+        Err(err) => {
+            span.in_scope(|| match err {
+                ServerError::NotFound(_) | ServerError::BadRequest(_) => {
+                    tracing::debug!("{:#}", err.error())
+                }
+                _ => tracing::error!("{:#}", err.error()),
+            });
+            err.into_response(&error_template)
+        }
+    }
+}
+
+async fn route(request: Request, site: &Site, tx: JobSender) -> Result<Response, ServerError> {
+    let (src_fs, policy) = (&site.src_fs, site.policy);
     let abspath = request.uri().path();
-    let relpath = abspath.trim_matches('/');
-
-    let (url, path, generator) = if let Some(file) = src_fs.find(relpath).await {
-        match file.kind {
-            SrcKind::Jsx | SrcKind::Mdx => (
-                Url::from_file_path(&file.path).unwrap(),
-                PathBuf::from_str(relpath).unwrap(),
-                file.generator,
-            ),
-            SrcKind::Css => {
-                return Ok(
-                    ([(header::CONTENT_TYPE, "text/css")], src_fs.read(&file)?).into_response()
-                );
-            }
-            SrcKind::Js => {
+    let query = request
+        .uri()
+        .query()
+        .map(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default();
+    let relpath = decode_route(abspath)
+        .ok_or_else(|| ServerError::BadRequest(anyhow!("invalid path {}", abspath)))?;
+
+    if Path::new(&relpath)
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(ServerError::BadRequest(anyhow!("invalid path {}", abspath)));
+    }
+
+    match abspath {
+        ROUTES_PATH => {
+            let routes = list_routes(site, tx).await?;
+            return Ok(Html(routes_html(&routes)).into_response());
+        }
+        ROUTES_JSON_PATH => {
+            let routes = list_routes(site, tx).await?;
+            return Ok(Json(routes.to_vec()).into_response());
+        }
+        _ => {}
+    }
+
+    let Some(file) = src_fs.find(abspath, policy.case_sensitivity).await else {
+        // Pages the default locale has but others do not are served in them too
+        if let Some((src, path, translations)) = src_fs.find_fallback(abspath).await {
+            let url = file_url(&src.path)?;
+            let job = Job::Page(translations);
+            return get_page(site, url, &to_slash(&path), query, job, tx).await;
+        }
+        // Outputs are only known once the page exporting them is rendered
+        let output = site.outputs.lock().unwrap().get(&relpath).cloned();
+        if let Some(contents) = output {
+            let content_type = output_content_type(&relpath);
+            return Ok(([(header::CONTENT_TYPE, content_type)], contents).into_response());
+        }
+        return get_taxonomy_page(site, &relpath, query, tx).await;
+    };
+
+    let canonical = canonical_path(src_fs, &file, &relpath).await?;
+    if canonical != relpath && canonical.to_lowercase() == relpath.to_lowercase() {
+        tracing::warn!(
+            "{} resolved case-insensitively, use {} instead",
+            abspath,
+            encode_route(Path::new(&canonical))
+        );
+    }
+
+    if abspath.len() > 1 && abspath.ends_with('/') {
+        match policy.trailing_slash {
+            TrailingSlash::Redirect => {
+                let mut location = encode_route(Path::new(&canonical));
+                if let Some(query) = request.uri().query() {
+                    location = format!("{}?{}", location, query);
+                }
                 return Ok((
-                    [(header::CONTENT_TYPE, "text/javascript")],
-                    src_fs.read(&file)?,
+                    StatusCode::MOVED_PERMANENTLY,
+                    [(header::LOCATION, location)],
                 )
                     .into_response());
             }
-            _ => {
-                return Ok(src_fs.read(&file)?.into_response());
+            TrailingSlash::Ignore => {}
+            TrailingSlash::Strict => {
+                return Err(ServerError::NotFound(anyhow!(
+                    "could not find page {}, did you mean {}",
+                    abspath,
+                    encode_route(Path::new(&canonical))
+                )));
             }
         }
-    } else {
-        return Err(anyhow!("could not find page").into());
+    }
+
+    match file.kind {
+        SrcKind::Jsx | SrcKind::Mdx | SrcKind::Md => {
+            let translations = src_fs.translations(&file).await?;
+            let job = if file.generator {
+                Job::Generator(translations)
+            } else {
+                Job::Page(translations)
+            };
+            let url = file_url(&file.path)?;
+            get_page(site, url, &canonical, query, job, tx).await
+        }
+        _ => get_asset(src_fs, &file).await,
+    }
+}
+
+/// Renders the taxonomy page at `relpath`, if there is one.
+async fn get_taxonomy_page(
+    site: &Site,
+    relpath: &str,
+    query: BTreeMap<String, String>,
+    tx: JobSender,
+) -> Result<Response, ServerError> {
+    let not_found = || ServerError::NotFound(anyhow!("could not find page /{}", relpath));
+    let relpath = relpath.trim_end_matches('/');
+    if !site.taxonomies.contains(Path::new(relpath)) {
+        return Err(not_found());
+    }
+
+    // Terms are collected for every request, so they follow changes to pages
+    for taxonomy in site.taxonomies.collect(&site.src_fs).await? {
+        if let Some(props) = taxonomy.pages.get(relpath) {
+            let pages = BTreeMap::from([(relpath.to_string(), props.clone())]);
+            let job = Job::Each(pages);
+            return get_page(site, taxonomy.template, relpath, query, job, tx).await;
+        }
+    }
+
+    Err(not_found())
+}
+
+/// WebSocket sending each [`Status`] of the envs as it changes.
+const STATUS_PATH: &str = "/__areum_status";
+
+/// Lists every route of the site, with links.
+const ROUTES_PATH: &str = "/__areum/routes";
+/// Lists every route of the site as JSON.
+const ROUTES_JSON_PATH: &str = "/__areum/routes.json";
+
+/// Route of the site and what serves it.
+#[derive(Serialize, Clone)]
+struct RouteEntry {
+    route: String,
+    /// Source relative to the root, with `/` separators.
+    source: String,
+    /// `page`, `generator` or `taxonomy`.
+    kind: &'static str,
+}
+
+/// Every route of the site, sorted: pages, the paths generators generate and
+/// taxonomy pages. Generators are evaluated on the env thread, so the listing
+/// is kept until envs restart.
+async fn list_routes(site: &Site, mut tx: JobSender) -> Result<Arc<Vec<RouteEntry>>, ServerError> {
+    if let Some(routes) = site.routes.lock().unwrap().clone() {
+        return Ok(routes);
+    }
+
+    let src_fs = &site.src_fs;
+    let root = src_fs.root().await;
+    let source = |path: &Path| to_slash(path.strip_prefix(&root).unwrap_or(path));
+    // Routes take the lock again, so it is released first
+    let (pages, generators): (Vec<SrcFile>, Vec<PathBuf>) = {
+        let guard = src_fs.lock().await;
+        (
+            guard.iter_pages().cloned().collect(),
+            guard
+                .iter_generators()
+                .map(|src| src.path.clone())
+                .collect(),
+        )
     };
+    let mut routes = Vec::new();
+    for src in &pages {
+        routes.push(RouteEntry {
+            route: src_fs.route(src).await?,
+            source: source(&src.path),
+            kind: "page",
+        });
+    }
 
-    let (tx_page, rx_page) = oneshot::channel();
-    tx.lock()
+    let (tx_routes, rx_routes) = oneshot::channel();
+    let message = Message::ListRoutes {
+        generators: generators
+            .iter()
+            .map(|path| file_url(path))
+            .collect::<Result<_, _>>()?,
+        responder: tx_routes,
+    };
+    let list = async {
+        tx.get()
+            .await?
+            .send(message)
+            .await
+            .map_err(|_| anyhow!("env is not running"))?;
+        rx_routes.await?
+    };
+    let generated = tokio::time::timeout(RENDER_TIMEOUT, list)
         .await
-        .send(Message {
-            url,
-            path,
-            generator,
-            responder: tx_page,
+        .map_err(|_| {
+            ServerError::Timeout(anyhow!(
+                "listing generated routes took longer than {:?}",
+                RENDER_TIMEOUT
+            ))
+        })??;
+    for (generator, paths) in generators.iter().zip(generated) {
+        for path in paths {
+            routes.push(RouteEntry {
+                route: encode_route(Path::new(&path)),
+                source: source(generator),
+                kind: "generator",
+            });
+        }
+    }
+
+    for taxonomy in site.taxonomies.collect(src_fs).await? {
+        let template = file_path(&taxonomy.template)?;
+        for path in taxonomy.pages.keys() {
+            routes.push(RouteEntry {
+                route: encode_route(Path::new(path)),
+                source: source(&template),
+                kind: "taxonomy",
+            });
+        }
+    }
+
+    routes.sort_by(|a, b| a.route.cmp(&b.route));
+    let routes = Arc::new(routes);
+    *site.routes.lock().unwrap() = Some(routes.clone());
+    Ok(routes)
+}
+
+fn routes_html(routes: &[RouteEntry]) -> String {
+    let rows = routes
+        .iter()
+        .map(|entry| {
+            format!(
+                r#"<tr><td><a href="{}">{}</a></td><td>{}</td><td>{}</td></tr>"#,
+                escape_html(&entry.route),
+                escape_html(&entry.route),
+                entry.kind,
+                escape_html(&entry.source)
+            )
         })
+        .collect::<String>();
+    format!(
+        r#"<!DOCTYPE html><html><head><meta charset="utf-8"><title>Routes</title></head><body><h1>{} routes</h1><table><tr><th>Route</th><th>Kind</th><th>Source</th></tr>{}</table></body></html>"#,
+        routes.len(),
+        rows
+    )
+}
+
+/// Canonical site path of the route `relpath`, which resolved to `file`.
+async fn canonical_path(
+    src_fs: &SrcFs,
+    file: &SrcFile,
+    relpath: &str,
+) -> Result<String, anyhow::Error> {
+    let site_path = src_fs.site_path(file).await?;
+
+    // Generators serve any name in their directory
+    let site_path = if file.generator {
+        let name = Path::new(relpath).file_name().unwrap_or_default();
+        site_path.parent().unwrap_or(&site_path).join(name)
+    } else {
+        site_path
+    };
+
+    Ok(to_slash(&site_path))
+}
+
+/// Serves a static asset straight from `SrcFs` without involving the env.
+async fn get_asset(src_fs: &SrcFs, file: &SrcFile) -> Result<Response, ServerError> {
+    let content_type = match file.kind {
+        SrcKind::Css => "text/css",
+        SrcKind::Js => "text/javascript",
+        _ => "application/octet-stream",
+    };
+
+    Ok((
+        [(header::CONTENT_TYPE, content_type)],
+        asset_body(src_fs, file).await?,
+    )
+        .into_response())
+}
+
+/// Content type of a page output, from its extension. Outputs are text, so
+/// anything unknown is served as plain text.
+fn output_content_type(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("json") => "application/json",
+        Some("webmanifest") => "application/manifest+json",
+        Some("xml") => "application/xml",
+        Some("rss") => "application/rss+xml",
+        Some("atom") => "application/atom+xml",
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") | Some("mjs") => "text/javascript",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        _ => "text/plain; charset=utf-8",
+    }
+}
+
+/// Renders a page on the env thread.
+async fn get_page(
+    site: &Site,
+    url: Url,
+    relpath: &str,
+    query: BTreeMap<String, String>,
+    job: Job,
+    mut tx: JobSender,
+) -> Result<Response, ServerError> {
+    let (tx_page, rx_page) = oneshot::channel();
+    // Looked up for every request, so they follow layouts being added
+    let layouts = site.src_fs.layouts(&file_path(&url)?).await?;
+    let message = Message::Render(Render {
+        url,
+        path: PathBuf::from_str(relpath).unwrap(),
+        layouts,
+        query,
+        responder: tx_page,
+        job,
+    });
+
+    // Waiting for a restarting env counts towards the timeout
+    let render = async {
+        tx.get()
+            .await?
+            .send(message)
+            .await
+            .map_err(|_| anyhow!("env is not running"))?;
+        Ok::<_, anyhow::Error>(rx_page.await?)
+    };
+
+    let page = tokio::time::timeout(RENDER_TIMEOUT, render)
         .await
-        .unwrap();
+        .map_err(|_| {
+            ServerError::Timeout(anyhow!(
+                "rendering /{} took longer than {:?}",
+                relpath,
+                RENDER_TIMEOUT
+            ))
+        })??;
+    if let Ok(page) = &page {
+        let mut outputs = site.outputs.lock().unwrap();
+        for (path, contents) in &page.outputs {
+            outputs.insert(to_slash(path), contents.clone());
+        }
+    }
 
-    let page = rx_page.await?;
-    let html = page?.render_to_string()?;
+    // Served as they are, only builds minify them
+    let mut global_hrefs = Vec::new();
+    let src_fs = &site.src_fs;
+    // Routes take the lock again, so it is released first
+    let globals: Vec<SrcFile> = {
+        let guard = src_fs.lock().await;
+        guard
+            .iter_global_styles(&site.global_styles)
+            .cloned()
+            .collect()
+    };
+    for src in &globals {
+        global_hrefs.push(src_fs.route(src).await?);
+    }
+
+    let html = page
+        .and_then(|mut page| {
+            page.link_global_styles(global_hrefs);
+            let mut output = Vec::new();
+            page.render_with(&mut output, &site.transforms.read().unwrap())?;
+            Ok(String::from_utf8(output)?)
+        })
+        .map_err(ServerError::Render)?;
 
     Ok(Html(html).into_response())
 }
 
-struct ServerError(anyhow::Error);
+/// Buffers small assets and streams large ones.
+async fn asset_body(src_fs: &SrcFs, file: &SrcFile) -> Result<Body, anyhow::Error> {
+    if file.size > SrcFs::STREAM_THRESHOLD {
+        let reader = src_fs.open(file).await?;
+        Ok(Body::from_stream(ReaderStream::with_capacity(
+            reader,
+            SrcFs::STREAM_BUFFER_SIZE,
+        )))
+    } else {
+        Ok(Body::from(src_fs.read(file)?))
+    }
+}
+
+enum ServerError {
+    NotFound(anyhow::Error),
+    BadRequest(anyhow::Error),
+    Render(anyhow::Error),
+    Timeout(anyhow::Error),
+    Internal(anyhow::Error),
+}
 
-impl IntoResponse for ServerError {
-    fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+impl ServerError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Render(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error(&self) -> &anyhow::Error {
+        match self {
+            Self::NotFound(err)
+            | Self::BadRequest(err)
+            | Self::Render(err)
+            | Self::Timeout(err)
+            | Self::Internal(err) => err,
+        }
+    }
+
+    fn into_response(self, template: &ErrorTemplate) -> Response {
+        let status = self.status();
+        (status, Html(template.render(status, self.error()))).into_response()
     }
 }
 
@@ -274,6 +1351,497 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(err: E) -> Self {
-        Self(err.into())
+        Self::Internal(err.into())
+    }
+}
+
+/// HTML template for error responses.
+///
+/// `{{status}}` and `{{error}}` are replaced with the status line and the
+/// HTML-escaped error message.
+#[derive(Clone)]
+struct ErrorTemplate(Arc<String>);
+
+impl ErrorTemplate {
+    const DEFAULT: &'static str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <title>{{status}}</title>
+    <style>
+      body { margin: 0; padding: 2rem; font-family: system-ui, sans-serif; background: #fff5f5; color: #1a1a1a; }
+      h1 { margin-top: 0; color: #c53030; font-size: 1.25rem; }
+      pre { padding: 1rem; overflow-x: auto; white-space: pre-wrap; background: #fff; border: 1px solid #feb2b2; border-radius: 0.25rem; }
+    </style>
+  </head>
+  <body>
+    <h1>{{status}}</h1>
+    <pre>{{error}}</pre>
+  </body>
+</html>
+"#;
+
+    fn new(template: Option<String>) -> Self {
+        Self(Arc::new(template.unwrap_or(Self::DEFAULT.into())))
+    }
+
+    fn render(&self, status: StatusCode, err: &anyhow::Error) -> String {
+        self.0
+            .replace("{{status}}", &status.to_string())
+            .replace("{{error}}", &escape_html(&format!("{:#}", err)))
+    }
+}
+
+fn escape_html(str: &str) -> String {
+    let mut escaped = String::with_capacity(str.len());
+    for c in str.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::*;
+    use crate::{src_fs::SrcChangeKind, testing::Fixture};
+
+    /// Sends a GET for `path` with `headers` to the server at `address`,
+    /// returning the head of its response.
+    async fn get(address: &str, path: &str, headers: &[&str]) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let mut request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n", path, address);
+        for header in headers {
+            request.push_str(&format!("{}\r\n", header));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = Vec::new();
+        let mut buf = [0; 1024];
+        while !response.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            response.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[tokio::test]
+    async fn requires_credentials_on_every_route() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("index.tsx", "export default () => <p>Home</p>;")
+            .unwrap();
+        let options = ServerOptions {
+            auth: Some("user:secret".into()),
+            auth_token: Some("token".into()),
+        };
+        let (server, tx) = Server::new(fixture.root(), options).unwrap();
+        let address = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+
+        let requests = async {
+            while TcpStream::connect(&address).await.is_err() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            let close = "Connection: close";
+            let basic = format!("Authorization: Basic {}", BASE64.encode("user:secret"));
+            let wrong = format!("Authorization: Basic {}", BASE64.encode("user:wrong"));
+
+            let rejected = get(&address, "/", &[close]).await;
+            assert!(rejected.starts_with("HTTP/1.1 401"));
+            assert!(rejected.contains(r#"www-authenticate: Basic realm="areum""#));
+            assert!(rejected.contains(r#"www-authenticate: Bearer realm="areum""#));
+            assert!(get(&address, "/", &[close, &wrong])
+                .await
+                .starts_with("HTTP/1.1 401"));
+            assert!(get(&address, "/", &[close, "Authorization: Bearer wrong"])
+                .await
+                .starts_with("HTTP/1.1 401"));
+
+            assert!(get(&address, "/", &[close, &basic])
+                .await
+                .starts_with("HTTP/1.1 200"));
+            assert!(get(&address, "/", &[close, "Authorization: Bearer token"])
+                .await
+                .starts_with("HTTP/1.1 200"));
+            let query = get(&address, "/?token=token", &[close]).await;
+            assert!(query.starts_with("HTTP/1.1 200"));
+            assert!(query.contains("set-cookie: areum_token=token;"));
+            assert!(get(&address, "/", &[close, "Cookie: areum_token=token"])
+                .await
+                .starts_with("HTTP/1.1 200"));
+
+            // The status WebSocket is behind the same check
+            let upgrade = [
+                "Connection: Upgrade",
+                "Upgrade: websocket",
+                "Sec-WebSocket-Version: 13",
+                "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==",
+            ];
+            assert!(get(&address, STATUS_PATH, &upgrade)
+                .await
+                .starts_with("HTTP/1.1 401"));
+            let upgraded = get(
+                &address,
+                STATUS_PATH,
+                &[&upgrade[..], &[basic.as_str()]].concat(),
+            )
+            .await;
+            assert!(upgraded.starts_with("HTTP/1.1 101"));
+
+            tx.send(Command::Stop).unwrap();
+        };
+
+        let (served, ()) = tokio::join!(server.serve(&address), requests);
+        served.unwrap();
+    }
+
+    /// Sends a GET for `path` to the server at `address`, returning the body
+    /// of its response.
+    async fn get_body(address: &str, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, address
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        body.to_string()
+    }
+
+    /// Opens the status WebSocket of the server at `address`.
+    async fn connect_status(address: &str) -> impl AsyncRead + Unpin {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            STATUS_PATH, address
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = Vec::new();
+        let mut buf = [0; 1024];
+        let end = loop {
+            if let Some(i) = response.windows(4).position(|w| w == b"\r\n\r\n") {
+                break i + 4;
+            }
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed before upgrading");
+            response.extend_from_slice(&buf[..n]);
+        };
+        assert!(response.starts_with(b"HTTP/1.1 101"));
+
+        // Frames may have been read along with the head
+        std::io::Cursor::new(response.split_off(end)).chain(stream)
+    }
+
+    /// Reads the next text message from a WebSocket, skipping other frames.
+    /// Servers send frames unmasked.
+    async fn next_text(ws: &mut (impl AsyncRead + Unpin)) -> String {
+        let read = async {
+            loop {
+                let mut head = [0; 2];
+                ws.read_exact(&mut head).await.unwrap();
+                let len = match head[1] & 0x7f {
+                    126 => ws.read_u16().await.unwrap() as usize,
+                    127 => ws.read_u64().await.unwrap() as usize,
+                    len => len as usize,
+                };
+                let mut payload = vec![0; len];
+                ws.read_exact(&mut payload).await.unwrap();
+                if head[0] & 0x0f == 1 {
+                    return String::from_utf8(payload).unwrap();
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(30), read)
+            .await
+            .expect("no message within 30s")
+    }
+
+    #[tokio::test]
+    async fn sends_status_across_restarts() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("index.tsx", "export default () => <p>Home</p>;")
+            .unwrap();
+        let (server, tx) = Server::new(fixture.root(), ServerOptions::default()).unwrap();
+        let address = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+
+        let statuses = async {
+            while TcpStream::connect(&address).await.is_err() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            // Pages are served once the first envs are ready
+            assert!(get(&address, "/", &["Connection: close"])
+                .await
+                .starts_with("HTTP/1.1 200"));
+            let mut ws = connect_status(&address).await;
+
+            // Envs are created with the config, so changing it restarts them
+            fixture
+                .file("areum.toml", "[build]\noutDir = \"public\"\n")
+                .unwrap();
+            tx.send(Command::Restart).unwrap();
+
+            // The first envs report being ready just after opening to requests
+            let mut status = next_text(&mut ws).await;
+            if status == r#"{"status":"ready"}"# {
+                status = next_text(&mut ws).await;
+            }
+            assert_eq!(status, r#"{"status":"restarting"}"#);
+            assert_eq!(next_text(&mut ws).await, r#"{"status":"ready"}"#);
+
+            tx.send(Command::Stop).unwrap();
+        };
+
+        let (served, ()) = tokio::join!(server.serve(&address), statuses);
+        served.unwrap();
+    }
+
+    #[tokio::test]
+    async fn lists_routes_with_generated_paths() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("index.tsx", "export default () => <p>Home</p>;")
+            .unwrap()
+            .file(
+                "blog/_.tsx",
+                "export default { c: () => <p>C</p>, a: () => <p>A</p>, b: () => <p>B</p> };",
+            )
+            .unwrap();
+        let (server, tx) = Server::new(fixture.root(), ServerOptions::default()).unwrap();
+        let address = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+
+        let requests = async {
+            while TcpStream::connect(&address).await.is_err() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            let json = get_body(&address, ROUTES_JSON_PATH).await;
+            let routes: serde_json::Value = serde_json::from_str(&json).unwrap();
+            let generated = |route: &str| {
+                serde_json::json!({
+                    "route": route,
+                    "source": "blog/_.tsx",
+                    "kind": "generator",
+                })
+            };
+            assert_eq!(
+                routes,
+                serde_json::json!([
+                    { "route": "/", "source": "index.tsx", "kind": "page" },
+                    generated("/blog/a"),
+                    generated("/blog/b"),
+                    generated("/blog/c"),
+                ])
+            );
+
+            let html = get_body(&address, ROUTES_PATH).await;
+            assert!(html.contains("<h1>4 routes</h1>"));
+            assert!(html.contains(r#"<a href="/blog/b">/blog/b</a>"#));
+            tx.send(Command::Stop).unwrap();
+        };
+
+        let (served, ()) = tokio::join!(server.serve(&address), requests);
+        served.unwrap();
+    }
+
+    #[tokio::test]
+    async fn handles_trailing_slashes_by_policy() {
+        let cases = [
+            ("redirect", ["200", "301", "301"]),
+            ("ignore", ["200", "200", "200"]),
+            ("strict", ["200", "404", "404"]),
+        ];
+        for (policy, expected) in cases {
+            let fixture = Fixture::new().unwrap();
+            let config = format!(
+                "[server]\ntrailing_slash = \"{}\"\ncase_sensitivity = \"insensitive-warn\"\n",
+                policy
+            );
+            fixture
+                .file("areum.toml", config)
+                .unwrap()
+                .file("about.tsx", "export default () => <p>About</p>;")
+                .unwrap();
+            let (server, tx) = Server::new(fixture.root(), ServerOptions::default()).unwrap();
+            let address = {
+                let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+                listener.local_addr().unwrap().to_string()
+            };
+
+            let requests = async {
+                while TcpStream::connect(&address).await.is_err() {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                for (route, status) in ["/about", "/about/", "/About/"].into_iter().zip(expected) {
+                    let response = get(&address, route, &["Connection: close"]).await;
+                    let status_line = format!("HTTP/1.1 {}", status);
+                    assert!(
+                        response.starts_with(&status_line),
+                        "{} {}: {}",
+                        policy,
+                        route,
+                        response
+                    );
+                    if status == "301" {
+                        assert!(response.contains("location: /about\r\n"), "{}", response);
+                    }
+                }
+                tx.send(Command::Stop).unwrap();
+            };
+
+            let (served, ()) = tokio::join!(server.serve(&address), requests);
+            served.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn times_out_shutting_down_stuck_envs() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "areum.toml",
+                "[server]\nworkers = 1\nshutdown_timeout = 1\n",
+            )
+            .unwrap()
+            .file("stuck.tsx", "export default () => { while (true) {} };")
+            .unwrap();
+        let (server, tx) = Server::new(fixture.root(), ServerOptions::default()).unwrap();
+        let address = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+
+        let stop = async {
+            while TcpStream::connect(&address).await.is_err() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            // The render never completes, so neither do the request and env
+            let address_ = address.clone();
+            let stuck = tokio::spawn(async move { get(&address_, "/stuck", &[]).await });
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            tx.send(Command::Stop).unwrap();
+            stuck
+        };
+
+        let (served, stuck) = tokio::join!(server.serve(&address), stop);
+        let err = served.unwrap_err().to_string();
+        assert_eq!(err, "open requests and 1 env did not shut down within 1s");
+        stuck.abort();
+    }
+
+    #[tokio::test]
+    async fn restarts_only_for_changes_to_what_envs_loaded() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "index.tsx",
+                r#"import { title } from "./title.ts"; export default () => <h1>{title}</h1>;"#,
+            )
+            .unwrap()
+            .file("title.ts", r#"export const title = "Home";"#)
+            .unwrap()
+            .file("unused.ts", "export const unused = 1;")
+            .unwrap()
+            .file("photo.png", "")
+            .unwrap();
+        let root = fixture.root();
+        let mut env = Env::new(root, &Config::default(), Mode::Dev).unwrap();
+        env.runtime
+            .add_root(&file_url(&root.join("index.tsx")).unwrap())
+            .await;
+        let pool = EnvPool {
+            envs: Vec::new(),
+            tx_job: mpsc::channel(1).0,
+            loaders: vec![env.runtime.graph_loader.clone()],
+            data_modules: Vec::new(),
+        };
+        let restarts = |path: &str, src_kind| {
+            let change = SrcChange {
+                path: root.join(path),
+                kind: SrcChangeKind::Modified,
+                src_kind,
+            };
+            pool.needs_restart(root, &change)
+        };
+
+        assert!(!restarts("photo.png", SrcKind::Other));
+        assert!(!restarts("unused.ts", SrcKind::Js));
+        assert!(restarts("index.tsx", SrcKind::Jsx));
+        assert!(restarts("title.ts", SrcKind::Js));
+        assert!(restarts(Config::FILE_NAME, SrcKind::Other));
+        assert!(restarts("_app.tsx", SrcKind::Jsx));
+    }
+
+    #[test]
+    fn page_scripts_index_generators_by_slash_path() {
+        let url = file_url(&std::env::temp_dir().join("site").join("_.tsx")).unwrap();
+        let script = page_script(&url, &Path::new("blog").join("post"));
+        assert!(script.contains(&format!(r#"from "{}""#, url)));
+        assert!(script.contains(r#"mod["blog/post"]"#));
+    }
+
+    #[tokio::test]
+    async fn keeps_serving_after_a_page_fails_to_render() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("areum.toml", "[server]\nworkers = 1\n")
+            .unwrap()
+            .file("index.tsx", "export default () => <p>Home</p>;")
+            .unwrap()
+            .file(
+                "broken.tsx",
+                "export default () => { throw new Error(\"boom\"); };",
+            )
+            .unwrap();
+        let (server, tx) = Server::new(fixture.root(), ServerOptions::default()).unwrap();
+        let address = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+
+        let requests = async {
+            while TcpStream::connect(&address).await.is_err() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            // The only env serves every request, before and after each failure
+            for _ in 0..2 {
+                let failed = get(&address, "/broken", &["Connection: close"]).await;
+                assert!(failed.starts_with("HTTP/1.1 500"), "{}", failed);
+                assert!(get_body(&address, "/").await.contains("<p>Home</p>"));
+            }
+            tx.send(Command::Stop).unwrap();
+        };
+
+        let (served, ()) = tokio::join!(server.serve(&address), requests);
+        served.unwrap();
     }
 }
@@ -1,12 +1,31 @@
 use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
     ffi::OsStr,
     fs,
     path::{Path, PathBuf},
-    sync::Arc,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    time::SystemTime,
 };
 
-use anyhow::Context;
-use tokio::sync::{RwLock, RwLockReadGuard};
+use anyhow::{anyhow, Context};
+use blake2::{digest::consts, Blake2b, Digest};
+use globset::GlobSet;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use time::{
+    format_description::well_known::Rfc3339, macros::format_description, Date, OffsetDateTime,
+};
+use tokio::{
+    io::BufReader,
+    sync::{broadcast, RwLock, RwLockReadGuard},
+};
+use unicode_normalization::UnicodeNormalization;
+use url::Url;
+
+use crate::markdown;
 
 #[derive(Clone)]
 pub struct SrcFs(Arc<RwLock<SrcFsInner>>);
@@ -14,42 +33,185 @@ pub struct SrcFs(Arc<RwLock<SrcFsInner>>);
 struct SrcFsInner {
     root: PathBuf,
     entries: Vec<SrcFile>,
+    options: SrcFsOptions,
+    watcher: Option<RecommendedWatcher>,
+    tx_change: broadcast::Sender<SrcChange>,
+}
+
+#[derive(Clone)]
+pub struct SrcFsOptions {
+    /// Whether symlinks are followed during scans. Entries reached through a
+    /// symlink keep their logical path under the root, so routes follow the
+    /// link's location rather than its target's. Symlinks to targets outside
+    /// the root and cycles are skipped with a warning. When disabled,
+    /// symlinks are ignored.
+    pub follow_symlinks: bool,
+    pub kinds: SrcKinds,
+    pub locales: Locales,
+    /// Whether pages with `draft: true` in their frontmatter are scanned.
+    /// When disabled, they are left out as if they did not exist.
+    pub drafts: bool,
+    /// Whether pages with a `date` in the future are scanned. When disabled,
+    /// they are left out like drafts until a scan after their date.
+    pub future: bool,
+    /// Directories under the root left out of scans, such as the output of
+    /// builds.
+    pub exclude: Vec<PathBuf>,
+    /// Whether assets under a path segment starting with `_` are copied and
+    /// served like others.
+    pub underscore_assets: bool,
+}
+
+impl Default for SrcFsOptions {
+    fn default() -> Self {
+        Self {
+            follow_symlinks: true,
+            kinds: SrcKinds::default(),
+            locales: Locales::default(),
+            drafts: true,
+            future: true,
+            exclude: Vec::new(),
+            underscore_assets: false,
+        }
+    }
+}
+
+/// Locales pages are translated into. Each locale's pages are under a
+/// top-level directory named after it, such as `ko/about.mdx` for `/ko/about`.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Locales {
+    pub locales: Vec<String>,
+    /// Locale pointed to by `x-default` alternate links. Defaults to the first
+    /// locale.
+    pub default_locale: Option<String>,
+}
+
+impl Locales {
+    pub fn default_locale(&self) -> Option<&str> {
+        self.default_locale
+            .as_deref()
+            .or_else(|| self.locales.first().map(String::as_str))
+    }
+
+    /// The locale a site path is in, and the path within it.
+    pub fn split<'a>(&self, site_path: &'a Path) -> Option<(&str, &'a Path)> {
+        let mut components = site_path.components();
+        let first = components.next()?.as_os_str();
+        let locale = self
+            .locales
+            .iter()
+            .find(|locale| first == locale.as_str())?;
+        Some((locale, components.as_path()))
+    }
+}
+
+/// A page's locale and the routes of its translations.
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct Translations {
+    pub locale: Option<String>,
+    pub default_locale: Option<String>,
+    /// Routes of the page in each locale it is translated into, including its
+    /// own. Empty for pages outside any locale and for generated pages.
+    pub alternates: BTreeMap<String, String>,
 }
 
 pub struct SrcFsGuard<'a>(RwLockReadGuard<'a, SrcFsInner>);
 
 impl SrcFsGuard<'_> {
+    /// Iterates over entries ordered by path, compared component-wise by
+    /// bytes. The order is the same on every platform and filesystem.
     pub fn iter(&self) -> impl Iterator<Item = &SrcFile> + '_ {
         self.0.entries.iter()
     }
 
+    /// Iterates over entries ordered by `compare`, with ties kept in path
+    /// order.
+    pub fn iter_sorted_by<F>(&self, mut compare: F) -> impl Iterator<Item = &SrcFile> + '_
+    where
+        F: FnMut(&SrcFile, &SrcFile) -> Ordering,
+    {
+        let mut entries = self.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| compare(a, b));
+        entries.into_iter()
+    }
+
+    /// Iterates over generators, pages named `_` such as `_.tsx`, which
+    /// produce several pages each.
     pub fn iter_generators(&self) -> impl Iterator<Item = &SrcFile> + '_ {
-        self.iter().filter(|f| match f.kind {
-            SrcKind::Jsx | SrcKind::Mdx if f.generator => true,
-            _ => false,
-        })
+        self.iter().filter(|f| f.generator)
     }
 
+    /// Iterates over pages, excluding generators and other files under a path
+    /// segment starting with `_`, such as `_layout.tsx` or `_drafts/post.mdx`.
     pub fn iter_pages(&self) -> impl Iterator<Item = &SrcFile> + '_ {
-        self.iter().filter(|f| match f.kind {
-            SrcKind::Jsx | SrcKind::Mdx if !f.underscore => true,
-            _ => false,
-        })
+        self.iter().filter(|f| f.is_page())
+    }
+
+    /// Layouts wrapping the source at `path`, one per directory from the root
+    /// down to its own, outermost first.
+    pub fn layouts(&self, path: &Path) -> Vec<&SrcFile> {
+        let mut layouts: Vec<&SrcFile> = Vec::new();
+        for file in self.iter().filter(|f| f.layout) {
+            let dir = file.path.parent().unwrap();
+            // Only the first of layouts with different extensions is used
+            if path.starts_with(dir) && !layouts.iter().any(|l| l.path.parent() == Some(dir)) {
+                layouts.push(file);
+            }
+        }
+        layouts.sort_by_key(|layout| layout.path.components().count());
+        layouts
     }
 
+    /// Iterates over files copied as they are, excluding pages, generators
+    /// and, unless `underscore_assets` is set, files under a path segment
+    /// starting with `_`, which can still be imported.
     pub fn iter_assets(&self) -> impl Iterator<Item = &SrcFile> + '_ {
-        self.iter().filter(|f| match f.kind {
-            SrcKind::Jsx | SrcKind::Mdx => false,
-            _ => true,
-        })
+        self.iter().filter(|f| self.is_asset(f))
+    }
+
+    pub fn is_asset(&self, file: &SrcFile) -> bool {
+        match file.kind {
+            SrcKind::Jsx | SrcKind::Mdx | SrcKind::Md => false,
+            _ => !file.generator && (!file.underscore || self.0.options.underscore_assets),
+        }
+    }
+
+    /// Iterates over stylesheets matching `globs` relative to the root, which
+    /// are linked into every page rather than copied as assets.
+    pub fn iter_global_styles<'a>(
+        &'a self,
+        globs: &'a GlobSet,
+    ) -> impl Iterator<Item = &'a SrcFile> + 'a {
+        self.iter().filter(|f| self.is_global_style(f, globs))
+    }
+
+    pub fn is_global_style(&self, file: &SrcFile, globs: &GlobSet) -> bool {
+        file.kind == SrcKind::Css
+            && file
+                .path
+                .strip_prefix(&self.0.root)
+                .map_or(false, |path| globs.is_match(path))
     }
 }
 
 impl SrcFs {
-    pub fn new(root: impl AsRef<Path>) -> Self {
+    /// Files larger than this many bytes should be streamed with [`SrcFs::open`]
+    /// instead of buffered with [`SrcFs::read`].
+    pub const STREAM_THRESHOLD: u64 = 1 << 20;
+
+    /// Buffer size used when streaming files.
+    pub const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+    const CHANGE_CAPACITY: usize = 64;
+
+    pub fn new(root: impl AsRef<Path>, options: SrcFsOptions) -> Self {
         let inner = SrcFsInner {
             root: root.as_ref().to_path_buf(),
             entries: Vec::new(),
+            options,
+            watcher: None,
+            tx_change: broadcast::channel(Self::CHANGE_CAPACITY).0,
         };
         let src_fs = SrcFs(Arc::new(RwLock::new(inner)));
         src_fs
@@ -59,18 +221,108 @@ impl SrcFs {
         self.0.read().await.root.clone()
     }
 
+    /// Leaves `dir` out of later scans.
+    pub async fn exclude(&self, dir: &Path) {
+        let mut inner = self.0.write().await;
+        if !inner.options.exclude.iter().any(|excluded| excluded == dir) {
+            inner.options.exclude.push(dir.to_path_buf());
+        }
+    }
+
     pub async fn scan(&self) -> Result<(), anyhow::Error> {
-        let entries = ignore::WalkBuilder::new(&self.0.write().await.root)
-            .add_custom_ignore_filename(".areumignore")
-            .build()
-            .filter(|x| x.clone().unwrap().file_type().unwrap().is_file())
-            .map(|dir| Ok(SrcFile::from(dir?)))
-            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        let (root, options) = {
+            let inner = self.0.read().await;
+            (inner.root.clone(), inner.options.clone())
+        };
 
+        let entries = walk(&root, &options, &root)?;
         self.0.write().await.entries = entries;
         Ok(())
     }
 
+    /// Watches the root for changes, updating entries as files are added,
+    /// removed or modified, and notifying the returned receiver.
+    ///
+    /// Changes go through the same ignore rules and classification as
+    /// [`SrcFs::scan`], so ignored files never show up as changes.
+    pub async fn watch(&self) -> Result<broadcast::Receiver<SrcChange>, anyhow::Error> {
+        let mut inner = self.0.write().await;
+
+        if inner.watcher.is_none() {
+            let weak = Arc::downgrade(&self.0);
+            let mut watcher =
+                notify::recommended_watcher(move |res: Result<notify::Event, notify::Error>| {
+                    let Some(inner) = weak.upgrade() else {
+                        return;
+                    };
+
+                    let applied = res
+                        .map_err(anyhow::Error::from)
+                        .and_then(|event| SrcFs(inner).apply(event));
+                    if let Err(err) = applied {
+                        tracing::error!("watch error: {:?}", err);
+                    }
+                })?;
+            watcher.watch(&inner.root, RecursiveMode::Recursive)?;
+            inner.watcher = Some(watcher);
+        }
+
+        Ok(inner.tx_change.subscribe())
+    }
+
+    /// Updates entries under the paths touched by a filesystem event.
+    ///
+    /// Called from the watcher thread, outside of the async runtime.
+    fn apply(&self, event: notify::Event) -> Result<(), anyhow::Error> {
+        match event.kind {
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {}
+            _ => return Ok(()),
+        }
+
+        let mut inner = self.0.blocking_write();
+        let mut changes = Vec::new();
+
+        for path in event.paths {
+            let found = walk(&inner.root, &inner.options, &path)?;
+            let (prev, mut rest): (Vec<_>, Vec<_>) = inner
+                .entries
+                .drain(..)
+                .partition(|f| f.path.starts_with(&path));
+
+            changes.extend(diff(&prev, &found));
+            rest.extend(found);
+            rest.sort_by(|a, b| a.path.cmp(&b.path));
+            inner.entries = rest;
+        }
+
+        for change in changes {
+            tracing::debug!(path = %change.path.display(), kind = ?change.kind, "source changed");
+            // No receivers is fine, nobody is listening yet
+            let _ = inner.tx_change.send(change);
+        }
+
+        Ok(())
+    }
+
+    /// Copies the current scan into a new, independent `SrcFs`.
+    pub async fn snapshot(&self) -> SrcFs {
+        let inner = self.0.read().await;
+        SrcFs(Arc::new(RwLock::new(SrcFsInner {
+            root: inner.root.clone(),
+            entries: inner.entries.clone(),
+            options: inner.options.clone(),
+            watcher: None,
+            tx_change: broadcast::channel(Self::CHANGE_CAPACITY).0,
+        })))
+    }
+
+    /// Diffs the current scan against a previous one.
+    pub async fn changed_since(&self, prev: &SrcFs) -> Vec<SrcChange> {
+        let current = self.lock().await;
+        let prev = prev.lock().await;
+        diff(&prev.0.entries, &current.0.entries)
+    }
+
     pub async fn lock(&self) -> SrcFsGuard<'_> {
         SrcFsGuard(self.0.read().await)
     }
@@ -83,49 +335,201 @@ impl SrcFs {
 
     pub async fn copy(&self, src: &SrcFile, to: &Path) -> Result<(), anyhow::Error> {
         let out = self.out_fpath(src, to).await?;
-        fs::create_dir_all(out.parent().unwrap())?;
-        fs::copy(&src.path, out)?;
+        tokio::fs::create_dir_all(out.parent().unwrap()).await?;
+
+        let mut reader = BufReader::with_capacity(Self::STREAM_BUFFER_SIZE, self.open(src).await?);
+        let mut writer = tokio::fs::File::create(out).await?;
+        tokio::io::copy_buf(&mut reader, &mut writer).await?;
         Ok(())
     }
 
+    /// Reads a whole file into memory. Prefer [`SrcFs::open`] for files over
+    /// [`SrcFs::STREAM_THRESHOLD`].
     pub fn read(&self, src: &SrcFile) -> Result<Vec<u8>, anyhow::Error> {
         Ok(fs::read(&src.path)?)
     }
 
-    pub async fn find(&self, path: impl AsRef<Path>) -> Option<SrcFile> {
-        let resolved = self.root().await.join(&path);
+    pub async fn open(&self, src: &SrcFile) -> Result<tokio::fs::File, anyhow::Error> {
+        Ok(tokio::fs::File::open(&src.path).await?)
+    }
+
+    /// Finds the file serving a route, which may be percent-encoded as
+    /// received in a request. With [`CaseSensitivity::InsensitiveWarn`],
+    /// routes differing only in case match if there is no exact match, with a
+    /// warning if several files do.
+    pub async fn find(&self, path: &str, case: CaseSensitivity) -> Option<SrcFile> {
+        let resolved = nfc(&self.root().await.join(decode_route(path)?));
         let guard = self.lock().await;
 
-        let found = if let Some(found) = guard.iter().find(|&f| {
-            f.path == resolved // direct match
-        }) {
-            found
-        } else if let Some(found) = guard.iter().find(|&f| {
-            f.path.with_extension("") == resolved // page.jsx
-        }) {
-            found
-        } else if let Some(found) = guard.iter().find(|&f| {
-            f.path.with_extension("") == resolved.join("index") // page/index.jsx
-        }) {
-            found
-        } else if let Some(found) = guard.iter().find(|&f| {
-            f.path.with_extension("") == resolved.parent().unwrap_or(&resolved).join("_")
-            // _.jsx
-        }) {
-            found
-        } else {
+        if let Some(found) = find_route(&guard, &resolved, |a, b| a == b) {
+            return Some(found.clone());
+        }
+        if case == CaseSensitivity::Strict {
             return None;
         }
-        .clone();
 
-        return Some(found);
+        let lowercase = |path: &Path| path.to_string_lossy().to_lowercase();
+        let found = find_route(&guard, &resolved, |a, b| lowercase(a) == lowercase(b))?;
+        let stem = lowercase(&found.path.with_extension(""));
+        if let Some(other) = guard
+            .iter()
+            .find(|f| f.path != found.path && lowercase(&f.path.with_extension("")) == stem)
+        {
+            tracing::warn!(
+                "{} matches both {} and {}, which differ only in case; serving {}",
+                path,
+                found.path.display(),
+                other.path.display(),
+                found.path.display()
+            );
+        }
+
+        Some(found.clone())
+    }
+
+    /// Finds the locale of a page and the pages translating it, by looking for
+    /// pages at the same path under each locale's directory.
+    pub async fn translations(&self, src: &SrcFile) -> Result<Translations, anyhow::Error> {
+        let site_path = self.site_path(src).await?;
+        let locales = self.0.read().await.options.locales.clone();
+        let Some((locale, path)) = locales.split(&site_path) else {
+            return Ok(Translations::default());
+        };
+
+        let alternates = if src.generator {
+            BTreeMap::new()
+        } else {
+            self.alternates(&locales, path).await
+        };
+        Ok(Translations {
+            locale: Some(locale.into()),
+            default_locale: locales.default_locale().map(String::from),
+            alternates,
+        })
+    }
+
+    /// Site paths the default locale's page `src` is also rendered at, in the
+    /// locales it is not translated into, with its translations there.
+    pub async fn fallbacks(
+        &self,
+        src: &SrcFile,
+    ) -> Result<Vec<(PathBuf, Translations)>, anyhow::Error> {
+        if !src.is_page() {
+            return Ok(Vec::new());
+        }
+        let site_path = self.site_path(src).await?;
+        let locales = self.0.read().await.options.locales.clone();
+        let Some((locale, path)) = locales.split(&site_path) else {
+            return Ok(Vec::new());
+        };
+        if locales.default_locale() != Some(locale) {
+            return Ok(Vec::new());
+        }
+
+        let alternates = self.alternates(&locales, path).await;
+        let mut fallbacks = Vec::new();
+        for other in locales.locales.iter().filter(|other| *other != locale) {
+            let fallback = Path::new(other).join(path);
+            if !self.is_page_at(&fallback).await {
+                let translations = Translations {
+                    locale: Some(other.clone()),
+                    default_locale: Some(locale.into()),
+                    alternates: alternates.clone(),
+                };
+                fallbacks.push((fallback, translations));
+            }
+        }
+        Ok(fallbacks)
+    }
+
+    /// Finds the default locale's page rendered at `route` as a fallback, with
+    /// its site path and translations there.
+    pub async fn find_fallback(&self, route: &str) -> Option<(SrcFile, PathBuf, Translations)> {
+        let site_path = PathBuf::from(decode_route(route)?);
+        let locales = self.0.read().await.options.locales.clone();
+        let (_, path) = locales.split(&site_path)?;
+        let default = Path::new(locales.default_locale()?).join(path);
+        let src = self
+            .find(&encode_route(&default), CaseSensitivity::Strict)
+            .await?;
+
+        let fallbacks = self.fallbacks(&src).await.ok()?;
+        let (path, translations) = fallbacks.into_iter().find(|(path, _)| *path == site_path)?;
+        Some((src, path, translations))
+    }
+
+    /// Routes of the page at `path` in each locale, relative to the locale's
+    /// directory, where it is translated or falls back to the default locale.
+    async fn alternates(&self, locales: &Locales, path: &Path) -> BTreeMap<String, String> {
+        let in_default = match locales.default_locale() {
+            Some(default) => self.is_page_at(&Path::new(default).join(path)).await,
+            None => false,
+        };
+
+        let mut alternates = BTreeMap::new();
+        for locale in &locales.locales {
+            let site_path = Path::new(locale).join(path);
+            if in_default || self.is_page_at(&site_path).await {
+                alternates.insert(locale.clone(), encode_route(&site_path));
+            }
+        }
+        alternates
+    }
+
+    /// Whether a page, rather than a generator or asset, has the site path.
+    async fn is_page_at(&self, site_path: &Path) -> bool {
+        let found = self
+            .find(&encode_route(site_path), CaseSensitivity::Strict)
+            .await;
+        found.map_or(false, |file| file.is_page())
+    }
+
+    /// URLs of the layouts wrapping the source at `path`, outermost first.
+    pub async fn layouts(&self, path: &Path) -> Result<Vec<Url>, anyhow::Error> {
+        self.lock()
+            .await
+            .layouts(path)
+            .into_iter()
+            .map(|layout| file_url(&layout.path))
+            .collect()
+    }
+
+    /// Fails if a page's permalink is another page's route, as only one of
+    /// them could be served.
+    pub async fn check_permalinks(&self) -> Result<(), anyhow::Error> {
+        // Site paths take the lock again, so it is released first
+        let pages: Vec<SrcFile> = self.lock().await.iter_pages().cloned().collect();
+        let mut routes: HashMap<PathBuf, &SrcFile> = HashMap::new();
+        for src in &pages {
+            let site_path = self.site_path(src).await?;
+            let Some(other) = routes.insert(site_path.clone(), src) else {
+                continue;
+            };
+            if src.permalink.is_some() || other.permalink.is_some() {
+                return Err(anyhow!(
+                    "{} and {} both have the route {}",
+                    other.path.display(),
+                    src.path.display(),
+                    encode_route(&site_path)
+                ));
+            }
+        }
+        Ok(())
     }
 
     pub async fn site_path(&self, src: &SrcFile) -> Result<PathBuf, anyhow::Error> {
-        let relative = src.path.strip_prefix(&self.0.read().await.root)?;
+        let root = self.root().await;
+        let relative = src
+            .path
+            .strip_prefix(&root)
+            .with_context(|| format!("{} is not inside {}", src.path.display(), root.display()))?;
+
+        if let Some(permalink) = &src.permalink {
+            return Ok(permalink.clone());
+        }
 
         match src.kind {
-            SrcKind::Jsx | SrcKind::Mdx => {
+            SrcKind::Jsx | SrcKind::Mdx | SrcKind::Md => {
                 // /index.tsx -> /
                 // /dir/index.tsx -> /dir
                 // /dir.tsx -> /dir
@@ -139,78 +543,1065 @@ impl SrcFs {
                     without_ext
                 };
 
-                Ok(path)
+                Ok(nfc(&path))
             }
-            _ => Ok(relative.to_path_buf()),
+            _ => Ok(nfc(relative)),
         }
     }
 
+    /// Root-relative URL for a file, NFC-normalized and percent-encoded.
+    ///
+    /// Anything that emits links to a file (sitemaps, canonical tags, link
+    /// checks) should use this rather than `site_path`.
+    pub async fn route(&self, src: &SrcFile) -> Result<String, anyhow::Error> {
+        Ok(encode_route(&self.site_path(src).await?))
+    }
+
     pub async fn out_fpath(&self, src: &SrcFile, to: &Path) -> Result<PathBuf, anyhow::Error> {
-        let relative = src.path.strip_prefix(&self.0.read().await.root)?;
+        let root = self.root().await;
+        let relative = src
+            .path
+            .strip_prefix(&root)
+            .with_context(|| format!("{} is not inside {}", src.path.display(), root.display()))?;
         match src.kind {
-            SrcKind::Jsx | SrcKind::Mdx => {
+            SrcKind::Jsx | SrcKind::Mdx | SrcKind::Md => {
                 // /index.tsx -> /index.html
                 // /dir/index.tsx -> /dir/index.html
                 // /dir.tsx -> /dir/index.html
                 let site_path = self.site_path(src).await?.join("index.html");
                 Ok(to.join(site_path))
             }
-            _ => Ok(to.join(relative)),
+            _ => Ok(to.join(nfc(relative))),
+        }
+    }
+}
+
+const ROUTE_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Frontmatter fields deciding where a page is routed, and whether it is.
+#[derive(Default)]
+struct Routing {
+    permalink: Option<PathBuf>,
+    draft: bool,
+    date: Option<SystemTime>,
+}
+
+/// `permalink`, `draft` and `date` in a page's frontmatter.
+fn routing(path: &Path) -> Result<Routing, anyhow::Error> {
+    let code = fs::read_to_string(path)?;
+    let Some(frontmatter) = markdown::frontmatter(&code) else {
+        return Ok(Routing::default());
+    };
+    // Invalid frontmatter is reported when the page is rendered
+    let Ok(meta) = serde_yaml::from_str::<serde_yaml::Value>(frontmatter) else {
+        return Ok(Routing::default());
+    };
+
+    Ok(Routing {
+        permalink: meta
+            .get("permalink")
+            .and_then(serde_yaml::Value::as_str)
+            .map(|permalink| nfc(Path::new(permalink.trim_matches('/')))),
+        draft: meta
+            .get("draft")
+            .and_then(serde_yaml::Value::as_bool)
+            .unwrap_or(false),
+        date: meta
+            .get("date")
+            .and_then(serde_yaml::Value::as_str)
+            .and_then(parse_date),
+    })
+}
+
+/// Parses an RFC 3339 date and time, or a date alone as its start in UTC.
+fn parse_date(date: &str) -> Option<SystemTime> {
+    let date = OffsetDateTime::parse(date, &Rfc3339).ok().or_else(|| {
+        Date::parse(date, format_description!("[year]-[month]-[day]"))
+            .ok()
+            .map(|date| date.midnight().assume_utc())
+    })?;
+    Some(date.into())
+}
+
+fn nfc(path: &Path) -> PathBuf {
+    PathBuf::from(path.to_string_lossy().nfc().collect::<String>())
+}
+
+/// Encodes a site path into a root-relative URL path.
+pub fn encode_route(path: &Path) -> String {
+    let mut route = String::from("/");
+    let segments = path
+        .iter()
+        .map(|segment| {
+            let segment = segment.to_string_lossy().nfc().collect::<String>();
+            utf8_percent_encode(&segment, ROUTE_SEGMENT).to_string()
+        })
+        .collect::<Vec<_>>();
+    route.push_str(&segments.join("/"));
+    route
+}
+
+/// Joins the components of a site path with `/`, whatever the platform's
+/// separator, for props and scripts that treat it as a URL path.
+pub fn to_slash(path: &Path) -> String {
+    path.iter()
+        .map(|segment| segment.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Canonicalizes a path, without the `\\?\` prefix Windows adds, which
+/// `Url::from_file_path` rejects for network shares. Roots should be
+/// canonicalized with this, so every path below them converts to a URL.
+pub fn canonicalize(path: &Path) -> Result<PathBuf, anyhow::Error> {
+    let canonical = fs::canonicalize(path)?;
+    if cfg!(windows) {
+        let lossy = canonical.to_string_lossy();
+        if let Some(share) = lossy.strip_prefix(r"\\?\UNC\") {
+            return Ok(PathBuf::from(format!(r"\\{}", share)));
+        }
+        if let Some(disk) = lossy.strip_prefix(r"\\?\") {
+            return Ok(PathBuf::from(disk));
+        }
+    }
+    Ok(canonical)
+}
+
+/// File URL of an absolute path, used as a module specifier.
+pub fn file_url(path: &Path) -> Result<Url, anyhow::Error> {
+    Url::from_file_path(path).map_err(|_| anyhow!("{} has no file URL", path.display()))
+}
+
+/// Path of a module's file URL, as made by [`file_url`].
+pub fn file_path(url: &Url) -> Result<PathBuf, anyhow::Error> {
+    url.to_file_path()
+        .map_err(|_| anyhow!("{} is not the URL of a file", url))
+}
+
+/// Decodes a percent-encoded URL path into an NFC-normalized relative path.
+pub fn decode_route(route: &str) -> Option<String> {
+    let decoded = percent_decode_str(route).decode_utf8().ok()?;
+    Some(decoded.trim_matches('/').nfc().collect())
+}
+
+fn find_route<'a>(
+    guard: &'a SrcFsGuard<'_>,
+    resolved: &Path,
+    eq: impl Fn(&Path, &Path) -> bool,
+) -> Option<&'a SrcFile> {
+    // Pages with a permalink are only found by it, and files under `_` only
+    // through the pages importing them, except assets copied anyway
+    let by_path = || {
+        guard
+            .iter()
+            .filter(|f| f.permalink.is_none() && (f.is_page() || guard.is_asset(f)))
+    };
+    if let Some(found) = guard.iter().find(|&f| {
+        f.permalink.as_ref().map_or(false, |permalink| {
+            eq(&guard.0.root.join(permalink), resolved)
+        })
+    }) {
+        Some(found)
+    } else if let Some(found) = by_path().find(|&f| {
+        eq(&nfc(&f.path), resolved) // direct match
+    }) {
+        Some(found)
+    } else if let Some(found) = by_path().find(|&f| {
+        eq(&nfc(&f.path.with_extension("")), resolved) // page.jsx
+    }) {
+        Some(found)
+    } else if let Some(found) = by_path().find(|&f| {
+        eq(&nfc(&f.path.with_extension("")), &resolved.join("index")) // page/index.jsx
+    }) {
+        Some(found)
+    } else {
+        guard.iter_generators().find(|&f| {
+            eq(
+                &nfc(&f.path.with_extension("")),
+                &resolved.parent().unwrap_or(resolved).join("_"),
+            ) // _.jsx
+        })
+    }
+}
+
+/// How routes with a trailing slash are handled, given that canonical routes
+/// have none.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrailingSlash {
+    /// Redirects permanently to the canonical route.
+    Redirect,
+    /// Serves the route as if it had no trailing slash.
+    #[default]
+    Ignore,
+    /// Treats the route as not found.
+    Strict,
+}
+
+/// How routes differing from a file's route only in case are handled.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaseSensitivity {
+    /// Treats the route as not found.
+    #[default]
+    Strict,
+    /// Resolves the route, warning with the canonical route.
+    InsensitiveWarn,
+}
+
+/// Finds files at or below `path` using the rules of a full scan, sorted by
+/// path.
+fn walk(root: &Path, options: &SrcFsOptions, path: &Path) -> Result<Vec<SrcFile>, anyhow::Error> {
+    let target = path.to_path_buf();
+    let exclude = options.exclude.clone();
+    let (root_, follow) = (root.to_path_buf(), options.follow_symlinks);
+    let walker = ignore::WalkBuilder::new(root)
+        .add_custom_ignore_filename(".areumignore")
+        .follow_links(follow)
+        .filter_entry(move |entry| {
+            (entry.path().starts_with(&target) || target.starts_with(entry.path()))
+                && !exclude.iter().any(|dir| entry.path().starts_with(dir))
+                && !(follow && entry.path_is_symlink() && escapes(&root_, entry.path()))
+        })
+        .build();
+
+    let mut entries = Vec::new();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) if is_loop(&err) => {
+                tracing::warn!("skipping symlink cycle: {}", err);
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if entry.path().starts_with(path) && entry.file_type().map_or(false, |t| t.is_file()) {
+            if entry.path().to_str().is_none() {
+                return Err(anyhow!(
+                    "file name {} is not valid UTF-8",
+                    entry.path().display()
+                ));
+            }
+            let file = SrcFile::new(entry, root, &options.kinds)?;
+            if file.draft && !options.drafts {
+                continue;
+            }
+            if !options.future && file.date.map_or(false, |date| date > SystemTime::now()) {
+                continue;
+            }
+            entries.push(file);
+        }
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Changes between two lists of entries, as compared by their metadata.
+/// Additions and modifications come first, in the order of `current`.
+fn diff(prev: &[SrcFile], current: &[SrcFile]) -> Vec<SrcChange> {
+    let prev_files: HashMap<&Path, &SrcFile> = prev
+        .iter()
+        .map(|file| (file.path.as_path(), file))
+        .collect();
+    let current_paths: HashSet<&Path> = current.iter().map(|file| file.path.as_path()).collect();
+    let mut changes = Vec::new();
+
+    for file in current {
+        match prev_files.get(file.path.as_path()) {
+            None => changes.push(SrcChange::new(file, SrcChangeKind::Added)),
+            Some(old) if old.size != file.size || old.modified != file.modified => {
+                changes.push(SrcChange::new(file, SrcChangeKind::Modified))
+            }
+            _ => {}
+        }
+    }
+
+    for file in prev {
+        if !current_paths.contains(file.path.as_path()) {
+            changes.push(SrcChange::new(file, SrcChangeKind::Removed));
         }
     }
+
+    changes
+}
+
+/// Whether the symlink at `path` points outside of `root`, warning if so.
+fn escapes(root: &Path, path: &Path) -> bool {
+    let Ok(target) = canonicalize(path) else {
+        return false;
+    };
+    if target.starts_with(root) {
+        return false;
+    }
+    tracing::warn!(
+        "skipping symlink {}, which points to {} outside of {}",
+        path.display(),
+        target.display(),
+        root.display()
+    );
+    true
 }
 
+fn is_loop(err: &ignore::Error) -> bool {
+    match err {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => is_loop(err),
+        _ => false,
+    }
+}
+
+/// A change to a source file, as emitted by [`SrcFs::watch`] and returned by
+/// [`SrcFs::changed_since`].
 #[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrcChange {
+    pub path: PathBuf,
+    pub kind: SrcChangeKind,
+    pub src_kind: SrcKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SrcChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl SrcChange {
+    fn new(file: &SrcFile, kind: SrcChangeKind) -> Self {
+        SrcChange {
+            path: file.path.clone(),
+            kind,
+            src_kind: file.kind,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct SrcFile {
     pub path: PathBuf,
     pub kind: SrcKind,
+    /// Whether any segment of the path, relative to the root, starts with
+    /// `_`, as with `_partial.tsx` or `_drafts/post.mdx`. Such files are never
+    /// routes, but can be imported.
     pub underscore: bool,
     pub generator: bool,
+    /// Whether the file is a layout, such as `_layout.tsx`, wrapping every
+    /// page in its directory and below.
+    pub layout: bool,
+    /// Site path set by `permalink` in the frontmatter of a Markdown or MDX
+    /// page, replacing the one its file path gives.
+    pub permalink: Option<PathBuf>,
+    /// Whether the page has `draft: true` in its frontmatter.
+    pub draft: bool,
+    /// `date` in the page's frontmatter, if it is a valid date.
+    pub date: Option<SystemTime>,
+    pub size: u64,
+    pub modified: SystemTime,
+    hash: OnceLock<String>,
 }
 
-impl From<ignore::DirEntry> for SrcFile {
-    fn from(dir: ignore::DirEntry) -> Self {
-        Self {
+impl SrcFile {
+    /// File name, without extension, of layouts.
+    pub const LAYOUT_NAME: &'static str = "_layout";
+
+    fn new(dir: ignore::DirEntry, root: &Path, kinds: &SrcKinds) -> Result<Self, anyhow::Error> {
+        let metadata = dir.metadata()?;
+        let kind = kinds.kind(dir.path());
+        let relative = dir.path().strip_prefix(root).unwrap_or(dir.path());
+        let is_underscore = |path: &Path| {
+            path.components()
+                .any(|component| component.as_os_str().to_string_lossy().starts_with('_'))
+        };
+        let underscore = is_underscore(relative);
+        let routing = match kind {
+            SrcKind::Mdx | SrcKind::Md if !underscore => routing(dir.path())?,
+            _ => Routing::default(),
+        };
+
+        Ok(Self {
             path: dir.path().into(),
-            kind: SrcKind::from(dir.path()),
-            underscore: dir
-                .path()
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
-                .starts_with("_"),
-            generator: dir
-                .path()
-                .with_extension("")
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
-                == "_",
+            kind,
+            underscore,
+            // Only `_` itself with a page extension, not `_layout.tsx`, a bare
+            // `_` file, or files in a directory starting with `_`
+            generator: matches!(kind, SrcKind::Jsx | SrcKind::Mdx)
+                && dir.path().file_stem().map_or(false, |stem| stem == "_")
+                && !relative.parent().map_or(false, is_underscore),
+            layout: matches!(kind, SrcKind::Jsx | SrcKind::Mdx)
+                && dir
+                    .path()
+                    .file_stem()
+                    .map_or(false, |stem| stem == Self::LAYOUT_NAME),
+            permalink: routing.permalink,
+            draft: routing.draft,
+            date: routing.date,
+            size: metadata.len(),
+            modified: metadata.modified()?,
+            hash: OnceLock::new(),
+        })
+    }
+
+    /// Whether the file renders a single page, unlike generators and other
+    /// files under a path segment starting with `_`.
+    pub fn is_page(&self) -> bool {
+        match self.kind {
+            SrcKind::Jsx | SrcKind::Mdx | SrcKind::Md => !self.underscore && !self.generator,
+            _ => false,
         }
     }
+
+    /// Hash of the file contents, read and computed on first use.
+    pub fn content_hash(&self) -> Result<&str, anyhow::Error> {
+        if let Some(hash) = self.hash.get() {
+            return Ok(hash);
+        }
+
+        let contents = fs::read(&self.path)?;
+        let hash = bs58::encode(Blake2b::<consts::U16>::digest(contents)).into_string();
+        Ok(self.hash.get_or_init(|| hash))
+    }
 }
 
+impl PartialEq for SrcFile {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+            && self.kind == other.kind
+            && self.underscore == other.underscore
+            && self.generator == other.generator
+            && self.layout == other.layout
+            && self.permalink == other.permalink
+            && self.draft == other.draft
+            && self.date == other.date
+            && self.size == other.size
+            && self.modified == other.modified
+    }
+}
+
+impl Eq for SrcFile {}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum SrcKind {
     Jsx,
     Mdx,
+    /// Plain Markdown, compiled to HTML without MDX's JSX and expressions.
+    Md,
     Js,
     Css,
     Other,
 }
 
-impl<P> From<P> for SrcKind
-where
-    P: AsRef<Path>,
-{
-    fn from(path: P) -> Self {
-        let ext = path.as_ref().extension().map(|x| x.to_string_lossy());
-        match ext.as_deref() {
-            Some("jsx" | "tsx") => Self::Jsx,
-            Some("mdx" | "md") => Self::Mdx,
-            Some("js" | "ts") => Self::Js,
-            Some("css") => Self::Css,
-            _ => Self::Other,
+impl FromStr for SrcKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsx" => Ok(Self::Jsx),
+            "mdx" => Ok(Self::Mdx),
+            "md" => Ok(Self::Md),
+            "js" => Ok(Self::Js),
+            "css" => Ok(Self::Css),
+            "other" => Ok(Self::Other),
+            _ => Err(anyhow!("unknown source kind {}", s)),
+        }
+    }
+}
+
+/// Maps file extensions to source kinds.
+#[derive(Clone, Debug)]
+pub struct SrcKinds(HashMap<String, SrcKind>);
+
+impl Default for SrcKinds {
+    fn default() -> Self {
+        Self(HashMap::from([
+            ("jsx".into(), SrcKind::Jsx),
+            ("tsx".into(), SrcKind::Jsx),
+            ("mdx".into(), SrcKind::Mdx),
+            ("md".into(), SrcKind::Md),
+            ("js".into(), SrcKind::Js),
+            ("ts".into(), SrcKind::Js),
+            ("css".into(), SrcKind::Css),
+        ]))
+    }
+}
+
+impl SrcKinds {
+    /// Extends the default mapping with `extension = "kind"` pairs.
+    pub fn new(custom: &HashMap<String, String>) -> Result<Self, anyhow::Error> {
+        let mut kinds = Self::default();
+        for (ext, kind) in custom {
+            let kind = kind
+                .parse()
+                .with_context(|| format!("invalid mapping for extension {}", ext))?;
+            kinds.0.insert(ext.trim_start_matches('.').into(), kind);
+        }
+        Ok(kinds)
+    }
+
+    pub fn kind(&self, path: impl AsRef<Path>) -> SrcKind {
+        path.as_ref()
+            .extension()
+            .and_then(|ext| self.0.get(ext.to_string_lossy().as_ref()))
+            .copied()
+            .unwrap_or(SrcKind::Other)
+    }
+
+    /// Extensions mapped to `kind`, in sorted order.
+    pub fn extensions(&self, kind: SrcKind) -> Vec<String> {
+        let mut exts = self
+            .0
+            .iter()
+            .filter(|(_, k)| **k == kind)
+            .map(|(ext, _)| ext.clone())
+            .collect::<Vec<_>>();
+        exts.sort();
+        exts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Absolute on every platform, so sources under it have file URLs.
+    fn root() -> PathBuf {
+        std::env::temp_dir().join("site")
+    }
+
+    /// Source at `relative`, a `/`-separated path, joined with the platform's
+    /// separator.
+    fn src(relative: &str, kind: SrcKind) -> SrcFile {
+        SrcFile {
+            path: relative
+                .split('/')
+                .fold(root(), |path, segment| path.join(segment)),
+            kind,
+            underscore: false,
+            generator: false,
+            layout: false,
+            permalink: None,
+            draft: false,
+            date: None,
+            size: 0,
+            modified: SystemTime::UNIX_EPOCH,
+            hash: OnceLock::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn site_paths_are_slash_separated() {
+        let src_fs = SrcFs::new(root(), SrcFsOptions::default());
+        let cases = [
+            ("index.tsx", SrcKind::Jsx, "", "/"),
+            ("about.mdx", SrcKind::Mdx, "about", "/about"),
+            ("blog/index.md", SrcKind::Md, "blog", "/blog"),
+            (
+                "blog/2024/post.mdx",
+                SrcKind::Mdx,
+                "blog/2024/post",
+                "/blog/2024/post",
+            ),
+            (
+                "images/a b.png",
+                SrcKind::Other,
+                "images/a b.png",
+                "/images/a%20b.png",
+            ),
+        ];
+        for (relative, kind, site_path, route) in cases {
+            let src = src(relative, kind);
+            let path = src_fs.site_path(&src).await.unwrap();
+            assert_eq!(to_slash(&path), site_path, "{}", relative);
+            assert_eq!(src_fs.route(&src).await.unwrap(), route, "{}", relative);
+        }
+    }
+
+    #[tokio::test]
+    async fn permalinks_replace_site_paths() {
+        let src_fs = SrcFs::new(root(), SrcFsOptions::default());
+        let mut src = src("blog/post.mdx", SrcKind::Mdx);
+        src.permalink = Some(Path::new("posts").join("first"));
+        assert_eq!(src_fs.route(&src).await.unwrap(), "/posts/first");
+    }
+
+    #[tokio::test]
+    async fn out_paths_are_under_the_output() {
+        let src_fs = SrcFs::new(root(), SrcFsOptions::default());
+        let out = std::env::temp_dir().join("dist");
+        let cases = [
+            ("index.tsx", SrcKind::Jsx, "index.html"),
+            ("blog/index.mdx", SrcKind::Mdx, "blog/index.html"),
+            ("blog/post.md", SrcKind::Md, "blog/post/index.html"),
+            ("styles/main.css", SrcKind::Css, "styles/main.css"),
+        ];
+        for (relative, kind, expected) in cases {
+            let path = src_fs.out_fpath(&src(relative, kind), &out).await.unwrap();
+            let relative_out = path.strip_prefix(&out).unwrap();
+            assert_eq!(to_slash(relative_out), expected, "{}", relative);
+        }
+    }
+
+    #[tokio::test]
+    async fn sources_outside_the_root_have_no_site_path() {
+        let src_fs = SrcFs::new(root(), SrcFsOptions::default());
+        let mut src = src("page.tsx", SrcKind::Jsx);
+        src.path = std::env::temp_dir().join("elsewhere").join("page.tsx");
+        assert!(src_fs.site_path(&src).await.is_err());
+    }
+
+    #[test]
+    fn routes_are_encoded_by_segment() {
+        let path = Path::new("블로그").join("a b");
+        assert_eq!(encode_route(&path), "/%EB%B8%94%EB%A1%9C%EA%B7%B8/a%20b");
+        assert_eq!(
+            decode_route("/%EB%B8%94%EB%A1%9C%EA%B7%B8/a%20b/").unwrap(),
+            "블로그/a b"
+        );
+    }
+
+    #[test]
+    fn special_characters_round_trip_through_routes() {
+        for name in ["100%", "a#b", "what?", "a b", "caf\u{e9}"] {
+            let route = encode_route(&Path::new("blog").join(name));
+            assert!(!route.contains(['#', '?', ' ']), "{}", route);
+            assert_eq!(decode_route(&route).unwrap(), format!("blog/{}", name));
+        }
+        assert_eq!(encode_route(Path::new("100%")), "/100%25");
+    }
+
+    #[tokio::test]
+    async fn finds_files_with_special_characters_by_encoded_route() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        fs::write(root.join("a b#1%.png"), "").unwrap();
+        let src_fs = SrcFs::new(&root, SrcFsOptions::default());
+        src_fs.scan().await.unwrap();
+
+        let route = encode_route(Path::new("a b#1%.png"));
+        assert_eq!(route, "/a%20b%231%25.png");
+        let found = src_fs.find(&route, CaseSensitivity::Strict).await.unwrap();
+        assert_eq!(found.path, root.join("a b#1%.png"));
+    }
+
+    // Other platforms' filesystems only take valid Unicode names
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn scans_reject_names_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        let path = root.join("blog").join(OsStr::from_bytes(b"caf\xe9.mdx"));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "# Caf\u{e9}").unwrap();
+
+        let src_fs = SrcFs::new(&root, SrcFsOptions::default());
+        let err = src_fs.scan().await.unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("is not valid UTF-8"), "{}", message);
+        assert!(message.contains(&path.display().to_string()), "{}", message);
+    }
+
+    #[test]
+    fn file_urls_of_relative_paths_fail() {
+        assert!(file_url(Path::new("relative/page.tsx")).is_err());
+        assert!(file_url(&root().join("page.tsx")).is_ok());
+    }
+
+    #[test]
+    fn md_files_are_plain_markdown_by_default() {
+        let kinds = SrcKinds::default();
+        assert_eq!(kinds.kind("post.md"), SrcKind::Md);
+        assert_eq!(kinds.kind("post.mdx"), SrcKind::Mdx);
+
+        let custom = HashMap::from([("md".to_string(), "mdx".to_string())]);
+        let kinds = SrcKinds::new(&custom).unwrap();
+        assert_eq!(kinds.kind("post.md"), SrcKind::Mdx);
+    }
+
+    #[test]
+    fn file_paths_of_other_urls_fail() {
+        let path = root().join("page.tsx");
+        assert_eq!(file_path(&file_url(&path).unwrap()).unwrap(), path);
+
+        let url = Url::parse("https://example.com/page.tsx").unwrap();
+        let err = file_path(&url).unwrap_err();
+        assert!(err.to_string().contains("https://example.com/page.tsx"));
+        assert!(file_path(&Url::parse("data:text/plain,page").unwrap()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn scans_follow_symlinked_page_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(root.join("shared/posts")).unwrap();
+        fs::write(root.join("shared/posts/hello.mdx"), "# Hello").unwrap();
+        std::os::unix::fs::symlink(root.join("shared/posts"), root.join("blog")).unwrap();
+
+        let src_fs = SrcFs::new(&root, SrcFsOptions::default());
+        src_fs.scan().await.unwrap();
+        let found = src_fs.find("/blog/hello", CaseSensitivity::Strict).await;
+        // Routes follow the link's location, not its target's
+        assert_eq!(found.unwrap().path, root.join("blog/hello.mdx"));
+
+        let options = SrcFsOptions {
+            follow_symlinks: false,
+            ..Default::default()
+        };
+        let src_fs = SrcFs::new(&root, options);
+        src_fs.scan().await.unwrap();
+        let found = src_fs.find("/blog/hello", CaseSensitivity::Strict).await;
+        assert!(found.is_none());
+        assert!(src_fs
+            .find("/shared/posts/hello", CaseSensitivity::Strict)
+            .await
+            .is_some());
+    }
+
+    #[test]
+    fn diffs_entries_by_metadata() {
+        let sized = |relative: &str, size: u64| SrcFile {
+            size,
+            ..src(relative, SrcKind::Jsx)
+        };
+        let prev = [sized("a.tsx", 1), sized("b.tsx", 1), sized("c.tsx", 1)];
+        let touched = SrcFile {
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            ..sized("c.tsx", 1)
+        };
+        let current = [sized("b.tsx", 2), touched, sized("d.tsx", 1)];
+
+        let changes: Vec<_> = diff(&prev, &current)
+            .into_iter()
+            .map(|change| (change.path, change.kind))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (root().join("b.tsx"), SrcChangeKind::Modified),
+                (root().join("c.tsx"), SrcChangeKind::Modified),
+                (root().join("d.tsx"), SrcChangeKind::Added),
+                (root().join("a.tsx"), SrcChangeKind::Removed),
+            ]
+        );
+        assert!(diff(&current, &current).is_empty());
+    }
+
+    #[tokio::test]
+    async fn changes_since_snapshots_are_found_by_rescanning() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        fs::write(root.join("kept.tsx"), "").unwrap();
+        fs::write(root.join("removed.tsx"), "").unwrap();
+        fs::write(root.join("modified.css"), "p {}").unwrap();
+
+        let src_fs = SrcFs::new(&root, SrcFsOptions::default());
+        src_fs.scan().await.unwrap();
+        let prev = src_fs.snapshot().await;
+
+        fs::remove_file(root.join("removed.tsx")).unwrap();
+        fs::write(root.join("modified.css"), "p { color: red; }").unwrap();
+        fs::write(root.join("added.mdx"), "").unwrap();
+        src_fs.scan().await.unwrap();
+
+        let mut changes: Vec<_> = src_fs
+            .changed_since(&prev)
+            .await
+            .into_iter()
+            .map(|change| (change.path, change.kind, change.src_kind))
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            changes,
+            [
+                (root.join("added.mdx"), SrcChangeKind::Added, SrcKind::Mdx),
+                (
+                    root.join("modified.css"),
+                    SrcChangeKind::Modified,
+                    SrcKind::Css
+                ),
+                (
+                    root.join("removed.tsx"),
+                    SrcChangeKind::Removed,
+                    SrcKind::Jsx
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_generators_apart_from_pages_layouts_and_assets() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        for name in [
+            "_",
+            "index.tsx",
+            "logo.png",
+            "blog/_.tsx",
+            "blog/_layout.tsx",
+            "docs/_/index.tsx",
+            "_drafts/_.tsx",
+            "vendor/_.tsx",
+        ] {
+            let path = root.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
         }
+        let options = SrcFsOptions {
+            exclude: vec![root.join("vendor")],
+            ..Default::default()
+        };
+        let src_fs = SrcFs::new(&root, options);
+        src_fs.scan().await.unwrap();
+
+        let guard = src_fs.lock().await;
+        let relative = |files: Vec<&SrcFile>| {
+            files
+                .into_iter()
+                .map(|src| to_slash(src.path.strip_prefix(&root).unwrap()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(relative(guard.iter_generators().collect()), ["blog/_.tsx"]);
+        assert_eq!(relative(guard.iter_pages().collect()), ["index.tsx"]);
+        assert_eq!(relative(guard.iter_assets().collect()), ["logo.png"]);
+        assert_eq!(
+            relative(guard.layouts(&root.join("blog").join("post.tsx"))),
+            ["blog/_layout.tsx"]
+        );
+    }
+
+    #[tokio::test]
+    async fn scans_iterate_in_path_order_whatever_the_creation_order() {
+        let names = [
+            "b.mdx", "a/z.tsx", "a.css", "A.png", "a/b.mdx", "_c.tsx", "c.md",
+        ];
+        let mut scans = Vec::new();
+        for names in [names.to_vec(), names.iter().rev().copied().collect()] {
+            let dir = tempfile::tempdir().unwrap();
+            let root = canonicalize(dir.path()).unwrap();
+            for name in names {
+                let path = root.join(name);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, "").unwrap();
+            }
+
+            let src_fs = SrcFs::new(&root, SrcFsOptions::default());
+            let mut paths = Vec::new();
+            // Rescanning keeps the order too
+            for _ in 0..2 {
+                src_fs.scan().await.unwrap();
+                let guard = src_fs.lock().await;
+                let relative = guard
+                    .iter()
+                    .map(|src| src.path.strip_prefix(&root).unwrap().to_path_buf())
+                    .collect::<Vec<_>>();
+                paths.push(relative);
+            }
+            assert_eq!(paths[0], paths[1]);
+            scans.push(paths.remove(0));
+        }
+
+        assert_eq!(scans[0], scans[1]);
+        assert_eq!(scans[0].len(), names.len());
+        // By component, so `a/b.mdx` comes before `a.css`
+        let mut sorted = scans[0].clone();
+        sorted.sort();
+        assert_eq!(scans[0], sorted);
+    }
+
+    #[tokio::test]
+    async fn finds_routes_by_case_sensitivity() {
+        use CaseSensitivity::{InsensitiveWarn, Strict};
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        fs::write(root.join("about.tsx"), "").unwrap();
+        let src_fs = SrcFs::new(&root, SrcFsOptions::default());
+        src_fs.scan().await.unwrap();
+        let find = |route, case| {
+            let src_fs = src_fs.clone();
+            async move { src_fs.find(route, case).await.map(|src| src.path) }
+        };
+
+        // Trailing slashes are left to the server's policy
+        let about = Some(root.join("about.tsx"));
+        for route in ["/about", "/about/"] {
+            assert_eq!(find(route, Strict).await, about, "{}", route);
+        }
+        for route in ["/About", "/About/", "/ABOUT"] {
+            assert_eq!(find(route, Strict).await, None, "{}", route);
+            assert_eq!(find(route, InsensitiveWarn).await, about, "{}", route);
+        }
+        assert_eq!(find("/contact", InsensitiveWarn).await, None);
+    }
+
+    #[tokio::test]
+    async fn finds_one_of_routes_differing_only_in_case() {
+        use CaseSensitivity::{InsensitiveWarn, Strict};
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        // Different extensions, so both exist on case-insensitive filesystems
+        fs::write(root.join("Notes.mdx"), "# Notes").unwrap();
+        fs::write(root.join("notes.tsx"), "").unwrap();
+        let src_fs = SrcFs::new(&root, SrcFsOptions::default());
+        src_fs.scan().await.unwrap();
+        let find = |route, case| {
+            let src_fs = src_fs.clone();
+            async move { src_fs.find(route, case).await.map(|src| src.path) }
+        };
+
+        // Exact matches win over case-insensitive ones
+        for case in [Strict, InsensitiveWarn] {
+            assert_eq!(find("/Notes", case).await, Some(root.join("Notes.mdx")));
+            assert_eq!(find("/notes", case).await, Some(root.join("notes.tsx")));
+        }
+        // Otherwise the first in path order is served, with a warning
+        assert_eq!(find("/NOTES", Strict).await, None);
+        assert_eq!(
+            find("/NOTES", InsensitiveWarn).await,
+            Some(root.join("Notes.mdx"))
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn scans_skip_symlinks_when_not_following() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        fs::create_dir_all(root.join("shared")).unwrap();
+        fs::write(root.join("shared/hello.mdx"), "# Hello").unwrap();
+        std::os::unix::fs::symlink(root.join("shared"), root.join("blog")).unwrap();
+        std::os::unix::fs::symlink(root.join("shared/hello.mdx"), root.join("hi.mdx")).unwrap();
+
+        let options = SrcFsOptions {
+            follow_symlinks: false,
+            ..Default::default()
+        };
+        let src_fs = SrcFs::new(&root, options);
+        src_fs.scan().await.unwrap();
+        let paths: Vec<_> = src_fs
+            .lock()
+            .await
+            .iter()
+            .map(|src| src.path.clone())
+            .collect();
+        assert_eq!(paths, [root.join("shared/hello.mdx")]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn scans_skip_symlinks_outside_the_root() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.mdx"), "# Secret").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        fs::write(root.join("index.mdx"), "# Home").unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("notes")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret.mdx"), root.join("secret.mdx"))
+            .unwrap();
+
+        let src_fs = SrcFs::new(&root, SrcFsOptions::default());
+        src_fs.scan().await.unwrap();
+        let paths: Vec<_> = src_fs
+            .lock()
+            .await
+            .iter()
+            .map(|src| src.path.clone())
+            .collect();
+        assert_eq!(paths, [root.join("index.mdx")]);
+    }
+
+    /// Waits for the watcher to send a change of `kind` to `path`.
+    async fn changed(
+        rx_change: &mut broadcast::Receiver<SrcChange>,
+        path: &Path,
+        kind: SrcChangeKind,
+    ) -> SrcChange {
+        let wait = async {
+            loop {
+                let change = rx_change.recv().await.unwrap();
+                if change.path == path && change.kind == kind {
+                    return change;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), wait)
+            .await
+            .unwrap_or_else(|_| panic!("no {:?} change to {}", kind, path.display()))
+    }
+
+    #[tokio::test]
+    async fn watch_sends_changes_and_updates_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        fs::write(root.join("index.tsx"), "export default () => <p />").unwrap();
+        fs::write(root.join(".areumignore"), "ignored.tsx\n").unwrap();
+
+        let src_fs = SrcFs::new(&root, SrcFsOptions::default());
+        src_fs.scan().await.unwrap();
+        let mut rx_change = src_fs.watch().await.unwrap();
+
+        let about = root.join("about.mdx");
+        fs::write(root.join("ignored.tsx"), "").unwrap();
+        fs::write(&about, "# About").unwrap();
+        let change = changed(&mut rx_change, &about, SrcChangeKind::Added).await;
+        assert_eq!(change.src_kind, SrcKind::Mdx);
+        assert!(src_fs
+            .find("/about", CaseSensitivity::Strict)
+            .await
+            .is_some());
+        assert!(src_fs
+            .find("/ignored", CaseSensitivity::Strict)
+            .await
+            .is_none());
+
+        fs::write(&about, "# About us").unwrap();
+        changed(&mut rx_change, &about, SrcChangeKind::Modified).await;
+
+        fs::remove_file(&about).unwrap();
+        changed(&mut rx_change, &about, SrcChangeKind::Removed).await;
+        assert!(src_fs
+            .find("/about", CaseSensitivity::Strict)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn streams_files_larger_than_the_buffer() {
+        use tokio::io::AsyncReadExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = canonicalize(dir.path()).unwrap();
+        // Not a multiple of the buffer size, so the last read is partial
+        let contents: Vec<u8> = (0..SrcFs::STREAM_BUFFER_SIZE * 3 + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        fs::write(root.join("video.bin"), &contents).unwrap();
+        let src_fs = SrcFs::new(&root, SrcFsOptions::default());
+        src_fs.scan().await.unwrap();
+        let src = src_fs
+            .find("/video.bin", CaseSensitivity::Strict)
+            .await
+            .unwrap();
+
+        let mut streamed = Vec::new();
+        let mut file = src_fs.open(&src).await.unwrap();
+        file.read_to_end(&mut streamed).await.unwrap();
+        assert!(streamed == contents);
+
+        let out = root.join("dist");
+        src_fs.copy(&src, &out).await.unwrap();
+        assert!(fs::read(out.join("video.bin")).unwrap() == contents);
     }
 }
@@ -0,0 +1,269 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::{
+    excerpt, markdown,
+    src_fs::{file_url, SrcFs, SrcKind},
+};
+
+/// Pages grouping other pages by the terms in their frontmatter, such as
+/// `/tags/rust` for pages with `tags: [rust]`.
+#[derive(Deserialize, Clone)]
+pub struct TaxonomyConfig {
+    /// Component rendered for each term, relative to the root, such as
+    /// `_tag.tsx`. Gets `term`, `slug` and `pages` as props.
+    pub template: String,
+    /// Component rendered at the taxonomy's route, listing its terms. Gets
+    /// `terms` as props.
+    pub index: Option<String>,
+}
+
+/// Pages rendered from one component, by site path, with their props.
+pub struct TaxonomyPages {
+    pub template: Url,
+    pub pages: BTreeMap<String, Value>,
+}
+
+#[derive(Clone, Default)]
+pub struct Taxonomies {
+    root: PathBuf,
+    taxonomies: BTreeMap<String, TaxonomyConfig>,
+}
+
+impl Taxonomies {
+    pub fn new(root: &Path, taxonomies: BTreeMap<String, TaxonomyConfig>) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            taxonomies,
+        }
+    }
+
+    /// Whether a site path could be a taxonomy page, before collecting terms.
+    pub fn contains(&self, site_path: &Path) -> bool {
+        site_path
+            .components()
+            .next()
+            .and_then(|name| name.as_os_str().to_str())
+            .map_or(false, |name| self.taxonomies.contains_key(name))
+    }
+
+    /// Groups pages by the terms in their frontmatter, returning the pages to
+    /// render for every taxonomy. Only MDX and Markdown pages have frontmatter.
+    pub async fn collect(&self, src_fs: &SrcFs) -> Result<Vec<TaxonomyPages>, anyhow::Error> {
+        if self.taxonomies.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Terms by slug, by taxonomy
+        let mut terms: BTreeMap<&str, BTreeMap<String, (String, Vec<Value>)>> = BTreeMap::new();
+
+        // Routes take the lock again, so it is released first
+        let srcs: Vec<_> = src_fs.lock().await.iter_pages().cloned().collect();
+        for src in &srcs {
+            if !matches!(src.kind, SrcKind::Mdx | SrcKind::Md) {
+                continue;
+            }
+
+            let code = fs::read_to_string(&src.path)?;
+            let Some(frontmatter) = markdown::frontmatter(&code) else {
+                continue;
+            };
+            let meta: Value = serde_yaml::from_str(frontmatter)
+                .with_context(|| format!("invalid frontmatter in {}", src.path.display()))?;
+
+            let route = src_fs.route(src).await?;
+            let entry = json!({
+                "route": route,
+                "title": meta.get("title"),
+                "excerpt": excerpt::from_source(&code),
+                "meta": meta,
+            });
+
+            for name in self.taxonomies.keys() {
+                let values = match meta.get(name) {
+                    Some(Value::Array(values)) => values.iter().collect(),
+                    Some(value) => vec![value],
+                    None => Vec::new(),
+                };
+
+                for term in values.into_iter().filter_map(Value::as_str) {
+                    let term = term.trim();
+                    let slug = slugify(term);
+                    if slug.is_empty() {
+                        continue;
+                    }
+
+                    let (_, pages) = terms
+                        .entry(name.as_str())
+                        .or_default()
+                        .entry(slug)
+                        .or_insert_with(|| (term.to_string(), Vec::new()));
+                    pages.push(entry.clone());
+                }
+            }
+        }
+
+        let mut collected = Vec::new();
+        for (name, config) in &self.taxonomies {
+            let terms = terms.remove(name.as_str()).unwrap_or_default();
+
+            let pages = terms
+                .iter()
+                .map(|(slug, (term, pages))| {
+                    let props = json!({ "term": term, "slug": slug, "pages": pages });
+                    (format!("{}/{}", name, slug), props)
+                })
+                .collect();
+            collected.push(TaxonomyPages {
+                template: self.template(name, &config.template)?,
+                pages,
+            });
+
+            if let Some(index) = &config.index {
+                let terms: Vec<_> = terms
+                    .iter()
+                    .map(|(slug, (term, pages))| {
+                        json!({
+                            "term": term,
+                            "slug": slug,
+                            "route": format!("/{}/{}", name, slug),
+                            "count": pages.len(),
+                        })
+                    })
+                    .collect();
+                collected.push(TaxonomyPages {
+                    template: self.template(name, index)?,
+                    pages: BTreeMap::from([(name.clone(), json!({ "terms": terms }))]),
+                });
+            }
+        }
+
+        Ok(collected)
+    }
+
+    fn template(&self, name: &str, path: &str) -> Result<Url, anyhow::Error> {
+        let path = self.root.join(path);
+        if !path.is_file() {
+            return Err(anyhow!(
+                "could not find template {} for taxonomy {}",
+                path.display(),
+                name
+            ));
+        }
+        file_url(&path)
+    }
+}
+
+/// Lowercases `text` and joins its words with `-`, keeping non-ASCII letters.
+pub fn slugify(text: &str) -> String {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::BuilderOptions, src_fs::SrcFsOptions, testing::Fixture};
+
+    /// Three posts across two tags, one of them also given a blank tag.
+    fn posts() -> Fixture {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "areum.toml",
+                "[taxonomies.tags]\ntemplate = \"_tag.tsx\"\nindex = \"_tags.tsx\"\n",
+            )
+            .unwrap()
+            .file(
+                "_tag.tsx",
+                "export default ({ slug, pages }) => \
+                 <ul id={slug}>{pages.map((page) => <li>{page.title}</li>)}</ul>",
+            )
+            .unwrap()
+            .file(
+                "_tags.tsx",
+                "export default ({ terms }) => \
+                 <ul>{terms.map((term) => <li>{term.slug}: {term.count}</li>)}</ul>",
+            )
+            .unwrap()
+            .file("posts/a.mdx", "---\ntitle: A\ntags: [Rust, css]\n---\nA")
+            .unwrap()
+            .file("posts/b.mdx", "---\ntitle: B\ntags: rust\n---\nB")
+            .unwrap()
+            .file("posts/c.md", "---\ntitle: C\ntags: [CSS, \" \"]\n---\nC")
+            .unwrap();
+        fixture
+    }
+
+    fn tags(root: &Path) -> Taxonomies {
+        let config = TaxonomyConfig {
+            template: "_tag.tsx".into(),
+            index: Some("_tags.tsx".into()),
+        };
+        Taxonomies::new(root, BTreeMap::from([("tags".to_string(), config)]))
+    }
+
+    #[tokio::test]
+    async fn groups_pages_by_term() {
+        let fixture = posts();
+        let src_fs = SrcFs::new(fixture.root(), SrcFsOptions::default());
+        src_fs.scan().await.unwrap();
+
+        let collected = tags(fixture.root()).collect(&src_fs).await.unwrap();
+        assert_eq!(collected.len(), 2);
+
+        let terms = &collected[0].pages;
+        assert_eq!(terms.keys().collect::<Vec<_>>(), ["tags/css", "tags/rust"]);
+        let routes = |term: &str| {
+            let mut routes: Vec<_> = terms[term]["pages"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|page| page["route"].as_str().unwrap().to_string())
+                .collect();
+            routes.sort();
+            routes
+        };
+        assert_eq!(routes("tags/rust"), ["/posts/a", "/posts/b"]);
+        assert_eq!(routes("tags/css"), ["/posts/a", "/posts/c"]);
+
+        let index = &collected[1].pages["tags"]["terms"];
+        assert_eq!(index[0]["slug"], "css");
+        assert_eq!(index[0]["route"], "/tags/css");
+        assert_eq!(index[1]["count"], 2);
+    }
+
+    #[tokio::test]
+    async fn builds_term_pages() {
+        let fixture = posts();
+        let report = fixture.build(BuilderOptions::default()).await.unwrap();
+        assert_eq!(report.pages, 3);
+        assert_eq!(report.generated_pages, 3);
+
+        let rust = fixture.output("tags/rust/index.html").unwrap();
+        assert!(rust.contains("<li>A</li>") && rust.contains("<li>B</li>"));
+        assert!(!rust.contains("<li>C</li>"));
+        let css = fixture.output("tags/css/index.html").unwrap();
+        assert!(css.contains("<li>A</li>") && css.contains("<li>C</li>"));
+        let index = fixture.output("tags/index.html").unwrap();
+        assert!(index.contains("<li>css: 2</li>") && index.contains("<li>rust: 2</li>"));
+    }
+
+    #[test]
+    fn slugs_keep_non_ascii_letters() {
+        assert_eq!(slugify("Rust & WebAssembly"), "rust-webassembly");
+        assert_eq!(slugify("  한국어 글 "), "한국어-글");
+        assert_eq!(slugify(" - "), "");
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use tempfile::TempDir;
+
+use crate::{
+    builder::{BuildReport, Builder, BuilderOptions},
+    render::{render_source, RenderOptions},
+    src_fs::SrcKind,
+};
+
+/// Renders a TSX page, as a build would with the default config, returning
+/// its HTML without a client script.
+pub async fn render_jsx(source: &str) -> Result<String, anyhow::Error> {
+    render_source(source, SrcKind::Jsx, RenderOptions::default()).await
+}
+
+/// Renders an MDX page, as [`render_jsx`] does.
+pub async fn render_mdx(source: &str) -> Result<String, anyhow::Error> {
+    render_source(source, SrcKind::Mdx, RenderOptions::default()).await
+}
+
+/// Site in a temporary directory, deleted when dropped, for tests needing
+/// sources on disk, such as layouts, assets or `areum.toml`.
+pub struct Fixture {
+    dir: TempDir,
+}
+
+impl Fixture {
+    /// Directory builds are written to, relative to the root.
+    pub const OUT_DIR: &'static str = "dist";
+
+    pub fn new() -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            dir: tempfile::tempdir()?,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn out_dir(&self) -> PathBuf {
+        self.root().join(Self::OUT_DIR)
+    }
+
+    /// Writes `contents` to `path`, relative to the root, creating its
+    /// directories.
+    pub fn file(
+        &self,
+        path: impl AsRef<Path>,
+        contents: impl AsRef<[u8]>,
+    ) -> Result<&Self, anyhow::Error> {
+        let path = self.root().join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, contents)?;
+        Ok(self)
+    }
+
+    /// Builds the site with `options` into [`Fixture::OUT_DIR`].
+    pub async fn build(&self, options: BuilderOptions) -> Result<BuildReport, anyhow::Error> {
+        let mut site = Builder::new(self.root(), options).await?;
+        site.build(&self.out_dir()).await
+    }
+
+    /// Renders the page at `route` alone, returning its HTML.
+    pub async fn render(&self, route: &str) -> Result<String, anyhow::Error> {
+        let mut site = Builder::new(self.root(), BuilderOptions::default()).await?;
+        Ok(site.build_route(&self.out_dir(), route).await?.html)
+    }
+
+    /// Contents of the output at `path`, relative to the output directory.
+    pub fn output(&self, path: impl AsRef<Path>) -> Result<String, anyhow::Error> {
+        let path = self.out_dir().join(path);
+        fs::read_to_string(&path).with_context(|| format!("no output {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn renders_jsx() {
+        let html = render_jsx("export default () => <p>Hello</p>")
+            .await
+            .unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<p>Hello</p>"));
+        assert!(!html.contains("<script"));
+    }
+
+    #[tokio::test]
+    async fn renders_mdx() {
+        let html = render_mdx("# Hello\n\nFrom *MDX*").await.unwrap();
+        assert!(html.contains("Hello</h1>"));
+        assert!(html.contains("<p>From <em>MDX</em></p>"));
+    }
+
+    #[tokio::test]
+    async fn builds_fixtures() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file("index.tsx", "export default () => <p>Home</p>")
+            .unwrap()
+            .file("blog/post.mdx", "# Post")
+            .unwrap()
+            .file("robots.txt", "User-agent: *")
+            .unwrap();
+
+        let report = fixture.build(BuilderOptions::default()).await.unwrap();
+        assert_eq!(report.pages, 2);
+        assert!(fixture
+            .output("index.html")
+            .unwrap()
+            .contains("<p>Home</p>"));
+        assert!(fixture
+            .output("blog/post/index.html")
+            .unwrap()
+            .contains("Post</h1>"));
+        assert_eq!(fixture.output("robots.txt").unwrap(), "User-agent: *");
+    }
+
+    #[tokio::test]
+    async fn renders_fixture_routes() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "_layout.tsx",
+                "export default ({ children }) => <main>{children}</main>",
+            )
+            .unwrap()
+            .file("about.tsx", "export default () => <p>About</p>")
+            .unwrap();
+
+        let html = fixture.render("/about").await.unwrap();
+        assert!(html.contains("<main><p>About</p></main>"));
+        assert!(fixture.render("/missing").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn renders_md_routes_as_commonmark() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "notes.md",
+                "---\ntitle: Notes\n---\n\n| a | b |\n|---|---|\n| {1} | ~~2~~ |\n",
+            )
+            .unwrap();
+
+        // Found by the scan, then rendered by its kind
+        let html = fixture.render("/notes").await.unwrap();
+        assert!(html.contains("<table>"), "{}", html);
+        assert!(html.contains("<td>{1}</td>"), "{}", html);
+        assert!(html.contains("<del>2</del>"), "{}", html);
+        assert!(!html.contains("title: Notes"), "{}", html);
+    }
+}
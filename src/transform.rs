@@ -0,0 +1,254 @@
+use std::{borrow::Cow, path::PathBuf};
+
+use lol_html::{element, html_content::ContentType, text, ElementContentHandlers, Selector};
+
+use url::Url;
+
+use crate::{images::ImageFormat, page::Page, src_fs::encode_route};
+
+/// A selector and the handlers run on what it matches as a page is serialized.
+pub type ElementHandler<'a> = (Cow<'static, Selector>, ElementContentHandlers<'a>);
+
+/// What a transform knows about the page being rendered besides the page
+/// itself.
+pub struct TransformCtx {
+    /// Site root.
+    pub root: PathBuf,
+    /// Whether the page is rendered by the dev server, rather than a build.
+    pub dev: bool,
+}
+
+/// A pass over every page, registered with `Builder::with_transform` or
+/// `Server::with_transform`.
+///
+/// Transforms run once a page's styles are processed: first
+/// [`PageTransform::transform`] of each transform in the order they were
+/// registered, then the page is serialized with the built-in handlers, which
+/// render math, harden external links, point images to their optimized
+/// variants and inject styles and scripts, followed
+/// by the [`PageTransform::element_handlers`] of each transform, again in
+/// order.
+pub trait PageTransform: Send + Sync {
+    fn transform(&self, _page: &mut Page, _ctx: &TransformCtx) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Handlers for lol_html to run as the page is serialized.
+    fn element_handlers<'a>(
+        &'a self,
+        _page: &'a Page,
+        _ctx: &'a TransformCtx,
+    ) -> Vec<ElementHandler<'a>> {
+        Vec::new()
+    }
+}
+
+/// Renders math from Markdown with KaTeX.
+pub(crate) struct Katex;
+
+impl PageTransform for Katex {
+    fn element_handlers<'a>(
+        &'a self,
+        _page: &'a Page,
+        _ctx: &'a TransformCtx,
+    ) -> Vec<ElementHandler<'a>> {
+        vec![
+            text!(".language-math.math-inline", |t| {
+                if !t.last_in_text_node() {
+                    let rendered = katex::render(t.as_str().trim())?;
+                    t.replace(&rendered, ContentType::Html);
+                }
+                Ok(())
+            }),
+            text!(".language-math.math-display", |t| {
+                if !t.last_in_text_node() {
+                    let opts = katex::Opts::builder().display_mode(true).build()?;
+                    let rendered = katex::render_with_opts(t.as_str(), opts)?;
+                    t.replace(&rendered, ContentType::Html);
+                }
+                Ok(())
+            }),
+            element!(".language-math.math-display", |el| {
+                el.remove_and_keep_content();
+                Ok(())
+            }),
+            element!(".language-math.math-inline", |el| {
+                el.remove_and_keep_content();
+                Ok(())
+            }),
+        ]
+    }
+}
+
+/// Hardens links to other sites, as configured by `[links]`.
+pub(crate) struct ExternalLinks;
+
+impl PageTransform for ExternalLinks {
+    fn element_handlers<'a>(
+        &'a self,
+        page: &'a Page,
+        _ctx: &'a TransformCtx,
+    ) -> Vec<ElementHandler<'a>> {
+        let links = &page.links;
+        if !links.noopener && !links.new_tab {
+            return Vec::new();
+        }
+
+        vec![element!("a[href]", move |el| {
+            let href = el.get_attribute("href").unwrap_or_default();
+            if !links.is_external(&href) {
+                return Ok(());
+            }
+
+            if links.noopener {
+                let mut rel = el.get_attribute("rel").unwrap_or_default();
+                for value in ["noopener", "noreferrer"] {
+                    if !rel.split_ascii_whitespace().any(|v| v == value) {
+                        if !rel.is_empty() {
+                            rel.push(' ');
+                        }
+                        rel.push_str(value);
+                    }
+                }
+                el.set_attribute("rel", &rel)?;
+            }
+            if links.new_tab && !el.has_attribute("target") {
+                el.set_attribute("target", "_blank")?;
+            }
+            Ok(())
+        })]
+    }
+}
+
+/// Gives `<img>` tags referencing images processed by `[images]` a `srcset`
+/// of their variants, keeping the original as `src` for other browsers.
+pub(crate) struct ResponsiveImages;
+
+impl PageTransform for ResponsiveImages {
+    fn element_handlers<'a>(
+        &'a self,
+        page: &'a Page,
+        _ctx: &'a TransformCtx,
+    ) -> Vec<ElementHandler<'a>> {
+        let images = &page.images;
+
+        vec![element!("img[src]:not([srcset])", move |el| {
+            let src = el.get_attribute("src").unwrap_or_default();
+            // Relative sources resolve against the page's own route
+            let route = encode_route(&page.path);
+            let base = Url::parse("http://localhost")?
+                .join(&format!("{}/", route.trim_end_matches('/')))?;
+            let Ok(resolved) = base.join(&src) else {
+                return Ok(());
+            };
+            if resolved.host_str() != Some("localhost") {
+                return Ok(());
+            }
+            let Some(image) = images.get(resolved.path()) else {
+                return Ok(());
+            };
+
+            el.set_attribute("srcset", &image.srcset(ImageFormat::Webp))?;
+            el.set_attribute("sizes", &images.sizes)?;
+            // Reserves the image's space before it loads
+            if !el.has_attribute("width") && !el.has_attribute("height") {
+                el.set_attribute("width", &image.width.to_string())?;
+                el.set_attribute("height", &image.height.to_string())?;
+            }
+
+            let avif = image.srcset(ImageFormat::Avif);
+            if !avif.is_empty() {
+                el.before(
+                    &format!(
+                        r#"<picture><source type="{}" srcset="{}" sizes="{}">"#,
+                        ImageFormat::Avif.mime(),
+                        avif,
+                        images.sizes
+                    ),
+                    ContentType::Html,
+                );
+                el.after("</picture>", ContentType::Html);
+            }
+            Ok(())
+        })]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::{Builder, BuilderOptions},
+        testing::Fixture,
+    };
+
+    /// The example transform from the README, marking pages with the build
+    /// they are from.
+    struct BuildId(String);
+
+    impl PageTransform for BuildId {
+        fn element_handlers<'a>(
+            &'a self,
+            _page: &'a Page,
+            _ctx: &'a TransformCtx,
+        ) -> Vec<ElementHandler<'a>> {
+            vec![element!("html", |el| Ok(
+                el.set_attribute("data-build-id", &self.0)?
+            ))]
+        }
+    }
+
+    #[tokio::test]
+    async fn transforms_add_attributes() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "index.tsx",
+                "export default () => <html><head></head><body><p>Home</p></body></html>",
+            )
+            .unwrap();
+
+        let mut site = Builder::new(fixture.root(), BuilderOptions::default())
+            .await
+            .unwrap()
+            .with_transform(Box::new(BuildId("1234".into())));
+        site.build(&fixture.out_dir()).await.unwrap();
+
+        let html = fixture.output("index.html").unwrap();
+        assert!(html.contains(r#"<html data-build-id="1234">"#), "{}", html);
+        assert!(html.contains("<p>Home</p>"));
+    }
+
+    #[tokio::test]
+    async fn hardens_only_external_links() {
+        let fixture = Fixture::new().unwrap();
+        fixture
+            .file(
+                "areum.toml",
+                "[links]\nhost = \"example.com\"\nnew_tab = true\n",
+            )
+            .unwrap()
+            .file(
+                "index.tsx",
+                r##"export default () => <nav>
+                    <a href="/about">About</a>
+                    <a href="https://example.com/blog">Blog</a>
+                    <a href="#comments">Comments</a>
+                    <a href="https://other.org/">Other</a>
+                    <a href="//cdn.org/file" rel="me" target="_self">File</a>
+                </nav>"##,
+            )
+            .unwrap();
+        fixture.build(BuilderOptions::default()).await.unwrap();
+
+        let html = fixture.output("index.html").unwrap();
+        assert!(html.contains(r#"<a href="/about">"#));
+        assert!(html.contains(r#"<a href="https://example.com/blog">"#));
+        assert!(html.contains(r##"<a href="#comments">"##));
+        assert!(html.contains(
+            r#"<a href="https://other.org/" rel="noopener noreferrer" target="_blank">"#
+        ));
+        assert!(html
+            .contains(r#"<a href="//cdn.org/file" rel="me noopener noreferrer" target="_self">"#));
+    }
+}
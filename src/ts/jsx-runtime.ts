@@ -1,6 +1,28 @@
 const hashString =
   "Deno" in globalThis ? Deno.core.ops.hashString : (str) => "";
 
+// Version of the nodes `render` produces, which Areum checks against the
+// version it reads. Bump it with any change to their shape.
+const protocol = 1;
+
+// Key under which modules compiled from `.module.css` files hold their CSS
+const stylesheetKey = Symbol.for("areum.stylesheet");
+
+// Key under which components hold the URL of the module defining them, which
+// imports and URLs in their styles are relative to
+const sourceKey = Symbol.for("areum.source");
+
+// Called by code Areum appends to each module, with the module's
+// `import.meta.url` and what it declares at the top level. Components
+// re-exported from other modules keep the URL they were first given.
+const setSource = (url: string, declared: unknown[]) => {
+  for (const value of declared) {
+    if (typeof value === "function" && !Object.hasOwn(value, sourceKey)) {
+      Object.defineProperty(value, sourceKey, { value: url });
+    }
+  }
+};
+
 const run = (page: JSX.FunctionalElement, props: JSX.PageProps) => {
   if ("Deno" in window || typeof page !== "function") {
     return;
@@ -10,7 +32,7 @@ const run = (page: JSX.FunctionalElement, props: JSX.PageProps) => {
 };
 
 const runChildren = (children?: JSX.Children) => {
-  if (typeof children === "string" || children === undefined) {
+  if (typeof children !== "object" || children === null) {
     return;
   } else if (Array.isArray(children)) {
     for (const child of children) {
@@ -27,7 +49,7 @@ type Children = Node | string | Children[];
 interface IntrinsicNode {
   kind: "intrinsic";
   props: JSX.Props;
-  children: Children;
+  children?: Children;
   scope: string;
 
   tag: string;
@@ -36,20 +58,106 @@ interface IntrinsicNode {
 interface VirtualNode {
   kind: "virtual";
   props: JSX.Props;
-  children: Children;
+  children?: Children;
   scope: string;
 
   style?: string;
   script?: () => void;
+  source?: string;
+  stylesheet?: string;
+  name?: string;
+  // Id Areum keeps the node under, for components with `cache = true`
+  cache?: number;
+}
+
+// Component with `cache = true` rendered earlier with the same props, which
+// Areum kept when it was first sent in full
+interface CachedNode {
+  kind: "cached";
+  id: number;
 }
 
-type Node = IntrinsicNode | VirtualNode;
+type Node = IntrinsicNode | VirtualNode | CachedNode;
+
+// Ids of the nodes of components with `cache = true`, by component and props.
+// Kept for the life of the env, so cached components must be pure functions of
+// their props.
+const cachedNodes = new WeakMap<JSX.FunctionalElement, Map<string, number>>();
+let nextCacheId = 0;
+// Entries added by the batch being rendered, dropped if it fails, as Areum
+// only keeps the nodes of batches it receives
+let pendingNodes: [Map<string, number>, string][] = [];
+
+// Ids standing in for functions, such as components passed as children, in
+// cache keys
+const functionIds = new WeakMap<Function, number>();
+let nextFunctionId = 0;
+
+// Key of a component's props, or undefined if they cannot be serialized
+const cacheKey = (props: JSX.Props) => {
+  try {
+    return JSON.stringify(props, (_, value) => {
+      if (typeof value !== "function") {
+        return value;
+      }
+      if (!functionIds.has(value)) {
+        functionIds.set(value, nextFunctionId++);
+      }
+      return `\0${functionIds.get(value)}`;
+    });
+  } catch {
+    // Cyclic, or holding a bigint
+    return undefined;
+  }
+};
+
+// Renders the element of a component with `cache = true`, or refers to its
+// node if it was rendered with the same props before
+const renderCached = (element: JSX.Element): Node | undefined => {
+  const component = element.element as JSX.FunctionalElement;
+  const key = cacheKey({ ...element.props, children: element.children });
+  if (key === undefined) {
+    return renderElement(element);
+  }
+
+  let nodes = cachedNodes.get(component);
+  if (nodes === undefined) {
+    nodes = new Map();
+    cachedNodes.set(component, nodes);
+  }
+  const id = nodes.get(key);
+  if (id !== undefined) {
+    return { kind: "cached", id };
+  }
+
+  const node = renderElement(element) as VirtualNode | undefined;
+  if (node !== undefined) {
+    node.cache = nextCacheId++;
+    nodes.set(key, node.cache);
+    pendingNodes.push([nodes, key]);
+  }
+  return node;
+};
+
+// Renders the pages of one call from Areum with `render`, which receives the
+// nodes of cached components only if the whole batch succeeds
+const renderBatch = <T>(render: () => T): T => {
+  try {
+    return render();
+  } catch (err) {
+    for (const [nodes, key] of pendingNodes) {
+      nodes.delete(key);
+    }
+    throw err;
+  } finally {
+    pendingNodes = [];
+  }
+};
 
 const applyScopeChildren = (children: JSX.Children, scope: string) => {
-  if (typeof children === "string") {
-  } else if (Array.isArray(children)) {
+  if (Array.isArray(children)) {
     children.forEach((child) => applyScopeChildren(child, scope));
-  } else if (children) {
+  } else if (typeof children === "object" && children !== null) {
     applyScope(children, scope);
   }
 };
@@ -64,22 +172,38 @@ const applyScope = (element: JSX.Element, scope: string) => {
   applyScopeChildren(element.children, scope);
 };
 
-const renderChildren = (children: JSX.Children): Children => {
-  let rendered: Children;
-
-  if (typeof children === "string") {
-    rendered = children;
+// Children are sent to Areum as strings, nodes or arrays of them. `null`,
+// `undefined` and booleans, as left by conditionals like `{cond && <X />}`,
+// render nothing, while numbers, including 0, render as text.
+const renderChildren = (children: JSX.Children): Children | undefined => {
+  if (children === null || children === undefined) {
+    return undefined;
+  } else if (typeof children === "boolean") {
+    return undefined;
+  } else if (typeof children === "number" || typeof children === "bigint") {
+    return String(children);
+  } else if (typeof children === "string") {
+    return children;
   } else if (Array.isArray(children)) {
-    rendered = children.map((child) => renderChildren(child)).filter((x) => x);
-  } else if (children) {
-    rendered = render(children);
+    return children
+      .map((child) => renderChildren(child))
+      .filter((child) => child !== undefined && child !== "");
+  } else {
+    return render(children);
   }
-
-  return rendered;
 };
 
 const render = (element: JSX.Element): Node | undefined => {
-  let node_: Node;
+  if (
+    typeof element?.element === "function" && element.element.cache === true
+  ) {
+    return renderCached(element);
+  }
+  return renderElement(element);
+};
+
+const renderElement = (element: JSX.Element): Node | undefined => {
+  let node_: IntrinsicNode | VirtualNode;
 
   if (!element || (Array.isArray(element) && element.length === 0)) {
     return undefined;
@@ -91,30 +215,48 @@ const render = (element: JSX.Element): Node | undefined => {
 
     // const newScope = randString(8);
 
-    if (typeof element.element.style === "function") {
-      node.style = element.element.style(element.props);
+    const style = element.element.style;
+    if (typeof style === "function") {
+      // Called for each instance with the props the component gets. The scope
+      // is a hash of the resulting CSS, so instances whose props produce the
+      // same CSS share a scope and others each get their own.
+      const { __scope, ...props } = element.props;
+      node.style = style({ ...props, children: element.children }) || undefined;
+    } else if (typeof style === "object") {
+      node.stylesheet = style[stylesheetKey];
     } else {
-      node.style = element.element.style;
+      node.style = style;
     }
     node.script = element.element.script;
+    node.name = element.element.name;
 
     const newScope = hashString(node.style);
 
     if (element.element !== Fragment) {
       applyScope(element, newScope);
       element.props.__scope = newScope;
+
+      if (node.style?.includes("@import") || node.style?.includes("url(")) {
+        node.source = element.element[sourceKey];
+      }
       const inner = element.element({
         ...element.props,
         children: element.children,
       });
-      applyScope(inner, newScope);
-      node.children = render(inner);
+
+      // Components may return anything a child can be
+      applyScopeChildren(inner, newScope);
+      node.children = renderChildren(inner);
     } else {
       node.children = renderChildren(element.children);
     }
 
     node_ = node;
   } else {
+    if (typeof element.element !== "string") {
+      throw new Error(`cannot render ${describe(element)}, expected an element`);
+    }
+
     let node = {} as IntrinsicNode;
     node.kind = "intrinsic";
 
@@ -131,6 +273,30 @@ const render = (element: JSX.Element): Node | undefined => {
   return node_;
 };
 
+// What a value is, with a truncated preview, for errors about values that
+// should have been components or elements
+const describe = (value: unknown): string => {
+  if (value === null || value === undefined) {
+    return String(value);
+  } else if (value instanceof Promise) {
+    return "a promise, as async components return";
+  }
+
+  let preview: string | undefined;
+  try {
+    preview = JSON.stringify(value);
+  } catch {
+    // Cyclic, or holding a bigint
+  }
+  preview ??= String(value);
+  if (preview.length > 120) {
+    preview = `${preview.slice(0, 120)}...`;
+  }
+
+  const type = Array.isArray(value) ? "an array" : `a ${typeof value}`;
+  return `${type} ${preview}`;
+};
+
 const jsx = (element: JSX.ElementType, props: JSX.Props) => {
   let { children, ...rest } = props;
 
@@ -184,17 +350,53 @@ namespace JSX {
 
   // Custom
 
-  export type Children = string | Element | Children[];
+  export type Children =
+    | string
+    | number
+    | boolean
+    | null
+    | undefined
+    | Element
+    | Children[];
 
   export interface FunctionalElement {
     (props: Props): Element;
-    style?: string | ((props: Props) => string);
+    style?: string | CssModule | ((props: Props) => string);
     script?: () => void;
+    // Whether the component is a pure function of its props, so it is only
+    // rendered once per props
+    cache?: boolean;
+  }
+
+  // Default export of a `.module.css` file, mapping its class names to their
+  // hashed names
+  export interface CssModule {
+    readonly [name: string]: string;
+    readonly [stylesheetKey]: string;
   }
 
   export interface PageProps {
     path: string;
+    // Absolute URL, if `links.host` is set
+    url: string | null;
+    route: string;
+    segments: string[];
+    is_index: boolean;
+    // Last commit of the source, or else its modification time
+    last_modified: {
+      date: string;
+      author: string | null;
+      hash: string | null;
+    } | null;
     generator: string;
+    locale: string | null;
+    default_locale: string | null;
+    // Routes of the page's translations by locale
+    alternates: Record<string, string>;
+    // Frontmatter `description`, or content above `<!--more-->`
+    excerpt: string | null;
+    // Query parameters of the dev server request, empty in builds
+    query: Record<string, string>;
   }
 
   export interface Props {
@@ -203,4 +405,15 @@ namespace JSX {
   }
 }
 
-export { jsx, jsxs, Fragment, run, render, type JSX };
+export {
+  jsx,
+  jsxs,
+  Fragment,
+  run,
+  render,
+  renderBatch,
+  describe,
+  setSource,
+  protocol,
+  type JSX,
+};
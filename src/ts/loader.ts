@@ -1,24 +1,233 @@
-import { jsx, render, type JSX } from "/areum/jsx-runtime";
+import {
+  describe,
+  jsx,
+  render,
+  renderBatch,
+  type JSX,
+} from "/areum/jsx-runtime";
 
-const load = async (url: string, props: JSX.PageProps) => {
-  const fn = (await import(url)).default;
-  const page = jsx(fn, props);
-  return render(page);
+// URLs of the `_document` and `_app` components, if any
+interface ShellUrls {
+  document: string | null;
+  app: string | null;
+}
+
+interface Shell {
+  document?: JSX.FunctionalElement;
+  app?: JSX.FunctionalElement;
+}
+
+const loadShell = async ({ document, app }: ShellUrls): Promise<Shell> => ({
+  document: document ? (await import(document)).default : undefined,
+  app: app ? (await import(app)).default : undefined,
+});
+
+// Layouts wrapping the page module `mod`, outermost first, unless it exports
+// `layout = false`
+const loadLayouts = async (
+  mod: Record<string, unknown>,
+  urls: string[],
+): Promise<JSX.FunctionalElement[]> => {
+  if (mod.layout === false) {
+    return [];
+  }
+  return Promise.all(urls.map(async (url) => (await import(url)).default));
+};
+
+// Named exports of a page module other than its component, `layout` and
+// `outputs`, passed to layouts as `meta`
+const pageMeta = (mod: Record<string, unknown>) => {
+  const { default: _, layout: __, outputs: ___, ...meta } = mod;
+  return meta;
+};
+
+// Files a page module exports as `outputs`, by path relative to its directory.
+// Each is a function returning the file's contents: a string as it is, or
+// anything else as JSON.
+const pageOutputs = async (url: string, mod: Record<string, unknown>) => {
+  const outputs = mod.outputs;
+  if (outputs === undefined) {
+    return {};
+  } else if (
+    typeof outputs !== "object" || outputs === null || Array.isArray(outputs)
+  ) {
+    throw new Error(
+      `page ${url} exports ${describe(outputs)} as outputs, outputs must be an object of functions by path`,
+    );
+  }
+
+  const entries = await Promise.all(
+    Object.entries(outputs).map(async ([path, fn]) => {
+      if (typeof fn !== "function") {
+        throw new Error(
+          `page ${url} exports ${describe(fn)} as output ${path}, outputs must be functions returning their contents`,
+        );
+      }
+      const contents = await fn();
+      return [
+        path,
+        typeof contents === "string"
+          ? contents
+          : JSON.stringify(contents ?? null, null, 2),
+      ];
+    }),
+  );
+  return Object.fromEntries(entries);
+};
+
+// Renders a page inside its layouts, innermost last, then inside `_app`, which
+// receives the page component and its rendered element, inside `_document`
+const wrap = (
+  fn: JSX.FunctionalElement,
+  { document, app }: Shell,
+  layouts: JSX.FunctionalElement[],
+  meta: Record<string, unknown>,
+  props: JSX.PageProps,
+) => {
+  let page = jsx(fn, props);
+  for (const layout of [...layouts].reverse()) {
+    page = jsx(layout, { ...props, meta, children: page });
+  }
+  if (app) {
+    page = jsx(app, { ...props, Component: fn, children: page });
+  }
+  if (document) {
+    page = jsx(document, { ...props, children: page });
+  }
+  return page;
+};
+
+// The component a page module exports, or an error saying what it exports
+// instead
+const component = (url: string, mod: Record<string, unknown>) => {
+  if (!("default" in mod)) {
+    throw new Error(
+      `page ${url} has no default export, pages must export a component function`,
+    );
+  } else if (typeof mod.default !== "function") {
+    throw new Error(
+      `page ${url} exports ${describe(mod.default)} by default, pages must export a component function`,
+    );
+  }
+  return mod.default as JSX.FunctionalElement;
+};
+
+// Renders a page's element, which must render to something
+const renderPage = (url: string, element: JSX.Element) => {
+  const node = render(element);
+  if (node === undefined) {
+    throw new Error(`page ${url} rendered nothing`);
+  }
+  return node;
+};
+
+const load = async (
+  url: string,
+  props: JSX.PageProps,
+  urls: ShellUrls,
+  layoutUrls: string[],
+) => {
+  const mod = await import(url);
+  const fn = component(url, mod);
+  const shell = await loadShell(urls);
+  const layouts = await loadLayouts(mod, layoutUrls);
+  const outputs = await pageOutputs(url, mod);
+  const node = renderBatch(() =>
+    renderPage(url, wrap(fn, shell, layouts, pageMeta(mod), props))
+  );
+  return { node, outputs };
+};
+
+// Props of a page generated at `path`, located as `PageProps::new` locates
+// other pages
+const atPath = (props: JSX.PageProps, path: string): JSX.PageProps => {
+  const route = Deno.core.ops.encode_route(path);
+  // Page URLs are the site's origin followed by their route
+  const origin = props.url?.slice(0, props.url.length - props.route.length);
+  const segments = path.split("/").filter((segment) => segment !== "");
+  return {
+    ...props,
+    path,
+    url: origin === undefined ? null : origin + route,
+    route,
+    segments,
+    is_index: segments.length === 0,
+  };
+};
+
+// The page components a generator module exports by path, or an error saying
+// what it exports instead
+const generated = (url: string, mod: Record<string, unknown>) => {
+  const mods = mod.default;
+  if (typeof mods !== "object" || mods === null || Array.isArray(mods)) {
+    throw new Error(
+      `generator ${url} exports ${describe(mods)} by default, generators must export an object of page components by path`,
+    );
+  }
+  return mods as Record<string, unknown>;
 };
 
-const loadGenerator = async (url: string, props: JSX.PageProps) => {
-  const mods = (await import(url)).default;
+// Paths of the pages a generator at `root` generates, without rendering them
+const listGenerator = async (url: string, root: string) => {
+  const mod = await import(url);
+  return Object.keys(generated(url, mod)).map((relpath) =>
+    Deno.core.ops.join_path(root, relpath)
+  );
+};
+
+const loadGenerator = async (
+  url: string,
+  props: JSX.PageProps,
+  urls: ShellUrls,
+  layoutUrls: string[],
+) => {
+  const mod = await import(url);
+  const mods = generated(url, mod);
+  const shell = await loadShell(urls);
+  const layouts = await loadLayouts(mod, layoutUrls);
+  const meta = pageMeta(mod);
+  const outputs = await pageOutputs(url, mod);
   const root = props.path;
 
-  let entries = Object.entries(mods).map(([relpath, fn]) => {
-    const path = Deno.core.ops.join_path(root, relpath);
-    const page_props = { ...props, path };
-    const page = jsx(fn, page_props);
+  const entries = renderBatch(() =>
+    Object.entries(mods).map(([relpath, fn]) => {
+      if (typeof fn !== "function") {
+        throw new Error(
+          `generator ${url} exports ${describe(fn)} for ${relpath}, generators must export a component function for each path`,
+        );
+      }
+      const path = Deno.core.ops.join_path(root, relpath);
+      const page_props = atPath(props, path);
+
+      return [path, renderPage(url, wrap(fn, shell, layouts, meta, page_props))];
+    })
+  );
+
+  return { pages: new Map(entries), outputs };
+};
+
+// Renders one component at several paths, each with its own extra props
+const loadEach = async (
+  url: string,
+  props: JSX.PageProps,
+  urls: ShellUrls,
+  pages: Record<string, JSX.Props>,
+  layoutUrls: string[],
+) => {
+  const mod = await import(url);
+  const fn = component(url, mod);
+  const shell = await loadShell(urls);
+  const layouts = await loadLayouts(mod, layoutUrls);
+  const meta = pageMeta(mod);
 
-    return [path, render(page)];
-  });
+  const entries = renderBatch(() =>
+    Object.entries(pages).map(([path, extra]) => {
+      const page_props = { ...atPath(props, path), ...extra };
+      return [path, renderPage(url, wrap(fn, shell, layouts, meta, page_props))];
+    })
+  );
 
   return new Map(entries);
 };
 
-export { load, loadGenerator };
+export { listGenerator, load, loadEach, loadGenerator };
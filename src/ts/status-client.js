@@ -0,0 +1,54 @@
+// Shows the dev server's status in a corner of the page, and reloads the page
+// once envs restarted after a change. Inlined into every page the dev server
+// renders, so it must not contain characters needing escaping in XHTML.
+(() => {
+  const indicator = document.createElement("div");
+  indicator.setAttribute("role", "status");
+  indicator.style.cssText = [
+    "position: fixed",
+    "right: 1rem",
+    "bottom: 1rem",
+    "z-index: 2147483647",
+    "max-width: 40rem",
+    "padding: 0.5rem 0.75rem",
+    "border-radius: 0.375rem",
+    "color: #fff",
+    "font: 13px/1.4 system-ui, sans-serif",
+    "white-space: pre-wrap",
+    "display: none",
+  ].join(";");
+  document.body.append(indicator);
+
+  const show = (text, background) => {
+    indicator.textContent = text;
+    indicator.style.background = background;
+    indicator.style.display = "block";
+  };
+
+  let restarted = false;
+  const connect = () => {
+    const protocol = location.protocol === "https:" ? "wss:" : "ws:";
+    const socket = new WebSocket(`${protocol}//${location.host}/__areum_status`);
+    socket.addEventListener("message", (event) => {
+      const status = JSON.parse(event.data);
+      switch (status.status) {
+        case "restarting":
+          restarted = true;
+          show("Rebuilding…", "#444");
+          break;
+        case "ready":
+          indicator.style.display = "none";
+          if (restarted) {
+            location.reload();
+          }
+          break;
+        case "failed":
+          show(`Rebuild failed\n${status.message}`, "#b00020");
+          break;
+      }
+    });
+    // The server was stopped or restarted, so try again until it is back
+    socket.addEventListener("close", () => setTimeout(connect, 1000));
+  };
+  connect();
+})();